@@ -0,0 +1,446 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use serde::de::DeserializeOwned;
+use serde_with::{DurationSecondsWithFrac, serde_as};
+
+/// Name of the file describing the capture session in the output directory
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Description of a capture session, written to the output directory at the end of a run
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Manifest {
+    /// Version of bpfmeter
+    pub version: String,
+    /// Command line arguments of the run
+    pub args: Vec<String>,
+    /// Start time of the run, RFC 3339
+    pub start: String,
+    /// End time of the run, RFC 3339
+    pub end: String,
+    /// Host the capture was taken on
+    pub host: HostInfo,
+    /// Version of the monitored workload, e.g. git commit of the ebpf programs under test
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workload_version: Option<String>,
+    /// Csv files produced by the run
+    pub files: Vec<String>,
+}
+
+/// Description of the host the capture was taken on
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct HostInfo {
+    /// Host name
+    pub hostname: String,
+    /// Kernel release
+    pub kernel: String,
+    /// Number of available CPUs
+    pub cpus: usize,
+}
+
+impl HostInfo {
+    /// Collects information about the current host
+    pub fn current() -> Self {
+        let read_proc = |path: &str| {
+            std::fs::read_to_string(path)
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default()
+        };
+        Self {
+            hostname: read_proc("/proc/sys/kernel/hostname"),
+            kernel: read_proc("/proc/sys/kernel/osrelease"),
+            cpus: std::thread::available_parallelism().map_or(0, |n| n.get()),
+        }
+    }
+}
+
+/// Reads the manifest of the capture if the directory has one
+///
+/// # Arguments
+///
+/// * `dir` - Directory with csv files
+pub fn read_manifest(dir: &Path) -> Result<Option<Manifest>> {
+    let path = dir.join(MANIFEST_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let manifest = serde_json::from_reader(BufReader::new(File::open(&path)?))
+        .with_context(|| format!("Invalid manifest {}", path.display()))?;
+    Ok(Some(manifest))
+}
+
+/// Measurements of a single bpf program/map, stored in one or several csv files
+#[derive(Debug)]
+pub struct Series {
+    /// Name of the series shown in the legend
+    pub name: String,
+    /// Csv files with measurements in chronological order
+    pub files: Vec<PathBuf>,
+}
+
+/// Time of a row of csv file written by the file exporter, leading columns of the row
+#[serde_as]
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RowTime {
+    /// Tick number of the measurement
+    pub tick: u64,
+    /// Time the stats were received since the start of the measurements
+    #[serde_as(as = "DurationSecondsWithFrac<String>")]
+    pub time_recieved: Duration,
+}
+
+/// Record of csv file with its time, empty for files written without the time columns
+pub type TimedRecord<R> = (Option<RowTime>, R);
+
+/// Kinds of measurements in names of csv files: of bpf programs and of bpf maps
+const FILE_SUFFIXES: [&str; 2] = ["prog", "map"];
+
+/// Parts of the name of a csv file written by the file exporter, format:
+/// <bpf_id>_<bpf_name>_<prog|map>[_<date>|_<start_time>]_<measurement_period>.csv
+#[derive(Debug, PartialEq)]
+pub struct FileName<'a> {
+    /// Bpf program/map id
+    pub id: u32,
+    /// Bpf program/map name, may contain underscores
+    pub name: &'a str,
+    /// Kind of the measurements: prog or map
+    pub suffix: &'a str,
+    /// Date of the daily rotated file or start time of the file rotated by size or time
+    pub date: Option<&'a str>,
+    /// Measurement period, e.g. 30s
+    pub period: &'a str,
+}
+
+impl<'a> FileName<'a> {
+    /// Parses the file name without extension. The id is taken from the start, the period,
+    /// date and suffix from the end, so the name keeps all its underscores
+    ///
+    /// # Arguments
+    ///
+    /// * `file_stem` - File name without extension
+    pub fn parse(file_stem: &'a str) -> Option<Self> {
+        let (rest, period) = file_stem.rsplit_once('_')?;
+        let (mut rest, mut suffix) = rest.rsplit_once('_')?;
+        let mut date = None;
+        if is_date(suffix) || is_rotation_time(suffix) {
+            date = Some(suffix);
+            (rest, suffix) = rest.rsplit_once('_')?;
+        }
+        if !FILE_SUFFIXES.contains(&suffix) {
+            return None;
+        }
+        let (id, name) = rest.split_once('_')?;
+        Some(Self {
+            id: id.parse().ok()?,
+            name,
+            suffix,
+            date,
+            period,
+        })
+    }
+}
+
+/// Returns measurement period from the name of the csv file, e.g. 30s
+///
+/// # Arguments
+///
+/// * `file` - Csv file written by the file exporter
+pub fn file_period(file: &Path) -> Result<String> {
+    let file_stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    match FileName::parse(&file_stem) {
+        Some(file_name) => Ok(file_name.period.to_string()),
+        None => bail!(
+            "File name of csv should be in format <bpf_id>_<bpf_name>_<prog|map>_<measurement_period>.csv, given: {}",
+            file.display()
+        ),
+    }
+}
+
+/// Groups csv files into series, each file is a separate series unless daily rotated files
+/// are merged
+///
+/// # Arguments
+///
+/// * `files` - The input csv files
+///
+/// * `merge_daily` - Merge daily rotated files of the same bpf program/map into one series
+pub fn group_series(files: Vec<PathBuf>, merge_daily: bool) -> Vec<Series> {
+    let mut series_files: BTreeMap<String, Vec<(String, PathBuf)>> = BTreeMap::new();
+    for file in files {
+        let file_stem = file.file_stem().unwrap().to_string_lossy();
+        let (name, date) = match FileName::parse(&file_stem) {
+            Some(file_name) => {
+                let name = format!("{}_{}_{}", file_name.id, file_name.name, file_name.suffix);
+                match file_name.date {
+                    Some(date) if merge_daily => (name, date.to_string()),
+                    Some(date) => (format!("{name}_{date}"), String::new()),
+                    None => (name, String::new()),
+                }
+            }
+            // Measurement period is the last part of the name of files not written by bpfmeter
+            None => (
+                file_stem
+                    .rsplit_once('_')
+                    .map_or(file_stem.as_ref(), |(name, _)| name)
+                    .to_string(),
+                String::new(),
+            ),
+        };
+        series_files.entry(name).or_default().push((date, file));
+    }
+
+    series_files
+        .into_iter()
+        .map(|(name, mut files)| {
+            files.sort();
+            Series {
+                name,
+                files: files.into_iter().map(|(_, file)| file).collect(),
+            }
+        })
+        .collect()
+}
+
+/// Checks if the series has map measurements, which have size column instead of cpu usage
+///
+/// # Arguments
+///
+/// * `series` - Series to check
+pub fn is_map_series(series: &Series) -> Result<bool> {
+    let Some(file) = series.files.first() else {
+        return Ok(false);
+    };
+    let headers = csv::Reader::from_reader(BufReader::new(File::open(file)?))
+        .headers()?
+        .clone();
+    Ok(headers.iter().any(|h| h == "size"))
+}
+
+/// Checks if the part of the file name is a date of daily rotated file, format: YYYY-MM-DD
+///
+/// # Arguments
+///
+/// * `s` - Part of the file name
+pub(crate) fn is_date(s: &str) -> bool {
+    s.len() == 10
+        && s.char_indices().all(|(idx, c)| match idx {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+/// Checks if the part of the file name is a start time of file rotated by size or time,
+/// format: YYYY-MM-DDTHH-MM-SS.mmmZ
+///
+/// # Arguments
+///
+/// * `s` - Part of the file name
+pub(crate) fn is_rotation_time(s: &str) -> bool {
+    s.len() == 24
+        && s.is_char_boundary(10)
+        && is_date(&s[..10])
+        && s[10..].char_indices().all(|(idx, c)| match idx {
+            0 => c == 'T',
+            3 | 6 => c == '-',
+            9 => c == '.',
+            13 => c == 'Z',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+/// Lists csv files, the path is either a csv file or a directory with csv files
+///
+/// # Arguments
+///
+/// * `path` - Path to the csv file or directory
+pub fn csv_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let files = path
+        .read_dir()?
+        .flatten()
+        .filter(|e| e.path().extension().unwrap_or_default() == "csv")
+        .map(|e| e.path())
+        .collect::<Vec<_>>();
+    if files.is_empty() {
+        bail!("No bpf data csv files found in {}", path.display());
+    }
+    Ok(files)
+}
+
+/// Reads records from csv files of the series, unparsable records are skipped
+///
+/// # Arguments
+///
+/// * `series` - Series to read records of
+pub fn read_records<R: DeserializeOwned>(series: &Series) -> Result<Vec<R>> {
+    Ok(read_records_counting_errors(series)?.0)
+}
+
+/// Reads records from csv files of the series and counts unparsable records
+///
+/// # Arguments
+///
+/// * `series` - Series to read records of
+///
+/// Returns parsed records and the number of skipped records
+pub fn read_records_counting_errors<R: DeserializeOwned>(
+    series: &Series,
+) -> Result<(Vec<R>, usize)> {
+    let (records, errors) = read_timed_records_counting_errors(series)?;
+    Ok((
+        records.into_iter().map(|(_, record)| record).collect(),
+        errors,
+    ))
+}
+
+/// Reads records from csv files of the series along with their times, unparsable records
+/// are skipped
+///
+/// # Arguments
+///
+/// * `series` - Series to read records of
+///
+pub fn read_timed_records<R: DeserializeOwned>(series: &Series) -> Result<Vec<TimedRecord<R>>> {
+    Ok(read_timed_records_counting_errors(series)?.0)
+}
+
+/// Reads records from csv files of the series along with their times and counts unparsable
+/// records
+///
+/// # Arguments
+///
+/// * `series` - Series to read records of
+///
+/// Returns parsed records with their times and the number of skipped records
+pub fn read_timed_records_counting_errors<R: DeserializeOwned>(
+    series: &Series,
+) -> Result<(Vec<TimedRecord<R>>, usize)> {
+    let (mut records, mut errors) = (Vec::new(), 0);
+    for file in &series.files {
+        let mut reader = csv::Reader::from_reader(BufReader::new(File::open(file)?));
+        let headers = reader.headers()?.clone();
+        for record in reader.records() {
+            let Ok(record) = record else {
+                errors += 1;
+                continue;
+            };
+            match record.deserialize(Some(&headers)) {
+                Ok(stats) => records.push((record.deserialize(Some(&headers)).ok(), stats)),
+                Err(_) => errors += 1,
+            }
+        }
+    }
+    Ok((records, errors))
+}
+
+/// Parses measurement period from the csv file name into the number of time units
+/// and the time unit
+///
+/// # Arguments
+///
+/// * `period` - Measurement period from the file name, e.g. 30s or 100ms
+pub fn parse_period(period: &str) -> Result<(u64, &'static str)> {
+    let time_unit = if period.ends_with("ms") {
+        "ms"
+    } else if period.ends_with("s") {
+        "s"
+    } else {
+        bail!("Invalid measurement period: {}", period);
+    };
+    let factor = period
+        .trim_end_matches(time_unit)
+        .parse::<u64>()
+        .with_context(|| format!("Invalid measurement period: {period}"))?;
+    Ok((factor, time_unit))
+}
+
+/// Returns measurement period of the series, taken from the name of its first file
+///
+/// # Arguments
+///
+/// * `series` - Series to get the period of
+pub fn series_period(series: &Series) -> Result<Duration> {
+    let first_file = series.files.first().context("Series has no files")?;
+    let (factor, time_unit) = parse_period(&file_period(first_file)?)
+        .with_context(|| format!("Invalid file name {}", first_file.display()))?;
+    Ok(match time_unit {
+        "ms" => Duration::from_millis(factor),
+        _ => Duration::from_secs(factor),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_series_merge_daily() {
+        let files = vec![
+            PathBuf::from("1_prog_prog_2026-01-02_30s.csv"),
+            PathBuf::from("1_prog_prog_2026-01-01_30s.csv"),
+            PathBuf::from("2_other_prog_30s.csv"),
+        ];
+
+        let series = group_series(files.clone(), false);
+        assert_eq!(series.len(), 3);
+
+        let series = group_series(files, true);
+        assert_eq!(series.len(), 2);
+        assert_eq!(series[0].name, "1_prog_prog");
+        assert_eq!(
+            series[0].files,
+            [
+                PathBuf::from("1_prog_prog_2026-01-01_30s.csv"),
+                PathBuf::from("1_prog_prog_2026-01-02_30s.csv"),
+            ]
+        );
+        assert_eq!(series[1].name, "2_other_prog");
+    }
+
+    #[test]
+    fn test_file_name_with_underscores() {
+        assert_eq!(
+            FileName::parse("12_sys_enter_openat_prog_30s"),
+            Some(FileName {
+                id: 12,
+                name: "sys_enter_openat",
+                suffix: "prog",
+                date: None,
+                period: "30s",
+            })
+        );
+        assert_eq!(
+            FileName::parse("7_conn_track_map_map_2026-01-01_500ms"),
+            Some(FileName {
+                id: 7,
+                name: "conn_track_map",
+                suffix: "map",
+                date: Some("2026-01-01"),
+                period: "500ms",
+            })
+        );
+        assert_eq!(
+            FileName::parse("7_xdp_prog_2026-01-01T12-30-00.250Z_1s").map(|f| f.date),
+            Some(Some("2026-01-01T12-30-00.250Z"))
+        );
+        assert_eq!(FileName::parse("sys_enter_openat_30s"), None);
+
+        let files = vec![
+            PathBuf::from("3_tc_ingress_prog_2026-01-02_30s.csv"),
+            PathBuf::from("3_tc_ingress_prog_2026-01-01_30s.csv"),
+        ];
+        let series = group_series(files.clone(), false);
+        assert_eq!(series[0].name, "3_tc_ingress_prog_2026-01-01");
+        let series = group_series(files, true);
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].name, "3_tc_ingress_prog");
+    }
+}
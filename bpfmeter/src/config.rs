@@ -1,9 +1,24 @@
-use std::{path::PathBuf, sync::LazyLock};
+use std::{
+    ffi::OsString,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
 
-use anyhow::{Result, bail};
-use clap::{Args, Parser, Subcommand, ValueEnum, builder::PossibleValuesParser};
+use anyhow::{Context, Result, bail};
+use clap::{
+    ArgGroup, Args, Command, CommandFactory, Parser, Subcommand, ValueEnum,
+    builder::{PossibleValuesParser, TypedValueParser},
+    error::ErrorKind,
+    parser::ValueSource,
+};
 
-use crate::exporter::prometheus_exporter::{Labels, PromExportType};
+use crate::exporter::prometheus_exporter::{Labels, ProgramGroup, PromExportType};
+use crate::meter::{
+    OnMissing,
+    bpf_types::{self, BpfMapType, BpfProgramType},
+    cpu_meter::{CpuUsageMode, RateDenominator},
+};
 
 #[derive(Clone, Debug, Parser)]
 #[command(name = "bpfmeter", version)]
@@ -16,17 +31,42 @@ pub struct Config {
     pub log_level: String,
 }
 
+// Arguments are parsed once, so the size of the enum does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, Subcommand)]
 pub enum SubCommands {
     /// Run monitoring process
     Run(RunArgs),
-    /// Draw results from csv files
+    /// Draw results from csv files or from metrics of a running instance
     #[cfg(feature = "draw")]
     Draw(DrawArgs),
+    /// Validate and summarize csv files
+    Inspect(InspectArgs),
+    /// Compare cpu usage and event rate of bpf programs between two captures
+    Diff(DiffArgs),
+    /// Report which kernel features used by bpfmeter are available and exit
+    Probe(ProbeArgs),
+    /// List loaded ebpf programs and measured maps with their ids, names and types
+    List(ListArgs),
+    /// Process raw stats captured with --raw-output again and write the results to csv files
+    Reprocess(ReprocessArgs),
 }
 
 #[derive(Clone, Debug, Args)]
 pub struct RunArgs {
+    /// TOML file with options of the run, keys are names of the options, e.g.
+    /// `cpu_period = "1s"`. Options given on the command line override the file
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Print eBPF program type names accepted by --program-types and exit
+    #[arg(long, default_value_t = false)]
+    pub list_program_types: bool,
+
+    /// Print eBPF map type names accepted by --map-types and exit
+    #[arg(long, default_value_t = false)]
+    pub list_map_types: bool,
+
     /// Disable cpu usage monitoring
     #[arg(long, default_value_t = false)]
     pub disable_cpu: bool,
@@ -35,10 +75,93 @@ pub struct RunArgs {
     #[arg(short='p', long, value_delimiter = ',', num_args(1..))]
     pub bpf_programs: Option<Vec<u32>>,
 
+    /// Types of ebpf programs to monitor, e.g. xdp,sched_cls, all types if not specified.
+    /// Names follow bpftool, see --list-program-types
+    #[arg(long, value_delimiter = ',', num_args(1..))]
+    pub program_types: Option<Vec<BpfProgramType>>,
+
+    /// What to do if none of the ebpf programs/maps listed by id is loaded at the start:
+    /// wait for one of them, start measuring anyway or exit with an error
+    #[arg(value_enum, long, default_value_t = OnMissing::Fail)]
+    pub on_missing: OnMissing,
+
+    /// Measure only ebpf programs/maps loaded after the start, e.g. by a deploy being validated
+    #[arg(long, default_value_t = false, conflicts_with_all = ["bpf_programs", "bpf_maps"])]
+    pub only_new: bool,
+
+    /// Export cumulative run time and run count starting from the first measurement,
+    /// cpu usage is left empty until the second one
+    #[arg(long, default_value_t = false)]
+    pub raw_counters: bool,
+
+    /// Merge ebpf programs with the same tag (e.g. reloaded instances of the same program)
+    /// into a single series identified by the id of the first instance seen
+    #[arg(long, default_value_t = false)]
+    pub dedupe_by_tag: bool,
+
+    /// Read per-action packet counters of XDP programs from their stats maps named xdp_stats*
+    #[arg(long, default_value_t = false)]
+    pub xdp_actions: bool,
+
+    /// Log durations of enumeration, reads and channel send on every tick at debug level
+    #[arg(long, default_value_t = false)]
+    pub verbose_timing: bool,
+
+    /// Fail on the first ebpf program/map which stats can't be read instead of skipping it,
+    /// and on measurement periods too short for the number of programs/maps instead of warning
+    #[arg(long, default_value_t = false)]
+    pub strict: bool,
+
+    /// Report ebpf programs which run time and run count haven't changed for the number
+    /// of ticks as stuck (e.g. detached but still loaded) in ebpf_program_stuck metric
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub stuck_threshold: Option<u32>,
+
+    /// Export z-score of cpu usage relative to the mean of the program on the number of
+    /// previous ticks in ebpf_cpu_usage_zscore metric
+    #[arg(long, value_parser = clap::value_parser!(u32).range(2..))]
+    pub zscore_window: Option<u32>,
+
+    /// What cpu usage is reported relative to: a single CPU only (may exceed 1 for programs
+    /// running on several CPUs at once) or also all online CPUs of the host (from 0 to 1),
+    /// exported in ebpf_cpu_usage_host_fraction metric and host_cpu_usage column
+    #[arg(value_enum, long, default_value_t = CpuUsageMode::PerCore)]
+    pub cpu_usage_mode: CpuUsageMode,
+
+    /// Export the time cpu usage of ebpf programs has been continuously above the threshold
+    /// (e.g. 0.05 for 5%) in ebpf_cpu_usage_above_threshold_seconds metric
+    #[arg(long)]
+    pub cpu_usage_threshold: Option<f32>,
+
+    /// Export exponential moving average of cpu usage with the weight of the current
+    /// value from 0 (exclusive) to 1 in ebpf_cpu_usage_smoothed metric
+    #[arg(long, value_parser = alpha_parser)]
+    pub cpu_smoothing_alpha: Option<f32>,
+
+    /// Denominator of event rate: wall-clock interval between measurements (runs per second)
+    /// or time spent in the program in the interval (runs per second of execution)
+    #[arg(value_enum, long, default_value_t = RateDenominator::Wall)]
+    pub rate_denominator: RateDenominator,
+
     /// Period of time between two measurements (ticks) for cpu usage calculation
     #[arg(long, value_parser = duration_parser, default_value = "30s")]
     pub cpu_period: std::time::Duration,
 
+    /// Sample idle ebpf programs less often, up to once per the given period, while hot ones
+    /// are sampled every --cpu-period. Bounds the cost of exporting many mostly idle programs
+    #[arg(long, value_parser = duration_parser)]
+    pub adaptive_max_period: Option<std::time::Duration>,
+
+    /// Take the given number of evenly spaced samples within every period and export their
+    /// average as the measurement of the period, to smooth noisy values
+    #[arg(long, conflicts_with = "adaptive_max_period", value_parser = clap::value_parser!(u64).range(1..))]
+    pub sample_count: Option<u64>,
+
+    /// Cpu usage (e.g. 0.001 for 0.1%) at or below which a program is sampled less often
+    /// in adaptive sampling mode
+    #[arg(long, requires = "adaptive_max_period", default_value_t = 0.001)]
+    pub adaptive_idle_threshold: f32,
+
     /// Enable maps usage monitoring
     #[arg(long, default_value_t = false)]
     pub enable_maps: bool,
@@ -46,10 +169,75 @@ pub struct RunArgs {
     #[arg(short='m', long, value_delimiter = ',', num_args(1..))]
     pub bpf_maps: Option<Vec<u32>>,
 
+    /// Types of maps to measure, all supported types if not specified: hash, percpu_hash,
+    /// lru_hash, lru_percpu_hash, lpm_trie, array and percpu_array. Sizes of arrays are
+    /// their max sizes, all their entries are preallocated
+    #[arg(long, value_delimiter = ',', num_args(1..), value_parser = map_type_parser())]
+    pub map_types: Option<Vec<BpfMapType>>,
+
+    /// Maximum total time of walking all maps on a tick. Maps left when the budget is
+    /// exhausted report their size from the previous tick, marked stale in ebpf_map_size_stale,
+    /// and are walked first on the next tick
+    #[arg(long, value_parser = duration_parser)]
+    pub map_walk_budget: Option<std::time::Duration>,
+
+    /// Maximum number of map entries read with a single BPF_MAP_LOOKUP_BATCH syscall.
+    /// Keys are walked one by one if 0 or if the kernel doesn't support batch lookups
+    #[arg(long, default_value_t = 1024)]
+    pub map_batch_size: u32,
+
+    /// Count entries added to and deleted from maps by comparing their keys between ticks.
+    /// Hashes of all keys of measured maps are kept in memory
+    #[arg(long, default_value_t = false)]
+    pub map_churn: bool,
+
     /// Period of time between two measurements (ticks) for map stats calculation
     #[arg(long, value_parser = duration_parser, default_value = "30s")]
     pub map_period: std::time::Duration,
 
+    /// Sleep a random duration up to the value before the first measurement to desynchronize
+    /// collection of bpfmeter instances started at the same time
+    #[arg(long, value_parser = duration_parser, default_value = "0s")]
+    pub start_jitter: std::time::Duration,
+
+    /// Export stats of ebpf programs/maps only when they have changed since the last export
+    #[arg(long, default_value_t = false)]
+    pub on_change_only: bool,
+
+    /// Maximum time between two exports of unchanged stats in on change only mode
+    #[arg(long, requires = "on_change_only", value_parser = duration_parser, default_value = "300s")]
+    pub heartbeat_interval: std::time::Duration,
+
+    /// Export stats of an ebpf program only once its event rate reaches the value, to keep
+    /// the number of series small on hosts with many occasionally active programs
+    #[arg(long)]
+    pub export_on_rate: Option<f32>,
+
+    /// Event rate below which an exported ebpf program is stopped being exported after
+    /// --export-off-debounce. Defaults to --export-on-rate
+    #[arg(long, requires = "export_on_rate")]
+    pub export_off_rate: Option<f32>,
+
+    /// Time the event rate of an exported ebpf program has to stay below --export-off-rate
+    /// before it is stopped being exported
+    #[arg(long, requires = "export_on_rate", value_parser = duration_parser, default_value = "60s")]
+    pub export_off_debounce: std::time::Duration,
+
+    /// File to save the last seen counters of ebpf programs to on exit. If the file exists on
+    /// start, cpu usage is calculated on the first tick over the time since the counters were saved
+    #[arg(long)]
+    pub checkpoint: Option<std::path::PathBuf>,
+
+    /// File to write raw stats of all ebpf programs/maps to on every tick, as they were read
+    /// from the kernel. The `reprocess` command processes them again offline
+    #[arg(long)]
+    pub raw_output: Option<std::path::PathBuf>,
+
+    /// Version of the monitored workload, e.g. git commit of the ebpf programs under test.
+    /// Added as `workload_version` label to all metrics and written to the manifest
+    #[arg(long)]
+    pub workload_version: Option<String>,
+
     /// Number of measurements to take, if not specified, run indefinitely until Ctrl+C
     #[arg(short, long)]
     pub ticks: Option<u64>,
@@ -63,6 +251,15 @@ pub struct RunArgs {
     pub output_mode: OutputMode,
 }
 
+/// Parser of --map-types accepting only types which sizes can be measured, so they are
+/// listed in errors and help
+fn map_type_parser() -> impl TypedValueParser<Value = BpfMapType> {
+    PossibleValuesParser::new(
+        bpf_types::measured_map_types().filter_map(|map_type| map_type.to_possible_value()),
+    )
+    .map(|name| BpfMapType::from_str(&name, false).unwrap())
+}
+
 fn duration_parser(s: &str) -> Result<std::time::Duration> {
     if !s.ends_with("sec") && !s.ends_with("s") && !s.ends_with("msec") && !s.ends_with("ms") {
         bail!(
@@ -74,14 +271,93 @@ fn duration_parser(s: &str) -> Result<std::time::Duration> {
 
 #[derive(Default, Clone, Debug, Args)]
 #[group(required = false, multiple = false)]
+#[command(group(
+    ArgGroup::new("csv_rotation")
+        .args(["csv_rotate_daily", "csv_max_size", "csv_rotate_interval"])
+        .multiple(true)
+))]
 pub struct OutputMode {
     /// Output directory to store csv files
     #[arg(short, long, value_parser = check_dir, conflicts_with = "prometheus")]
     pub output_dir: Option<std::path::PathBuf>,
 
+    /// Format of the files written to the output directory, or table to print measurements
+    /// to the terminal (or journald to write them to systemd journal) instead
+    #[arg(long, value_enum, requires_if("jsonl", "output_dir"), default_value_t = OutputFormat::Csv)]
+    pub output_format: OutputFormat,
+
+    /// Include the current date in csv filenames and start new files at midnight
+    #[arg(long, requires = "output_dir")]
+    pub csv_rotate_daily: bool,
+
+    /// Maximum number of rotated csv files kept for each program/map, the oldest ones
+    /// are deleted
+    #[arg(long, requires = "csv_rotation", value_parser = clap::value_parser!(u32).range(1..))]
+    pub csv_max_files: Option<u32>,
+
+    /// Start a new csv file of a program/map once the current one reaches the size in bytes,
+    /// checked every --flush-ticks ticks. The start time of a file is included in its name
+    #[arg(long, requires = "output_dir", conflicts_with = "csv_rotate_daily", value_parser = clap::value_parser!(u64).range(1..))]
+    pub csv_max_size: Option<u64>,
+
+    /// Start a new csv file of a program/map once the current one has been written for
+    /// the interval, the start time of a file is included in its name
+    #[arg(long, requires = "output_dir", conflicts_with = "csv_rotate_daily", value_parser = duration_parser)]
+    pub csv_rotate_interval: Option<std::time::Duration>,
+
+    /// Delimiter of fields in csv files, a single ASCII character or \t for tab
+    #[arg(long, requires = "output_dir", value_parser = delimiter_parser, default_value = ",")]
+    pub csv_delimiter: u8,
+
+    /// Do not write the header row to csv files
+    #[arg(long, requires = "output_dir")]
+    pub csv_no_header: bool,
+
+    /// Number of ticks between two flushes of written files, so measurements are not lost
+    /// if the process is killed
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), default_value = "10")]
+    pub flush_ticks: u64,
+
+    /// Use UTC instead of the local time zone for daily rotation of csv files
+    #[arg(long, requires = "csv_rotate_daily")]
+    pub utc: bool,
+
+    /// Write a single row per window instead of every tick, must be a multiple of the period.
+    /// Cpu usage and map size are averaged, cumulative counters are taken from the last tick
+    #[arg(long, requires = "output_dir", value_parser = duration_parser)]
+    pub csv_aggregate: Option<std::time::Duration>,
+
+    /// Write manifest.json describing the capture session (files, arguments, times, host)
+    /// to the output directory when the run finishes
+    #[arg(long, requires = "output_dir")]
+    pub manifest: bool,
+
+    /// Also serve prometheus metrics on the port while writing csv files
+    #[arg(long, requires = "output_dir", value_parser = clap::value_parser!(u16).range(1..))]
+    pub tee_port: Option<u16>,
+
     /// Prometheus exporter arguments
     #[command(flatten)]
     pub prometheus: PrometheusArgs,
+
+    /// CloudWatch exporter arguments
+    #[cfg(feature = "cloudwatch")]
+    #[command(flatten)]
+    pub cloudwatch: CloudWatchArgs,
+
+    /// Graphite exporter arguments
+    #[command(flatten)]
+    pub graphite: GraphiteArgs,
+
+    /// OTLP exporter arguments
+    #[command(flatten)]
+    pub otlp: OtlpArgs,
+
+    /// Redis url, enables RedisTimeSeries exporter instead of prometheus.
+    /// Prometheus labels are added to time series labels
+    #[cfg(feature = "redis")]
+    #[arg(long, conflicts_with = "output_dir")]
+    pub redis_url: Option<String>,
 }
 
 #[derive(Default, Clone, Debug, Args)]
@@ -91,6 +367,19 @@ pub struct PrometheusArgs {
     #[arg(short = 'P', long, value_parser = clap::value_parser!(u16).range(1..), default_value = "9100")]
     pub port: u16,
 
+    /// Address the prometheus exporter listens on: IP or IP:PORT, the port overrides --port
+    #[arg(long, value_parser = bind_parser, default_value = "0.0.0.0")]
+    pub metrics_bind: BindAddr,
+
+    /// Set SO_REUSEPORT on the listener, so several instances can listen on the same port.
+    /// The kernel balances connections between them, each scrape gets metrics of one of them
+    #[arg(long)]
+    pub reuse_port: bool,
+
+    /// Path metrics are served on, must start with /
+    #[arg(long, value_parser = metrics_path_parser, default_value = "/metrics")]
+    pub metrics_path: String,
+
     /// Prometheus exporter labels. Format: label1=value1,label2=value2,...
     #[arg(short, long, value_parser = label_parser)]
     pub labels: Option<Labels>,
@@ -99,11 +388,189 @@ pub struct PrometheusArgs {
     #[arg(short, long, default_values_t = [PromExportType::RunTime, PromExportType::EventCount], value_delimiter = ',')]
     pub export_types: Vec<PromExportType>,
 
+    /// Upper bounds of buckets of the cpu usage histogram exported with cpu-usage-histogram
+    /// export type, in ascending order
+    #[arg(long, value_delimiter = ',', default_values_t = [0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0])]
+    pub cpu_histogram_buckets: Vec<f64>,
+
+    /// Identify series by ebpf program/map id only, names are exported in
+    /// ebpf_program_info/ebpf_map_info metrics to be joined by id
+    #[arg(long, default_value_t = false)]
+    pub id_only_series: bool,
+
+    /// Replace spaces and non-ascii characters in ebpf_name label with underscores,
+    /// the original name is exported in ebpf_name_raw label
+    #[arg(long, default_value_t = false)]
+    pub sanitize_names: bool,
+
+    /// Group of ebpf programs exported as a whole in ebpf_group_* metrics, can be repeated.
+    /// Format: name=id1,id2,...
+    #[arg(long = "group", value_parser = group_parser)]
+    pub groups: Vec<ProgramGroup>,
+
+    /// Csv file with columns name,help,unit overriding help texts and units of metrics.
+    /// Units are appended to metric names as required by OpenMetrics
+    #[arg(long)]
+    pub metrics_metadata: Option<std::path::PathBuf>,
+
+    /// Bearer token required in Authorization header of /metrics requests
+    #[arg(long, conflicts_with_all = ["metrics_basic_user", "agent_mode"])]
+    pub metrics_auth_token: Option<String>,
+
+    /// User name of basic authentication required by /metrics endpoint
+    #[arg(long, requires = "metrics_basic_pass", conflicts_with = "agent_mode")]
+    pub metrics_basic_user: Option<String>,
+
+    /// Password of basic authentication required by /metrics endpoint
+    #[arg(long, requires = "metrics_basic_user")]
+    pub metrics_basic_pass: Option<String>,
+
+    /// Certificate chain in PEM format, serves /metrics over HTTPS with the key
+    #[arg(long, requires = "tls_key", conflicts_with = "agent_mode")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// Private key of the certificate in PEM format
+    #[arg(long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Push metrics to the remote write endpoint instead of serving them, for hosts that
+    /// can't be scraped (e.g. behind NAT)
+    #[arg(long, default_value_t = false, requires = "remote_write_url")]
+    pub agent_mode: bool,
+
+    /// Prometheus remote write endpoint to push metrics to in agent mode
+    #[arg(long, requires = "agent_mode")]
+    pub remote_write_url: Option<String>,
+
+    /// Interval between two pushes to the remote write endpoint in agent mode
+    #[arg(long, value_parser = duration_parser, default_value = "30s")]
+    pub remote_write_interval: std::time::Duration,
+
+    /// Pushgateway to push metrics to instead of serving them, for short runs that end
+    /// before Prometheus scrapes them. The last metrics are pushed on exit
+    #[arg(long, conflicts_with_all = ["agent_mode", "metrics_auth_token", "metrics_basic_user", "tls_cert"])]
+    pub pushgateway_url: Option<String>,
+
+    /// Job label of the metrics pushed to Pushgateway, grouped by the job and the host name
+    #[arg(long, requires = "pushgateway_url", default_value = "bpfmeter")]
+    pub pushgateway_job: String,
+
+    /// Interval between two pushes to Pushgateway, --cpu-period by default to push every tick
+    #[arg(long, requires = "pushgateway_url", value_parser = duration_parser)]
+    pub pushgateway_interval: Option<std::time::Duration>,
+
     /// Prometheus garbage collector interval,set to 0s to disable
     #[arg(long, value_parser = duration_parser, default_value = "30s")]
     pub gc_period: std::time::Duration,
 }
 
+#[cfg(feature = "cloudwatch")]
+#[derive(Default, Clone, Debug, Args)]
+#[group(id = "cloudwatch", required = false, multiple = true)]
+pub struct CloudWatchArgs {
+    /// CloudWatch namespace to put metrics to, enables CloudWatch exporter instead of prometheus.
+    /// Prometheus labels are sent as metric dimensions
+    #[arg(long, conflicts_with = "output_dir")]
+    pub cloudwatch_namespace: Option<String>,
+
+    /// AWS region, if not specified, the region is taken from the environment
+    #[arg(long, requires = "cloudwatch_namespace")]
+    pub cloudwatch_region: Option<String>,
+}
+
+#[derive(Default, Clone, Debug, Args)]
+#[group(id = "graphite", required = false, multiple = true)]
+pub struct GraphiteArgs {
+    /// Address of Graphite plaintext receiver (e.g. 127.0.0.1:2003), enables Graphite exporter
+    /// instead of prometheus. Prometheus labels are sent as Graphite tags
+    #[arg(long, conflicts_with = "output_dir")]
+    pub graphite_addr: Option<String>,
+
+    /// Prefix of Graphite metric paths: <prefix>.<program name>.<metric>
+    #[arg(long, requires = "graphite_addr", default_value = "bpfmeter")]
+    pub graphite_prefix: String,
+}
+
+#[derive(Default, Clone, Debug, Args)]
+#[group(id = "otlp", required = false, multiple = true)]
+pub struct OtlpArgs {
+    /// OpenTelemetry collector gRPC endpoint (e.g. http://127.0.0.1:4317), enables OTLP
+    /// exporter instead of prometheus. Prometheus labels are sent as resource attributes
+    #[arg(long, conflicts_with = "output_dir")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Interval between two pushes to OpenTelemetry collector, measurements are batched in between
+    #[arg(long, requires = "otlp_endpoint", value_parser = duration_parser, default_value = "10s")]
+    pub otlp_push_interval: std::time::Duration,
+}
+
+/// Address the prometheus exporter listens on
+#[derive(Clone, Debug, PartialEq)]
+pub struct BindAddr {
+    /// IP address of the interface
+    pub ip: IpAddr,
+    /// Port overriding the exporter port, if specified
+    pub port: Option<u16>,
+}
+
+impl Default for BindAddr {
+    fn default() -> Self {
+        Self {
+            ip: IpAddr::V4(std::net::Ipv4Addr::UNSPECIFIED),
+            port: None,
+        }
+    }
+}
+
+fn bind_parser(s: &str) -> Result<BindAddr> {
+    if let std::result::Result::Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(BindAddr {
+            ip: addr.ip(),
+            port: Some(addr.port()),
+        });
+    }
+    let ip = s
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .map_err(|e| anyhow::anyhow!("Invalid bind address {s}, expected IP or IP:PORT: {e}"))?;
+    Ok(BindAddr { ip, port: None })
+}
+
+fn metrics_path_parser(s: &str) -> Result<String> {
+    if !s.starts_with('/') {
+        bail!("Invalid metrics path {s}, expected a path starting with /");
+    }
+    if s == "/healthz" {
+        bail!("Metrics path {s} is taken by the health check");
+    }
+    Ok(s.to_string())
+}
+
+fn group_parser(s: &str) -> Result<ProgramGroup> {
+    let Some((name, ids)) = s.split_once('=') else {
+        bail!("Invalid group format: {s}, expected name=id1,id2,...");
+    };
+    if name.is_empty() {
+        bail!("Group name is empty in {s}");
+    }
+    let mut group_ids = Vec::new();
+    for id in ids.split(',') {
+        let id = id
+            .parse::<u32>()
+            .map_err(|e| anyhow::anyhow!("Invalid program id {id} in group {name}: {e}"))?;
+        // A program counted twice would be summed twice
+        if group_ids.contains(&id) {
+            bail!("Program id {id} is repeated in group {name}");
+        }
+        group_ids.push(id);
+    }
+    Ok(ProgramGroup {
+        name: name.to_string(),
+        ids: group_ids,
+    })
+}
+
 fn label_parser(s: &str) -> Result<Labels> {
     let mut labels = Vec::new();
     for label in s.split(',') {
@@ -122,20 +589,147 @@ fn label_parser(s: &str) -> Result<Labels> {
 #[derive(Clone, Debug, Args)]
 pub struct DrawArgs {
     /// Input directory with csv files, generated by tool
-    #[arg(short, long, value_parser = check_dir)]
-    pub input_dir: std::path::PathBuf,
+    #[arg(short, long, value_parser = check_dir, required_unless_present = "from_url")]
+    pub input_dir: Option<std::path::PathBuf>,
 
-    /// Output directory with images
-    #[arg(short, long, value_parser = check_dir)]
+    /// Scrape metrics of a running instance instead of reading csv files,
+    /// e.g. http://127.0.0.1:9100/metrics
+    #[arg(long, conflicts_with_all = ["input_dir", "merge_daily"])]
+    pub from_url: Option<String>,
+
+    /// Time to scrape the metrics for, the chart is drawn earlier on Ctrl+C
+    #[arg(long, requires = "from_url", value_parser = duration_parser, default_value = "60s")]
+    pub scrape_duration: std::time::Duration,
+
+    /// Interval between two scrapes, should not be shorter than the period of the instance
+    #[arg(long, requires = "from_url", value_parser = duration_parser, default_value = "5s")]
+    pub scrape_interval: std::time::Duration,
+
+    /// Output directory with images, `-` to write the image to stdout
+    #[arg(short, long, visible_alias = "output", value_parser = check_output_dir)]
     pub output_dir: std::path::PathBuf,
 
     /// Draw separate chart for each bpf program
     #[arg(short, long)]
     pub multiple: bool,
 
-    /// Draw type: cpu usage or event count
+    /// Draw daily or size/time rotated csv files of the same bpf program as one series
+    #[arg(long)]
+    pub merge_daily: bool,
+
+    /// Embed a table with average and maximum of each bpf program below the chart
+    #[arg(long)]
+    pub embed_data: bool,
+
+    /// Draw type: cpu usage, event count, map size or run time
     #[arg(value_enum, short='t', long, default_value_t = DrawType::CPUUsage)]
     pub draw_type: DrawType,
+
+    /// Format of the image
+    #[arg(value_enum, long, default_value_t = ImageFormat::Svg)]
+    pub image_format: ImageFormat,
+
+    /// Width of the image in pixels
+    #[arg(long, default_value_t = 1920, value_parser = clap::value_parser!(u32).range(320..=16384))]
+    pub width: u32,
+
+    /// Height of the chart in pixels, the embedded data table is drawn below it
+    #[arg(long, default_value_t = 1080, value_parser = clap::value_parser!(u32).range(240..=16384))]
+    pub height: u32,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct InspectArgs {
+    /// Csv file or directory with csv files, generated by tool
+    #[arg(short, long)]
+    pub input: std::path::PathBuf,
+
+    /// Summarize daily or size/time rotated csv files of the same bpf program as one series
+    #[arg(long)]
+    pub merge_daily: bool,
+
+    /// Print the summary as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct DiffArgs {
+    /// Csv file or directory with csv files of the baseline capture
+    #[arg(short, long)]
+    pub baseline: std::path::PathBuf,
+
+    /// Csv file or directory with csv files of the candidate capture
+    #[arg(short, long)]
+    pub candidate: std::path::PathBuf,
+
+    /// Compare daily or size/time rotated csv files of the same bpf program as one series
+    #[arg(long)]
+    pub merge_daily: bool,
+
+    /// Print the comparison as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct ReprocessArgs {
+    /// File with raw stats captured with --raw-output
+    #[arg(short, long)]
+    pub input: PathBuf,
+
+    /// Output directory to store csv files
+    #[arg(short, long, value_parser = check_dir)]
+    pub output_dir: PathBuf,
+
+    /// Export cumulative counters on the first observation of a program
+    #[arg(long)]
+    pub raw_counters: bool,
+
+    /// Continue counters of programs with the same tag across reloads
+    #[arg(long)]
+    pub dedupe_by_tag: bool,
+
+    /// Denominator of event rate: wall-clock interval between measurements (runs per second)
+    /// or time spent in the program in the interval (runs per second of execution)
+    #[arg(value_enum, long, default_value_t = RateDenominator::Wall)]
+    pub rate_denominator: RateDenominator,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct ProbeArgs {
+    /// Print the availability of the features as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Clone, Debug, Args)]
+pub struct ListArgs {
+    /// List only ebpf programs
+    #[arg(long, conflicts_with = "maps_only")]
+    pub programs_only: bool,
+
+    /// List only ebpf maps
+    #[arg(long)]
+    pub maps_only: bool,
+
+    /// Print the programs and maps as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Csv file per ebpf program/map with stats of each tick
+    #[default]
+    Csv,
+    /// JSON Lines file per ebpf program/map, each line includes id, name and tick
+    Jsonl,
+    /// Table in the terminal redrawn on every tick, no files are written
+    Table,
+    /// Entries with structured fields in systemd journal, no files are written
+    #[cfg(feature = "journald")]
+    Journald,
 }
 
 #[derive(Clone, Debug, ValueEnum)]
@@ -143,6 +737,41 @@ pub enum DrawType {
     CPUUsage,
     EventCount,
     MapSize,
+    MapChurn,
+    RunTime,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ImageFormat {
+    Svg,
+    Png,
+}
+
+#[cfg(feature = "draw")]
+impl ImageFormat {
+    /// Extension of the image file
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Svg => "svg",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+fn delimiter_parser(s: &str) -> Result<u8> {
+    match s.as_bytes() {
+        b"\\t" => Ok(b'\t'),
+        [delimiter] if delimiter.is_ascii() => Ok(*delimiter),
+        _ => bail!("Delimiter must be a single ASCII character or \\t, got {s:?}"),
+    }
+}
+
+fn alpha_parser(s: &str) -> Result<f32> {
+    let alpha: f32 = s.parse()?;
+    if !(alpha > 0.0 && alpha <= 1.0) {
+        bail!("Smoothing alpha must be greater than 0 and at most 1, got {s}");
+    }
+    Ok(alpha)
 }
 
 fn check_dir(s: &str) -> Result<PathBuf> {
@@ -153,10 +782,260 @@ fn check_dir(s: &str) -> Result<PathBuf> {
     Ok(path)
 }
 
+/// Path meaning that the output is written to stdout
+pub const STDOUT_PATH: &str = "-";
+
+fn check_output_dir(s: &str) -> Result<PathBuf> {
+    if s == STDOUT_PATH {
+        return Ok(PathBuf::from(s));
+    }
+    check_dir(s)
+}
+
 impl Config {
     fn new() -> Self {
-        Self::parse()
+        Self::try_new(std::env::args_os().collect()).unwrap_or_else(|e| e.exit())
     }
+
+    /// Parses the arguments, options of `run` given on the command line are merged with
+    /// the ones of the config file
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Command line arguments including the name of the binary
+    pub fn try_new(args: Vec<OsString>) -> Result<Self, clap::Error> {
+        let command = Self::command();
+        let matches = command.clone().ignore_errors(true).get_matches_from(&args);
+        let (Some(path), Some(run_position)) = (
+            matches
+                .subcommand_matches("run")
+                .and_then(|m| m.get_one::<PathBuf>("config")),
+            subcommand_position(&command, &args, "run"),
+        ) else {
+            return Self::try_parse_from(args);
+        };
+        let run_command = command.find_subcommand("run").expect("run subcommand");
+        let file_args =
+            config_file_args(path, run_command, &args[run_position + 1..]).map_err(|e| {
+                command
+                    .clone()
+                    .error(ErrorKind::ValueValidation, format!("{e:#}"))
+            })?;
+        // Options of the file go right after the subcommand, before the command line ones
+        Self::try_parse_from(
+            args[..=run_position]
+                .iter()
+                .cloned()
+                .chain(file_args.into_iter().map(OsString::from))
+                .chain(args[run_position + 1..].iter().cloned()),
+        )
+    }
+
+    /// Returns true if the command writes its results to stdout, so logs must not be mixed in
+    pub fn writes_to_stdout(&self) -> bool {
+        match &self.command {
+            #[cfg(feature = "draw")]
+            SubCommands::Draw(args) => args.output_dir == std::path::Path::new(STDOUT_PATH),
+            SubCommands::Inspect(_)
+            | SubCommands::Diff(_)
+            | SubCommands::Probe(_)
+            | SubCommands::List(_) => true,
+            SubCommands::Run(args) => args.output_mode.output_format == OutputFormat::Table,
+            SubCommands::Reprocess(_) => false,
+        }
+    }
+}
+
+/// Returns the position of the subcommand in the arguments, the same word may also be
+/// a value of an option before it
+///
+/// # Arguments
+///
+/// * `command` - Command of the binary
+///
+/// * `args` - Command line arguments including the name of the binary
+///
+/// * `name` - Name of the subcommand
+fn subcommand_position(command: &Command, args: &[OsString], name: &str) -> Option<usize> {
+    (1..args.len()).filter(|&i| args[i] == name).find(|&i| {
+        command
+            .clone()
+            .ignore_errors(true)
+            .get_matches_from(&args[..=i])
+            .subcommand_name()
+            == Some(name)
+    })
+}
+
+/// Converts options of the run from the TOML file to command line arguments. Options given
+/// on the command line are skipped, options conflicting with them are rejected
+///
+/// # Arguments
+///
+/// * `path` - Path to the TOML file
+///
+/// * `run_command` - Command of the `run` subcommand
+///
+/// * `cli_args` - Arguments of the `run` subcommand given on the command line
+fn config_file_args(
+    path: &Path,
+    run_command: &Command,
+    cli_args: &[OsString],
+) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let table = content
+        .parse::<toml::Table>()
+        .with_context(|| format!("Invalid config file {}", path.display()))?;
+    let with_cli_args = |args: &[String]| {
+        std::iter::once(OsString::from("run"))
+            .chain(args.iter().map(OsString::from))
+            .chain(cli_args.iter().cloned())
+            .collect::<Vec<_>>()
+    };
+    let cli = run_command
+        .clone()
+        .ignore_errors(true)
+        .get_matches_from(with_cli_args(&[]));
+
+    let mut args = Vec::new();
+    for (key, value) in &table {
+        let Some(arg) = run_command
+            .get_arguments()
+            .find(|arg| arg.get_id() == key.replace('-', "_").as_str())
+            .filter(|arg| arg.get_id() != "config" && arg.get_long().is_some())
+        else {
+            bail!("Unknown option `{key}` in config file {}", path.display());
+        };
+        if cli.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        let long = arg.get_long().expect("long option");
+        let values = match value {
+            toml::Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        let mut key_args = Vec::new();
+        for value in values {
+            let value = match value {
+                toml::Value::String(s) => s.clone(),
+                toml::Value::Integer(i) => i.to_string(),
+                toml::Value::Float(f) => f.to_string(),
+                toml::Value::Boolean(b) if !arg.get_action().takes_values() => {
+                    if *b {
+                        key_args.push(format!("--{long}"));
+                    }
+                    continue;
+                }
+                toml::Value::Boolean(b) => b.to_string(),
+                _ => bail!(
+                    "Unsupported value of option `{key}` in config file {}",
+                    path.display()
+                ),
+            };
+            key_args.push(format!("--{long}={value}"));
+        }
+        // Options of the file conflicting with the command line ones, e.g. another output mode
+        let conflicts_with_cli = run_command
+            .clone()
+            .try_get_matches_from(with_cli_args(&key_args))
+            .is_err_and(|e| e.kind() == ErrorKind::ArgumentConflict);
+        if conflicts_with_cli {
+            bail!(
+                "Option `{key}` in config file {} conflicts with the command line options",
+                path.display()
+            );
+        }
+        args.extend(key_args);
+    }
+    Ok(args)
 }
 
 pub static CONFIG: LazyLock<Config> = LazyLock::new(Config::new);
+
+#[cfg(test)]
+mod tests {
+    use aya::maps::MapType;
+
+    use super::*;
+
+    #[test]
+    fn test_config_file_args() {
+        let path =
+            std::env::temp_dir().join(format!("bpfmeter_config_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "cpu_period = \"5s\"\ndisable-cpu = true\nticks = 3\n",
+        )
+        .unwrap();
+        let command = Config::command();
+        let run_command = command.find_subcommand("run").unwrap();
+        let cli_args = ["--ticks", "10", "--output-dir", "/tmp"].map(OsString::from);
+        let args = config_file_args(&path, run_command, &cli_args).unwrap();
+        // Ticks are given on the command line
+        assert_eq!(args, ["--cpu-period=5s", "--disable-cpu"]);
+
+        // The prometheus port conflicts with the output dir
+        std::fs::write(&path, "port = 9200\n").unwrap();
+        let err = config_file_args(&path, run_command, &cli_args).unwrap_err();
+        assert!(err.to_string().contains("Option `port`"));
+
+        // The subcommand is found after global options
+        std::fs::write(&path, "ticks = 3\n").unwrap();
+        let config = Config::try_new(
+            [
+                "bpfmeter",
+                "-l",
+                "debug",
+                "run",
+                "--config",
+                path.to_str().unwrap(),
+            ]
+            .map(OsString::from)
+            .to_vec(),
+        )
+        .unwrap();
+        let SubCommands::Run(args) = config.command else {
+            panic!("Expected run command");
+        };
+        assert_eq!(args.ticks, Some(3));
+
+        std::fs::write(&path, "cpu_perod = \"5s\"\n").unwrap();
+        let err = config_file_args(&path, run_command, &cli_args).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.to_string().contains("Unknown option `cpu_perod`"));
+    }
+
+    #[test]
+    fn test_map_types_accept_only_measured_types() {
+        let config =
+            Config::try_parse_from(["bpfmeter", "run", "--map-types", "lru_hash,array"]).unwrap();
+        let SubCommands::Run(args) = config.command else {
+            panic!("Expected run command");
+        };
+        assert_eq!(
+            args.map_types,
+            Some(vec![
+                BpfMapType(MapType::LruHash),
+                BpfMapType(MapType::Array)
+            ])
+        );
+
+        let err =
+            Config::try_parse_from(["bpfmeter", "run", "--map-types", "ringbuf"]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidValue);
+        assert!(err.to_string().contains("percpu_hash"));
+    }
+
+    #[test]
+    fn test_group_parser() {
+        let group = group_parser("lb=1,2").unwrap();
+        assert_eq!(group.name, "lb");
+        assert_eq!(group.ids, vec![1, 2]);
+
+        assert!(group_parser("=1,2").is_err());
+        assert!(group_parser("lb=").is_err());
+        assert!(group_parser("lb=1,x").is_err());
+        assert!(group_parser("lb=1,2,1").is_err());
+    }
+}
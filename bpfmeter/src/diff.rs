@@ -0,0 +1,291 @@
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    capture::{Series, csv_files, group_series, is_map_series, read_records, series_period},
+    config::DiffArgs,
+    meter::cpu_meter::BpfCPUStatsInfo,
+};
+
+/// Cpu usage and event rate of a bpf program in a capture
+#[derive(Debug, Default, PartialEq, serde::Serialize)]
+struct ProgramStats {
+    /// Average cpu usage
+    avg_cpu_usage: Option<f64>,
+    /// Maximum cpu usage
+    max_cpu_usage: Option<f64>,
+    /// Average number of runs per second
+    avg_event_rate: Option<f64>,
+    /// Maximum number of runs per second
+    max_event_rate: Option<f64>,
+}
+
+/// Comparison of a bpf program between baseline and candidate captures
+#[derive(Debug, serde::Serialize)]
+struct ProgramDiff {
+    /// Name of the program series without the program id, ids usually differ between captures
+    program: String,
+    /// Stats in the baseline capture, empty if the program is missing there
+    baseline: Option<ProgramStats>,
+    /// Stats in the candidate capture, empty if the program is missing there
+    candidate: Option<ProgramStats>,
+    /// Candidate stats minus baseline stats, empty if the program is missing in a capture
+    delta: Option<ProgramStats>,
+}
+
+pub fn diff(args: &DiffArgs) -> Result<()> {
+    let mut baseline = capture_stats(&args.baseline, args.merge_daily)?;
+    let mut candidate = capture_stats(&args.candidate, args.merge_daily)?;
+    let mut programs = baseline.keys().cloned().collect::<Vec<_>>();
+    programs.extend(
+        candidate
+            .keys()
+            .filter(|program| !baseline.contains_key(*program))
+            .cloned()
+            .collect::<Vec<_>>(),
+    );
+    programs.sort();
+
+    let diffs = programs
+        .into_iter()
+        .map(|program| {
+            let baseline = baseline.remove(&program);
+            let candidate = candidate.remove(&program);
+            let delta = baseline
+                .as_ref()
+                .zip(candidate.as_ref())
+                .map(|(baseline, candidate)| delta(baseline, candidate));
+            ProgramDiff {
+                program,
+                baseline,
+                candidate,
+                delta,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diffs)?);
+        return Ok(());
+    }
+
+    println!("Programs compared: {}", diffs.len());
+    for diff in diffs {
+        println!();
+        println!("{}", diff.program);
+        let (baseline, candidate) = match (diff.baseline, diff.candidate) {
+            (Some(baseline), Some(candidate)) => (baseline, candidate),
+            (Some(_), None) => {
+                println!("  Only in baseline");
+                continue;
+            }
+            _ => {
+                println!("  Only in candidate");
+                continue;
+            }
+        };
+        let rows = [
+            (
+                "Avg cpu usage",
+                baseline.avg_cpu_usage,
+                candidate.avg_cpu_usage,
+            ),
+            (
+                "Max cpu usage",
+                baseline.max_cpu_usage,
+                candidate.max_cpu_usage,
+            ),
+            (
+                "Avg event rate",
+                baseline.avg_event_rate,
+                candidate.avg_event_rate,
+            ),
+            (
+                "Max event rate",
+                baseline.max_event_rate,
+                candidate.max_event_rate,
+            ),
+        ];
+        for (name, baseline, candidate) in rows {
+            println!("  {name}: {}", format_change(baseline, candidate));
+        }
+    }
+    Ok(())
+}
+
+/// Reads stats of all bpf programs of the capture, map series are skipped
+///
+/// # Arguments
+///
+/// * `path` - Csv file or directory with csv files of the capture
+///
+/// * `merge_daily` - Merge daily rotated files of the same bpf program into one series
+///
+/// Returns map of program keys to their stats
+fn capture_stats(path: &Path, merge_daily: bool) -> Result<BTreeMap<String, ProgramStats>> {
+    let series = group_series(csv_files(path)?, merge_daily);
+    let mut keyed: BTreeMap<String, Vec<&Series>> = BTreeMap::new();
+    for series in &series {
+        if is_map_series(series)? {
+            continue;
+        }
+        keyed
+            .entry(program_key(&series.name))
+            .or_default()
+            .push(series);
+    }
+    if keyed.is_empty() {
+        bail!("No bpf program csv files found in {}", path.display());
+    }
+
+    let mut stats = BTreeMap::new();
+    for (key, series) in keyed {
+        // Programs with the same name can't be matched by name, so they keep their ids
+        let unique = series.len() == 1;
+        for series in series {
+            let records = read_records::<BpfCPUStatsInfo>(series)?;
+            let key = if unique {
+                key.clone()
+            } else {
+                series.name.clone()
+            };
+            stats.insert(key, program_stats(&records, series_period(series)?));
+        }
+    }
+    Ok(stats)
+}
+
+/// Returns the key matching series of the same program in different captures: the series
+/// name without the program id
+///
+/// # Arguments
+///
+/// * `name` - Name of the series, format: <id>_<name>_prog
+fn program_key(name: &str) -> String {
+    name.split_once('_')
+        .map_or(name, |(_, program)| program)
+        .to_string()
+}
+
+/// Calculates stats of the program from its records
+///
+/// # Arguments
+///
+/// * `records` - Records of the program in chronological order
+///
+/// * `period` - Period of time between two records
+fn program_stats(records: &[BpfCPUStatsInfo], period: Duration) -> ProgramStats {
+    let cpu_usages = records
+        .iter()
+        .filter_map(|r| r.exact_cpu_usage.map(f64::from))
+        .collect::<Vec<_>>();
+    // Counters go down if the program was reloaded, such intervals have no rate
+    let event_rates = records
+        .windows(2)
+        .filter(|w| w[1].run_count >= w[0].run_count)
+        .map(|w| (w[1].run_count - w[0].run_count) as f64 / period.as_secs_f64())
+        .collect::<Vec<_>>();
+    ProgramStats {
+        avg_cpu_usage: average(&cpu_usages),
+        max_cpu_usage: cpu_usages.iter().copied().reduce(f64::max),
+        avg_event_rate: average(&event_rates),
+        max_event_rate: event_rates.iter().copied().reduce(f64::max),
+    }
+}
+
+/// Returns average of the values, empty if there are no values
+///
+/// # Arguments
+///
+/// * `values` - Values to average
+fn average(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// Subtracts baseline stats from candidate stats
+///
+/// # Arguments
+///
+/// * `baseline` - Stats in the baseline capture
+///
+/// * `candidate` - Stats in the candidate capture
+fn delta(baseline: &ProgramStats, candidate: &ProgramStats) -> ProgramStats {
+    let sub = |baseline: Option<f64>, candidate: Option<f64>| Some(candidate? - baseline?);
+    ProgramStats {
+        avg_cpu_usage: sub(baseline.avg_cpu_usage, candidate.avg_cpu_usage),
+        max_cpu_usage: sub(baseline.max_cpu_usage, candidate.max_cpu_usage),
+        avg_event_rate: sub(baseline.avg_event_rate, candidate.avg_event_rate),
+        max_event_rate: sub(baseline.max_event_rate, candidate.max_event_rate),
+    }
+}
+
+/// Formats change of the value between captures, e.g. `0.010000 -> 0.012000 (+0.002000, +20.0%)`
+///
+/// # Arguments
+///
+/// * `baseline` - Value in the baseline capture
+///
+/// * `candidate` - Value in the candidate capture
+fn format_change(baseline: Option<f64>, candidate: Option<f64>) -> String {
+    let (Some(baseline), Some(candidate)) = (baseline, candidate) else {
+        let format = |v: Option<f64>| v.map_or("no values".to_string(), |v| format!("{v:.6}"));
+        return format!("{} -> {}", format(baseline), format(candidate));
+    };
+    let delta = candidate - baseline;
+    if baseline == 0.0 {
+        return format!("{baseline:.6} -> {candidate:.6} ({delta:+.6})");
+    }
+    format!(
+        "{baseline:.6} -> {candidate:.6} ({delta:+.6}, {:+.1}%)",
+        delta / baseline * 100.0
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(exact_cpu_usage: Option<f32>, run_count: u64) -> BpfCPUStatsInfo {
+        BpfCPUStatsInfo {
+            exact_cpu_usage,
+            run_count,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_program_stats_and_delta() {
+        let period = Duration::from_secs(10);
+        let baseline = program_stats(
+            &[
+                record(None, 0),
+                record(Some(0.25), 100),
+                record(Some(0.5), 300),
+            ],
+            period,
+        );
+        assert_eq!(
+            baseline,
+            ProgramStats {
+                avg_cpu_usage: Some(0.375),
+                max_cpu_usage: Some(0.5),
+                avg_event_rate: Some(15.0),
+                max_event_rate: Some(20.0),
+            }
+        );
+
+        // The program was reloaded between the records, the interval has no rate
+        let candidate = program_stats(&[record(Some(0.5), 500), record(Some(0.5), 100)], period);
+        assert_eq!(
+            delta(&baseline, &candidate),
+            ProgramStats {
+                avg_cpu_usage: Some(0.125),
+                max_cpu_usage: Some(0.0),
+                avg_event_rate: None,
+                max_event_rate: None,
+            }
+        );
+        assert_eq!(program_key("12_xdp_fw_prog"), "xdp_fw_prog");
+    }
+}
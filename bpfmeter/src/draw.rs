@@ -1,75 +1,395 @@
 use std::{
-    collections::HashMap, fmt::Display, fs::File, io::BufReader, ops::AddAssign, path::PathBuf,
-    time::SystemTime,
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    io::Write,
+    ops::AddAssign,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 
 use crate::{
-    config::{DrawArgs, DrawType},
+    capture::{
+        RowTime, Series, csv_files, file_period, group_series, is_map_series, parse_period,
+        read_timed_records,
+    },
+    config::{DrawArgs, DrawType, ImageFormat, STDOUT_PATH},
+    exporter::remote_write,
     meter::{cpu_meter::BpfCPUStatsInfo, map_meter::BpfMapStatsInfo},
 };
 use anyhow::{Context, Result, bail};
 use humantime::format_rfc3339_seconds;
-use log::info;
+use image::ImageEncoder;
+use log::{info, warn};
 use plotters::{
-    coord::ranged1d::{AsRangedCoord, ValueFormatter},
+    coord::{
+        Shift,
+        ranged1d::{AsRangedCoord, ValueFormatter},
+    },
     prelude::*,
 };
+use serde::de::DeserializeOwned;
+
+/// Width of the reference layout, sizes of elements are scaled from it to the image width
+const CHART_WIDTH: u32 = 1920;
+/// Height of the reference layout, sizes of elements are scaled from it to the chart height
+const CHART_HEIGHT: u32 = 1080;
+/// Height of a row of the embedded data table in the reference layout
+const TABLE_ROW_HEIGHT: u32 = 30;
 
 const USAGE_MAX_TICKS: [f32; 6] = [1.0f32, 5.0f32, 10.0f32, 20.0f32, 50.0f32, 100.0f32];
 
 pub fn draw(args: &DrawArgs) -> Result<()> {
-    let bpf_data_paths = args
-        .input_dir
-        .read_dir()?
-        .flatten()
-        .filter(|e| e.path().extension().unwrap_or_default() == "csv")
-        .map(|e| e.path())
-        .collect::<Vec<_>>();
-
-    if bpf_data_paths.is_empty() {
+    if args.multiple && args.output_dir == Path::new(STDOUT_PATH) {
+        bail!("Separate charts for each bpf program can't be written to stdout");
+    }
+    if let Some(ref url) = args.from_url {
+        return draw_scraped(url, args);
+    }
+    let input_dir = args.input_dir.as_deref().context("No input directory")?;
+    let bpf_data_paths = csv_files(input_dir)?;
+
+    // Map csv files have size column instead of cpu usage, only files matching the draw type
+    // are drawn
+    let draw_maps = matches!(args.draw_type, DrawType::MapSize | DrawType::MapChurn);
+    let mut series = Vec::new();
+    for one_series in group_series(bpf_data_paths, args.merge_daily) {
+        if is_map_series(&one_series)? == draw_maps {
+            series.push(one_series);
+        }
+    }
+    if series.is_empty() {
+        if draw_maps {
+            bail!(
+                "No map csv files found in {}, map size is drawn from files written by the map exporter: <bpf_id>_<bpf_name>_map_<measurement_period>.csv",
+                input_dir.display()
+            );
+        }
         bail!(
-            "No bpf data csv files found in {}",
-            args.input_dir.display()
+            "No program csv files found in {}, map csv files can only be drawn with --draw-type map-size or map-churn",
+            input_dir.display()
         );
     }
 
-    let draw_func = match args.draw_type {
-        DrawType::CPUUsage => draw_cpu_usage,
-        DrawType::EventCount => draw_event_count,
-        DrawType::MapSize => draw_map_size,
-    };
-
     if args.multiple {
-        for path in bpf_data_paths {
-            draw_func(&[path], &args.output_dir)?;
+        for one_series in series {
+            draw_series(&[one_series], args)?;
         }
         Ok(())
     } else {
-        draw_func(&bpf_data_paths, &args.output_dir)
+        draw_series(&series, args)
+    }
+}
+
+/// Draws the chart of the draw type from csv files of the series
+///
+/// # Arguments
+///
+/// * `series` - Series to draw
+///
+/// * `args` - Draw arguments
+fn draw_series(series: &[Series], args: &DrawArgs) -> Result<()> {
+    match args.draw_type {
+        DrawType::CPUUsage => draw_cpu_usage(&ChartData::from_series(series)?, args),
+        DrawType::EventCount => draw_event_count(&ChartData::from_series(series)?, args),
+        DrawType::MapSize => draw_map_size(&ChartData::from_series(series)?, args),
+        DrawType::MapChurn => draw_map_churn(&ChartData::from_series(series)?, args),
+        DrawType::RunTime => draw_run_time(&ChartData::from_series(series)?, args),
+    }
+}
+
+/// Scrapes the metric of the draw type from a running instance and draws the chart
+///
+/// # Arguments
+///
+/// * `url` - Url of the metrics endpoint of the instance
+///
+/// * `args` - Draw arguments
+fn draw_scraped(url: &str, args: &DrawArgs) -> Result<()> {
+    let metric = match args.draw_type {
+        DrawType::CPUUsage => "ebpf_cpu_usage",
+        DrawType::EventCount => "ebpf_event_count",
+        DrawType::MapSize => "ebpf_map_size",
+        DrawType::RunTime => "ebpf_run_time",
+        DrawType::MapChurn => {
+            bail!("Map churn can't be scraped, it is drawn from csv files written with --map-churn")
+        }
+    };
+    let scraped = scrape(url, metric, args.scrape_duration, args.scrape_interval)?;
+    if scraped.is_empty() {
+        bail!(
+            "No {metric} series scraped from {url}, check that the instance exports it (--export-types)"
+        );
+    }
+
+    let charts = if args.multiple {
+        scraped.into_iter().map(|series| vec![series]).collect()
+    } else {
+        vec![scraped.into_iter().collect::<Vec<_>>()]
+    };
+    for series in charts {
+        match args.draw_type {
+            DrawType::CPUUsage => draw_cpu_usage(
+                &ChartData::from_scraped(series, url, args, |value| BpfCPUStatsInfo {
+                    exact_cpu_usage: Some(value as f32),
+                    ..Default::default()
+                }),
+                args,
+            )?,
+            DrawType::EventCount => draw_event_count(
+                &ChartData::from_scraped(series, url, args, |value| BpfCPUStatsInfo {
+                    run_count: value as u64,
+                    ..Default::default()
+                }),
+                args,
+            )?,
+            DrawType::MapSize => draw_map_size(
+                &ChartData::from_scraped(series, url, args, |value| BpfMapStatsInfo {
+                    size: value as u32,
+                    ..Default::default()
+                }),
+                args,
+            )?,
+            DrawType::MapChurn => unreachable!("Map churn is not scraped"),
+            DrawType::RunTime => draw_run_time(
+                &ChartData::from_scraped(series, url, args, |value| BpfCPUStatsInfo {
+                    run_time: Duration::from_secs_f64(value.max(0.0)),
+                    ..Default::default()
+                }),
+                args,
+            )?,
+        }
+    }
+    Ok(())
+}
+
+/// Scrapes the metric from the metrics endpoint for the duration and returns values
+/// of each series, scraping stops earlier on Ctrl+C
+///
+/// # Arguments
+///
+/// * `url` - Url of the metrics endpoint
+///
+/// * `metric` - Name of the metric to collect
+///
+/// * `duration` - Time to scrape for
+///
+/// * `interval` - Interval between two scrapes
+///
+/// Returns map of series names, in the same format as names of csv series, to their values
+fn scrape(
+    url: &str,
+    metric: &str,
+    duration: Duration,
+    interval: Duration,
+) -> Result<BTreeMap<String, Vec<f64>>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let client = reqwest::Client::new();
+        let mut ticker = tokio::time::interval(interval);
+        let scrapes = (duration.as_millis() / interval.as_millis().max(1)).max(1);
+        let mut series: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+        info!("Scraping {metric} from {url} every {interval:?} for {duration:?}");
+        for _ in 0..scrapes {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = tokio::signal::ctrl_c() => {
+                    info!("Ctrl+C pressed, drawing scraped measurements");
+                    break;
+                }
+            }
+            let text = match scrape_once(&client, url).await {
+                Ok(text) => text,
+                Err(e) => {
+                    warn!("Failed to scrape {url}: {e:#}");
+                    continue;
+                }
+            };
+            for sample in remote_write::parse_samples(&text)? {
+                let label = |name: &str| {
+                    sample
+                        .labels
+                        .iter()
+                        .find(|(label, _)| label == name)
+                        .map(|(_, value)| value.as_str())
+                };
+                if label("__name__") != Some(metric) {
+                    continue;
+                }
+                let (id, name, suffix) = match label("ebpf_id") {
+                    Some(id) => (id, label("ebpf_name"), "prog"),
+                    None => (
+                        label("ebpf_map_id").unwrap_or_default(),
+                        label("ebpf_map_name"),
+                        "map",
+                    ),
+                };
+                let series_name = match name {
+                    Some(name) => format!("{id}_{name}_{suffix}"),
+                    // Series are identified by id only in id-only series mode
+                    None => format!("{id}_{suffix}"),
+                };
+                series.entry(series_name).or_default().push(sample.value);
+            }
+        }
+        Ok(series)
+    })
+}
+
+/// Scrapes the metrics endpoint once
+///
+/// # Arguments
+///
+/// * `client` - Http client
+///
+/// * `url` - Url of the metrics endpoint
+async fn scrape_once(client: &reqwest::Client, url: &str) -> Result<String> {
+    Ok(client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?)
+}
+
+/// Measurements of ebpf programs/maps to draw, read from csv files or scraped from
+/// a running instance
+struct ChartData<R> {
+    /// Names of the series with their records in chronological order
+    series: Vec<(String, Vec<R>)>,
+    /// Times of the records of each series in time units, in the order of the series
+    times: Vec<Vec<u64>>,
+    /// Number of time units between two records
+    factor: u64,
+    /// Time unit of the period between two records
+    time_unit: &'static str,
+    /// Description of the data sources used in error messages
+    source: String,
+    /// Title of the footer
+    footer_title: String,
+}
+
+impl<R: DeserializeOwned> ChartData<R> {
+    /// Reads records of the series from their csv files
+    ///
+    /// # Arguments
+    ///
+    /// * `series` - Series of the input csv files
+    fn from_series(series: &[Series]) -> Result<Self> {
+        let (factor, time_unit) = get_period_from_filenames(series)?;
+        let (series_records, times) = series
+            .iter()
+            .map(|one_series| {
+                let (row_times, records) = read_timed_records(one_series)?
+                    .into_iter()
+                    .unzip::<_, _, Vec<_>, Vec<_>>();
+                Ok((
+                    (one_series.name.clone(), records),
+                    record_times(&row_times, factor, time_unit),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+        Ok(Self {
+            series: series_records,
+            times,
+            factor,
+            time_unit,
+            source: format!("{series:?}"),
+            footer_title: format!(
+                "Data Sources {}...",
+                series
+                    .iter()
+                    .flat_map(|s| &s.files)
+                    .take(3)
+                    .map(|x| x.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        })
     }
 }
 
-fn draw_cpu_usage(files: &[PathBuf], output_dir: &std::path::Path) -> Result<()> {
+impl<R> ChartData<R> {
+    /// Creates chart data from values scraped from a running instance
+    ///
+    /// # Arguments
+    ///
+    /// * `series` - Names of the series with their scraped values
+    ///
+    /// * `url` - Url the values were scraped from
+    ///
+    /// * `args` - Draw arguments with the scrape interval
+    ///
+    /// * `to_record` - Converts a scraped value to a record
+    fn from_scraped(
+        series: Vec<(String, Vec<f64>)>,
+        url: &str,
+        args: &DrawArgs,
+        to_record: impl Fn(f64) -> R,
+    ) -> Self {
+        let interval = args.scrape_interval;
+        let (factor, time_unit) = if interval.subsec_millis() == 0 {
+            (interval.as_secs(), "s")
+        } else {
+            (interval.as_millis() as u64, "ms")
+        };
+        Self {
+            times: series
+                .iter()
+                .map(|(_, values)| (0..values.len() as u64).map(|idx| idx * factor).collect())
+                .collect(),
+            series: series
+                .into_iter()
+                .map(|(name, values)| (name, values.into_iter().map(&to_record).collect()))
+                .collect(),
+            factor,
+            time_unit,
+            source: url.to_string(),
+            footer_title: format!("Data Source {url}"),
+        }
+    }
+
+    /// Returns path to the output image file
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Draw arguments with the output directory and the image format
+    ///
+    /// * `file_suffix` - The suffix of the output image file
+    fn output_path(&self, args: &DrawArgs, file_suffix: &str) -> PathBuf {
+        if args.output_dir == Path::new(STDOUT_PATH) {
+            return PathBuf::from(STDOUT_PATH);
+        }
+        let time = format_rfc3339_seconds(SystemTime::now()).to_string();
+        let file_name = if let [(name, _)] = self.series.as_slice() {
+            PathBuf::from([time.as_str(), name.as_str(), file_suffix].join("_"))
+        } else {
+            PathBuf::from([time.as_str(), "bpf_programs", file_suffix].join("_"))
+        }
+        .with_extension(args.image_format.extension());
+        args.output_dir.join(file_name)
+    }
+}
+
+fn draw_cpu_usage(data: &ChartData<BpfCPUStatsInfo>, args: &DrawArgs) -> Result<()> {
     let mut file_readers_map: HashMap<String, Vec<(u64, f32)>> = HashMap::new();
     let (mut max_time, mut max_usage) = (0u64, 0.0f32);
 
-    let (output_svg, factor, time_unit) =
-        get_parameters_from_filenames(files, output_dir, "cpu_usage")?;
+    let time_unit = data.time_unit;
 
-    for file in files {
-        let time_cpu = csv::Reader::from_reader(BufReader::new(File::open(file)?))
-            .deserialize()
-            .filter_map(|r: std::result::Result<BpfCPUStatsInfo, csv::Error>| r.ok())
-            .enumerate()
-            .map(
-                |(
-                    idx,
-                    BpfCPUStatsInfo {
-                        exact_cpu_usage: cpu_usage,
-                        ..
-                    },
-                )| (idx as u64 * factor, cpu_usage * 100.0),
-            )
+    for ((name, records), times) in data.series.iter().zip(&data.times) {
+        let time_cpu = records
+            .iter()
+            .zip(times)
+            .filter_map(|(record, time)| {
+                record
+                    .exact_cpu_usage
+                    .map(|cpu_usage| (*time, cpu_usage * 100.0))
+            })
             .collect::<Vec<(u64, f32)>>();
         if time_cpu.is_empty() {
             continue;
@@ -81,20 +401,11 @@ fn draw_cpu_usage(files: &[PathBuf], output_dir: &std::path::Path) -> Result<()>
                 .map(|(_, usage)| *usage)
                 .fold(0.0f32, |f1, f2| f1.max(f2)),
         );
-        let bpf_program_name = file
-            .file_stem()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .rsplit_once("_")
-            .unwrap()
-            .0
-            .to_string();
-        file_readers_map.insert(bpf_program_name, time_cpu);
+        file_readers_map.insert(name.clone(), time_cpu);
     }
 
     if file_readers_map.is_empty() {
-        bail!("No bpf data csv files found in {:?}", files);
+        bail!("No measurements to draw in {}", data.source);
     }
 
     // Calculate image shapes
@@ -110,42 +421,85 @@ fn draw_cpu_usage(files: &[PathBuf], output_dir: &std::path::Path) -> Result<()>
         max_y: max_usage,
 
         time_step: (max_time / 20).max(1),
-        step_y: max_usage / 10.0,
+        step_y: (max_usage / 10.0).max(USAGE_MAX_TICKS[0] / 10.0),
 
         title: "eBPF programs CPU usage",
         y_desc: "CPU usage, %",
         time_unit,
+        embed_data: args.embed_data,
+        width: args.width,
+        height: args.height,
         ..Default::default()
     };
 
-    image_parameters.set_footer_title(files);
+    image_parameters.footer_title = data.footer_title.clone();
+
+    image_parameters.draw_image(
+        file_readers_map,
+        data.output_path(args, "cpu_usage").as_path(),
+        args.image_format,
+    )
+}
+
+/// Calculates the event count between every two measurements of the ebpf program. A run count
+/// lower than the previous one means the program was reloaded or its counter wrapped,
+/// the event count of such interval is 0
+///
+/// # Arguments
+///
+/// * `records` - Measurements of the ebpf program
+///
+/// * `times` - Times of the measurements in time units
+///
+/// Returns pairs of the time of the interval start and the event count in the interval
+fn interval_event_counts(records: &[BpfCPUStatsInfo], times: &[u64]) -> Vec<(u64, u64)> {
+    records
+        .windows(2)
+        .zip(times)
+        .map(|(w, time)| (*time, w[1].run_count.saturating_sub(w[0].run_count)))
+        .collect()
+}
 
-    image_parameters.draw_image(file_readers_map, output_svg.as_path())
+/// Returns times of the records in time units: the time the stats were received if all
+/// records have it and it doesn't go back (e.g. merged files of different runs), otherwise
+/// the record index multiplied by the period, which drifts if ticks were delayed
+///
+/// # Arguments
+///
+/// * `row_times` - Times of the records read from csv files
+///
+/// * `factor` - Number of time units between two measurements
+///
+/// * `time_unit` - Time unit of the period, s or ms
+fn record_times(row_times: &[Option<RowTime>], factor: u64, time_unit: &str) -> Vec<u64> {
+    let received = row_times
+        .iter()
+        .map(|row_time| {
+            row_time.map(|row_time| match time_unit {
+                "ms" => row_time.time_recieved.as_millis() as u64,
+                _ => row_time.time_recieved.as_secs(),
+            })
+        })
+        .collect::<Option<Vec<_>>>();
+    match received {
+        Some(received) if received.is_sorted() => received,
+        _ => (0..row_times.len() as u64)
+            .map(|idx| idx * factor)
+            .collect(),
+    }
 }
 
-fn draw_event_count(files: &[PathBuf], output_dir: &std::path::Path) -> Result<()> {
+fn draw_event_count(data: &ChartData<BpfCPUStatsInfo>, args: &DrawArgs) -> Result<()> {
     let mut file_readers_map: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
     let (mut max_time, mut max_run_count) = (0u64, 0u64);
 
-    let (output_svg, factor, time_unit) =
-        get_parameters_from_filenames(files, output_dir, "event_count")?;
+    let time_unit = data.time_unit;
 
-    for file in files {
-        let mut prog_events_count = csv::Reader::from_reader(BufReader::new(File::open(file)?))
-            .deserialize()
-            .filter_map(|r: std::result::Result<BpfCPUStatsInfo, csv::Error>| r.ok())
-            .enumerate()
-            .map(|(idx, BpfCPUStatsInfo { run_count, .. })| (idx as u64 * factor, run_count))
-            .collect::<Vec<(u64, u64)>>();
-        // Calculate the event count between two measurements
-        if prog_events_count.is_empty() {
+    for ((name, records), times) in data.series.iter().zip(&data.times) {
+        if records.is_empty() {
             continue;
-        } else {
-            prog_events_count = prog_events_count
-                .windows(2)
-                .map(|w| (w[0].0, w[1].1 - w[0].1))
-                .collect();
         }
+        let prog_events_count = interval_event_counts(records, times);
         max_time = max_time.max(
             prog_events_count
                 .iter()
@@ -160,52 +514,118 @@ fn draw_event_count(files: &[PathBuf], output_dir: &std::path::Path) -> Result<(
                 .max()
                 .unwrap_or_default(),
         );
-        let bpf_program_name = file
-            .file_stem()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .rsplit_once("_")
-            .unwrap()
-            .0
-            .to_string();
-        file_readers_map.insert(bpf_program_name, prog_events_count);
+        file_readers_map.insert(name.clone(), prog_events_count);
     }
 
     if file_readers_map.is_empty() {
-        bail!("No bpf data csv files found in {:?}", files);
+        bail!("No measurements to draw in {}", data.source);
     }
 
     // Calculate image shapes
     let mut image_parameters = ImageParameters {
         max_time,
-        max_y: max_run_count * 3 / 2,
+        max_y: (max_run_count * 3 / 2).max(1),
 
         time_step: (max_time / 20).max(1),
-        step_y: max_run_count / 10,
+        step_y: (max_run_count / 10).max(1),
 
         title: "eBPF programs event count",
         y_desc: "Event count",
         time_unit,
+        embed_data: args.embed_data,
+        width: args.width,
+        height: args.height,
+        ..Default::default()
+    };
+
+    image_parameters.footer_title = data.footer_title.clone();
+
+    image_parameters.draw_image(
+        file_readers_map,
+        data.output_path(args, "event_count").as_path(),
+        args.image_format,
+    )
+}
+
+fn draw_run_time(data: &ChartData<BpfCPUStatsInfo>, args: &DrawArgs) -> Result<()> {
+    let mut file_readers_map: HashMap<String, Vec<(u64, f32)>> = HashMap::new();
+    let (mut max_time, mut max_run_time) = (0u64, 0.0f32);
+
+    let (factor, time_unit) = (data.factor, data.time_unit);
+
+    for (name, records) in &data.series {
+        let time_run_time = records
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(idx, BpfCPUStatsInfo { run_time, .. })| {
+                (idx as u64 * factor, run_time.as_secs_f32())
+            })
+            .collect::<Vec<(u64, f32)>>();
+        if time_run_time.is_empty() {
+            continue;
+        }
+        max_time = max_time.max(
+            time_run_time
+                .iter()
+                .map(|(time, _)| *time)
+                .max()
+                .unwrap_or(0),
+        );
+        max_run_time = max_run_time.max(
+            time_run_time
+                .iter()
+                .map(|(_, run_time)| *run_time)
+                .fold(0.0f32, |f1, f2| f1.max(f2)),
+        );
+        file_readers_map.insert(name.clone(), time_run_time);
+    }
+
+    if file_readers_map.is_empty() {
+        bail!("No measurements to draw in {}", data.source);
+    }
+
+    // Calculate image shapes, programs that were never run still get a visible y axis
+    let max_y = if max_run_time > 0.0 {
+        max_run_time * 1.5
+    } else {
+        1.0
+    };
+    let mut image_parameters = ImageParameters {
+        max_time,
+        max_y,
+
+        time_step: (max_time / 20).max(1),
+        step_y: max_y / 10.0,
+
+        title: "eBPF programs run time",
+        y_desc: "Run time, s",
+        time_unit,
+        embed_data: args.embed_data,
+        width: args.width,
+        height: args.height,
         ..Default::default()
     };
 
-    image_parameters.set_footer_title(files);
+    image_parameters.footer_title = data.footer_title.clone();
 
-    image_parameters.draw_image(file_readers_map, output_svg.as_path())
+    image_parameters.draw_image(
+        file_readers_map,
+        data.output_path(args, "run_time").as_path(),
+        args.image_format,
+    )
 }
 
-fn draw_map_size(files: &[PathBuf], output_dir: &std::path::Path) -> Result<()> {
+fn draw_map_size(data: &ChartData<BpfMapStatsInfo>, args: &DrawArgs) -> Result<()> {
     let mut file_readers_map: HashMap<String, Vec<(u64, u32)>> = HashMap::new();
     let (mut max_time, mut max_size) = (0u64, 0u32);
 
-    let (output_svg, factor, time_unit) =
-        get_parameters_from_filenames(files, output_dir, "map_size")?;
+    let (factor, time_unit) = (data.factor, data.time_unit);
 
-    for file in files {
-        let map_size = csv::Reader::from_reader(BufReader::new(File::open(file)?))
-            .deserialize()
-            .filter_map(|r: std::result::Result<BpfMapStatsInfo, csv::Error>| r.ok())
+    for (name, records) in &data.series {
+        let map_size = records
+            .iter()
+            .cloned()
             .enumerate()
             .map(|(idx, BpfMapStatsInfo { size, .. })| (idx as u64 * factor, size))
             .collect::<Vec<(u64, u32)>>();
@@ -219,39 +639,103 @@ fn draw_map_size(files: &[PathBuf], output_dir: &std::path::Path) -> Result<()>
                 .map(|(_, usage)| *usage)
                 .fold(0u32, |f1, f2| f1.max(f2)),
         );
-        let bpf_program_name = file
-            .file_stem()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .rsplit_once("_")
-            .unwrap()
-            .0
-            .to_string();
-        file_readers_map.insert(bpf_program_name, map_size);
+        file_readers_map.insert(name.clone(), map_size);
     }
 
     if file_readers_map.is_empty() {
-        bail!("No bpf data csv files found in {:?}", files);
+        bail!("No measurements to draw in {}", data.source);
     }
 
     // Calculate image shapes
     let mut image_parameters = ImageParameters {
         max_time,
-        max_y: max_size * 3 / 2,
+        max_y: (max_size * 3 / 2).max(1),
 
         time_step: (max_time / 20).max(1),
-        step_y: max_size / 10,
+        step_y: (max_size / 10).max(1),
 
         title: "eBPF map size",
         y_desc: "Elements in map",
         time_unit,
+        embed_data: args.embed_data,
+        width: args.width,
+        height: args.height,
+        ..Default::default()
+    };
+
+    image_parameters.footer_title = data.footer_title.clone();
+
+    image_parameters.draw_image(
+        file_readers_map,
+        data.output_path(args, "map_size").as_path(),
+        args.image_format,
+    )
+}
+
+fn draw_map_churn(data: &ChartData<BpfMapStatsInfo>, args: &DrawArgs) -> Result<()> {
+    let mut file_readers_map: HashMap<String, Vec<(u64, u64)>> = HashMap::new();
+    let (mut max_time, mut max_churn) = (0u64, 0u64);
+
+    let (factor, time_unit) = (data.factor, data.time_unit);
+
+    for (name, records) in &data.series {
+        // Counters are cumulative, the chart shows entries added and deleted between
+        // two measurements
+        for kind in ["adds", "deletes"] {
+            let counter = |stats: &BpfMapStatsInfo| match kind {
+                "adds" => stats.adds,
+                _ => stats.deletes,
+            };
+            let churn = records
+                .windows(2)
+                .enumerate()
+                .filter_map(|(idx, w)| {
+                    Some((
+                        idx as u64 * factor,
+                        counter(&w[1])?.saturating_sub(counter(&w[0])?),
+                    ))
+                })
+                .collect::<Vec<(u64, u64)>>();
+            if churn.is_empty() {
+                continue;
+            }
+            max_time = max_time.max(churn.iter().map(|(time, _)| *time).max().unwrap_or(0));
+            max_churn = max_churn.max(churn.iter().map(|(_, churn)| *churn).max().unwrap_or(0));
+            file_readers_map.insert(format!("{name} {kind}"), churn);
+        }
+    }
+
+    if file_readers_map.is_empty() {
+        bail!(
+            "No map churn to draw in {}, maps have to be measured with --map-churn",
+            data.source
+        );
+    }
+
+    // Calculate image shapes
+    let mut image_parameters = ImageParameters {
+        max_time,
+        max_y: (max_churn * 3 / 2).max(1),
+
+        time_step: (max_time / 20).max(1),
+        step_y: (max_churn / 10).max(1),
+
+        title: "eBPF map churn",
+        y_desc: "Entries added and deleted",
+        time_unit,
+        embed_data: args.embed_data,
+        width: args.width,
+        height: args.height,
         ..Default::default()
     };
 
-    image_parameters.set_footer_title(files);
+    image_parameters.footer_title = data.footer_title.clone();
 
-    image_parameters.draw_image(file_readers_map, output_svg.as_path())
+    image_parameters.draw_image(
+        file_readers_map,
+        data.output_path(args, "map_churn").as_path(),
+        args.image_format,
+    )
 }
 
 /// Struct representing the parameters of the image
@@ -273,24 +757,40 @@ struct ImageParameters<T> {
     y_desc: &'static str,
     /// Time unit
     time_unit: &'static str,
+    /// Draw a table with average and maximum of each ebpf program below the chart
+    embed_data: bool,
+    /// Width of the image
+    width: u32,
+    /// Height of the chart, the image is higher if the data table is embedded
+    height: u32,
 }
 
 impl<T> ImageParameters<T> {
-    /// Set the footer title
+    /// Scales the horizontal size in the reference layout to the image width
     ///
     /// # Arguments
     ///
-    /// * `files` - Vector of file paths with data sources
-    fn set_footer_title(&mut self, files: &[PathBuf]) {
-        self.footer_title = format!(
-            "Data Sources {}...",
-            files
-                .iter()
-                .take(3)
-                .map(|x| x.display().to_string())
-                .collect::<Vec<_>>()
-                .join(", ")
-        );
+    /// * `size` - Size in pixels in the reference layout
+    ///
+    /// * `min` - Minimum size in pixels to keep the element readable on small images
+    fn scale_x(&self, size: u32, min: u32) -> u32 {
+        (size * self.width / CHART_WIDTH).max(min)
+    }
+
+    /// Scales the vertical size in the reference layout to the chart height
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - Size in pixels in the reference layout
+    ///
+    /// * `min` - Minimum size in pixels to keep the element readable on small images
+    fn scale_y(&self, size: u32, min: u32) -> u32 {
+        (size * self.height / CHART_HEIGHT).max(min)
+    }
+
+    /// Height of a row of the embedded data table
+    fn table_row_height(&self) -> u32 {
+        self.scale_y(TABLE_ROW_HEIGHT, 14)
     }
 
     /// Draw the image
@@ -299,11 +799,14 @@ impl<T> ImageParameters<T> {
     ///
     /// * `file_readers_map` - Map of ebpf program name to vector of (time, value) pairs
     ///
-    /// * `output_svg` - Path to the output svg file
+    /// * `output_image` - Path to the output image file, `-` to write it to stdout
+    ///
+    /// * `image_format` - Format of the image
     fn draw_image(
         &self,
         file_readers_map: HashMap<String, Vec<(u64, T)>>,
-        output_svg: &std::path::Path,
+        output_image: &std::path::Path,
+        image_format: ImageFormat,
     ) -> Result<()>
     where
         std::ops::Range<T>: AsRangedCoord<Value = T, CoordDescType: ValueFormatter<T>>,
@@ -318,18 +821,104 @@ impl<T> ImageParameters<T> {
             + num_traits::NumRef
             + 'static,
     {
-        let root = SVGBackend::new(&output_svg, (1920, 1080)).into_drawing_area();
+        let (width, height) = if self.embed_data {
+            let rows = file_readers_map.len() as u32 + 2;
+            (self.width, self.height + self.table_row_height() * rows)
+        } else {
+            (self.width, self.height)
+        };
+        let image = match image_format {
+            ImageFormat::Svg => {
+                let mut svg = String::new();
+                self.render(
+                    SVGBackend::with_string(&mut svg, (width, height)).into_drawing_area(),
+                    file_readers_map,
+                )?;
+                svg.into_bytes()
+            }
+            ImageFormat::Png => {
+                let mut rgb = vec![0u8; (width * height * 3) as usize];
+                self.render(
+                    BitMapBackend::with_buffer(&mut rgb, (width, height)).into_drawing_area(),
+                    file_readers_map,
+                )?;
+                let mut png = Vec::new();
+                image::codecs::png::PngEncoder::new(&mut png)
+                    .write_image(&rgb, width, height, image::ColorType::Rgb8)
+                    .with_context(|| "Unable to encode png image")?;
+                png
+            }
+        };
+
+        if output_image == Path::new(STDOUT_PATH) {
+            std::io::stdout()
+                .lock()
+                .write_all(&image)
+                .with_context(|| "Unable to write result to stdout")?;
+            return Ok(());
+        }
+
+        std::fs::write(output_image, image).with_context(|| {
+            format!("Unable to write result to file {}", output_image.display())
+        })?;
+
+        info!("Image saved to {}", output_image.display());
+
+        Ok(())
+    }
+
+    /// Render the image on the drawing area
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - Drawing area of the whole image
+    ///
+    /// * `file_readers_map` - Map of ebpf program name to vector of (time, value) pairs
+    fn render<DB: DrawingBackend>(
+        &self,
+        root: DrawingArea<DB, Shift>,
+        file_readers_map: HashMap<String, Vec<(u64, T)>>,
+    ) -> Result<()>
+    where
+        DB::ErrorType: 'static,
+        std::ops::Range<T>: AsRangedCoord<Value = T, CoordDescType: ValueFormatter<T>>,
+        for<'a> T: Display
+            + Default
+            + Copy
+            + PartialOrd
+            + AddAssign<T>
+            + std::iter::Sum<&'a T>
+            + num_traits::cast::FromPrimitive
+            + num_traits::Num
+            + num_traits::NumRef
+            + 'static,
+    {
         root.fill(&WHITE)?;
 
-        // Title: 80, Body: 920, Footer: 80
-        let (title_box, body) = root.split_vertically(80);
-        let (body_box, footer) = body.split_vertically(920);
+        // Table is drawn on the canvas extended below the chart
+        let (root, table_box) = root.split_vertically(self.height);
+        if self.embed_data {
+            self.draw_table(&table_box, &file_readers_map)?;
+        }
 
-        title_box.titled(self.title, ("sans-serif", 50).into_font().color(&BLACK))?;
+        // Title: 80, Body: 920, Footer: 80 in the reference layout
+        let (title_height, footer_height) = (self.scale_y(80, 20), self.scale_y(80, 16));
+        let (title_box, body) = root.split_vertically(title_height);
+        let (body_box, footer) =
+            body.split_vertically(self.height.saturating_sub(title_height + footer_height));
+
+        title_box.titled(
+            self.title,
+            ("sans-serif", self.scale_y(50, 14))
+                .into_font()
+                .color(&BLACK),
+        )?;
 
         footer.titled(
             self.footer_title.as_str(),
-            ("sans-serif", 10).into_font().color(&BLACK.mix(0.5)),
+            ("sans-serif", self.scale_y(10, 8))
+                .into_font()
+                .color(&BLACK.mix(0.5)),
         )?;
 
         // Calculate avg, min and max on y axisx
@@ -362,10 +951,12 @@ impl<T> ImageParameters<T> {
                 format!(
                     "Overall events: Avg: {avg_overall_usage:.2}, Min: {min_overall_usage:.2}, Max: {max_overall_usage:.2}"
                 ),
-                ("sans-serif", (3).percent_height()),
+                ("sans-serif", self.scale_y(28, 12)),
             )
-            .set_label_area_size(LabelAreaPosition::Left, (8).percent())
-            .set_label_area_size(LabelAreaPosition::Bottom, (4).percent())
+            // Label areas are 8% and 4% of the chart body in the reference layout, but not
+            // smaller than the axis labels on small images
+            .set_label_area_size(LabelAreaPosition::Left, self.scale_y(74, 60))
+            .set_label_area_size(LabelAreaPosition::Bottom, self.scale_y(37, 30))
             .margin((1).percent())
             .build_cartesian_2d(
                 (0u64..self.max_time).step(self.time_step),
@@ -388,88 +979,107 @@ impl<T> ImageParameters<T> {
                 });
         }
 
+        // Reference lines are muted and dashed to keep the series of programs readable
+        for (idx, (title, value)) in [
+            ("Overall avg", avg_overall_usage),
+            ("Overall min", min_overall_usage),
+            ("Overall max", max_overall_usage),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let style = BLACK.mix(0.6 - 0.15 * idx as f64).stroke_width(2);
+            chart
+                .draw_series(DashedLineSeries::new(
+                    [(0, value), (self.max_time, value)],
+                    10,
+                    5,
+                    style,
+                ))?
+                .label(format!("{title}: {value:.2}"))
+                .legend(move |(x, y)| PathElement::new([(x, y), (x + 10, y)], style));
+        }
+
         chart.configure_series_labels().border_style(BLACK).draw()?;
 
-        // To avoid the IO failure being ignored silently, we manually call the present function
-        root.present()
-            .with_context(|| format!("Unable to write result to file {}", output_svg.display()))?;
+        // To avoid the failure being ignored silently, we manually call the present function
+        root.present().with_context(|| "Unable to render image")?;
 
-        info!("Image saved to {}", output_svg.display());
+        Ok(())
+    }
+    /// Draw a table with average and maximum of each ebpf program
+    ///
+    /// # Arguments
+    ///
+    /// * `area` - Drawing area below the chart
+    ///
+    /// * `file_readers_map` - Map of ebpf program name to vector of (time, value) pairs
+    fn draw_table<DB: DrawingBackend>(
+        &self,
+        area: &DrawingArea<DB, Shift>,
+        file_readers_map: &HashMap<String, Vec<(u64, T)>>,
+    ) -> Result<()>
+    where
+        DB::ErrorType: 'static,
+        for<'a> T: Display
+            + Copy
+            + PartialOrd
+            + std::iter::Sum<&'a T>
+            + num_traits::cast::FromPrimitive
+            + num_traits::Num
+            + 'static,
+    {
+        let font_size = self.scale_y(20, 10);
+        let header_style = ("sans-serif", font_size)
+            .into_font()
+            .style(FontStyle::Bold)
+            .color(&BLACK);
+        let style = ("sans-serif", font_size).into_font().color(&BLACK);
+        let columns = [40, 1200, 1550].map(|x| self.scale_x(x, 0) as i32);
+        let row_height = self.table_row_height();
+        let row_y = |row: u32| (row_height * row + row_height / 2) as i32;
 
+        for (x, title) in columns.iter().zip(["eBPF program", "Avg", "Max"]) {
+            area.draw_text(title, &header_style, (*x, row_y(0)))?;
+        }
+
+        let mut names = file_readers_map.keys().collect::<Vec<_>>();
+        names.sort();
+        for (row, name) in names.into_iter().enumerate() {
+            let values = file_readers_map[name].iter().map(|(_, value)| value);
+            let count = T::from_usize(file_readers_map[name].len()).unwrap();
+            let avg = values.clone().sum::<T>() / count;
+            let max = values
+                .copied()
+                .max_by(|a, b| a.partial_cmp(b).unwrap())
+                .unwrap();
+
+            let y = row_y(row as u32 + 1);
+            area.draw_text(name, &style, (columns[0], y))?;
+            area.draw_text(&format!("{avg:.2}"), &style, (columns[1], y))?;
+            area.draw_text(&format!("{max:.2}"), &style, (columns[2], y))?;
+        }
         Ok(())
     }
 }
 
-/// Get the output svg file name, multiply factor and the time unit from the first file
-/// or use the default values.
+/// Get the multiply factor and the time unit from the first file, all files must have
+/// the same measurement period
 ///
 /// # Arguments
 ///
-/// * `files` - The input csv files
-///
-/// * `output_dir` - The output directory to save results
-///
-/// * `file_suffix` - The suffix of the output svg file
-fn get_parameters_from_filenames(
-    files: &[PathBuf],
-    output_dir: &std::path::Path,
-    file_suffix: &str,
-) -> Result<(PathBuf, u64, &'static str)> {
+/// * `series` - Series of the input csv files
+fn get_period_from_filenames(series: &[Series]) -> Result<(u64, &'static str)> {
+    let files = series.iter().flat_map(|s| &s.files).collect::<Vec<_>>();
     if files.is_empty() {
         bail!("No files to draw");
     }
-    let file_stem = files
-        .first()
-        .context("No files to draw")?
-        .file_stem()
-        .unwrap()
-        .to_str()
-        .unwrap();
-    let Some((program_name, period)) = file_stem.rsplit_once('_') else {
-        bail!(
-            "File name of csv should be in format <bpf_id>_<bpf_name>_prog_<measurement_period>.csv, given: {}",
-            file_stem
-        );
-    };
+    let period = file_period(files.first().context("No files to draw")?)?;
 
-    let time = format_rfc3339_seconds(SystemTime::now()).to_string();
-    let mut output_svg = if files.len() == 1 {
-        PathBuf::from([time.as_str(), program_name, file_suffix].join("_"))
-    } else {
-        PathBuf::from([time.as_str(), "bpf_programs", file_suffix].join("_"))
-    }
-    .with_extension("svg");
-    output_svg = output_dir.join(output_svg);
-
-    let (factor, time_unit) = if period.ends_with("ms") {
-        (
-            period
-                .trim_end_matches("ms")
-                .parse::<u64>()
-                .with_context(|| format!("Invalid measurement period: {period}"))?,
-            "ms",
-        )
-    } else if period.ends_with("s") {
-        (
-            period
-                .trim_end_matches("s")
-                .parse::<u64>()
-                .with_context(|| format!("Invalid measurement period: {period}"))?,
-            "s",
-        )
-    } else {
-        bail!("Invalid measurement period: {}", period);
-    };
+    let (factor, time_unit) = parse_period(&period)?;
 
     for file in files {
-        let other_period = file
-            .file_stem()
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .split("_")
-            .last()
-            .unwrap();
+        let other_period = file_period(file)?;
         if other_period != period {
             bail!(
                 "All files should have the same measurement period, given: {} and {}",
@@ -479,5 +1089,107 @@ fn get_parameters_from_filenames(
         }
     }
 
-    Ok((output_svg, factor, time_unit))
+    Ok((factor, time_unit))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::{
+        capture::read_records,
+        config::{Config, SubCommands},
+    };
+
+    #[test]
+    fn test_event_count_is_zero_after_reset() {
+        let path =
+            std::env::temp_dir().join(format!("bpfmeter_draw_{}_prog_1s.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "exact_cpu_usage,run_time,run_count\n\
+             0.1,0.001,100\n\
+             0.1,0.002,250\n\
+             0.1,0.000,20\n\
+             0.1,0.001,70\n",
+        )
+        .unwrap();
+        let records = read_records::<BpfCPUStatsInfo>(&Series {
+            name: "prog".to_string(),
+            files: vec![path.clone()],
+        });
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            interval_event_counts(&records.unwrap(), &[0, 1, 2, 3]),
+            [(0, 150), (1, 0), (2, 50)]
+        );
+    }
+
+    #[test]
+    fn test_record_times() {
+        let row_time = |time_recieved_ms| {
+            Some(RowTime {
+                time_recieved: Duration::from_millis(time_recieved_ms),
+                ..Default::default()
+            })
+        };
+        // The second tick was delayed
+        let row_times = [row_time(1000), row_time(2500), row_time(3000)];
+        assert_eq!(record_times(&row_times, 1, "s"), [1, 2, 3]);
+        assert_eq!(record_times(&row_times, 1000, "ms"), [1000, 2500, 3000]);
+        // Files without the time columns and merged files of different runs
+        assert_eq!(record_times(&[None, None], 500, "ms"), [0, 500]);
+        let row_times = [row_time(2000), row_time(1000)];
+        assert_eq!(record_times(&row_times, 1, "s"), [0, 1]);
+    }
+
+    #[test]
+    fn test_draw_small_event_counts() {
+        let dir = std::env::temp_dir().join(format!("bpfmeter_draw_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Event counts between measurements are 1, 2, 3, 0 and 1
+        std::fs::write(
+            dir.join("1_prog_prog_1s.csv"),
+            "exact_cpu_usage,run_time,run_count\n\
+             0.1,0.001,0\n\
+             0.1,0.001,1\n\
+             0.1,0.001,3\n\
+             0.1,0.001,6\n\
+             0.1,0.001,6\n\
+             0.1,0.001,7\n",
+        )
+        .unwrap();
+        let dir_arg = dir.to_str().unwrap();
+        let config = Config::parse_from([
+            "bpfmeter",
+            "draw",
+            "-i",
+            dir_arg,
+            "-o",
+            dir_arg,
+            "-t",
+            "event-count",
+        ]);
+        let SubCommands::Draw(args) = config.command else {
+            unreachable!();
+        };
+        let result = draw(&args);
+        let svg_count = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .path()
+                    .extension()
+                    .is_some_and(|ext| ext == "svg")
+            })
+            .count();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        result.unwrap();
+        assert_eq!(svg_count, 1);
+    }
 }
@@ -0,0 +1,378 @@
+use std::{collections::HashMap, future::Future, sync::mpsc, thread, time::Duration};
+
+use anyhow::{Context, Result, anyhow};
+use log::{debug, error, warn};
+use tokio::sync::oneshot;
+
+use crate::meter::{BpfInfo, BpfStatsInfo, Meter, cpu_meter::CpuMeter, map_meter::MapMeter};
+
+/// Maximum number of batches waiting to be sent, exporting waits for the sender once
+/// it is reached
+const CHANNEL_CAPACITY: usize = 64;
+/// Maximum time to wait for the waiting batches to be sent on flush
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Failure of sending a batch to the storage
+#[derive(Debug)]
+pub enum SendError {
+    /// The batch may be accepted if it is sent again, e.g. the request was throttled,
+    /// timed out, failed on the server or the connection was lost
+    Transient(anyhow::Error),
+    /// The batch is rejected and dropped
+    Permanent(anyhow::Error),
+}
+
+/// Storage batches of exported values are sent to
+pub trait BatchSink<T> {
+    /// Name of the storage in logs
+    const NAME: &'static str;
+
+    /// Sends the batch to the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `batch` - Values to send
+    fn send(&mut self, batch: &[T]) -> impl Future<Output = Result<(), SendError>>;
+}
+
+/// Retries of batches failed with transient errors
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts to send a batch
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled on every attempt
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(200),
+        }
+    }
+}
+
+/// Message to the sending thread
+enum Message<T> {
+    /// Batch to send
+    Batch(Vec<T>),
+    /// Request to acknowledge once all previous batches are sent
+    Flush(mpsc::Sender<()>),
+}
+
+/// Values exported on a tick of a meter
+struct Batch<T> {
+    /// Tick of the values
+    tick: u64,
+    /// Values waiting to be sent
+    values: Vec<T>,
+}
+
+/// Collects values exported on a tick into batches and sends them to the storage from
+/// a dedicated thread, so the batches are delivered even while the runtime of the meters
+/// shuts down. Values of the cpu and map meters are batched separately, as each meter
+/// counts its own ticks
+pub struct BatchSender<T> {
+    /// Name of the storage in logs
+    name: &'static str,
+    /// Maximum number of values in a single batch
+    max_batch_size: usize,
+    /// Batches of the current tick of each meter
+    batches: HashMap<&'static str, Batch<T>>,
+    /// Channel to the sending thread
+    tx: mpsc::SyncSender<Message<T>>,
+}
+
+impl<T: Send + 'static> BatchSender<T> {
+    /// Starts the thread sending batches to the storage
+    ///
+    /// # Arguments
+    ///
+    /// * `max_batch_size` - Maximum number of values in a single batch, values of a tick
+    ///   exceeding it are sent in several batches
+    ///
+    /// * `retry` - Retries of batches failed with transient errors
+    ///
+    /// * `connect` - Creates the sink on the sending thread, so its connections live
+    ///   on the runtime of the thread
+    pub async fn start<S, F, Fut>(
+        max_batch_size: usize,
+        retry: RetryPolicy,
+        connect: F,
+    ) -> Result<Self>
+    where
+        S: BatchSink<T>,
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<S>>,
+    {
+        let (tx, rx) = mpsc::sync_channel(CHANNEL_CAPACITY);
+        let (ready_tx, ready_rx) = oneshot::channel();
+        thread::Builder::new()
+            .name(format!("{}-sender", S::NAME.to_lowercase()))
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(anyhow::Error::from);
+                let sink = runtime.and_then(|runtime| Ok((runtime.block_on(connect())?, runtime)));
+                match sink {
+                    Ok((sink, runtime)) => {
+                        let _ = ready_tx.send(Ok(()));
+                        send_batches(&runtime, sink, retry, rx);
+                    }
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                    }
+                }
+            })
+            .with_context(|| format!("Failed to start {} sender", S::NAME))?;
+        ready_rx
+            .await
+            .with_context(|| format!("{} sender is stopped", S::NAME))??;
+
+        Ok(Self {
+            name: S::NAME,
+            max_batch_size,
+            batches: HashMap::new(),
+            tx,
+        })
+    }
+
+    /// Adds values exported for the ebpf program/map to the batch of its meter. The batch
+    /// of the previous tick of the meter is sent first, without waiting for it to be full
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - BpfInfo the values are exported for
+    ///
+    /// * `values` - Values to send
+    pub fn push(&mut self, data: &BpfInfo, values: impl IntoIterator<Item = T>) {
+        let meter = match data.stats {
+            BpfStatsInfo::Cpu(_) => CpuMeter::NAME,
+            BpfStatsInfo::Map(_) => MapMeter::NAME,
+        };
+        let batch = self.batches.entry(meter).or_insert_with(|| Batch {
+            tick: data.tick,
+            values: Vec::new(),
+        });
+        if batch.tick != data.tick {
+            send(self.name, &self.tx, std::mem::take(&mut batch.values));
+            batch.tick = data.tick;
+        }
+        for value in values {
+            batch.values.push(value);
+            if batch.values.len() >= self.max_batch_size {
+                send(self.name, &self.tx, std::mem::take(&mut batch.values));
+            }
+        }
+    }
+}
+
+impl<T> BatchSender<T> {
+    /// Sends the batches of the current ticks and waits until all batches are sent
+    /// or given up
+    pub fn flush(&mut self) -> Result<()> {
+        for batch in self.batches.values_mut() {
+            send(self.name, &self.tx, std::mem::take(&mut batch.values));
+        }
+        let (ack_tx, ack_rx) = mpsc::channel();
+        self.tx
+            .send(Message::Flush(ack_tx))
+            .map_err(|_| anyhow!("{} sender is stopped", self.name))?;
+        ack_rx
+            .recv_timeout(FLUSH_TIMEOUT)
+            .with_context(|| format!("Failed to wait for metrics to be sent to {}", self.name))
+    }
+}
+
+impl<T> Drop for BatchSender<T> {
+    fn drop(&mut self) {
+        // Values of the last ticks are sent before the process exits
+        if let Err(e) = self.flush() {
+            error!("{e:#}");
+        }
+    }
+}
+
+/// Passes the batch to the sending thread, waits if the thread is behind
+///
+/// # Arguments
+///
+/// * `name` - Name of the storage in logs
+///
+/// * `tx` - Channel to the sending thread
+///
+/// * `batch` - Values to send
+fn send<T>(name: &str, tx: &mpsc::SyncSender<Message<T>>, batch: Vec<T>) {
+    if batch.is_empty() {
+        return;
+    }
+    if tx.send(Message::Batch(batch)).is_err() {
+        error!("{name} sender is stopped, metrics are dropped");
+    }
+}
+
+/// Sends batches received from the channel until it is closed
+///
+/// # Arguments
+///
+/// * `runtime` - Runtime of the sending thread
+///
+/// * `sink` - Storage to send batches to
+///
+/// * `retry` - Retries of batches failed with transient errors
+///
+/// * `rx` - Channel to receive batches from
+fn send_batches<T, S: BatchSink<T>>(
+    runtime: &tokio::runtime::Runtime,
+    mut sink: S,
+    retry: RetryPolicy,
+    rx: mpsc::Receiver<Message<T>>,
+) {
+    while let Ok(message) = rx.recv() {
+        match message {
+            Message::Batch(batch) => runtime.block_on(send_with_retry(&mut sink, &batch, retry)),
+            Message::Flush(ack) => {
+                let _ = ack.send(());
+            }
+        }
+    }
+}
+
+/// Sends the batch, retrying transient failures with backoff
+///
+/// # Arguments
+///
+/// * `sink` - Storage to send the batch to
+///
+/// * `batch` - Values to send
+///
+/// * `retry` - Retries of transient failures
+async fn send_with_retry<T, S: BatchSink<T>>(sink: &mut S, batch: &[T], retry: RetryPolicy) {
+    let mut backoff = retry.initial_backoff;
+    for attempt in 1..=retry.max_attempts {
+        match sink.send(batch).await {
+            Ok(()) => {
+                debug!("Sent {} metrics to {}", batch.len(), S::NAME);
+                return;
+            }
+            Err(SendError::Transient(e)) if attempt < retry.max_attempts => {
+                warn!(
+                    "Failed to send metrics to {}, retrying in {backoff:?}: {e:#}",
+                    S::NAME
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(SendError::Transient(e) | SendError::Permanent(e)) => {
+                error!(
+                    "Failed to send {} metrics to {}: {e:#}",
+                    batch.len(),
+                    S::NAME
+                );
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::meter::{cpu_meter::BpfCPUStatsInfo, map_meter::BpfMapStatsInfo};
+
+    /// Sink recording sent batches, failing the first sends with the given errors
+    struct TestSink {
+        sent: Arc<Mutex<Vec<Vec<u32>>>>,
+        failures: Vec<SendError>,
+    }
+
+    impl BatchSink<u32> for TestSink {
+        const NAME: &'static str = "Test";
+
+        async fn send(&mut self, batch: &[u32]) -> Result<(), SendError> {
+            if let Some(failure) = self.failures.pop() {
+                return Err(failure);
+            }
+            self.sent.lock().unwrap().push(batch.to_vec());
+            Ok(())
+        }
+    }
+
+    async fn start_sender(
+        max_batch_size: usize,
+        failures: Vec<SendError>,
+    ) -> (BatchSender<u32>, Arc<Mutex<Vec<Vec<u32>>>>) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sink = TestSink {
+            sent: sent.clone(),
+            failures,
+        };
+        let retry = RetryPolicy {
+            max_attempts: 2,
+            initial_backoff: Duration::from_millis(1),
+        };
+        let sender = BatchSender::start(max_batch_size, retry, || async { Ok(sink) })
+            .await
+            .unwrap();
+        (sender, sent)
+    }
+
+    fn info(tick: u64, stats: BpfStatsInfo) -> BpfInfo<'static> {
+        BpfInfo {
+            id: 1,
+            name: "test",
+            tick,
+            time_recieved: Duration::ZERO,
+            stats,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_kept_per_meter_and_flushed() {
+        let (mut sender, sent) = start_sender(10, Vec::new()).await;
+        let cpu = |tick| info(tick, BpfStatsInfo::Cpu(BpfCPUStatsInfo::default()));
+        let map = |tick| info(tick, BpfStatsInfo::Map(BpfMapStatsInfo::default()));
+        // Ticks of the meters are counted separately and interleave
+        sender.push(&cpu(0), [1, 2]);
+        sender.push(&map(0), [10]);
+        sender.push(&cpu(0), [3]);
+        sender.push(&map(1), [11]);
+        sender.push(&cpu(1), [4]);
+        sender.flush().unwrap();
+
+        let sent = sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 4);
+        assert_eq!(sent[..2], [vec![10], vec![1, 2, 3]]);
+        // Batches of the last ticks are sent on flush
+        assert!(sent[2..].contains(&vec![4]) && sent[2..].contains(&vec![11]));
+    }
+
+    #[tokio::test]
+    async fn test_large_batches_are_split() {
+        let (mut sender, sent) = start_sender(2, Vec::new()).await;
+        let cpu = info(0, BpfStatsInfo::Cpu(BpfCPUStatsInfo::default()));
+        sender.push(&cpu, [1, 2, 3]);
+        drop(sender);
+        assert_eq!(*sent.lock().unwrap(), [vec![1, 2], vec![3]]);
+    }
+
+    #[tokio::test]
+    async fn test_transient_failures_are_retried() {
+        let failures = vec![SendError::Transient(anyhow!("503 Service Unavailable"))];
+        let (mut sender, sent) = start_sender(10, failures).await;
+        let cpu = info(0, BpfStatsInfo::Cpu(BpfCPUStatsInfo::default()));
+        sender.push(&cpu, [1]);
+        sender.flush().unwrap();
+        assert_eq!(*sent.lock().unwrap(), [vec![1]]);
+
+        let failures = vec![SendError::Permanent(anyhow!("400 Bad Request"))];
+        let (mut sender, sent) = start_sender(10, failures).await;
+        sender.push(&cpu, [1]);
+        sender.flush().unwrap();
+        assert!(sent.lock().unwrap().is_empty());
+    }
+}
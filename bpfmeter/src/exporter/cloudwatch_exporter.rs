@@ -0,0 +1,286 @@
+use std::time::SystemTime;
+
+use anyhow::Result;
+use aws_config::{BehaviorVersion, Region};
+use aws_sdk_cloudwatch::{
+    Client,
+    error::{ProvideErrorMetadata, SdkError},
+    operation::put_metric_data::PutMetricDataError,
+    primitives::DateTime,
+    types::{Dimension, MetricDatum, StandardUnit},
+};
+use log::info;
+
+use crate::exporter::batch_sender::{BatchSender, BatchSink, RetryPolicy, SendError};
+use crate::exporter::prometheus_exporter::Labels;
+use crate::exporter::{BpfStatsInfo, Exporter};
+use crate::meter::BpfInfo;
+
+/// Maximum number of metrics in a single PutMetricData request
+const MAX_BATCH_SIZE: usize = 20;
+
+/// Exports BpfInfo to AWS CloudWatch via PutMetricData API
+pub struct CloudWatchExporter {
+    /// Static labels to be added to all metrics as dimensions
+    static_labels: Labels,
+    /// Sender of batches of metrics to CloudWatch
+    sender: BatchSender<MetricDatum>,
+}
+
+impl CloudWatchExporter {
+    /// Creates a new CloudWatchExporter and starts the thread sending metrics to CloudWatch
+    ///
+    /// # Arguments
+    ///
+    /// * `namespace` - CloudWatch namespace to put metrics to
+    ///
+    /// * `region` - AWS region, region from the environment is used if not specified
+    ///
+    /// * `labels` - Static labels to be added to all metrics as dimensions
+    pub async fn new(namespace: &str, region: Option<&str>, labels: Labels) -> Result<Self> {
+        let namespace = namespace.to_string();
+        let region = region.map(|region| Region::new(region.to_string()));
+        info!("Exporting metrics to CloudWatch namespace: {namespace}");
+        let sender = BatchSender::start(MAX_BATCH_SIZE, RetryPolicy::default(), || async move {
+            let mut config = aws_config::defaults(BehaviorVersion::latest());
+            if let Some(region) = region {
+                config = config.region(region);
+            }
+            Ok(CloudWatchSink {
+                client: Client::new(&config.load().await),
+                namespace,
+            })
+        })
+        .await?;
+
+        Ok(Self {
+            static_labels: labels,
+            sender,
+        })
+    }
+
+    /// Returns metrics of the BpfInfo with static labels and ebpf program/map identity
+    /// as dimensions
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - BpfInfo to build metrics of
+    fn metric_data(&self, data: &BpfInfo) -> Vec<MetricDatum> {
+        let mut dimensions = self
+            .static_labels
+            .iter()
+            .map(|(name, value)| Dimension::builder().name(name).value(value).build())
+            .collect::<Vec<_>>();
+        let mut metrics = Vec::new();
+        match &data.stats {
+            BpfStatsInfo::Cpu(stats) => {
+                dimensions.push(
+                    Dimension::builder()
+                        .name("ebpf_id")
+                        .value(data.id.to_string())
+                        .build(),
+                );
+                dimensions.push(
+                    Dimension::builder()
+                        .name("ebpf_name")
+                        .value(data.name)
+                        .build(),
+                );
+                if let Some(cpu_usage) = stats.exact_cpu_usage {
+                    metrics.push(("ebpf_cpu_usage", cpu_usage as f64, StandardUnit::None));
+                }
+                metrics.push((
+                    "ebpf_run_time",
+                    stats.run_time.as_secs_f64(),
+                    StandardUnit::Seconds,
+                ));
+                metrics.push((
+                    "ebpf_event_count",
+                    stats.run_count as f64,
+                    StandardUnit::Count,
+                ));
+            }
+            BpfStatsInfo::Map(stats) => {
+                dimensions.push(
+                    Dimension::builder()
+                        .name("ebpf_map_id")
+                        .value(data.id.to_string())
+                        .build(),
+                );
+                dimensions.push(
+                    Dimension::builder()
+                        .name("ebpf_map_name")
+                        .value(data.name)
+                        .build(),
+                );
+                dimensions.push(
+                    Dimension::builder()
+                        .name("ebpf_map_max_size")
+                        .value(stats.max_size.to_string())
+                        .build(),
+                );
+                metrics.push(("ebpf_map_size", stats.size as f64, StandardUnit::Count));
+            }
+        }
+        let timestamp = DateTime::from(SystemTime::now());
+        metrics
+            .into_iter()
+            .map(|(name, value, unit)| {
+                MetricDatum::builder()
+                    .metric_name(name)
+                    .value(value)
+                    .unit(unit)
+                    .timestamp(timestamp)
+                    .set_dimensions(Some(dimensions.clone()))
+                    .build()
+            })
+            .collect()
+    }
+}
+
+/// CloudWatch namespace batches of metrics are put to
+struct CloudWatchSink {
+    /// CloudWatch client
+    client: Client,
+    /// CloudWatch namespace to put metrics to
+    namespace: String,
+}
+
+impl BatchSink<MetricDatum> for CloudWatchSink {
+    const NAME: &'static str = "CloudWatch";
+
+    async fn send(&mut self, batch: &[MetricDatum]) -> Result<(), SendError> {
+        self.client
+            .put_metric_data()
+            .namespace(&self.namespace)
+            .set_metric_data(Some(batch.to_vec()))
+            .send()
+            .await
+            .map(|_| ())
+            .map_err(|e| match is_transient(&e) {
+                true => SendError::Transient(e.into()),
+                false => SendError::Permanent(e.into()),
+            })
+    }
+}
+
+/// Returns true if the failed request may succeed when it is sent again: it timed out,
+/// the connection failed, or it was throttled or failed on the server
+///
+/// # Arguments
+///
+/// * `error` - Error of PutMetricData request
+fn is_transient(error: &SdkError<PutMetricDataError>) -> bool {
+    match error {
+        SdkError::TimeoutError(_) => true,
+        SdkError::DispatchFailure(failure) => failure.is_io() || failure.is_timeout(),
+        _ => is_transient_response(
+            error.code(),
+            error
+                .raw_response()
+                .map(|response| response.status().as_u16()),
+        ),
+    }
+}
+
+/// Returns true if the response of the failed request allows sending it again
+///
+/// # Arguments
+///
+/// * `code` - Error code of the response
+///
+/// * `status` - HTTP status of the response
+fn is_transient_response(code: Option<&str>, status: Option<u16>) -> bool {
+    code == Some("Throttling") || status.is_some_and(|status| (500..600).contains(&status))
+}
+
+impl Exporter for CloudWatchExporter {
+    fn export_info(&mut self, data: &BpfInfo) -> Result<()> {
+        let metrics = self.metric_data(data);
+        self.sender.push(data, metrics);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.sender.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::meter::cpu_meter::BpfCPUStatsInfo;
+
+    #[test]
+    fn test_transient_errors() {
+        assert!(is_transient(&SdkError::timeout_error("timed out")));
+        assert!(is_transient_response(Some("Throttling"), Some(400)));
+        assert!(is_transient_response(None, Some(503)));
+        assert!(is_transient_response(Some("InternalFailure"), Some(500)));
+        assert!(!is_transient_response(
+            Some("InvalidParameterValue"),
+            Some(400)
+        ));
+        assert!(!is_transient_response(None, None));
+    }
+
+    #[tokio::test]
+    async fn test_metric_data_of_program() {
+        let sink = CloudWatchSink {
+            client: Client::from_conf(
+                aws_sdk_cloudwatch::Config::builder()
+                    .behavior_version(BehaviorVersion::latest())
+                    .build(),
+            ),
+            namespace: "test".to_string(),
+        };
+        let exporter = CloudWatchExporter {
+            static_labels: vec![("system".to_string(), "bpfmeter".to_string())],
+            sender: BatchSender::start(MAX_BATCH_SIZE, RetryPolicy::default(), || async {
+                Ok(sink)
+            })
+            .await
+            .unwrap(),
+        };
+        let metrics = exporter.metric_data(&BpfInfo {
+            id: 7,
+            name: "xdp_fw",
+            tick: 0,
+            time_recieved: Duration::ZERO,
+            stats: BpfStatsInfo::Cpu(BpfCPUStatsInfo {
+                exact_cpu_usage: Some(0.25),
+                run_time: Duration::from_millis(1500),
+                run_count: 3,
+                ..Default::default()
+            }),
+        });
+
+        let names = metrics
+            .iter()
+            .map(|datum| (datum.metric_name().unwrap(), datum.value().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                ("ebpf_cpu_usage", 0.25),
+                ("ebpf_run_time", 1.5),
+                ("ebpf_event_count", 3.0)
+            ]
+        );
+        let dimensions = metrics[0]
+            .dimensions()
+            .iter()
+            .map(|dimension| (dimension.name().unwrap(), dimension.value().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dimensions,
+            [
+                ("system", "bpfmeter"),
+                ("ebpf_id", "7"),
+                ("ebpf_name", "xdp_fw")
+            ]
+        );
+    }
+}
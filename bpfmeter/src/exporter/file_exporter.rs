@@ -1,19 +1,127 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::Path,
+    time::{Duration, SystemTime},
+};
 
-use crate::{exporter::Exporter, meter::BpfInfo};
+use crate::{
+    capture::{RowTime, is_date, is_rotation_time},
+    exporter::{Exporter, meter_metrics::MeterMetrics},
+    meter::{BpfInfo, BpfStatsInfo, sampling::aggregate},
+};
 use anyhow::{Ok, Result};
-use log::debug;
+use humantime::{format_rfc3339_millis, format_rfc3339_seconds};
+use log::{debug, info, warn};
+use prometheus_client::metrics::counter::Counter;
+
+/// Time zone used to determine the date of daily rotated files
+#[derive(Clone, Copy, Debug)]
+pub enum RotationTimezone {
+    Local,
+    Utc,
+}
+
+/// Limits of a csv file of a program after which a new file is started
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RotationLimits {
+    /// Size of the file in bytes, unlimited if not set
+    pub max_size: Option<u64>,
+    /// Time the file is written to, unlimited if not set
+    pub interval: Option<Duration>,
+}
+
+impl RotationLimits {
+    /// Returns true if files are rotated by size or time
+    fn is_enabled(&self) -> bool {
+        self.max_size.is_some() || self.interval.is_some()
+    }
+}
+
+/// Csv file of a bpf program/map being written
+struct ActiveFile {
+    /// Csv writer of the file
+    writer: csv::Writer<CountingWriter<std::fs::File>>,
+    /// Time the file was started
+    opened: SystemTime,
+}
+
+/// Row of csv file: time of the measurement followed by its stats
+#[derive(serde::Serialize)]
+struct CsvRow<'a>(RowTime, &'a BpfStatsInfo);
 
 /// Exports BpfProgramInfo to file
 pub struct FileExporter {
     /// Period of time between two measurements (ticks)
     period: std::time::Duration,
-    /// Map of bpf program ids to csv writers
-    writers: HashMap<u32, csv::Writer<std::fs::File>>,
+    /// Map of bpf program ids to their current files
+    writers: HashMap<u32, ActiveFile>,
     /// Directory to write the file to
     output_dir: std::path::PathBuf,
     /// Suffix to add to the filenames
     filename_suffix: String,
+    /// Time zone of daily rotation, files are not rotated if not set
+    daily_rotation: Option<RotationTimezone>,
+    /// Date of the current files in daily rotation mode, format: YYYY-MM-DD
+    date: String,
+    /// Counter of bytes written to all files
+    bytes_written: Counter,
+    /// Number of ticks aggregated into a single row
+    aggregate_ticks: u32,
+    /// Map of bpf program ids to time of the last tick and stats of the current aggregation
+    /// window
+    pending: HashMap<u32, (RowTime, Vec<BpfStatsInfo>)>,
+    /// Maximum number of daily rotated files kept for each program, unlimited if not set
+    max_files: Option<usize>,
+    /// Builder of csv writers with the delimiter and header settings of the files
+    writer_builder: csv::WriterBuilder,
+    /// Limits of the size and time of files after which new files are started
+    rotation_limits: RotationLimits,
+}
+
+/// Writer counting the number of bytes written to the inner writer
+pub(crate) struct CountingWriter<W> {
+    /// Inner writer
+    inner: W,
+    /// Counter of bytes written
+    bytes_written: Counter,
+    /// Number of bytes written to the inner writer by this writer
+    written: u64,
+}
+
+impl<W> CountingWriter<W> {
+    /// Creates a new CountingWriter
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - Inner writer
+    ///
+    /// * `bytes_written` - Counter of bytes written
+    pub(crate) fn new(inner: W, bytes_written: Counter) -> Self {
+        Self {
+            inner,
+            bytes_written,
+            written: 0,
+        }
+    }
+
+    /// Returns the number of bytes written to the inner writer by this writer
+    pub(crate) fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes_written.inc_by(written as u64);
+        self.written += written as u64;
+        std::io::Result::Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
 }
 
 impl FileExporter {
@@ -26,13 +134,89 @@ impl FileExporter {
     /// * `suffix` - Suffix to add to the filenames
     ///
     /// * `output_dir` - Directory to write the files to
-    pub fn new(period: std::time::Duration, suffix: &str, output_dir: &Path) -> Self {
+    ///
+    /// * `daily_rotation` - Time zone to include the current date in filenames and start
+    ///   new files at midnight, files are not rotated if not set
+    ///
+    /// * `meter_metrics` - Metrics of bpfmeter itself to count written bytes in
+    ///
+    /// * `aggregate_ticks` - Number of ticks aggregated into a single row, 1 to write every tick
+    ///
+    /// * `max_files` - Maximum number of daily rotated files kept for each program, the oldest
+    ///   ones are deleted. Unlimited if not set
+    ///
+    /// * `writer_builder` - Builder of csv writers, sets the delimiter and the header row
+    ///
+    /// * `rotation_limits` - Size and time of files after which new files are started with
+    ///   the start time in their names, files are not rotated if no limit is set
+    // Arguments are the independent settings of the files
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        period: std::time::Duration,
+        suffix: &str,
+        output_dir: &Path,
+        daily_rotation: Option<RotationTimezone>,
+        meter_metrics: &MeterMetrics,
+        aggregate_ticks: u32,
+        max_files: Option<usize>,
+        writer_builder: csv::WriterBuilder,
+        rotation_limits: RotationLimits,
+    ) -> Self {
         Self {
+            bytes_written: meter_metrics.bytes_written(suffix),
             period,
             writers: HashMap::new(),
             output_dir: output_dir.to_path_buf(),
             filename_suffix: suffix.into(),
+            daily_rotation,
+            date: daily_rotation.map(current_date).unwrap_or_default(),
+            aggregate_ticks: aggregate_ticks.max(1),
+            pending: HashMap::new(),
+            max_files,
+            writer_builder,
+            rotation_limits,
+        }
+    }
+
+    /// Starts new files if the date has changed since the current files were created
+    fn rotate_if_needed(&mut self) {
+        let Some(timezone) = self.daily_rotation else {
+            return;
+        };
+        let date = current_date(timezone);
+        if date == self.date {
+            return;
+        }
+        info!("Rotating measurement files for {date}");
+        if let Err(e) = self.flush() {
+            debug!("Failed to flush writers: {e}");
+        }
+        self.writers.clear();
+        self.date = date;
+    }
+
+    /// Closes the file of the program if it has been written for the rotation interval, a new
+    /// file is started on the next write. Files exceeding the max size are closed on flush,
+    /// when their size is known
+    ///
+    /// # Arguments
+    ///
+    /// * `bpf_id` - Bpf program id
+    fn rotate_file_if_needed(&mut self, bpf_id: u32) -> Result<()> {
+        let Some(file) = self.writers.get_mut(&bpf_id) else {
+            return Ok(());
+        };
+        let now = SystemTime::now();
+        let expired = self.rotation_limits.interval.is_some_and(|interval| {
+            now.duration_since(file.opened).unwrap_or_default() >= interval
+        });
+        if !expired || !can_rotate(file, now) {
+            return Ok(());
         }
+        debug!("Rotating measurement file of {bpf_id}");
+        file.writer.flush()?;
+        self.writers.remove(&bpf_id);
+        Ok(())
     }
 
     /// Adds a new writer to the exporter
@@ -45,34 +229,391 @@ impl FileExporter {
     ///
     /// * `bpf_name` - Bpf program name
     fn add_writer(&mut self, bpf_id: u32, bpf_name: &str) -> Result<()> {
-        let file = self.output_dir.join(format!(
-            "{bpf_id}_{bpf_name}_{}_{:?}.csv",
-            self.filename_suffix, self.period
-        ));
+        // Each row covers the whole aggregation window
+        let row_period = self.period * self.aggregate_ticks;
+        let opened = SystemTime::now();
+        let file = if self.daily_rotation.is_some() {
+            self.output_dir.join(format!(
+                "{bpf_id}_{bpf_name}_{}_{}_{row_period:?}.csv",
+                self.filename_suffix, self.date
+            ))
+        } else if self.rotation_limits.is_enabled() {
+            self.output_dir.join(format!(
+                "{bpf_id}_{bpf_name}_{}_{}_{row_period:?}.csv",
+                self.filename_suffix,
+                rotation_time(opened)
+            ))
+        } else {
+            self.output_dir.join(format!(
+                "{bpf_id}_{bpf_name}_{}_{row_period:?}.csv",
+                self.filename_suffix
+            ))
+        };
         debug!("Writing measurements to file: {file:?}");
-        let writer = csv::Writer::from_path(file)?;
-        self.writers.insert(bpf_id, writer);
+        let writer = self.writer_builder.from_writer(CountingWriter::new(
+            std::fs::File::create(file)?,
+            self.bytes_written.clone(),
+        ));
+        self.writers.insert(bpf_id, ActiveFile { writer, opened });
+        if self.daily_rotation.is_some() || self.rotation_limits.is_enabled() {
+            self.remove_old_files(
+                &format!("{bpf_id}_{bpf_name}_{}_", self.filename_suffix),
+                &format!("_{row_period:?}.csv"),
+            );
+        }
         Ok(())
     }
+
+    /// Deletes the oldest rotated files of the program exceeding the maximum number of files,
+    /// files of previous runs are counted too
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - Beginning of the file names of the program before the date or start time
+    ///
+    /// * `suffix` - End of the file names of the program after the date or start time
+    fn remove_old_files(&self, prefix: &str, suffix: &str) {
+        let Some(max_files) = self.max_files else {
+            return;
+        };
+        let std::result::Result::Ok(entries) = self.output_dir.read_dir() else {
+            return;
+        };
+        let mut files = entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| name.strip_prefix(prefix)?.strip_suffix(suffix))
+                    .is_some_and(|date| is_date(date) || is_rotation_time(date))
+            })
+            .collect::<Vec<_>>();
+        // Dates and start times in the names sort files chronologically
+        files.sort();
+        let excess = files.len().saturating_sub(max_files);
+        for file in &files[..excess] {
+            match std::fs::remove_file(file) {
+                std::result::Result::Ok(()) => {
+                    info!("Removed old measurement file {}", file.display())
+                }
+                Err(e) => warn!(
+                    "Failed to remove old measurement file {}: {e}",
+                    file.display()
+                ),
+            }
+        }
+    }
 }
 
 impl Drop for FileExporter {
     fn drop(&mut self) {
-        for writer in self.writers.values_mut() {
-            if let Err(e) = writer.flush() {
-                debug!("Failed to flush writer: {e}");
+        // Write incomplete aggregation windows, writers of their programs already exist
+        for (id, (time, window)) in std::mem::take(&mut self.pending) {
+            if let (Some(file), Some(stats)) = (self.writers.get_mut(&id), aggregate(&window))
+                && let Err(e) = file.writer.serialize(CsvRow(time, &stats))
+            {
+                debug!("Failed to write aggregated stats: {e}");
             }
         }
+        if let Err(e) = self.flush() {
+            debug!("Failed to flush writers: {e}");
+        }
     }
 }
 
 impl Exporter for FileExporter {
     fn export_info(&mut self, info: &BpfInfo) -> Result<()> {
+        self.rotate_if_needed();
+        self.rotate_file_if_needed(info.id)?;
         if !self.writers.contains_key(&info.id) {
             self.add_writer(info.id, info.name)?;
         }
-        let writer: &mut csv::Writer<std::fs::File> = self.writers.get_mut(&info.id).unwrap();
-        writer.serialize(&info.stats)?;
+
+        let time = RowTime {
+            tick: info.tick,
+            time_recieved: info.time_recieved,
+        };
+        let stats = if self.aggregate_ticks > 1 {
+            // Rows of aggregation windows have the time of their last tick
+            let (window_time, window) = self.pending.entry(info.id).or_default();
+            *window_time = time;
+            window.push(info.stats.clone());
+            if window.len() < self.aggregate_ticks as usize {
+                return Ok(());
+            }
+            let stats = aggregate(window);
+            window.clear();
+            stats
+        } else {
+            Some(info.stats.clone())
+        };
+
+        if let Some(stats) = stats {
+            let file = self.writers.get_mut(&info.id).unwrap();
+            file.writer.serialize(CsvRow(time, &stats))?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for file in self.writers.values_mut() {
+            file.writer.flush()?;
+        }
+        // Buffered rows are counted in the size of a file once it is flushed, full files are
+        // closed and new ones are started on the next write
+        if let Some(max_size) = self.rotation_limits.max_size {
+            let now = SystemTime::now();
+            self.writers.retain(|bpf_id, file| {
+                let full = file.writer.get_ref().written() >= max_size && can_rotate(file, now);
+                if full {
+                    debug!("Rotating measurement file of {bpf_id}");
+                }
+                !full
+            });
+        }
         Ok(())
     }
 }
+
+/// Returns the current date in the time zone, format: YYYY-MM-DD
+///
+/// # Arguments
+///
+/// * `timezone` - Time zone of the date
+fn current_date(timezone: RotationTimezone) -> String {
+    match timezone {
+        RotationTimezone::Utc => {
+            format_rfc3339_seconds(SystemTime::now()).to_string()[..10].to_string()
+        }
+        RotationTimezone::Local => {
+            let now = unsafe { libc::time(std::ptr::null_mut()) };
+            let mut tm = unsafe { std::mem::zeroed::<libc::tm>() };
+            unsafe { libc::localtime_r(&now, &mut tm) };
+            format!(
+                "{:04}-{:02}-{:02}",
+                tm.tm_year + 1900,
+                tm.tm_mon + 1,
+                tm.tm_mday
+            )
+        }
+    }
+}
+
+/// Returns true if a new file started now has a different name than the current file.
+/// Files started in the same millisecond would have the same name, the rotation is
+/// postponed then
+///
+/// # Arguments
+///
+/// * `file` - Current file of the program
+///
+/// * `now` - Current time
+fn can_rotate(file: &ActiveFile, now: SystemTime) -> bool {
+    rotation_time(now) != rotation_time(file.opened)
+}
+
+/// Returns the start time of a file rotated by size or time in UTC,
+/// format: YYYY-MM-DDTHH-MM-SS.mmmZ
+///
+/// # Arguments
+///
+/// * `time` - Start time of the file
+fn rotation_time(time: SystemTime) -> String {
+    format_rfc3339_millis(time).to_string().replace(':', "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::meter::{cpu_meter::BpfCPUStatsInfo, map_meter::BpfMapStatsInfo};
+
+    fn cpu_stats(exact_cpu_usage: Option<f32>, run_count: u64) -> BpfStatsInfo {
+        BpfStatsInfo::Cpu(BpfCPUStatsInfo {
+            exact_cpu_usage,
+            run_time: Duration::from_millis(run_count),
+            run_count,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_aggregate_cpu_stats() {
+        let window = [
+            cpu_stats(None, 10),
+            cpu_stats(Some(0.1), 20),
+            cpu_stats(Some(0.3), 30),
+        ];
+        let Some(BpfStatsInfo::Cpu(stats)) = aggregate(&window) else {
+            panic!("Expected cpu stats");
+        };
+        assert!((stats.exact_cpu_usage.unwrap() - 0.2).abs() < 1e-6);
+        assert_eq!(stats.run_count, 30);
+        assert_eq!(stats.run_time, Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_oldest_rotated_files_are_removed() {
+        let output_dir =
+            std::env::temp_dir().join(format!("bpfmeter_rotation_{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let files = [
+            "1_xdp_prog_2026-01-01_30s.csv",
+            "1_xdp_prog_2026-01-02_30s.csv",
+            "1_xdp_prog_2026-01-03_30s.csv",
+            "1_xdp_prog_2026-01-03T10-00-00.000Z_30s.csv",
+            "12_xdp_prog_2026-01-01_30s.csv",
+        ];
+        for file in files {
+            std::fs::write(output_dir.join(file), "").unwrap();
+        }
+        let exporter = FileExporter::new(
+            Duration::from_secs(30),
+            "prog",
+            &output_dir,
+            Some(RotationTimezone::Utc),
+            &MeterMetrics::default(),
+            1,
+            Some(2),
+            csv::WriterBuilder::new(),
+            RotationLimits::default(),
+        );
+        exporter.remove_old_files("1_xdp_prog_", "_30s.csv");
+
+        let mut remaining = std::fs::read_dir(&output_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            [
+                "12_xdp_prog_2026-01-01_30s.csv",
+                "1_xdp_prog_2026-01-03T10-00-00.000Z_30s.csv",
+                "1_xdp_prog_2026-01-03_30s.csv",
+            ]
+        );
+        std::fs::remove_dir_all(output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_csv_delimiter_and_header() {
+        let output_dir =
+            std::env::temp_dir().join(format!("bpfmeter_delimiter_{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let mut writer_builder = csv::WriterBuilder::new();
+        writer_builder.delimiter(b';').has_headers(false);
+        let mut exporter = FileExporter::new(
+            Duration::from_secs(1),
+            "map",
+            &output_dir,
+            None,
+            &MeterMetrics::default(),
+            1,
+            None,
+            writer_builder,
+            RotationLimits::default(),
+        );
+        exporter
+            .export_info(&BpfInfo {
+                id: 1,
+                name: "sizes",
+                tick: 0,
+                time_recieved: Duration::ZERO,
+                stats: BpfStatsInfo::Map(BpfMapStatsInfo {
+                    max_size: 16,
+                    size: 4,
+                    fill_ratio: Some(0.25),
+                    ..Default::default()
+                }),
+            })
+            .unwrap();
+        drop(exporter);
+
+        let content = std::fs::read_to_string(output_dir.join("1_sizes_map_1s.csv")).unwrap();
+        std::fs::remove_dir_all(output_dir).unwrap();
+        assert_eq!(content, "0;0;4;0.25\n");
+    }
+
+    #[test]
+    fn test_files_are_rotated_by_size() {
+        let output_dir =
+            std::env::temp_dir().join(format!("bpfmeter_size_rotation_{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let mut exporter = FileExporter::new(
+            Duration::from_secs(1),
+            "prog",
+            &output_dir,
+            None,
+            &MeterMetrics::default(),
+            1,
+            None,
+            csv::WriterBuilder::new(),
+            RotationLimits {
+                max_size: Some(90),
+                interval: None,
+            },
+        );
+        for run_count in 1..=3 {
+            exporter
+                .export_info(&BpfInfo {
+                    id: 1,
+                    name: "xdp",
+                    tick: run_count,
+                    time_recieved: Duration::from_secs(run_count),
+                    stats: cpu_stats(Some(0.5), run_count),
+                })
+                .unwrap();
+            exporter.flush().unwrap();
+            // Files started in the same millisecond are not rotated
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        drop(exporter);
+
+        let mut files = std::fs::read_dir(&output_dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        let contents = files
+            .iter()
+            .map(|file| std::fs::read_to_string(file).unwrap())
+            .collect::<Vec<_>>();
+        std::fs::remove_dir_all(output_dir).unwrap();
+        // The first file reaches the size after two rows, every file starts with the header
+        assert_eq!(
+            contents,
+            [
+                "tick,time_recieved,exact_cpu_usage,run_time,run_count,peak_cpu_usage\n\
+                 1,1,0.5,0.001,1,\n\
+                 2,2,0.5,0.002,2,\n",
+                "tick,time_recieved,exact_cpu_usage,run_time,run_count,peak_cpu_usage\n\
+                 3,3,0.5,0.003,3,\n",
+            ]
+        );
+        let file_stem = files[0].file_stem().unwrap().to_string_lossy();
+        let file_name = crate::capture::FileName::parse(&file_stem).unwrap();
+        assert_eq!((file_name.id, file_name.period), (1, "1s"));
+        assert!(file_name.date.is_some());
+    }
+
+    #[test]
+    fn test_aggregate_map_stats() {
+        let window = [3, 4, 8].map(|size| {
+            BpfStatsInfo::Map(BpfMapStatsInfo {
+                max_size: 16,
+                size,
+                ..Default::default()
+            })
+        });
+        let Some(BpfStatsInfo::Map(stats)) = aggregate(&window) else {
+            panic!("Expected map stats");
+        };
+        assert_eq!(stats.size, 5);
+        assert_eq!(stats.max_size, 16);
+        // Fill ratio follows the averaged size
+        assert_eq!(stats.fill_ratio, Some(5.0 / 16.0));
+    }
+}
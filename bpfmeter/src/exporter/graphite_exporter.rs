@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+use crate::exporter::batch_sender::{BatchSender, BatchSink, RetryPolicy, SendError};
+use crate::exporter::prometheus_exporter::Labels;
+use crate::exporter::{BpfStatsInfo, Exporter};
+use crate::meter::BpfInfo;
+
+/// Maximum number of lines buffered while Graphite is unreachable, the oldest lines are dropped
+const MAX_BUFFERED_LINES: usize = 100_000;
+
+/// Exports BpfInfo to Graphite via plaintext protocol
+pub struct GraphiteExporter {
+    /// Prefix of all metric paths
+    prefix: String,
+    /// Tags added to all metric paths, built from static labels
+    tags: String,
+    /// Sender of batches of lines to Graphite
+    sender: BatchSender<String>,
+}
+
+impl GraphiteExporter {
+    /// Creates a new GraphiteExporter and starts the thread sending lines to Graphite
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Address of Graphite plaintext receiver, e.g. 127.0.0.1:2003
+    ///
+    /// * `prefix` - Prefix of all metric paths
+    ///
+    /// * `labels` - Static labels to be added to all metric paths as tags
+    pub async fn new(addr: &str, prefix: &str, labels: Labels) -> Result<Self> {
+        info!("Exporting metrics to Graphite at {addr}");
+        let addr = addr.to_string();
+        // Lines are buffered by the sink until Graphite is reachable, so they are not retried
+        let retry = RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        };
+        let sender = BatchSender::start(MAX_BUFFERED_LINES, retry, || async move {
+            Ok(GraphiteSink::new(addr))
+        })
+        .await?;
+
+        Ok(Self {
+            prefix: prefix.to_string(),
+            tags: labels
+                .iter()
+                .map(|(name, value)| format!(";{name}={value}"))
+                .collect(),
+            sender,
+        })
+    }
+
+    /// Returns lines with the values of the metrics of the BpfInfo
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - BpfInfo to build lines of
+    fn lines(&self, data: &BpfInfo) -> Vec<String> {
+        let mut metrics = Vec::new();
+        match &data.stats {
+            BpfStatsInfo::Cpu(stats) => {
+                if let Some(cpu_usage) = stats.exact_cpu_usage {
+                    metrics.push(("cpu_usage", cpu_usage as f64));
+                }
+                metrics.push(("run_time", stats.run_time.as_secs_f64()));
+                metrics.push(("event_count", stats.run_count as f64));
+            }
+            BpfStatsInfo::Map(stats) => {
+                metrics.push(("map_size", stats.size as f64));
+            }
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        metrics
+            .into_iter()
+            .map(|(metric, value)| {
+                let path = metric_path(&self.prefix, data.name, data.id, metric);
+                format!("{path}{} {value} {timestamp}\n", self.tags)
+            })
+            .collect()
+    }
+}
+
+/// Builds Graphite metric path, characters of the name that are not allowed in a path
+/// component (including dots separating the hierarchy) are replaced with underscores
+///
+/// # Arguments
+///
+/// * `prefix` - Prefix of the path
+///
+/// * `name` - Name of ebpf program/map
+///
+/// * `id` - Ebpf program/map id, distinguishes programs/maps with the same name
+///
+/// * `metric` - Metric name
+fn metric_path(prefix: &str, name: &str, id: u32, metric: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if prefix.is_empty() {
+        format!("{name}.{id}.{metric}")
+    } else {
+        format!("{prefix}.{name}.{id}.{metric}")
+    }
+}
+
+/// Graphite receiver batches of lines are written to. Lines are buffered while Graphite
+/// is unreachable and sent after reconnection
+struct GraphiteSink {
+    /// Address of Graphite plaintext receiver
+    addr: String,
+    /// Connection to Graphite, empty until connected or after the connection is lost
+    stream: Option<TcpStream>,
+    /// Lines waiting to be written
+    buffer: VecDeque<String>,
+}
+
+impl GraphiteSink {
+    /// Creates a new GraphiteSink, it connects to Graphite on the first batch
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - Address of Graphite plaintext receiver
+    fn new(addr: String) -> Self {
+        Self {
+            addr,
+            stream: None,
+            buffer: VecDeque::new(),
+        }
+    }
+}
+
+impl BatchSink<String> for GraphiteSink {
+    const NAME: &'static str = "Graphite";
+
+    async fn send(&mut self, batch: &[String]) -> Result<(), SendError> {
+        self.buffer.extend(batch.iter().cloned());
+        if self.buffer.len() > MAX_BUFFERED_LINES {
+            let dropped = self.buffer.len() - MAX_BUFFERED_LINES;
+            self.buffer.drain(..dropped);
+            warn!("Graphite buffer is full, {dropped} oldest lines are dropped");
+        }
+
+        if self.stream.is_none() {
+            match TcpStream::connect(&self.addr).await {
+                Ok(connected) => {
+                    info!("Connected to Graphite at {}", self.addr);
+                    self.stream = Some(connected);
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to connect to Graphite at {}, {} lines are buffered: {e}",
+                        self.addr,
+                        self.buffer.len()
+                    );
+                    return Ok(());
+                }
+            }
+        }
+
+        let Some(connected) = self.stream.as_mut() else {
+            return Ok(());
+        };
+        let payload = self.buffer.iter().map(String::as_str).collect::<String>();
+        match connected.write_all(payload.as_bytes()).await {
+            Ok(()) => {
+                debug!("Sent {} lines to Graphite", self.buffer.len());
+                self.buffer.clear();
+            }
+            Err(e) => {
+                // Lines are sent again after reconnection, Graphite overwrites
+                // the values with the same timestamp
+                warn!("Graphite connection is lost, reconnecting on the next batch: {e}");
+                self.stream = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Exporter for GraphiteExporter {
+    fn export_info(&mut self, data: &BpfInfo) -> Result<()> {
+        let lines = self.lines(data);
+        self.sender.push(data, lines);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.sender.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metric_path_sanitizes_name() {
+        assert_eq!(
+            metric_path("bpfmeter", "tc.ingress prog", 7, "cpu_usage"),
+            "bpfmeter.tc_ingress_prog.7.cpu_usage"
+        );
+        assert_eq!(
+            metric_path("", "xdp-fw", 8, "run_time"),
+            "xdp-fw.8.run_time"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lines_are_buffered_until_reconnection() {
+        use tokio::io::AsyncReadExt;
+
+        // Reserve a free port, nothing is listening on it until the listener is bound again
+        let addr = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut sink = GraphiteSink::new(addr.to_string());
+
+        // The sink fails to connect before Graphite is started
+        assert!(sink.send(&["a.cpu_usage 1 10\n".to_string()]).await.is_ok());
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        assert!(sink.send(&["a.cpu_usage 2 20\n".to_string()]).await.is_ok());
+        drop(sink);
+
+        let (mut connection, _) = listener.accept().await.unwrap();
+        let mut received = String::new();
+        connection.read_to_string(&mut received).await.unwrap();
+        assert_eq!(received, "a.cpu_usage 1 10\na.cpu_usage 2 20\n");
+    }
+}
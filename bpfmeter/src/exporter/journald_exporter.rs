@@ -0,0 +1,125 @@
+use std::os::unix::net::UnixDatagram;
+
+use anyhow::{Context, Result};
+use log::info;
+
+use crate::exporter::{BpfStatsInfo, Exporter};
+use crate::meter::BpfInfo;
+
+/// Socket of the native protocol of systemd journal, the one used by sd_journal_send
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Priority of journal entries, informational
+const PRIORITY_INFO: &str = "6";
+
+/// Exports BpfInfo to systemd journal as entries with structured fields,
+/// e.g. `journalctl SYSLOG_IDENTIFIER=bpfmeter EBPF_NAME=xdp_fw`
+pub struct JournaldExporter {
+    /// Socket connected to the journal
+    socket: UnixDatagram,
+}
+
+impl JournaldExporter {
+    /// Creates a new JournaldExporter connected to systemd journal
+    pub fn new() -> Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect(JOURNAL_SOCKET)
+            .with_context(|| format!("Failed to connect to systemd journal at {JOURNAL_SOCKET}"))?;
+        info!("Exporting metrics to systemd journal");
+        Ok(Self { socket })
+    }
+}
+
+/// Builds journal fields of the ebpf program/map stats
+///
+/// # Arguments
+///
+/// * `data` - BpfInfo to build fields of
+fn entry_fields(data: &BpfInfo) -> Vec<(&'static str, String)> {
+    let mut fields = vec![
+        ("PRIORITY", PRIORITY_INFO.to_string()),
+        ("SYSLOG_IDENTIFIER", "bpfmeter".to_string()),
+        ("EBPF_ID", data.id.to_string()),
+        ("EBPF_NAME", data.name.to_string()),
+        ("EBPF_TICK", data.tick.to_string()),
+    ];
+    match &data.stats {
+        BpfStatsInfo::Cpu(stats) => {
+            fields.push(("EBPF_KIND", "prog".to_string()));
+            let mut message = format!("ebpf program {} {}:", data.id, data.name);
+            if let Some(cpu_usage) = stats.exact_cpu_usage {
+                fields.push(("CPU_USAGE", cpu_usage.to_string()));
+                message.push_str(&format!(" cpu usage {cpu_usage},"));
+            }
+            fields.push(("RUN_TIME", stats.run_time.as_secs_f64().to_string()));
+            fields.push(("RUN_COUNT", stats.run_count.to_string()));
+            message.push_str(&format!(
+                " run time {:?}, run count {}",
+                stats.run_time, stats.run_count
+            ));
+            fields.push(("MESSAGE", message));
+        }
+        BpfStatsInfo::Map(stats) => {
+            fields.push(("EBPF_KIND", "map".to_string()));
+            fields.push(("MAP_SIZE", stats.size.to_string()));
+            fields.push(("MAP_MAX_SIZE", stats.max_size.to_string()));
+            fields.push((
+                "MESSAGE",
+                format!(
+                    "ebpf map {} {}: size {} of {}",
+                    data.id, data.name, stats.size, stats.max_size
+                ),
+            ));
+        }
+    }
+    fields
+}
+
+/// Encodes fields into a datagram of the journal native protocol
+///
+/// # Arguments
+///
+/// * `fields` - Names and values of the fields
+fn encode_entry(fields: &[(&str, String)]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    for (name, value) in fields {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            // Values with newlines are prefixed with their length instead of '='
+            entry.push(b'\n');
+            entry.extend((value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+    entry
+}
+
+impl Exporter for JournaldExporter {
+    fn export_info(&mut self, data: &BpfInfo) -> Result<()> {
+        self.socket
+            .send(&encode_entry(&entry_fields(data)))
+            .with_context(|| "Failed to write to systemd journal")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_entry() {
+        let fields = [
+            ("EBPF_NAME", "xdp_fw".to_string()),
+            ("MESSAGE", "a\nb".to_string()),
+        ];
+        let mut expected = b"EBPF_NAME=xdp_fw\nMESSAGE\n".to_vec();
+        expected.extend(3u64.to_le_bytes());
+        expected.extend(b"a\nb\n");
+        assert_eq!(encode_entry(&fields), expected);
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    collections::HashMap,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    exporter::{Exporter, file_exporter::CountingWriter, meter_metrics::MeterMetrics},
+    meter::BpfInfo,
+};
+use anyhow::{Ok, Result};
+use log::debug;
+use prometheus_client::metrics::counter::Counter;
+
+/// Exports BpfInfo to JSON Lines files, one JSON object per tick
+pub struct JsonLinesExporter {
+    /// Period of time between two measurements (ticks)
+    period: std::time::Duration,
+    /// Map of bpf program ids to file writers
+    writers: HashMap<u32, BufWriter<CountingWriter<std::fs::File>>>,
+    /// Directory to write the files to
+    output_dir: std::path::PathBuf,
+    /// Suffix to add to the filenames
+    filename_suffix: String,
+    /// Counter of bytes written to all files
+    bytes_written: Counter,
+}
+
+impl JsonLinesExporter {
+    /// Creates a new JsonLinesExporter
+    ///
+    /// # Arguments
+    ///
+    /// * `period` - Period of time between two measurements (ticks)
+    ///
+    /// * `suffix` - Suffix to add to the filenames
+    ///
+    /// * `output_dir` - Directory to write the files to
+    ///
+    /// * `meter_metrics` - Metrics of bpfmeter itself to count written bytes in
+    pub fn new(
+        period: std::time::Duration,
+        suffix: &str,
+        output_dir: &Path,
+        meter_metrics: &MeterMetrics,
+    ) -> Self {
+        Self {
+            bytes_written: meter_metrics.bytes_written(suffix),
+            period,
+            writers: HashMap::new(),
+            output_dir: output_dir.to_path_buf(),
+            filename_suffix: suffix.into(),
+        }
+    }
+
+    /// Adds a new writer to the exporter
+    ///
+    /// # Arguments
+    ///
+    /// * `bpf_id` - Bpf program id
+    ///
+    /// * `bpf_name` - Bpf program name
+    fn add_writer(&mut self, bpf_id: u32, bpf_name: &str) -> Result<()> {
+        let file = self.output_dir.join(format!(
+            "{bpf_id}_{bpf_name}_{}_{:?}.jsonl",
+            self.filename_suffix, self.period
+        ));
+        debug!("Writing measurements to file: {file:?}");
+        let writer = BufWriter::new(CountingWriter::new(
+            std::fs::File::create(file)?,
+            self.bytes_written.clone(),
+        ));
+        self.writers.insert(bpf_id, writer);
+        Ok(())
+    }
+}
+
+impl Drop for JsonLinesExporter {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            debug!("Failed to flush writers: {e}");
+        }
+    }
+}
+
+impl Exporter for JsonLinesExporter {
+    fn export_info(&mut self, info: &BpfInfo) -> Result<()> {
+        if !self.writers.contains_key(&info.id) {
+            self.add_writer(info.id, info.name)?;
+        }
+        let writer = self.writers.get_mut(&info.id).unwrap();
+        serde_json::to_writer(&mut *writer, info)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for writer in self.writers.values_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::meter::{BpfStatsInfo, cpu_meter::BpfCPUStatsInfo};
+
+    #[test]
+    fn test_lines_contain_program_identity() {
+        let output_dir =
+            std::env::temp_dir().join(format!("bpfmeter_jsonl_{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let mut exporter = JsonLinesExporter::new(
+            Duration::from_secs(1),
+            "prog",
+            &output_dir,
+            &MeterMetrics::default(),
+        );
+        for tick in 1..=2 {
+            let info = BpfInfo {
+                id: 7,
+                name: "xdp_fw",
+                tick,
+                time_recieved: Duration::ZERO,
+                stats: BpfStatsInfo::Cpu(BpfCPUStatsInfo {
+                    exact_cpu_usage: Some(0.5),
+                    run_time: Duration::from_millis(250),
+                    run_count: 10,
+                    peak_cpu_usage: Some(0.5),
+                    ..Default::default()
+                }),
+            };
+            exporter.export_info(&info).unwrap();
+        }
+        drop(exporter);
+
+        let file = output_dir.join("7_xdp_fw_prog_1s.jsonl");
+        let content = std::fs::read_to_string(&file).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                r#"{"id":7,"name":"xdp_fw","tick":1,"exact_cpu_usage":0.5,"run_time":"0.25","run_count":10,"peak_cpu_usage":0.5}"#,
+                r#"{"id":7,"name":"xdp_fw","tick":2,"exact_cpu_usage":0.5,"run_time":"0.25","run_count":10,"peak_cpu_usage":0.5}"#,
+            ]
+        );
+        std::fs::remove_dir_all(output_dir).unwrap();
+    }
+}
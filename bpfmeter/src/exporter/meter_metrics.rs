@@ -0,0 +1,207 @@
+use std::sync::atomic::AtomicU64;
+
+use prometheus_client::{
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
+    registry::Registry,
+};
+
+use crate::exporter::prometheus_exporter::{Labels, MetadataLookup, register_metric};
+
+/// Metrics describing bpfmeter itself and the host rather than individual ebpf programs/maps.
+///
+/// Metrics are shared between clones, so meters can update them from the monitoring task
+/// while the prometheus exporter serves them.
+#[derive(Debug, Default, Clone)]
+pub struct MeterMetrics {
+    /// Static labels to be added to all metrics
+    static_labels: Labels,
+    /// Number of ebpf programs/maps excluded by each filter on the last tick
+    filtered_programs: Family<Labels, Gauge>,
+    /// Number of ticks where collecting stats took longer than the measurement period
+    period_overruns: Family<Labels, Counter>,
+    /// Number of bytes written by file exporters
+    bytes_written: Family<Labels, Counter>,
+    /// Fraction of intended ebpf programs/maps measured on the last tick
+    collection_completeness: Family<Labels, Gauge<f64, AtomicU64>>,
+    /// Total memory locked by all loaded ebpf programs and maps on the host
+    total_locked_memory: Family<Labels, Gauge>,
+    /// Memory used by all loaded ebpf programs and maps on the host by kind
+    memory: Family<Labels, Gauge>,
+}
+
+impl MeterMetrics {
+    /// Creates a new MeterMetrics
+    ///
+    /// # Arguments
+    ///
+    /// * `static_labels` - Static labels to be added to all metrics
+    pub fn new(static_labels: Labels) -> Self {
+        Self {
+            static_labels,
+            ..Default::default()
+        }
+    }
+
+    /// Registers metrics in prometheus registry
+    ///
+    /// # Arguments
+    ///
+    /// * `registry` - Registry to register metrics in
+    ///
+    /// * `metadata` - Overrides of help texts and units of metrics
+    pub fn register(&self, registry: &mut Registry, metadata: &mut MetadataLookup) {
+        register_metric(
+            registry,
+            metadata,
+            "ebpf_meter_filtered_programs",
+            "Number of ebpf programs/maps excluded by each filter on the last tick",
+            self.filtered_programs.clone(),
+        );
+        register_metric(
+            registry,
+            metadata,
+            "ebpf_meter_period_overrun",
+            "Number of ticks where collecting stats took longer than the measurement period",
+            self.period_overruns.clone(),
+        );
+        register_metric(
+            registry,
+            metadata,
+            "ebpf_meter_bytes_written",
+            "Number of bytes written by file exporters",
+            self.bytes_written.clone(),
+        );
+        register_metric(
+            registry,
+            metadata,
+            "ebpf_meter_collection_completeness",
+            "Fraction of intended ebpf programs/maps measured on the last tick",
+            self.collection_completeness.clone(),
+        );
+        register_metric(
+            registry,
+            metadata,
+            "ebpf_total_locked_memory_bytes",
+            "Total memory locked by all loaded ebpf programs and maps on the host",
+            self.total_locked_memory.clone(),
+        );
+        register_metric(
+            registry,
+            metadata,
+            "ebpf_memory_bytes",
+            "Memory used by all loaded ebpf programs and maps on the host by kind",
+            self.memory.clone(),
+        );
+    }
+
+    /// Sets the number of ebpf programs/maps excluded by the filter
+    ///
+    /// # Arguments
+    ///
+    /// * `meter` - Name of the meter
+    ///
+    /// * `reason` - Name of the filter
+    ///
+    /// * `count` - Number of excluded programs/maps
+    pub fn set_filtered(&self, meter: &str, reason: &str, count: u64) {
+        let mut labels = self.meter_labels(meter);
+        labels.push(("reason".to_string(), reason.to_string()));
+        self.filtered_programs
+            .get_or_create(&labels)
+            .set(count as i64);
+    }
+
+    /// Labels of metrics describing the meter
+    ///
+    /// # Arguments
+    ///
+    /// * `meter` - Name of the meter
+    fn meter_labels(&self, meter: &str) -> Labels {
+        let mut labels = self.static_labels.clone();
+        labels.push(("meter".to_string(), meter.to_string()));
+        labels
+    }
+
+    /// Increments the number of ticks where collecting stats took longer than the period
+    ///
+    /// # Arguments
+    ///
+    /// * `meter` - Name of the meter
+    pub fn inc_period_overrun(&self, meter: &str) {
+        self.period_overruns
+            .get_or_create(&self.meter_labels(meter))
+            .inc();
+    }
+
+    /// Sets the fraction of intended ebpf programs/maps measured on the last tick
+    ///
+    /// # Arguments
+    ///
+    /// * `meter` - Name of the meter
+    ///
+    /// * `completeness` - Fraction of measured programs/maps from 0 to 1
+    pub fn set_completeness(&self, meter: &str, completeness: f64) {
+        self.collection_completeness
+            .get_or_create(&self.meter_labels(meter))
+            .set(completeness);
+    }
+
+    /// Sets the total memory locked by all loaded ebpf programs and maps
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - Locked memory in bytes
+    pub fn set_total_locked_memory(&self, bytes: u64) {
+        self.total_locked_memory
+            .get_or_create(&self.static_labels)
+            .set(bytes as i64);
+    }
+
+    /// Sets the memory of the kind used by all loaded ebpf programs and maps
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - Kind of the memory: program, program_jited, map or percpu_map
+    ///
+    /// * `bytes` - Memory in bytes
+    pub fn set_memory(&self, kind: &str, bytes: u64) {
+        let mut labels = self.static_labels.clone();
+        labels.push(("kind".to_string(), kind.to_string()));
+        self.memory.get_or_create(&labels).set(bytes as i64);
+    }
+
+    /// Returns the counter of bytes written by file exporter
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - Kind of the files written by exporter: prog or map
+    pub fn bytes_written(&self, kind: &str) -> Counter {
+        let mut labels = self.static_labels.clone();
+        labels.push(("kind".to_string(), kind.to_string()));
+        self.bytes_written.get_or_create(&labels).clone()
+    }
+
+    /// Returns the number of ticks where collecting stats took longer than the period
+    ///
+    /// # Arguments
+    ///
+    /// * `meter` - Name of the meter
+    #[cfg(test)]
+    pub fn period_overruns(&self, meter: &str) -> u64 {
+        self.period_overruns
+            .get_or_create(&self.meter_labels(meter))
+            .get()
+    }
+
+    /// Returns the fraction of intended ebpf programs/maps measured on the last tick
+    ///
+    /// # Arguments
+    ///
+    /// * `meter` - Name of the meter
+    #[cfg(test)]
+    pub fn completeness(&self, meter: &str) -> f64 {
+        self.collection_completeness
+            .get_or_create(&self.meter_labels(meter))
+            .get()
+    }
+}
@@ -1,6 +1,24 @@
+pub mod batch_sender;
+#[cfg(feature = "cloudwatch")]
+pub mod cloudwatch_exporter;
 pub mod file_exporter;
+pub mod graphite_exporter;
+#[cfg(feature = "journald")]
+pub mod journald_exporter;
+pub mod jsonl_exporter;
+pub mod meter_metrics;
+pub mod on_change_exporter;
+pub mod otlp_exporter;
 pub mod prometheus_exporter;
 pub mod prometheus_gc;
+pub mod pushgateway;
+pub mod rate_gate_exporter;
+pub mod raw_exporter;
+#[cfg(feature = "redis")]
+pub mod redis_exporter;
+pub mod remote_write;
+pub mod table_exporter;
+pub mod tee_exporter;
 
 use anyhow::Result;
 
@@ -15,4 +33,20 @@ pub trait Exporter {
     ///
     /// * `data` - BpfProgramInfo to export
     fn export_info(&mut self, data: &BpfInfo) -> Result<()>;
+
+    /// Removes everything exported for the ebpf program/map, when it stops being exported.
+    /// Does nothing for storages where exported stats are not kept as current values
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Last BpfProgramInfo of the program/map
+    fn remove_info(&mut self, _data: &BpfInfo) -> Result<()> {
+        Ok(())
+    }
+
+    /// Writes out stats buffered by the exporter, so they are not lost if the process
+    /// exits before the exporter is dropped. Does nothing for unbuffered storages
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
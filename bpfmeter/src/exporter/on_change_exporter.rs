@@ -0,0 +1,125 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::{
+    exporter::Exporter,
+    meter::{BpfInfo, BpfStatsInfo},
+};
+
+/// Forwards BpfInfo to exporter only if the stats have changed since the last export of
+/// the same program/map or the heartbeat interval has passed
+pub struct OnChangeExporter {
+    /// Exporter to forward BpfInfo to
+    exporter: Rc<RefCell<dyn Exporter>>,
+    /// Maximum time between two exports of unchanged stats
+    heartbeat_interval: Duration,
+    /// Map of bpf program/map ids to their last exported stats and time of the export
+    last_exported: HashMap<u32, (BpfStatsInfo, Instant)>,
+}
+
+impl OnChangeExporter {
+    /// Creates a new OnChangeExporter
+    ///
+    /// # Arguments
+    ///
+    /// * `exporter` - Exporter to forward BpfInfo to
+    ///
+    /// * `heartbeat_interval` - Maximum time between two exports of unchanged stats
+    pub fn new(exporter: Rc<RefCell<dyn Exporter>>, heartbeat_interval: Duration) -> Self {
+        Self {
+            exporter,
+            heartbeat_interval,
+            last_exported: HashMap::new(),
+        }
+    }
+
+    /// Checks if the stats have to be exported
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - BpfInfo to check
+    ///
+    /// * `now` - Current time
+    fn should_export(&self, data: &BpfInfo, now: Instant) -> bool {
+        match self.last_exported.get(&data.id) {
+            Some((stats, exported_at)) => {
+                *stats != data.stats || now - *exported_at >= self.heartbeat_interval
+            }
+            None => true,
+        }
+    }
+}
+
+impl Exporter for OnChangeExporter {
+    fn export_info(&mut self, data: &BpfInfo) -> Result<()> {
+        let now = Instant::now();
+        if !self.should_export(data, now) {
+            return Ok(());
+        }
+        self.exporter.borrow_mut().export_info(data)?;
+        self.last_exported
+            .insert(data.id, (data.stats.clone(), now));
+        Ok(())
+    }
+
+    fn remove_info(&mut self, data: &BpfInfo) -> Result<()> {
+        self.last_exported.remove(&data.id);
+        self.exporter.borrow_mut().remove_info(data)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.exporter.borrow_mut().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meter::map_meter::BpfMapStatsInfo;
+
+    /// Exporter remembering sizes of exported maps
+    #[derive(Default)]
+    struct SizesExporter(Vec<u32>);
+
+    impl Exporter for SizesExporter {
+        fn export_info(&mut self, data: &BpfInfo) -> Result<()> {
+            if let BpfStatsInfo::Map(stats) = &data.stats {
+                self.0.push(stats.size);
+            }
+            Ok(())
+        }
+    }
+
+    fn map_info(tick: u64, size: u32) -> BpfInfo<'static> {
+        BpfInfo {
+            id: 1,
+            name: "map",
+            tick,
+            time_recieved: Duration::ZERO,
+            stats: BpfStatsInfo::Map(BpfMapStatsInfo {
+                size,
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_unchanged_stats_are_skipped_until_heartbeat() {
+        let sizes = Rc::new(RefCell::new(SizesExporter::default()));
+        let mut exporter = OnChangeExporter::new(sizes.clone(), Duration::from_millis(100));
+        for (tick, size) in [(1, 3), (2, 3), (3, 4), (4, 4)] {
+            exporter.export_info(&map_info(tick, size)).unwrap();
+        }
+        assert_eq!(sizes.borrow().0, [3, 4]);
+
+        std::thread::sleep(Duration::from_millis(100));
+        exporter.export_info(&map_info(5, 4)).unwrap();
+        assert_eq!(sizes.borrow().0, [3, 4, 4]);
+    }
+}
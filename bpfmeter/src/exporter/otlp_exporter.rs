@@ -0,0 +1,298 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use log::{debug, error, info, warn};
+use tokio::sync::mpsc;
+
+use crate::exporter::prometheus_exporter::Labels;
+use crate::exporter::remote_write::put_bytes;
+use crate::exporter::{BpfStatsInfo, Exporter};
+use crate::meter::BpfInfo;
+
+/// Path of the Export method of OTLP metrics service
+const EXPORT_PATH: &str = "/opentelemetry.proto.collector.metrics.v1.MetricsService/Export";
+
+/// Single gauge value of ebpf program/map
+#[derive(Debug, Clone, PartialEq)]
+struct Point {
+    /// Metric name
+    name: &'static str,
+    /// Metric unit, empty for dimensionless metrics
+    unit: &'static str,
+    /// Attributes identifying ebpf program/map
+    attributes: Labels,
+    /// Metric value
+    value: f64,
+    /// Time of the measurement in nanoseconds since the epoch
+    time_unix_nano: u64,
+}
+
+/// Exports BpfInfo as OTLP gauges to OpenTelemetry collector over gRPC
+pub struct OtlpExporter {
+    /// Channel to the task pushing points to the collector
+    tx: mpsc::UnboundedSender<Point>,
+}
+
+impl OtlpExporter {
+    /// Creates a new OtlpExporter and starts the task pushing points to the collector
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - Collector gRPC endpoint, e.g. http://127.0.0.1:4317
+    ///
+    /// * `interval` - Interval between two pushes, points are batched in between
+    ///
+    /// * `labels` - Static labels to be added to the resource attributes
+    pub fn new(endpoint: &str, interval: Duration, labels: Labels) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .with_context(|| "Failed to create OTLP client")?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        let url = format!("{}{EXPORT_PATH}", endpoint.trim_end_matches('/'));
+        info!("Pushing metrics to OTLP collector {endpoint} every {interval:?}");
+        tokio::spawn(push_batches(client, url, interval, labels, rx));
+        Ok(Self { tx })
+    }
+
+    /// Sends point to the pushing task
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Metric name
+    ///
+    /// * `unit` - Metric unit
+    ///
+    /// * `attributes` - Attributes identifying ebpf program/map
+    ///
+    /// * `value` - Metric value
+    fn push(&self, name: &'static str, unit: &'static str, attributes: &Labels, value: f64) {
+        let point = Point {
+            name,
+            unit,
+            attributes: attributes.clone(),
+            value,
+            time_unix_nano: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos() as u64,
+        };
+        if self.tx.send(point).is_err() {
+            error!("OTLP sender is stopped, metrics are dropped");
+        }
+    }
+}
+
+impl Exporter for OtlpExporter {
+    fn export_info(&mut self, data: &BpfInfo) -> Result<()> {
+        match &data.stats {
+            BpfStatsInfo::Cpu(stats) => {
+                let attributes = vec![
+                    ("ebpf_id".to_string(), data.id.to_string()),
+                    ("ebpf_name".to_string(), data.name.to_string()),
+                ];
+                if let Some(cpu_usage) = stats.exact_cpu_usage {
+                    self.push("cpu_usage", "1", &attributes, cpu_usage as f64);
+                }
+                self.push("run_time", "s", &attributes, stats.run_time.as_secs_f64());
+                self.push("event_count", "1", &attributes, stats.run_count as f64);
+            }
+            BpfStatsInfo::Map(stats) => {
+                let attributes = vec![
+                    ("ebpf_map_id".to_string(), data.id.to_string()),
+                    ("ebpf_map_name".to_string(), data.name.to_string()),
+                    ("ebpf_map_max_size".to_string(), stats.max_size.to_string()),
+                ];
+                self.push("map_size", "1", &attributes, stats.size as f64);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects points and pushes them to the collector once per interval. The remaining
+/// points are pushed when the exporter is dropped
+///
+/// # Arguments
+///
+/// * `client` - Http/2 client
+///
+/// * `url` - Url of the Export method of the collector
+///
+/// * `interval` - Interval between two pushes
+///
+/// * `resource` - Resource attributes of all points
+///
+/// * `rx` - Channel to receive points from
+async fn push_batches(
+    client: reqwest::Client,
+    url: String,
+    interval: Duration,
+    resource: Labels,
+    mut rx: mpsc::UnboundedReceiver<Point>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    let mut batch = Vec::new();
+    loop {
+        let closed = tokio::select! {
+            point = rx.recv() => match point {
+                Some(point) => {
+                    batch.push(point);
+                    continue;
+                }
+                None => true,
+            },
+            _ = ticker.tick() => false,
+        };
+        if !batch.is_empty() {
+            let request = encode_export_request(&resource, &batch);
+            match push(&client, &url, request).await {
+                Ok(()) => debug!("Pushed {} points to OTLP collector", batch.len()),
+                Err(e) => warn!("Failed to push metrics to OTLP collector: {e:#}"),
+            }
+            batch.clear();
+        }
+        if closed {
+            return;
+        }
+    }
+}
+
+/// Sends ExportMetricsServiceRequest message as a gRPC call
+///
+/// # Arguments
+///
+/// * `client` - Http/2 client
+///
+/// * `url` - Url of the Export method of the collector
+///
+/// * `request` - Encoded ExportMetricsServiceRequest message
+async fn push(client: &reqwest::Client, url: &str, request: Vec<u8>) -> Result<()> {
+    // gRPC message is prefixed with the compression flag and the length
+    let mut body = Vec::with_capacity(request.len() + 5);
+    body.push(0);
+    body.extend((request.len() as u32).to_be_bytes());
+    body.extend(request);
+
+    let response = client
+        .post(url)
+        .header("Content-Type", "application/grpc")
+        .header("TE", "trailers")
+        .body(body)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("OTLP collector responded with {}", response.status());
+    }
+    // Errors are reported in headers of trailers-only responses
+    if let Some(status) = response.headers().get("grpc-status")
+        && status != "0"
+    {
+        let message = response
+            .headers()
+            .get("grpc-message")
+            .and_then(|m| m.to_str().ok())
+            .unwrap_or_default();
+        bail!("OTLP collector responded with gRPC status {status:?}: {message}");
+    }
+    Ok(())
+}
+
+/// Encodes points into protobuf ExportMetricsServiceRequest message, points of the same
+/// metric are data points of a single gauge
+///
+/// # Arguments
+///
+/// * `resource` - Resource attributes of all points
+///
+/// * `points` - Points to encode
+fn encode_export_request(resource: &Labels, points: &[Point]) -> Vec<u8> {
+    let mut names: Vec<(&str, &str)> = Vec::new();
+    for point in points {
+        if !names.iter().any(|(name, _)| *name == point.name) {
+            names.push((point.name, point.unit));
+        }
+    }
+
+    let mut scope = Vec::new();
+    put_bytes(&mut scope, 1, b"bpfmeter");
+    let mut scope_metrics = Vec::new();
+    put_bytes(&mut scope_metrics, 1, &scope);
+    for (name, unit) in names {
+        let mut gauge = Vec::new();
+        for point in points.iter().filter(|p| p.name == name) {
+            let mut data_point = Vec::new();
+            // Field 3 (time_unix_nano) is fixed64, field 4 (as_double) is double
+            data_point.push(3 << 3 | 1);
+            data_point.extend(point.time_unix_nano.to_le_bytes());
+            data_point.push(4 << 3 | 1);
+            data_point.extend(point.value.to_le_bytes());
+            put_attributes(&mut data_point, 7, &point.attributes);
+            put_bytes(&mut gauge, 1, &data_point);
+        }
+        let mut metric = Vec::new();
+        put_bytes(&mut metric, 1, name.as_bytes());
+        if !unit.is_empty() {
+            put_bytes(&mut metric, 3, unit.as_bytes());
+        }
+        put_bytes(&mut metric, 5, &gauge);
+        put_bytes(&mut scope_metrics, 2, &metric);
+    }
+
+    let mut resource_attributes = Vec::new();
+    put_attributes(&mut resource_attributes, 1, resource);
+    let mut resource_metrics = Vec::new();
+    put_bytes(&mut resource_metrics, 1, &resource_attributes);
+    put_bytes(&mut resource_metrics, 2, &scope_metrics);
+    let mut request = Vec::new();
+    put_bytes(&mut request, 1, &resource_metrics);
+    request
+}
+
+/// Appends attributes as repeated KeyValue field with string values
+///
+/// # Arguments
+///
+/// * `buf` - Buffer to append to
+///
+/// * `field` - Field number
+///
+/// * `attributes` - Attributes to append
+fn put_attributes(buf: &mut Vec<u8>, field: u64, attributes: &Labels) {
+    for (key, value) in attributes {
+        let mut any_value = Vec::new();
+        put_bytes(&mut any_value, 1, value.as_bytes());
+        let mut key_value = Vec::new();
+        put_bytes(&mut key_value, 1, key.as_bytes());
+        put_bytes(&mut key_value, 2, &any_value);
+        put_bytes(buf, field, &key_value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_export_request() {
+        let points = [Point {
+            name: "m",
+            unit: "",
+            attributes: vec![("a".to_string(), "b".to_string())],
+            value: 1.0,
+            time_unix_nano: 2,
+        }];
+        let mut data_point = vec![0x19];
+        data_point.extend(2u64.to_le_bytes());
+        data_point.push(0x21);
+        data_point.extend(1.0f64.to_le_bytes());
+        data_point.extend([0x3a, 0x08, 0x0a, 0x01, b'a', 0x12, 0x03, 0x0a, 0x01, b'b']);
+
+        let mut expected = vec![0x0a, 0x35, 0x0a, 0x00, 0x12, 0x31];
+        expected.extend([0x0a, 0x0a, 0x0a, 0x08]);
+        expected.extend(b"bpfmeter");
+        expected.extend([0x12, 0x23, 0x0a, 0x01, b'm', 0x2a, 0x1e, 0x0a, 0x1c]);
+        expected.extend(data_point);
+        assert_eq!(encode_export_request(&Labels::new(), &points), expected);
+    }
+}
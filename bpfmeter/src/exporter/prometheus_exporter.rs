@@ -1,28 +1,47 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
-use std::sync::atomic::AtomicU64;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, atomic::AtomicU32};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
 use axum::routing::get;
 use axum::{
-    Router,
+    Json, Router,
     body::Body,
     extract::State,
-    http::{StatusCode, header::CONTENT_TYPE},
+    http::{
+        HeaderMap, StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+    },
     response::{IntoResponse, Response},
 };
+use axum_server::tls_rustls::RustlsConfig;
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
 use clap::ValueEnum;
-use log::info;
+use log::{info, warn};
 use prometheus_client::{
     encoding::text::encode,
-    metrics::{family::Family, gauge::Gauge},
-    registry::Registry,
+    metrics::{
+        counter::Counter,
+        family::{Family, MetricConstructor},
+        gauge::Gauge,
+        histogram::Histogram,
+    },
+    registry::{Metric, Registry, Unit},
 };
+use socket2::{Domain, Protocol, Socket, Type};
 use tokio::sync::Mutex;
 
+use crate::exporter::meter_metrics::MeterMetrics;
 use crate::exporter::prometheus_gc::PromGC;
+use crate::exporter::pushgateway;
+use crate::exporter::remote_write;
 use crate::exporter::{BpfStatsInfo, Exporter};
-use crate::meter::BpfInfo;
+use crate::meter::cpu_meter::BpfCPUStatsInfo;
+use crate::meter::{BpfInfo, xdp_actions};
 
 /// Exports BpfInfo to prometheus format and starts prometheus exporter
 #[derive(Debug, Default)]
@@ -33,18 +52,236 @@ pub struct PrometheusExporter {
     metrics: EBPFMetrics,
     /// Garbage collector for prometheus metrics
     gc: Option<PromGC>,
+    /// Metrics of bpfmeter itself
+    meter_metrics: MeterMetrics,
+    /// Options defining which series and metrics are exported
+    options: PromExporterOptions,
+    /// Map of group names to their usage summed over the programs on the current tick,
+    /// exported once the tick is over
+    groups_usage: HashMap<String, GroupUsage>,
+    /// Tick the usage of groups is summed for
+    groups_tick: u64,
+    /// Url of the Pushgateway group and the state pushed to it, pushed once more on drop
+    pushgateway: Option<(String, Arc<Mutex<AppState>>)>,
+}
+
+/// Options of the local server serving metrics endpoint
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// Paths to certificate chain and private key in PEM format, metrics are served
+    /// over plain HTTP if not set
+    pub tls: Option<(PathBuf, PathBuf)>,
+    /// Credentials required to get metrics, metrics are served to anyone if not set
+    pub auth: Option<MetricsAuth>,
+    /// Path metrics are served on, e.g. /metrics
+    pub metrics_path: String,
+    /// Set SO_REUSEPORT on the listener to share the port with other instances
+    pub reuse_port: bool,
+}
+
+/// Credentials required by /metrics endpoint in Authorization header
+#[derive(Debug, Clone)]
+pub enum MetricsAuth {
+    /// Bearer token
+    Bearer(String),
+    /// User name and password of basic authentication
+    Basic(String, String),
+}
+
+impl MetricsAuth {
+    /// Returns the expected value of Authorization header
+    fn authorization(&self) -> String {
+        match self {
+            MetricsAuth::Bearer(token) => format!("Bearer {token}"),
+            MetricsAuth::Basic(user, pass) => {
+                format!("Basic {}", BASE64.encode(format!("{user}:{pass}")))
+            }
+        }
+    }
+
+    /// Returns the challenge sent in WWW-Authenticate header of unauthorized responses
+    fn challenge(&self) -> &'static str {
+        match self {
+            MetricsAuth::Bearer(_) => "Bearer",
+            MetricsAuth::Basic(..) => "Basic realm=\"bpfmeter\"",
+        }
+    }
+}
+
+/// Options defining which series and metrics are exported by PrometheusExporter
+#[derive(Debug, Clone, Default)]
+pub struct PromExporterOptions {
+    /// Identify series by ebpf program/map id only, names are exported in info metrics
+    pub id_only_series: bool,
+    /// Replace spaces and non-ascii characters in program names with underscores,
+    /// the original name is exported in `ebpf_name_raw` label
+    pub sanitize_names: bool,
+    /// Groups of ebpf programs to export summed cpu usage and event rate of
+    pub groups: Vec<ProgramGroup>,
+    /// Export whether counters of ebpf programs are stuck
+    pub stuck_detection: bool,
+    /// Export z-score of cpu usage of ebpf programs
+    pub zscore: bool,
+    /// Export the time cpu usage of ebpf programs has been continuously above the threshold
+    pub above_threshold: bool,
+    /// Export exponential moving average of cpu usage of ebpf programs
+    pub cpu_usage_smoothed: bool,
+    /// Export whether map sizes are taken from the previous tick
+    pub stale_map_sizes: bool,
+    /// Upper bounds of buckets of cpu usage histogram, the histogram is exported if set
+    pub cpu_histogram_buckets: Option<Vec<f64>>,
+    /// Number of online CPUs of the host, cpu usage as a share of the whole host is exported
+    /// if set
+    pub host_cpus: Option<usize>,
+}
+
+/// Named group of ebpf programs, e.g. programs implementing the same service
+#[derive(Debug, Clone, Default)]
+pub struct ProgramGroup {
+    /// Name of the group
+    pub name: String,
+    /// Ids of ebpf programs in the group
+    pub ids: Vec<u32>,
+}
+
+/// Creates cpu usage histograms of ebpf programs with the configured buckets
+#[derive(Debug, Clone)]
+pub struct CpuUsageBuckets(Vec<f64>);
+
+impl MetricConstructor<Histogram> for CpuUsageBuckets {
+    fn new_metric(&self) -> Histogram {
+        Histogram::new(self.0.iter().copied())
+    }
+}
+
+/// Usage of the group of ebpf programs summed over the programs
+#[derive(Debug, Default)]
+struct GroupUsage {
+    /// Sum of cpu usage of the programs
+    cpu_usage: f32,
+    /// Sum of event rate of the programs
+    event_rate: f32,
 }
 
 #[derive(Debug, Default)]
 pub struct EBPFMetrics {
     /// Map of bpf program ids to cpu usage
     pub cpu_usage: Family<Labels, Gauge<f32, AtomicU32>>,
+    /// Map of bpf program ids to histogram of all their cpu usage values, set if enabled
+    pub cpu_usage_histogram: Option<Family<Labels, Histogram, CpuUsageBuckets>>,
+    /// Map of bpf program ids to cpu usage divided by the number of host CPUs
+    pub cpu_usage_host_fraction: Family<Labels, Gauge<f32, AtomicU32>>,
+    /// Number of online CPUs of the host
+    pub host_cpus: Gauge,
+    /// Map of bpf program ids to max cpu usage since their first measurement
+    pub cpu_usage_peak: Family<Labels, Gauge<f32, AtomicU32>>,
     /// Map of bpf program ids to run time
     pub run_time: Family<Labels, Gauge<f32, AtomicU32>>,
     /// Map of bpf program ids to event count
     pub event_count: Family<Labels, Gauge<u64, AtomicU64>>,
+    /// Map of bpf program ids to event rate
+    pub event_rate: Family<Labels, Gauge<f32, AtomicU32>>,
     /// Map of bpf program ids to map size
     pub map_size: Family<Labels, Gauge<u32, AtomicU32>>,
+    /// Map of bpf map ids to 1 if their size is taken from the previous tick, 0 otherwise
+    pub map_size_stale: Family<Labels, Gauge>,
+    /// Map of bpf map ids to fraction of their max size used
+    pub map_fill_ratio: Family<Labels, Gauge<f32, AtomicU32>>,
+    /// Map of bpf map ids to 1 if all their entries are preallocated, 0 otherwise
+    pub map_preallocated: Family<Labels, Gauge>,
+    /// Map of bpf map ids and cpus to number of entries of per-cpu map with a nonzero value
+    /// on the cpu
+    pub map_cpu_size: Family<Labels, Gauge<u32, AtomicU32>>,
+    /// Map of bpf map ids to number of entries added since their first measurement
+    pub map_adds: Family<Labels, Counter<u64, AtomicU64>>,
+    /// Map of bpf map ids to number of entries deleted since their first measurement
+    pub map_deletes: Family<Labels, Counter<u64, AtomicU64>>,
+    /// Map of XDP program ids and actions to number of processed packets
+    pub xdp_actions: Family<Labels, Counter<u64, AtomicU64>>,
+    /// Map of bpf program ids to their names, exported in id-only series mode
+    pub program_info: Family<Labels, Gauge>,
+    /// Map of bpf map ids to their names and max sizes, exported in id-only series mode
+    pub map_info: Family<Labels, Gauge>,
+    /// Map of program group names to their cpu usage
+    pub group_cpu_usage: Family<Labels, Gauge<f32, AtomicU32>>,
+    /// Map of program group names to their event rate
+    pub group_event_rate: Family<Labels, Gauge<f32, AtomicU32>>,
+    /// Map of bpf program ids to 1 if their counters are stuck, 0 otherwise
+    pub program_stuck: Family<Labels, Gauge>,
+    /// Map of bpf program ids to z-score of their cpu usage
+    pub cpu_usage_zscore: Family<Labels, Gauge<f32, AtomicU32>>,
+    /// Map of bpf program ids to the time their cpu usage has been continuously above the threshold
+    pub cpu_usage_above_threshold: Family<Labels, Gauge<f32, AtomicU32>>,
+    /// Map of bpf program ids to exponential moving average of cpu usage
+    pub cpu_usage_smoothed: Family<Labels, Gauge<f32, AtomicU32>>,
+}
+
+impl EBPFMetrics {
+    /// Removes all series of ebpf program
+    ///
+    /// # Arguments
+    ///
+    /// * `labels` - Labels of metric series of the program
+    ///
+    /// * `info_labels` - Labels of info metric series of the program, if it is exported
+    pub fn remove_program(&self, labels: &Labels, info_labels: Option<&Labels>) {
+        self.cpu_usage.remove(labels);
+        if let Some(cpu_usage_histogram) = &self.cpu_usage_histogram {
+            cpu_usage_histogram.remove(labels);
+        }
+        self.cpu_usage_host_fraction.remove(labels);
+        self.cpu_usage_peak.remove(labels);
+        self.run_time.remove(labels);
+        self.event_count.remove(labels);
+        self.event_rate.remove(labels);
+        self.program_stuck.remove(labels);
+        self.cpu_usage_zscore.remove(labels);
+        self.cpu_usage_above_threshold.remove(labels);
+        self.cpu_usage_smoothed.remove(labels);
+        for action in xdp_actions::XDP_ACTIONS {
+            let mut action_labels = labels.clone();
+            action_labels.push(("action".to_string(), action.to_string()));
+            self.xdp_actions.remove(&action_labels);
+        }
+        if let Some(info_labels) = info_labels {
+            self.program_info.remove(info_labels);
+        }
+    }
+
+    /// Removes all series of ebpf map
+    ///
+    /// # Arguments
+    ///
+    /// * `labels` - Labels of metric series of the map
+    ///
+    /// * `info_labels` - Labels of info metric series of the map, if it is exported
+    pub fn remove_map(&self, labels: &Labels, info_labels: Option<&Labels>) {
+        self.map_size.remove(labels);
+        self.map_size_stale.remove(labels);
+        self.map_fill_ratio.remove(labels);
+        self.map_preallocated.remove(labels);
+        self.map_adds.remove(labels);
+        self.map_deletes.remove(labels);
+        for cpu in 0..aya::util::nr_cpus().unwrap_or_default() {
+            self.map_cpu_size.remove(&cpu_labels(labels, cpu));
+        }
+        if let Some(info_labels) = info_labels {
+            self.map_info.remove(info_labels);
+        }
+    }
+}
+
+/// Returns labels of per-cpu series
+///
+/// # Arguments
+///
+/// * `labels` - Labels of metric series of ebpf map
+///
+/// * `cpu` - Index of the cpu
+fn cpu_labels(labels: &Labels, cpu: usize) -> Labels {
+    let mut cpu_labels = labels.clone();
+    cpu_labels.push(("cpu".to_string(), cpu.to_string()));
+    cpu_labels
 }
 
 /// Prometheus export metric type
@@ -52,21 +289,42 @@ pub struct EBPFMetrics {
 pub enum PromExportType {
     /// CPU usage in percent
     CPUUsage,
+    /// Histogram of CPU usage with --cpu-histogram-buckets buckets
+    CPUUsageHistogram,
+    /// CPU usage as a share of all CPUs of the host
+    CPUUsageHostFraction,
+    /// Max CPU usage since the first measurement of the program
+    CPUUsagePeak,
     /// Accumulated run time in seconds
     RunTime,
     /// Number of times the ebpf program was run
     EventCount,
+    /// Number of runs per second of the rate denominator
+    EventRate,
     /// Size of ebpf map
     MapSize,
+    /// Fraction of the max size of ebpf map used
+    MapFillRatio,
+    /// Number of entries added to and deleted from ebpf map, measured with --map-churn
+    MapChurn,
+    /// Number of packets processed by XDP program for each action
+    XdpActions,
 }
 
 impl Display for PromExportType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             PromExportType::CPUUsage => write!(f, "cpu-usage"),
+            PromExportType::CPUUsageHistogram => write!(f, "cpu-usage-histogram"),
+            PromExportType::CPUUsageHostFraction => write!(f, "cpu-usage-host-fraction"),
+            PromExportType::CPUUsagePeak => write!(f, "cpu-usage-peak"),
             PromExportType::RunTime => write!(f, "run-time"),
             PromExportType::EventCount => write!(f, "event-count"),
+            PromExportType::EventRate => write!(f, "event-rate"),
             PromExportType::MapSize => write!(f, "map-size"),
+            PromExportType::MapFillRatio => write!(f, "map-fill-ratio"),
+            PromExportType::MapChurn => write!(f, "map-churn"),
+            PromExportType::XdpActions => write!(f, "xdp-actions"),
         }
     }
 }
@@ -74,10 +332,123 @@ impl Display for PromExportType {
 /// Vector of OpenMetrics labels and their values, format: [(label, value), (label, value), ...]
 pub type Labels = Vec<(String, String)>;
 
+/// Map of metric names to overrides of their help texts and units
+pub type MetricsMetadata = HashMap<String, MetricMetadata>;
+
+/// Override of the help text and unit of a metric
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct MetricMetadata {
+    /// Name of the metric as registered by bpfmeter, e.g. ebpf_cpu_usage
+    pub name: String,
+    /// Help text of the metric
+    pub help: String,
+    /// Unit of the metric, appended to the metric name as required by OpenMetrics
+    pub unit: Option<String>,
+}
+
+/// Reads overrides of metric help texts and units from csv file with columns: name,help,unit
+///
+/// # Arguments
+///
+/// * `path` - Path to the csv file
+pub fn load_metrics_metadata(path: &Path) -> Result<MetricsMetadata> {
+    let mut reader = csv::Reader::from_path(path)
+        .with_context(|| format!("Failed to open metrics metadata {}", path.display()))?;
+    let mut metadata = MetricsMetadata::new();
+    for record in reader.deserialize() {
+        let record: MetricMetadata =
+            record.with_context(|| format!("Invalid metrics metadata in {}", path.display()))?;
+        metadata.insert(record.name.clone(), record);
+    }
+    Ok(metadata)
+}
+
+/// Overrides of metrics looked up while registering the metrics, overrides that are never
+/// looked up belong to metrics that are not exported
+pub struct MetadataLookup<'a> {
+    /// Overrides of help texts and units of metrics
+    metadata: &'a MetricsMetadata,
+    /// Names of the registered metrics
+    registered: HashSet<String>,
+}
+
+impl<'a> MetadataLookup<'a> {
+    /// Creates a new MetadataLookup
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - Overrides of help texts and units of metrics
+    pub fn new(metadata: &'a MetricsMetadata) -> Self {
+        Self {
+            metadata,
+            registered: HashSet::new(),
+        }
+    }
+
+    /// Returns the override of the metric and remembers the metric as registered
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the metric
+    fn get(&mut self, name: &str) -> Option<&'a MetricMetadata> {
+        self.registered.insert(name.to_string());
+        self.metadata.get(name)
+    }
+
+    /// Returns names of overridden metrics that were not registered, e.g. misspelled
+    /// or disabled by the export types
+    pub fn unregistered(&self) -> Vec<&'a str> {
+        let mut names = self
+            .metadata
+            .keys()
+            .filter(|name| !self.registered.contains(*name))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// Registers metric in prometheus registry, help text and unit are taken from metadata
+/// if the metric is overridden there
+///
+/// # Arguments
+///
+/// * `registry` - Registry to register metric in
+///
+/// * `metadata` - Overrides of help texts and units of metrics
+///
+/// * `name` - Name of the metric
+///
+/// * `help` - Default help text of the metric
+///
+/// * `metric` - Metric to register
+pub fn register_metric(
+    registry: &mut Registry,
+    metadata: &mut MetadataLookup,
+    name: &str,
+    help: &str,
+    metric: impl Metric,
+) {
+    match metadata.get(name) {
+        Some(MetricMetadata {
+            help,
+            unit: Some(unit),
+            ..
+        }) if !unit.is_empty() => {
+            registry.register_with_unit(name, help, Unit::Other(unit.clone()), metric)
+        }
+        Some(MetricMetadata { help, .. }) => registry.register(name, help, metric),
+        None => registry.register(name, help, metric),
+    }
+}
+
 /// Application state for prometheus exporter
 #[derive(Debug)]
 pub struct AppState {
     pub registry: Registry,
+    /// Credentials required to get metrics, metrics are served to anyone if not set
+    pub auth: Option<MetricsAuth>,
 }
 
 impl PrometheusExporter {
@@ -88,84 +459,549 @@ impl PrometheusExporter {
     /// * `labels` - Static labels to be added to all metrics
     ///
     /// * `gc` - Garbage collector for prometheus metrics
-    pub fn new(labels: Labels, gc: Option<PromGC>) -> Self {
+    ///
+    /// * `options` - Options defining which series and metrics are exported
+    pub fn new(labels: Labels, gc: Option<PromGC>, options: PromExporterOptions) -> Self {
         Self {
+            meter_metrics: MeterMetrics::new(labels.clone()),
             static_lables: labels,
-            metrics: Default::default(),
+            metrics: EBPFMetrics {
+                cpu_usage_histogram: options
+                    .cpu_histogram_buckets
+                    .clone()
+                    .map(|buckets| Family::new_with_constructor(CpuUsageBuckets(buckets))),
+                ..Default::default()
+            },
             gc,
+            options,
+            groups_usage: HashMap::new(),
+            groups_tick: 0,
+            pushgateway: None,
+        }
+    }
+
+    /// Adds usage of the program to the groups it belongs to. Sums of the previous tick
+    /// are exported when the first program of a new tick arrives, so a scrape never sees
+    /// a sum over a part of the programs
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - BpfInfo of the program
+    ///
+    /// * `stats` - CPU stats of the program
+    fn export_groups(&mut self, data: &BpfInfo, stats: &BpfCPUStatsInfo) {
+        if self.options.groups.is_empty() {
+            return;
+        }
+        if data.tick != self.groups_tick {
+            self.set_group_usage();
+            self.groups_tick = data.tick;
+        }
+        for group in self
+            .options
+            .groups
+            .iter()
+            .filter(|g| g.ids.contains(&data.id))
+        {
+            let usage = self.groups_usage.entry(group.name.clone()).or_default();
+            usage.cpu_usage += stats.exact_cpu_usage.unwrap_or_default();
+            usage.event_rate += stats.event_rate.unwrap_or_default();
+        }
+    }
+
+    /// Exports usage of the groups summed on the tick and starts the sums over
+    fn set_group_usage(&mut self) {
+        for (name, usage) in self.groups_usage.drain() {
+            let mut labels = self.static_lables.clone();
+            labels.push(("group".to_string(), name));
+            self.metrics
+                .group_cpu_usage
+                .get_or_create(&labels)
+                .set(usage.cpu_usage);
+            self.metrics
+                .group_event_rate
+                .get_or_create(&labels)
+                .set(usage.event_rate);
+        }
+    }
+
+    /// Builds labels with the name of ebpf program
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of ebpf program
+    fn program_name_labels(&self, name: &str) -> Vec<(&'static str, String)> {
+        if !self.options.sanitize_names {
+            return vec![("ebpf_name", name.to_string())];
+        }
+        let sanitized = name
+            .chars()
+            .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+            .collect();
+        vec![
+            ("ebpf_name", sanitized),
+            ("ebpf_name_raw", name.to_string()),
+        ]
+    }
+
+    /// Builds labels of metric series and, in id-only series mode, labels of info series
+    ///
+    /// # Arguments
+    ///
+    /// * `id_label` - Label identifying ebpf program/map
+    ///
+    /// * `name_labels` - Descriptive labels, moved to info series in id-only series mode
+    fn series_labels(
+        &self,
+        id_label: (&str, String),
+        name_labels: Vec<(&str, String)>,
+    ) -> (Labels, Option<Labels>) {
+        let mut labels = self.static_lables.clone();
+        labels.push((id_label.0.to_string(), id_label.1));
+        let name_labels = name_labels.into_iter().map(|(k, v)| (k.to_string(), v));
+        if self.options.id_only_series {
+            let mut info_labels = labels.clone();
+            info_labels.extend(name_labels);
+            (labels, Some(info_labels))
+        } else {
+            labels.extend(name_labels);
+            (labels, None)
         }
     }
 
-    /// Starts prometheus exporter on localhost
+    /// Returns metrics of bpfmeter itself, which are served along with ebpf metrics
+    pub fn meter_metrics(&self) -> MeterMetrics {
+        self.meter_metrics.clone()
+    }
+
+    /// Starts prometheus exporter serving /metrics endpoint
     ///
     /// # Arguments
     ///
-    /// * `port` - Port to start exporter on
+    /// * `addr` - Address to start exporter on
     ///
     /// * `expoting_types` - Types of metrics to export
+    ///
+    /// * `metadata` - Overrides of help texts and units of metrics
+    ///
+    /// * `server_options` - TLS and authentication of the server
     pub async fn start_local_server(
         &mut self,
-        port: u16,
+        addr: SocketAddr,
         expoting_types: &[PromExportType],
+        metadata: &MetricsMetadata,
+        server_options: &ServerOptions,
     ) -> Result<()> {
+        // Certificate and key are loaded before serving, so invalid ones fail the start
+        let tls_config = match &server_options.tls {
+            Some((cert, key)) => Some(RustlsConfig::from_pem_file(cert, key).await.with_context(
+                || {
+                    format!(
+                        "Failed to load TLS certificate {} and key {}",
+                        cert.display(),
+                        key.display()
+                    )
+                },
+            )?),
+            None => None,
+        };
+
+        let listener = bind_listener(addr, server_options.reuse_port)
+            .with_context(|| "Error while starting prometheus exporter")?;
+        let router = self.metrics_router(expoting_types, metadata, server_options);
+
+        let metrics_path = server_options.metrics_path.clone();
+        match tls_config {
+            Some(tls_config) => {
+                let server = axum_server::from_tcp_rustls(listener.into_std()?, tls_config)?;
+                tokio::spawn(async move {
+                    info!("Prometheus node exporter is running at: https://{addr}{metrics_path}");
+                    server.serve(router.into_make_service()).await
+                });
+            }
+            None => {
+                tokio::spawn(async move {
+                    info!("Prometheus node exporter is running at: http://{addr}{metrics_path}");
+                    axum::serve(listener, router).await
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds router serving the metrics endpoint and /healthz, to be mounted onto a router
+    /// of another service instead of starting a server of its own. TLS and the port of
+    /// server options are left to the service
+    ///
+    /// # Arguments
+    ///
+    /// * `expoting_types` - Types of metrics to export
+    ///
+    /// * `metadata` - Overrides of help texts and units of metrics
+    ///
+    /// * `server_options` - Path and authentication of the metrics endpoint
+    pub fn metrics_router<S: Clone + Send + Sync + 'static>(
+        &mut self,
+        expoting_types: &[PromExportType],
+        metadata: &MetricsMetadata,
+        server_options: &ServerOptions,
+    ) -> Router<S> {
+        let mut state = self.build_state(expoting_types, metadata);
+        state.auth = server_options.auth.clone();
+        let state = Arc::new(Mutex::new(state));
+
+        if let Some(gc) = self.gc.as_mut() {
+            gc.start();
+        }
+
+        // /healthz doesn't require authentication and doesn't lock the state
+        let started = Instant::now();
+        Router::new()
+            .route(&server_options.metrics_path, get(metrics_handler))
+            .with_state(state)
+            .route("/healthz", get(move || healthz_handler(started)))
+    }
+
+    /// Starts pushing metrics to remote write endpoint instead of serving them
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - Remote write endpoint
+    ///
+    /// * `interval` - Interval between two pushes
+    ///
+    /// * `expoting_types` - Types of metrics to export
+    ///
+    /// * `metadata` - Overrides of help texts and units of metrics
+    pub fn start_agent(
+        &mut self,
+        url: &str,
+        interval: Duration,
+        expoting_types: &[PromExportType],
+        metadata: &MetricsMetadata,
+    ) {
+        let state = Arc::new(Mutex::new(self.build_state(expoting_types, metadata)));
+        info!("Pushing metrics to remote write endpoint {url} every {interval:?}");
+        tokio::spawn(remote_write::push_periodically(
+            url.to_string(),
+            interval,
+            state,
+        ));
+
+        if let Some(gc) = self.gc.as_mut() {
+            gc.start();
+        }
+    }
+
+    /// Starts pushing metrics to the Pushgateway instead of serving them
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - Url of the group on the Pushgateway
+    ///
+    /// * `interval` - Interval between two pushes
+    ///
+    /// * `expoting_types` - Types of metrics to export
+    ///
+    /// * `metadata` - Overrides of help texts and units of metrics
+    pub fn start_pushgateway(
+        &mut self,
+        url: &str,
+        interval: Duration,
+        expoting_types: &[PromExportType],
+        metadata: &MetricsMetadata,
+    ) {
+        let state = Arc::new(Mutex::new(self.build_state(expoting_types, metadata)));
+        info!("Pushing metrics to Pushgateway {url} every {interval:?}");
+        tokio::spawn(pushgateway::push_periodically(
+            url.to_string(),
+            interval,
+            state.clone(),
+        ));
+        self.pushgateway = Some((url.to_string(), state));
+
+        if let Some(gc) = self.gc.as_mut() {
+            gc.start();
+        }
+    }
+
+    /// Registers metrics of the export types in a new registry
+    ///
+    /// # Arguments
+    ///
+    /// * `expoting_types` - Types of metrics to export
+    ///
+    /// * `metadata` - Overrides of help texts and units of metrics
+    fn build_state(
+        &self,
+        expoting_types: &[PromExportType],
+        metadata: &MetricsMetadata,
+    ) -> AppState {
         let mut state = AppState {
             registry: Registry::default(),
+            auth: None,
         };
+        let metadata = &mut MetadataLookup::new(metadata);
         if expoting_types.contains(&PromExportType::CPUUsage) {
-            state.registry.register(
+            register_metric(
+                &mut state.registry,
+                metadata,
                 "ebpf_cpu_usage",
                 "CPU Usage of bpf programs",
                 self.metrics.cpu_usage.clone(),
             );
         }
+        if let Some(cpu_usage_histogram) = &self.metrics.cpu_usage_histogram {
+            // The gauge already takes ebpf_cpu_usage name, both can be exported at once
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_cpu_usage_histogram",
+                "Distribution of CPU Usage of bpf programs over all measurements",
+                cpu_usage_histogram.clone(),
+            );
+        }
+        if let Some(host_cpus) = self.options.host_cpus {
+            self.metrics.host_cpus.set(host_cpus as i64);
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_host_cpus",
+                "Number of online CPUs of the host",
+                self.metrics.host_cpus.clone(),
+            );
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_cpu_usage_host_fraction",
+                "CPU Usage of bpf programs as a share of all CPUs of the host",
+                self.metrics.cpu_usage_host_fraction.clone(),
+            );
+        }
+        if expoting_types.contains(&PromExportType::CPUUsagePeak) {
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_cpu_usage_peak",
+                "Max CPU Usage of bpf programs since their first measurement",
+                self.metrics.cpu_usage_peak.clone(),
+            );
+        }
         if expoting_types.contains(&PromExportType::RunTime) {
-            state.registry.register(
+            register_metric(
+                &mut state.registry,
+                metadata,
                 "ebpf_run_time",
                 "Time spent in the ebpf program starting from the first measurement (seconds)",
                 self.metrics.run_time.clone(),
             );
         }
         if expoting_types.contains(&PromExportType::EventCount) {
-            state.registry.register(
+            register_metric(
+                &mut state.registry,
+                metadata,
                 "ebpf_event_count",
                 "Number of times the ebpf program was run starting from the first measurement",
                 self.metrics.event_count.clone(),
             );
         }
+        if expoting_types.contains(&PromExportType::EventRate) {
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_event_rate",
+                "Number of runs per second (of wall-clock time or of execution, see --rate-denominator) of the ebpf program",
+                self.metrics.event_rate.clone(),
+            );
+        }
         if expoting_types.contains(&PromExportType::MapSize) {
-            state.registry.register(
+            register_metric(
+                &mut state.registry,
+                metadata,
                 "ebpf_map_size",
                 "Current size of ebpf map",
                 self.metrics.map_size.clone(),
             );
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_map_preallocated",
+                "1 if all entries of ebpf map are preallocated (arrays) and its size is the max size",
+                self.metrics.map_preallocated.clone(),
+            );
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_map_cpu_size",
+                "Number of entries of per-cpu ebpf map with a nonzero value on the cpu",
+                self.metrics.map_cpu_size.clone(),
+            );
+        }
+        if expoting_types.contains(&PromExportType::MapFillRatio) {
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_map_fill_ratio",
+                "Fraction of the max size of ebpf map used, from 0 to 1",
+                self.metrics.map_fill_ratio.clone(),
+            );
+        }
+        if expoting_types.contains(&PromExportType::MapChurn) {
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_map_adds",
+                "Number of entries added to ebpf map since its first measurement",
+                self.metrics.map_adds.clone(),
+            );
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_map_deletes",
+                "Number of entries deleted from ebpf map since its first measurement",
+                self.metrics.map_deletes.clone(),
+            );
+        }
+        if expoting_types.contains(&PromExportType::XdpActions) {
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_xdp_action",
+                "Number of packets processed by XDP program for each action starting from the first measurement",
+                self.metrics.xdp_actions.clone(),
+            );
         }
 
-        let state = Arc::new(Mutex::new(state));
+        if !self.options.groups.is_empty() {
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_group_cpu_usage",
+                "CPU Usage of groups of bpf programs",
+                self.metrics.group_cpu_usage.clone(),
+            );
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_group_event_rate",
+                "Number of runs per second of groups of bpf programs",
+                self.metrics.group_event_rate.clone(),
+            );
+        }
 
-        let router = Router::new()
-            .route("/metrics", get(metrics_handler))
-            .with_state(state);
+        if self.options.stale_map_sizes {
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_map_size_stale",
+                "1 if the size of ebpf map is taken from the previous tick because the map walk budget was exhausted",
+                self.metrics.map_size_stale.clone(),
+            );
+        }
+        if self.options.stuck_detection {
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_program_stuck",
+                "1 if run time and run count of the ebpf program haven't changed for the stuck threshold number of ticks",
+                self.metrics.program_stuck.clone(),
+            );
+        }
 
-        let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}"))
-            .await
-            .with_context(|| "Error while starting prometheus exporter")?;
+        if self.options.zscore {
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_cpu_usage_zscore",
+                "Z-score of CPU usage of bpf programs relative to their usage on the previous ticks",
+                self.metrics.cpu_usage_zscore.clone(),
+            );
+        }
 
-        tokio::spawn(async move {
-            info!("Prometheus node exporter is running at port: {port}");
-            axum::serve(listener, router).await
-        });
+        if self.options.above_threshold {
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_cpu_usage_above_threshold_seconds",
+                "Time CPU usage of bpf programs has been continuously above the threshold",
+                self.metrics.cpu_usage_above_threshold.clone(),
+            );
+        }
 
-        if let Some(gc) = self.gc.as_mut() {
-            gc.start();
+        if self.options.cpu_usage_smoothed {
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_cpu_usage_smoothed",
+                "Exponential moving average of CPU usage of bpf programs",
+                self.metrics.cpu_usage_smoothed.clone(),
+            );
         }
 
-        Ok(())
+        if self.options.id_only_series {
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_program_info",
+                "Names of ebpf programs, join with other metrics by ebpf_id",
+                self.metrics.program_info.clone(),
+            );
+            register_metric(
+                &mut state.registry,
+                metadata,
+                "ebpf_map_info",
+                "Names and max sizes of ebpf maps, join with other metrics by ebpf_map_id",
+                self.metrics.map_info.clone(),
+            );
+        }
+        self.meter_metrics.register(&mut state.registry, metadata);
+        for name in metadata.unregistered() {
+            warn!(
+                "Metric {name} from metrics metadata is not exported, check its name and the export types"
+            );
+        }
+        state
+    }
+}
+
+impl Drop for PrometheusExporter {
+    fn drop(&mut self) {
+        // Ticks measured after the last periodic push would be lost when the process exits,
+        // sums of groups of the last tick are complete by now
+        self.set_group_usage();
+        let Some((url, state)) = self.pushgateway.take() else {
+            return;
+        };
+        tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(async {
+                match pushgateway::push(&reqwest::Client::new(), &url, &state).await {
+                    Ok(()) => info!("Pushed the last metrics to Pushgateway"),
+                    Err(e) => warn!("Failed to push the last metrics to Pushgateway: {e:#}"),
+                }
+            })
+        });
     }
 }
 
 /// Handler for GET requests to /metrics endpoint
-async fn metrics_handler(State(state): State<Arc<Mutex<AppState>>>) -> impl IntoResponse {
+async fn metrics_handler(
+    State(state): State<Arc<Mutex<AppState>>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
     let state = state.lock().await;
+    if let Some(auth) = &state.auth {
+        let authorized = headers.get(AUTHORIZATION).is_some_and(|value| {
+            constant_time_eq(value.as_bytes(), auth.authorization().as_bytes())
+        });
+        if !authorized {
+            return Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .header(WWW_AUTHENTICATE, auth.challenge())
+                .body(Body::empty())
+                .unwrap();
+        }
+    }
     let mut buffer = String::new();
     encode(&mut buffer, &state.registry).unwrap();
 
@@ -179,17 +1015,72 @@ async fn metrics_handler(State(state): State<Arc<Mutex<AppState>>>) -> impl Into
         .unwrap()
 }
 
+/// Handler for GET requests to /healthz endpoint
+///
+/// # Arguments
+///
+/// * `started` - Time the server was started at
+async fn healthz_handler(started: Instant) -> impl IntoResponse {
+    Json(serde_json::json!({
+        "status": "ok",
+        "uptime_secs": started.elapsed().as_secs(),
+    }))
+}
+
+/// Binds the listener of the exporter with SO_REUSEADDR, so the port can be bound again
+/// right after a restart while connections of the previous process are in TIME_WAIT
+///
+/// # Arguments
+///
+/// * `addr` - Address to listen on
+///
+/// * `reuse_port` - Also set SO_REUSEPORT to share the port with other processes
+fn bind_listener(addr: SocketAddr, reuse_port: bool) -> Result<tokio::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(tokio::net::TcpListener::from_std(socket.into())?)
+}
+
+/// Compares byte strings in time independent of the position of the first difference,
+/// so credentials can't be guessed byte by byte from response times
+///
+/// # Arguments
+///
+/// * `a` - First byte string
+///
+/// * `b` - Second byte string
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
 impl Exporter for PrometheusExporter {
     fn export_info(&mut self, data: &BpfInfo) -> Result<()> {
-        let mut labels = self.static_lables.clone();
         match &data.stats {
             BpfStatsInfo::Cpu(stats) => {
-                labels.push(("ebpf_id".to_string(), data.id.to_string()));
-                labels.push(("ebpf_name".to_string(), data.name.to_string()));
-                self.metrics
-                    .cpu_usage
-                    .get_or_create(&labels)
-                    .set(stats.exact_cpu_usage);
+                let (labels, info_labels) = self.series_labels(
+                    ("ebpf_id", data.id.to_string()),
+                    self.program_name_labels(data.name),
+                );
+                if let Some(cpu_usage) = stats.exact_cpu_usage {
+                    self.metrics.cpu_usage.get_or_create(&labels).set(cpu_usage);
+                    if let Some(cpu_usage_histogram) = &self.metrics.cpu_usage_histogram {
+                        cpu_usage_histogram
+                            .get_or_create(&labels)
+                            .observe(cpu_usage as f64);
+                    }
+                    if let Some(host_cpus) = self.options.host_cpus {
+                        self.metrics
+                            .cpu_usage_host_fraction
+                            .get_or_create(&labels)
+                            .set(cpu_usage / host_cpus as f32);
+                    }
+                }
                 self.metrics
                     .run_time
                     .get_or_create(&labels)
@@ -198,17 +1089,114 @@ impl Exporter for PrometheusExporter {
                     .event_count
                     .get_or_create(&labels)
                     .set(stats.run_count);
+                if let Some(peak_cpu_usage) = stats.peak_cpu_usage {
+                    self.metrics
+                        .cpu_usage_peak
+                        .get_or_create(&labels)
+                        .set(peak_cpu_usage);
+                }
+                if let Some(event_rate) = stats.event_rate {
+                    self.metrics
+                        .event_rate
+                        .get_or_create(&labels)
+                        .set(event_rate);
+                }
+                for (action, packets) in xdp_actions::XDP_ACTIONS.iter().zip(&stats.xdp_actions) {
+                    let mut action_labels = labels.clone();
+                    action_labels.push(("action".to_string(), action.to_string()));
+                    self.metrics
+                        .xdp_actions
+                        .get_or_create(&action_labels)
+                        .inner()
+                        .store(*packets, Ordering::Relaxed);
+                }
+                match stats.cpu_usage_zscore {
+                    Some(zscore) => {
+                        self.metrics
+                            .cpu_usage_zscore
+                            .get_or_create(&labels)
+                            .set(zscore);
+                    }
+                    // Z-score of the previous tick must not be reported as the current one
+                    None if self.options.zscore => {
+                        self.metrics.cpu_usage_zscore.remove(&labels);
+                    }
+                    None => {}
+                }
+                if let Some(above_threshold) = stats.above_threshold {
+                    self.metrics
+                        .cpu_usage_above_threshold
+                        .get_or_create(&labels)
+                        .set(above_threshold.as_secs_f32());
+                }
+                if let Some(smoothed_cpu_usage) = stats.smoothed_cpu_usage.flatten() {
+                    self.metrics
+                        .cpu_usage_smoothed
+                        .get_or_create(&labels)
+                        .set(smoothed_cpu_usage);
+                }
+                if let Some(stuck) = stats.stuck {
+                    self.metrics
+                        .program_stuck
+                        .get_or_create(&labels)
+                        .set(stuck as i64);
+                }
+                if let Some(info_labels) = info_labels.as_ref() {
+                    self.metrics.program_info.get_or_create(info_labels).set(1);
+                }
                 if let Some(gc) = self.gc.as_mut() {
-                    gc.add_exported_program(data.id, data.name);
+                    gc.add_exported_program(&self.metrics, data.id, &labels, info_labels.as_ref());
                 }
+                self.export_groups(data, stats);
             }
             BpfStatsInfo::Map(stats) => {
-                labels.push(("ebpf_map_id".to_string(), data.id.to_string()));
-                labels.push(("ebpf_map_name".to_string(), data.name.to_string()));
-                labels.push(("ebpf_map_max_size".to_string(), stats.max_size.to_string()));
+                let (labels, info_labels) = self.series_labels(
+                    ("ebpf_map_id", data.id.to_string()),
+                    vec![
+                        ("ebpf_map_name", data.name.to_string()),
+                        ("ebpf_map_max_size", stats.max_size.to_string()),
+                    ],
+                );
                 self.metrics.map_size.get_or_create(&labels).set(stats.size);
+                self.metrics
+                    .map_preallocated
+                    .get_or_create(&labels)
+                    .set(stats.preallocated as i64);
+                if let Some(fill_ratio) = stats.fill_ratio {
+                    self.metrics
+                        .map_fill_ratio
+                        .get_or_create(&labels)
+                        .set(fill_ratio);
+                }
+                for (cpu, size) in stats.per_cpu_sizes.iter().flatten().enumerate() {
+                    self.metrics
+                        .map_cpu_size
+                        .get_or_create(&cpu_labels(&labels, cpu))
+                        .set(*size);
+                }
+                if let (Some(adds), Some(deletes)) = (stats.adds, stats.deletes) {
+                    self.metrics
+                        .map_adds
+                        .get_or_create(&labels)
+                        .inner()
+                        .store(adds, Ordering::Relaxed);
+                    self.metrics
+                        .map_deletes
+                        .get_or_create(&labels)
+                        .inner()
+                        .store(deletes, Ordering::Relaxed);
+                }
+                if self.options.stale_map_sizes {
+                    self.metrics
+                        .map_size_stale
+                        .get_or_create(&labels)
+                        .set(stats.stale as i64);
+                }
+                if let Some(info_labels) = info_labels.as_ref() {
+                    self.metrics.map_info.get_or_create(info_labels).set(1);
+                }
                 if let Some(gc) = self.gc.as_mut() {
-                    gc.add_exported_map(data.id, data.name, stats.max_size);
+                    gc.add_exported_map(&self.metrics, data.id, &labels, info_labels.as_ref());
                 }
             }
         }
@@ -216,9 +1204,257 @@ impl Exporter for PrometheusExporter {
         if let Some(gc) = self.gc.as_mut()
             && gc.collect_needed()
         {
-            gc.collect(&mut self.metrics, &self.static_lables);
+            gc.collect(&mut self.metrics);
         }
 
         Ok(())
     }
+
+    fn remove_info(&mut self, data: &BpfInfo) -> Result<()> {
+        // Sizes of maps are not gated, their series are removed only by the garbage collector
+        if let BpfStatsInfo::Cpu(_) = &data.stats {
+            let (labels, info_labels) = self.series_labels(
+                ("ebpf_id", data.id.to_string()),
+                self.program_name_labels(data.name),
+            );
+            self.metrics.remove_program(&labels, info_labels.as_ref());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_metrics_require_credentials() {
+        let state = Arc::new(Mutex::new(AppState {
+            registry: Registry::default(),
+            auth: Some(MetricsAuth::Basic("user".to_string(), "pass".to_string())),
+        }));
+        let status = |authorization: Option<&'static str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(authorization) = authorization {
+                headers.insert(AUTHORIZATION, HeaderValue::from_static(authorization));
+            }
+            let state = state.clone();
+            async move {
+                metrics_handler(State(state), headers)
+                    .await
+                    .into_response()
+                    .status()
+            }
+        };
+
+        assert_eq!(status(None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(Some("Basic dXNlcjp3cm9uZw==")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // Base64 of user:pass
+        assert_eq!(status(Some("Basic dXNlcjpwYXNz")).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_healthz_reports_uptime() {
+        let started = Instant::now() - Duration::from_secs(5);
+        let response = healthz_handler(started).await.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, r#"{"status":"ok","uptime_secs":5}"#);
+    }
+
+    #[test]
+    fn test_cpu_usage_histogram_observes_every_tick() {
+        let mut exporter = PrometheusExporter::new(
+            Labels::new(),
+            None,
+            PromExporterOptions {
+                cpu_histogram_buckets: Some(vec![0.1, 0.5]),
+                ..Default::default()
+            },
+        );
+        for cpu_usage in [0.05, 0.3, 0.2, 0.75] {
+            exporter
+                .export_info(&BpfInfo {
+                    id: 1,
+                    name: "prog",
+                    tick: 1,
+                    time_recieved: Duration::ZERO,
+                    stats: BpfStatsInfo::Cpu(BpfCPUStatsInfo {
+                        exact_cpu_usage: Some(cpu_usage),
+                        ..Default::default()
+                    }),
+                })
+                .unwrap();
+        }
+
+        let state = exporter.build_state(&[PromExportType::CPUUsage], &MetricsMetadata::new());
+        let mut text = String::new();
+        encode(&mut text, &state.registry).unwrap();
+        assert!(
+            text.contains("ebpf_cpu_usage_histogram_count{ebpf_id=\"1\",ebpf_name=\"prog\"} 4")
+        );
+        assert!(text.contains(
+            "ebpf_cpu_usage_histogram_bucket{le=\"0.5\",ebpf_id=\"1\",ebpf_name=\"prog\"} 3"
+        ));
+        assert!(text.contains("ebpf_cpu_usage{ebpf_id=\"1\",ebpf_name=\"prog\"} 0.75"));
+    }
+
+    #[test]
+    fn test_gc_removes_series_of_renamed_program() {
+        for id_only_series in [false, true] {
+            let mut exporter = PrometheusExporter::new(
+                Labels::new(),
+                Some(PromGC::new(Duration::from_secs(60))),
+                PromExporterOptions {
+                    id_only_series,
+                    ..Default::default()
+                },
+            );
+            for name in ["old_prog", "new_prog"] {
+                exporter
+                    .export_info(&BpfInfo {
+                        id: 1,
+                        name,
+                        tick: 1,
+                        time_recieved: Duration::ZERO,
+                        stats: BpfStatsInfo::Cpu(BpfCPUStatsInfo {
+                            exact_cpu_usage: Some(0.5),
+                            ..Default::default()
+                        }),
+                    })
+                    .unwrap();
+            }
+
+            let state = exporter.build_state(&[PromExportType::CPUUsage], &MetricsMetadata::new());
+            let mut text = String::new();
+            encode(&mut text, &state.registry).unwrap();
+            assert!(!text.contains("old_prog"), "{text}");
+            assert!(text.contains("new_prog"), "{text}");
+            assert_eq!(text.matches("ebpf_cpu_usage{").count(), 1, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_cpu_usage_host_fraction() {
+        let mut exporter = PrometheusExporter::new(
+            Labels::new(),
+            None,
+            PromExporterOptions {
+                host_cpus: Some(4),
+                ..Default::default()
+            },
+        );
+        exporter
+            .export_info(&BpfInfo {
+                id: 1,
+                name: "prog",
+                tick: 1,
+                time_recieved: Duration::ZERO,
+                stats: BpfStatsInfo::Cpu(BpfCPUStatsInfo {
+                    exact_cpu_usage: Some(0.5),
+                    ..Default::default()
+                }),
+            })
+            .unwrap();
+
+        let state = exporter.build_state(&[PromExportType::CPUUsage], &MetricsMetadata::new());
+        let mut text = String::new();
+        encode(&mut text, &state.registry).unwrap();
+        assert!(text.contains("ebpf_host_cpus 4"));
+        assert!(
+            text.contains("ebpf_cpu_usage_host_fraction{ebpf_id=\"1\",ebpf_name=\"prog\"} 0.125")
+        );
+        assert!(text.contains("ebpf_cpu_usage{ebpf_id=\"1\",ebpf_name=\"prog\"} 0.5"));
+    }
+
+    #[test]
+    fn test_group_usage_is_set_once_tick_is_over() {
+        let mut exporter = PrometheusExporter::new(
+            Labels::new(),
+            None,
+            PromExporterOptions {
+                groups: vec![ProgramGroup {
+                    name: "lb".to_string(),
+                    ids: vec![1, 2],
+                }],
+                ..Default::default()
+            },
+        );
+        let mut export = |id, tick, cpu_usage| {
+            exporter
+                .export_info(&BpfInfo {
+                    id,
+                    name: "prog",
+                    tick,
+                    time_recieved: Duration::ZERO,
+                    stats: BpfStatsInfo::Cpu(BpfCPUStatsInfo {
+                        exact_cpu_usage: Some(cpu_usage),
+                        ..Default::default()
+                    }),
+                })
+                .unwrap();
+        };
+        export(1, 1, 0.25);
+        export(2, 1, 0.5);
+        export(1, 2, 0.125);
+        let group_cpu_usage = |exporter: &PrometheusExporter| {
+            let state = exporter.build_state(&[PromExportType::CPUUsage], &MetricsMetadata::new());
+            let mut text = String::new();
+            encode(&mut text, &state.registry).unwrap();
+            text.lines()
+                .find(|line| line.starts_with("ebpf_group_cpu_usage{"))
+                .map(str::to_string)
+        };
+
+        // The second tick is not over, only the sum of the first one is visible
+        assert_eq!(
+            group_cpu_usage(&exporter).as_deref(),
+            Some("ebpf_group_cpu_usage{group=\"lb\"} 0.75")
+        );
+        exporter.set_group_usage();
+        assert_eq!(
+            group_cpu_usage(&exporter).as_deref(),
+            Some("ebpf_group_cpu_usage{group=\"lb\"} 0.125")
+        );
+    }
+
+    #[test]
+    fn test_unknown_metrics_in_metadata() {
+        let metadata = [
+            ("ebpf_cpu_usage", Some("ratio")),
+            ("ebpf_cpu_usge", None),
+            ("ebpf_map_size", None),
+        ]
+        .into_iter()
+        .map(|(name, unit)| {
+            let metric = MetricMetadata {
+                name: name.to_string(),
+                help: "help".to_string(),
+                unit: unit.map(str::to_string),
+            };
+            (name.to_string(), metric)
+        })
+        .collect::<MetricsMetadata>();
+        let exporter = PrometheusExporter::new(Labels::new(), None, Default::default());
+        let mut lookup = MetadataLookup::new(&metadata);
+        let mut registry = Registry::default();
+        register_metric(
+            &mut registry,
+            &mut lookup,
+            "ebpf_cpu_usage",
+            "CPU Usage of bpf programs",
+            exporter.metrics.cpu_usage.clone(),
+        );
+        exporter.meter_metrics.register(&mut registry, &mut lookup);
+
+        // The misspelled metric and the metric that is not registered
+        assert_eq!(lookup.unregistered(), ["ebpf_cpu_usge", "ebpf_map_size"]);
+    }
 }
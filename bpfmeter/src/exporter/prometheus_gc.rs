@@ -1,5 +1,5 @@
 use std::{
-    collections::HashSet,
+    collections::HashMap,
     sync::{Arc, atomic::AtomicBool},
     time::Duration,
 };
@@ -18,25 +18,49 @@ pub struct PromGC {
     waker_handle: Option<JoinHandle<()>>,
     /// Flag to indicate if garbage collection is needed
     collect_needed: Arc<AtomicBool>,
-    /// Set of currently used maps
-    used_maps: HashSet<MapLabels>,
-    /// Set of currently used cpus
-    used_progs: HashSet<ProgLabels>,
+    /// Map of currently used map ids to their labels
+    used_maps: HashMap<u32, ExportedLabels>,
+    /// Map of currently used program ids to their labels
+    used_progs: HashMap<u32, ExportedLabels>,
 }
 
-/// eBPF map identifiers
-#[derive(Debug, Default, Hash, Eq, PartialEq)]
-struct MapLabels {
-    id: u32,
-    name: String,
-    max_size: u32,
+/// Labels of series exported for eBPF program/map
+#[derive(Debug, Default)]
+struct ExportedLabels {
+    /// Labels of metric series
+    labels: Labels,
+    /// Labels of info metric series, if it is exported
+    info_labels: Option<Labels>,
 }
 
-/// eBPF programs identifiers
-#[derive(Debug, Default, Hash, Eq, PartialEq)]
-struct ProgLabels {
-    id: u32,
-    name: String,
+impl ExportedLabels {
+    fn new(labels: &Labels, info_labels: Option<&Labels>) -> Self {
+        Self {
+            labels: labels.clone(),
+            info_labels: info_labels.cloned(),
+        }
+    }
+
+    /// Returns the labels which differ from the new ones, series exported with them are
+    /// left over from the previous export
+    ///
+    /// # Arguments
+    ///
+    /// * `labels` - New labels of metric series
+    ///
+    /// * `info_labels` - New labels of info metric series
+    fn changed(
+        &self,
+        labels: &Labels,
+        info_labels: Option<&Labels>,
+    ) -> (Option<&Labels>, Option<&Labels>) {
+        let changed_labels = (self.labels != *labels).then_some(&self.labels);
+        let changed_info_labels = self
+            .info_labels
+            .as_ref()
+            .filter(|prev| Some(*prev) != info_labels);
+        (changed_labels, changed_info_labels)
+    }
 }
 
 impl PromGC {
@@ -45,8 +69,8 @@ impl PromGC {
             period,
             waker_handle: None,
             collect_needed: Arc::new(AtomicBool::new(false)),
-            used_maps: HashSet::new(),
-            used_progs: HashSet::new(),
+            used_maps: HashMap::new(),
+            used_progs: HashMap::new(),
         }
     }
 
@@ -67,36 +91,67 @@ impl PromGC {
     }
 
     /// Add map to currently used map which will not be garbage collected
-    /// on next garbage collection cycle
+    /// on next garbage collection cycle. Series exported with other labels of the same map,
+    /// e.g. before it was renamed, are removed
     ///
     /// # Arguments
     ///
+    /// * `metrics` - Metrics to remove series exported with the previous labels from
+    ///
     /// * `id` - eBPF map id
     ///
-    /// * `name` - eBPF map name
+    /// * `labels` - Labels of exported map series
     ///
-    /// * `max_size` - eBPF map max size
-    pub fn add_exported_map(&mut self, id: u32, name: &str, max_size: u32) {
-        self.used_maps.insert(MapLabels {
-            id,
-            name: name.to_string(),
-            max_size,
-        });
+    /// * `info_labels` - Labels of exported map info series
+    pub fn add_exported_map(
+        &mut self,
+        metrics: &EBPFMetrics,
+        id: u32,
+        labels: &Labels,
+        info_labels: Option<&Labels>,
+    ) {
+        let exported = ExportedLabels::new(labels, info_labels);
+        if let Some(prev) = self.used_maps.insert(id, exported) {
+            let (prev_labels, prev_info_labels) = prev.changed(labels, info_labels);
+            if let Some(prev_labels) = prev_labels {
+                metrics.remove_map(prev_labels, None);
+            }
+            if let Some(prev_info_labels) = prev_info_labels {
+                metrics.map_info.remove(prev_info_labels);
+            }
+        }
     }
 
     /// Add program to currently used cpu which will not be garbage collected
-    /// on next garbage collection cycle
+    /// on next garbage collection cycle. Series exported with other labels of the same
+    /// program, e.g. before it was renamed, are removed
     ///
     /// # Arguments
     ///
+    /// * `metrics` - Metrics to remove series exported with the previous labels from
+    ///
     /// * `id` - eBPF program id
     ///
-    /// * `name` - eBPF program name
-    pub fn add_exported_program(&mut self, id: u32, name: &str) {
-        self.used_progs.insert(ProgLabels {
-            id,
-            name: name.to_string(),
-        });
+    /// * `labels` - Labels of exported program series
+    ///
+    /// * `info_labels` - Labels of exported program info series
+    pub fn add_exported_program(
+        &mut self,
+        metrics: &EBPFMetrics,
+        id: u32,
+        labels: &Labels,
+        info_labels: Option<&Labels>,
+    ) {
+        let exported = ExportedLabels::new(labels, info_labels);
+        if let Some(prev) = self.used_progs.insert(id, exported) {
+            let (prev_labels, prev_info_labels) = prev.changed(labels, info_labels);
+            if let Some(prev_labels) = prev_labels {
+                metrics.remove_program(prev_labels, None);
+            }
+            if let Some(prev_info_labels) = prev_info_labels {
+                metrics.program_info.remove(prev_info_labels);
+            }
+        }
     }
 
     /// Check if garbage collection is needed
@@ -110,45 +165,30 @@ impl PromGC {
     /// # Arguments
     ///
     /// * `metrics` - metrics to garbage collect
-    ///
-    /// * `static_labels` - static labels to add to metrics
-    pub fn collect(&mut self, metrics: &mut EBPFMetrics, static_labels: &Labels) {
+    pub fn collect(&mut self, metrics: &mut EBPFMetrics) {
         self.collect_needed
             .store(false, std::sync::atomic::Ordering::Relaxed);
-        let mut labels = static_labels.clone();
 
         let current_map_ids = loaded_maps()
             .filter_map(|p| p.ok())
             .map(|p| p.id())
             .collect::<Vec<u32>>();
-        for map in self
+        for (_, map) in self
             .used_maps
-            .extract_if(|map| !current_map_ids.contains(&map.id))
+            .extract_if(|id, _| !current_map_ids.contains(id))
         {
-            labels.push(("ebpf_map_id".to_string(), map.id.to_string()));
-            labels.push(("ebpf_map_name".to_string(), map.name.clone()));
-            labels.push(("ebpf_map_max_size".to_string(), map.max_size.to_string()));
-            metrics.map_size.remove(&labels);
-            labels.pop();
-            labels.pop();
-            labels.pop();
+            metrics.remove_map(&map.labels, map.info_labels.as_ref());
         }
 
         let current_prog_ids = loaded_programs()
             .filter_map(|p| p.ok())
             .map(|p| p.id())
             .collect::<Vec<u32>>();
-        for prog in self
+        for (_, prog) in self
             .used_progs
-            .extract_if(|prog| !current_prog_ids.contains(&prog.id))
+            .extract_if(|id, _| !current_prog_ids.contains(id))
         {
-            labels.push(("ebpf_id".to_string(), prog.id.to_string()));
-            labels.push(("ebpf_name".to_string(), prog.name.clone()));
-            metrics.cpu_usage.remove(&labels);
-            metrics.run_time.remove(&labels);
-            metrics.event_count.remove(&labels);
-            labels.pop();
-            labels.pop();
+            metrics.remove_program(&prog.labels, prog.info_labels.as_ref());
         }
     }
 }
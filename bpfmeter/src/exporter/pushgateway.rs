@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use log::{debug, warn};
+use prometheus_client::encoding::text::encode;
+use tokio::sync::Mutex;
+
+use crate::exporter::prometheus_exporter::AppState;
+
+/// Builds the url of the Pushgateway grouping metrics of the job on this host
+///
+/// # Arguments
+///
+/// * `url` - Base url of the Pushgateway, e.g. http://127.0.0.1:9091
+///
+/// * `job` - Job the metrics are grouped by
+///
+/// * `instance` - Instance the metrics are grouped by, usually the host name
+pub fn group_url(url: &str, job: &str, instance: &str) -> String {
+    format!(
+        "{}/metrics/job/{job}/instance/{instance}",
+        url.trim_end_matches('/')
+    )
+}
+
+/// Periodically encodes the registry and pushes it to the Pushgateway
+///
+/// # Arguments
+///
+/// * `url` - Url of the group on the Pushgateway
+///
+/// * `interval` - Interval between two pushes
+///
+/// * `state` - State with the registry to push
+pub async fn push_periodically(url: String, interval: Duration, state: Arc<Mutex<AppState>>) {
+    let client = reqwest::Client::new();
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately, nothing is measured yet
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        match push(&client, &url, &state).await {
+            Ok(()) => debug!("Pushed metrics to Pushgateway"),
+            Err(e) => warn!("Failed to push metrics to Pushgateway: {e:#}"),
+        }
+    }
+}
+
+/// Encodes the registry in the same format it is served on /metrics and pushes it
+/// to the Pushgateway, replacing metrics with the same names in the group
+///
+/// # Arguments
+///
+/// * `client` - Http client
+///
+/// * `url` - Url of the group on the Pushgateway
+///
+/// * `state` - State with the registry to push
+pub async fn push(client: &reqwest::Client, url: &str, state: &Mutex<AppState>) -> Result<()> {
+    let mut buffer = String::new();
+    encode(&mut buffer, &state.lock().await.registry)?;
+
+    let response = client
+        .post(url)
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(buffer)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("Pushgateway responded with {}", response.status());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_url() {
+        assert_eq!(
+            group_url("http://127.0.0.1:9091/", "bpfmeter", "host-1"),
+            "http://127.0.0.1:9091/metrics/job/bpfmeter/instance/host-1"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use crate::{
+    exporter::Exporter,
+    meter::{BpfInfo, BpfStatsInfo},
+};
+
+/// Thresholds of event rate gating export of ebpf programs
+#[derive(Debug, Clone, Copy)]
+pub struct RateGate {
+    /// Event rate at or above which a program starts being exported
+    pub on_rate: f32,
+    /// Event rate below which an exported program is stopped being exported after the debounce
+    pub off_rate: f32,
+    /// Time the event rate has to stay below the off rate before the program is stopped
+    /// being exported
+    pub off_debounce: Duration,
+}
+
+/// Forwards BpfInfo of ebpf programs to exporter only while their event rate is high.
+/// A program starts being exported once its rate reaches the on rate and stops once
+/// the rate stays below the off rate for the debounce, so series don't flap on hosts
+/// with many occasionally active programs. Stats of maps are always forwarded
+pub struct RateGateExporter {
+    /// Exporter to forward BpfInfo to
+    exporter: Rc<RefCell<dyn Exporter>>,
+    /// Thresholds of event rate
+    gate: RateGate,
+    /// Map of exported bpf program ids to the time their event rate dropped below the off rate
+    exported: HashMap<u32, Option<Instant>>,
+}
+
+impl RateGateExporter {
+    /// Creates a new RateGateExporter
+    ///
+    /// # Arguments
+    ///
+    /// * `exporter` - Exporter to forward BpfInfo to
+    ///
+    /// * `gate` - Thresholds of event rate
+    pub fn new(exporter: Rc<RefCell<dyn Exporter>>, gate: RateGate) -> Self {
+        Self {
+            exporter,
+            gate,
+            exported: HashMap::new(),
+        }
+    }
+}
+
+impl Exporter for RateGateExporter {
+    fn export_info(&mut self, data: &BpfInfo) -> Result<()> {
+        let BpfStatsInfo::Cpu(stats) = &data.stats else {
+            return self.exporter.borrow_mut().export_info(data);
+        };
+        // Rate is unknown on the first tick and for idle programs with runtime denominator
+        let event_rate = stats.event_rate.unwrap_or_default();
+        match self.exported.get_mut(&data.id) {
+            None if event_rate < self.gate.on_rate => return Ok(()),
+            None => {
+                self.exported.insert(data.id, None);
+            }
+            Some(below_since) if event_rate >= self.gate.off_rate => *below_since = None,
+            Some(below_since) => {
+                let now = Instant::now();
+                if now - *below_since.get_or_insert(now) >= self.gate.off_debounce {
+                    self.exported.remove(&data.id);
+                    return self.exporter.borrow_mut().remove_info(data);
+                }
+            }
+        }
+        self.exporter.borrow_mut().export_info(data)
+    }
+
+    fn remove_info(&mut self, data: &BpfInfo) -> Result<()> {
+        self.exported.remove(&data.id);
+        self.exporter.borrow_mut().remove_info(data)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.exporter.borrow_mut().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meter::cpu_meter::BpfCPUStatsInfo;
+
+    /// Exporter remembering ticks of exported and removed programs
+    #[derive(Default)]
+    struct TicksExporter {
+        exported: Vec<u64>,
+        removed: Vec<u64>,
+    }
+
+    impl Exporter for TicksExporter {
+        fn export_info(&mut self, data: &BpfInfo) -> Result<()> {
+            self.exported.push(data.tick);
+            Ok(())
+        }
+
+        fn remove_info(&mut self, data: &BpfInfo) -> Result<()> {
+            self.removed.push(data.tick);
+            Ok(())
+        }
+    }
+
+    fn prog_info(tick: u64, event_rate: f32) -> BpfInfo<'static> {
+        BpfInfo {
+            id: 1,
+            name: "prog",
+            tick,
+            time_recieved: Duration::ZERO,
+            stats: BpfStatsInfo::Cpu(BpfCPUStatsInfo {
+                event_rate: Some(event_rate),
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_programs_are_exported_with_hysteresis() {
+        let ticks = Rc::new(RefCell::new(TicksExporter::default()));
+        let mut exporter = RateGateExporter::new(
+            ticks.clone(),
+            RateGate {
+                on_rate: 100.0,
+                off_rate: 10.0,
+                off_debounce: Duration::from_millis(100),
+            },
+        );
+        // Rates between the thresholds neither start nor stop the export
+        for (tick, event_rate) in [
+            (1, 50.0),
+            (2, 100.0),
+            (3, 50.0),
+            (4, 5.0),
+            (5, 20.0),
+            (6, 5.0),
+        ] {
+            exporter.export_info(&prog_info(tick, event_rate)).unwrap();
+        }
+        assert_eq!(ticks.borrow().exported, [2, 3, 4, 5, 6]);
+
+        std::thread::sleep(Duration::from_millis(100));
+        exporter.export_info(&prog_info(7, 5.0)).unwrap();
+        exporter.export_info(&prog_info(8, 50.0)).unwrap();
+        assert_eq!(ticks.borrow().exported, [2, 3, 4, 5, 6]);
+        assert_eq!(ticks.borrow().removed, [7]);
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+
+use crate::meter::BpfRawStats;
+
+/// Raw stats of ebpf program/map as they were collected, before any processing
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RawRecord {
+    /// Name of the meter the stats were collected by: cpu or map
+    pub meter: String,
+    /// Period of time between two measurements (ticks) of the meter
+    pub period: Duration,
+    /// Stats as they were received from the kernel
+    #[serde(flatten)]
+    pub stats: BpfRawStats,
+}
+
+/// Exports BpfRawStats of all meters to a JSON Lines file, one line per program/map and tick,
+/// so the stats can be processed again offline
+pub struct RawExporter {
+    /// Writer of the file
+    writer: BufWriter<File>,
+}
+
+impl RawExporter {
+    /// Creates a new RawExporter, the file is replaced if it exists
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the file to write raw stats to
+    pub fn new(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create raw stats file {}", path.display()))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Writes raw stats to the file
+    ///
+    /// # Arguments
+    ///
+    /// * `meter` - Name of the meter the stats were collected by
+    ///
+    /// * `period` - Period of time between two measurements (ticks) of the meter
+    ///
+    /// * `stats` - Raw stats of ebpf program/map
+    pub fn export_raw(&mut self, meter: &str, period: Duration, stats: &BpfRawStats) -> Result<()> {
+        let record = RawRecord {
+            meter: meter.to_string(),
+            period,
+            stats: stats.clone(),
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// Reads raw stats written by RawExporter
+///
+/// # Arguments
+///
+/// * `path` - Path to the file with raw stats
+pub fn read_raw(path: &Path) -> Result<Vec<RawRecord>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open raw stats file {}", path.display()))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().is_ok_and(|line| !line.is_empty()))
+        .map(|(number, line)| {
+            serde_json::from_str(&line?)
+                .with_context(|| format!("Invalid raw stats on line {}", number + 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_raw_stats_round_trip() {
+        let path = std::env::temp_dir().join(format!("bpfmeter_raw_{}.jsonl", std::process::id()));
+        let stats = BpfRawStats {
+            id: 7,
+            name: "xdp_fw".to_string(),
+            tick: 2,
+            time_recieved: Duration::from_millis(1500),
+            tag: 42,
+            run_count: 10,
+            run_time: Duration::from_micros(250),
+            xdp_actions: vec![0, 1, 2, 0, 0],
+            ..Default::default()
+        };
+        {
+            let mut exporter = RawExporter::new(&path).unwrap();
+            exporter
+                .export_raw("cpu", Duration::from_secs(1), &stats)
+                .unwrap();
+        }
+
+        let records = read_raw(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].meter, "cpu");
+        assert_eq!(records[0].period, Duration::from_secs(1));
+        assert_eq!(records[0].stats.run_time, stats.run_time);
+        assert_eq!(records[0].stats.xdp_actions, stats.xdp_actions);
+    }
+}
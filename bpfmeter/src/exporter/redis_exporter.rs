@@ -0,0 +1,264 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use log::info;
+use redis::aio::ConnectionManager;
+
+use crate::exporter::batch_sender::{BatchSender, BatchSink, RetryPolicy, SendError};
+use crate::exporter::prometheus_exporter::Labels;
+use crate::exporter::{BpfStatsInfo, Exporter};
+use crate::meter::BpfInfo;
+
+/// Maximum number of samples added in a single pipeline
+const MAX_BATCH_SIZE: usize = 1000;
+
+/// Single value of a time series
+#[derive(Debug, Clone)]
+struct Sample {
+    /// Key of the time series
+    key: String,
+    /// Timestamp in milliseconds since the epoch
+    timestamp: u64,
+    /// Value of the sample
+    value: f64,
+    /// Labels of the time series, applied when the series is created
+    labels: Labels,
+}
+
+/// Exports BpfInfo to RedisTimeSeries via TS.ADD command
+pub struct RedisExporter {
+    /// Static labels to be added to all time series
+    static_labels: Labels,
+    /// Sender of batches of samples to Redis
+    sender: BatchSender<Sample>,
+}
+
+impl RedisExporter {
+    /// Creates a new RedisExporter and starts the thread sending samples to Redis
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - Redis connection url, e.g. redis://127.0.0.1:6379
+    ///
+    /// * `labels` - Static labels to be added to all time series
+    pub async fn new(url: &str, labels: Labels) -> Result<Self> {
+        let client = redis::Client::open(url).with_context(|| "Invalid Redis url")?;
+        let url = url.to_string();
+        let sender = BatchSender::start(MAX_BATCH_SIZE, RetryPolicy::default(), || async move {
+            // Connection manager reconnects automatically when the connection is lost
+            let connection = client
+                .get_connection_manager()
+                .await
+                .with_context(|| format!("Failed to connect to Redis at {url}"))?;
+            info!("Exporting metrics to Redis at {url}");
+            Ok(RedisSink { connection })
+        })
+        .await?;
+
+        Ok(Self {
+            static_labels: labels,
+            sender,
+        })
+    }
+
+    /// Returns samples of the BpfInfo with static labels and ebpf program/map identity
+    /// as labels
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - BpfInfo to build samples of
+    fn samples(&self, data: &BpfInfo) -> Vec<Sample> {
+        let mut labels = self.static_labels.clone();
+        let mut values = Vec::new();
+        match &data.stats {
+            BpfStatsInfo::Cpu(stats) => {
+                labels.push(("ebpf_id".to_string(), data.id.to_string()));
+                labels.push(("ebpf_name".to_string(), data.name.to_string()));
+                if let Some(cpu_usage) = stats.exact_cpu_usage {
+                    values.push(("ebpf_cpu_usage", cpu_usage as f64));
+                }
+                values.push(("ebpf_run_time", stats.run_time.as_secs_f64()));
+                values.push(("ebpf_event_count", stats.run_count as f64));
+            }
+            BpfStatsInfo::Map(stats) => {
+                labels.push(("ebpf_map_id".to_string(), data.id.to_string()));
+                labels.push(("ebpf_map_name".to_string(), data.name.to_string()));
+                labels.push(("ebpf_map_max_size".to_string(), stats.max_size.to_string()));
+                values.push(("ebpf_map_size", stats.size as f64));
+            }
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        values
+            .into_iter()
+            .map(|(name, value)| {
+                let mut labels = labels.clone();
+                labels.push(("__name__".to_string(), name.to_string()));
+                Sample {
+                    key: format!("{name}:{}", data.id),
+                    timestamp,
+                    value,
+                    labels,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Redis batches of samples are added to in a pipeline
+struct RedisSink {
+    /// Redis connection
+    connection: ConnectionManager,
+}
+
+impl BatchSink<Sample> for RedisSink {
+    const NAME: &'static str = "Redis";
+
+    async fn send(&mut self, batch: &[Sample]) -> Result<(), SendError> {
+        pipeline(batch)
+            .exec_async(&mut self.connection)
+            .await
+            .map_err(|e| match is_transient(&e) {
+                true => SendError::Transient(e.into()),
+                false => SendError::Permanent(e.into()),
+            })
+    }
+}
+
+/// Returns pipeline of TS.ADD commands adding the samples
+///
+/// # Arguments
+///
+/// * `batch` - Samples to add
+fn pipeline(batch: &[Sample]) -> redis::Pipeline {
+    let mut pipe = redis::pipe();
+    for sample in batch {
+        let cmd = pipe
+            .cmd("TS.ADD")
+            .arg(&sample.key)
+            .arg(sample.timestamp)
+            .arg(sample.value)
+            .arg("LABELS");
+        for (name, value) in &sample.labels {
+            cmd.arg(name).arg(value);
+        }
+        cmd.ignore();
+    }
+    pipe
+}
+
+/// Returns true if the pipeline may succeed when it is sent again: the connection was
+/// lost, refused or timed out
+///
+/// # Arguments
+///
+/// * `error` - Error of the pipeline
+fn is_transient(error: &redis::RedisError) -> bool {
+    error.is_io_error()
+        || error.is_connection_dropped()
+        || error.is_connection_refusal()
+        || error.is_timeout()
+}
+
+impl Exporter for RedisExporter {
+    fn export_info(&mut self, data: &BpfInfo) -> Result<()> {
+        let samples = self.samples(data);
+        self.sender.push(data, samples);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.sender.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time::Duration};
+
+    use super::*;
+    use crate::meter::map_meter::BpfMapStatsInfo;
+
+    #[test]
+    fn test_transient_errors() {
+        let reset = io::Error::new(io::ErrorKind::ConnectionReset, "reset");
+        assert!(is_transient(&redis::RedisError::from(reset)));
+        let wrong_type =
+            redis::RedisError::from((redis::ErrorKind::UnexpectedReturnType, "wrong type"));
+        assert!(!is_transient(&wrong_type));
+    }
+
+    #[test]
+    fn test_pipeline_adds_samples_with_labels() {
+        let sample = Sample {
+            key: "ebpf_map_size:3".to_string(),
+            timestamp: 1000,
+            value: 5.0,
+            labels: vec![("__name__".to_string(), "ebpf_map_size".to_string())],
+        };
+        let packed = String::from_utf8(pipeline(&[sample]).get_packed_pipeline()).unwrap();
+        let args = packed.lines().skip(2).step_by(2).collect::<Vec<_>>();
+        assert_eq!(
+            args,
+            [
+                "TS.ADD",
+                "ebpf_map_size:3",
+                "1000",
+                "5.0",
+                "LABELS",
+                "__name__",
+                "ebpf_map_size"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_samples_of_map() {
+        struct NoSink;
+        impl BatchSink<Sample> for NoSink {
+            const NAME: &'static str = "none";
+            async fn send(&mut self, _: &[Sample]) -> Result<(), SendError> {
+                Ok(())
+            }
+        }
+        let exporter = RedisExporter {
+            static_labels: vec![("system".to_string(), "bpfmeter".to_string())],
+            sender: BatchSender::start(MAX_BATCH_SIZE, RetryPolicy::default(), || async {
+                Ok(NoSink)
+            })
+            .await
+            .unwrap(),
+        };
+        let samples = exporter.samples(&BpfInfo {
+            id: 3,
+            name: "conntrack",
+            tick: 0,
+            time_recieved: Duration::ZERO,
+            stats: BpfStatsInfo::Map(BpfMapStatsInfo {
+                size: 5,
+                max_size: 10,
+                ..Default::default()
+            }),
+        });
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].key, "ebpf_map_size:3");
+        assert_eq!(samples[0].value, 5.0);
+        let labels = samples[0]
+            .labels
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            labels,
+            [
+                ("system", "bpfmeter"),
+                ("ebpf_map_id", "3"),
+                ("ebpf_map_name", "conntrack"),
+                ("ebpf_map_max_size", "10"),
+                ("__name__", "ebpf_map_size")
+            ]
+        );
+    }
+}
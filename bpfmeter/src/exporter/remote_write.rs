@@ -0,0 +1,246 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use log::{debug, warn};
+use prometheus_client::encoding::text::encode;
+use tokio::sync::Mutex;
+
+use crate::exporter::prometheus_exporter::{AppState, Labels};
+
+/// Single series of the registry with its current value
+#[derive(Debug, PartialEq)]
+pub(crate) struct Sample {
+    /// Labels of the series including metric name in __name__ label, sorted by name
+    pub(crate) labels: Labels,
+    /// Current value of the series
+    pub(crate) value: f64,
+}
+
+/// Periodically encodes the registry and pushes its series to remote write endpoint
+///
+/// # Arguments
+///
+/// * `url` - Remote write endpoint
+///
+/// * `interval` - Interval between two pushes
+///
+/// * `state` - State with the registry to push
+pub async fn push_periodically(url: String, interval: Duration, state: Arc<Mutex<AppState>>) {
+    let client = reqwest::Client::new();
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately, nothing is measured yet
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let mut buffer = String::new();
+        if let Err(e) = encode(&mut buffer, &state.lock().await.registry) {
+            warn!("Failed to encode metrics for remote write: {e}");
+            continue;
+        }
+        match push(&client, &url, &buffer).await {
+            Ok(count) => debug!("Pushed {count} series to remote write endpoint"),
+            Err(e) => warn!("Failed to push metrics to remote write endpoint: {e:#}"),
+        }
+    }
+}
+
+/// Converts metrics in OpenMetrics text format to remote write request and sends it
+///
+/// # Arguments
+///
+/// * `client` - Http client
+///
+/// * `url` - Remote write endpoint
+///
+/// * `text` - Metrics in OpenMetrics text format
+///
+/// Returns the number of pushed series
+async fn push(client: &reqwest::Client, url: &str, text: &str) -> Result<usize> {
+    let samples = parse_samples(text)?;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64;
+    let body = snap::raw::Encoder::new()
+        .compress_vec(&encode_write_request(&samples, timestamp))
+        .with_context(|| "Failed to compress remote write request")?;
+
+    let response = client
+        .post(url)
+        .header("Content-Encoding", "snappy")
+        .header("Content-Type", "application/x-protobuf")
+        .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+        .body(body)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        bail!("Remote write endpoint responded with {}", response.status());
+    }
+    Ok(samples.len())
+}
+
+/// Parses series and their values from metrics in OpenMetrics text format
+///
+/// # Arguments
+///
+/// * `text` - Metrics in OpenMetrics text format
+pub(crate) fn parse_samples(text: &str) -> Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    for line in text
+        .lines()
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+    {
+        let name_end = line.find(['{', ' ']).context("Invalid sample line")?;
+        let mut labels = vec![("__name__".to_string(), line[..name_end].to_string())];
+        let mut rest = &line[name_end..];
+        if rest.starts_with('{') {
+            let (series_labels, after) = parse_labels(&rest[1..])
+                .with_context(|| format!("Invalid labels of sample: {line}"))?;
+            labels.extend(series_labels);
+            rest = after;
+        }
+        let value = rest
+            .split_whitespace()
+            .next()
+            .context("Sample has no value")?;
+        let value = value
+            .parse::<f64>()
+            .with_context(|| format!("Invalid value of sample: {line}"))?;
+        labels.sort();
+        samples.push(Sample { labels, value });
+    }
+    Ok(samples)
+}
+
+/// Parses labels of a sample up to the closing brace
+///
+/// # Arguments
+///
+/// * `s` - Part of the sample line after the opening brace
+///
+/// Returns labels and the rest of the line after the closing brace
+fn parse_labels(s: &str) -> Result<(Labels, &str)> {
+    let mut labels = Vec::new();
+    let mut rest = s;
+    loop {
+        rest = rest.trim_start_matches(',');
+        if let Some(after) = rest.strip_prefix('}') {
+            return Ok((labels, after));
+        }
+        let (name, after) = rest.split_once("=\"").context("Label has no value")?;
+        // Label values are not escaped by the encoder, names of ebpf programs/maps
+        // can't contain quotes
+        let (value, after) = after.split_once('"').context("Unterminated label value")?;
+        labels.push((name.to_string(), value.to_string()));
+        rest = after;
+    }
+}
+
+/// Encodes samples into protobuf WriteRequest message of remote write protocol
+///
+/// # Arguments
+///
+/// * `samples` - Samples to encode, each of them is a separate time series
+///
+/// * `timestamp` - Timestamp of all samples in milliseconds since the epoch
+fn encode_write_request(samples: &[Sample], timestamp: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    for sample in samples {
+        let mut series = Vec::new();
+        for (name, value) in &sample.labels {
+            let mut label = Vec::new();
+            put_bytes(&mut label, 1, name.as_bytes());
+            put_bytes(&mut label, 2, value.as_bytes());
+            put_bytes(&mut series, 1, &label);
+        }
+        let mut value = Vec::new();
+        // Field 1 (value) is double, field 2 (timestamp) is varint
+        value.push(1 << 3 | 1);
+        value.extend(sample.value.to_le_bytes());
+        value.push(2 << 3);
+        put_varint(&mut value, timestamp as u64);
+        put_bytes(&mut series, 2, &value);
+        put_bytes(&mut request, 1, &series);
+    }
+    request
+}
+
+/// Appends length-delimited protobuf field
+///
+/// # Arguments
+///
+/// * `buf` - Buffer to append to
+///
+/// * `field` - Field number
+///
+/// * `bytes` - Field value
+pub(crate) fn put_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(buf, field << 3 | 2);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// Appends protobuf varint
+///
+/// # Arguments
+///
+/// * `buf` - Buffer to append to
+///
+/// * `value` - Value to encode
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::{
+        metrics::{family::Family, gauge::Gauge},
+        registry::Registry,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_parse_samples_of_registry() {
+        let mut registry = Registry::default();
+        let family = Family::<Labels, Gauge>::default();
+        registry.register("ebpf_event_count", "Event count", family.clone());
+        let labels = vec![
+            ("ebpf_name".to_string(), "a, b={c}".to_string()),
+            ("ebpf_id".to_string(), "7".to_string()),
+        ];
+        family.get_or_create(&labels).set(42);
+
+        let mut text = String::new();
+        encode(&mut text, &registry).unwrap();
+        assert_eq!(
+            parse_samples(&text).unwrap(),
+            [Sample {
+                labels: vec![
+                    ("__name__".to_string(), "ebpf_event_count".to_string()),
+                    ("ebpf_id".to_string(), "7".to_string()),
+                    ("ebpf_name".to_string(), "a, b={c}".to_string()),
+                ],
+                value: 42.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_encode_write_request() {
+        let samples = [Sample {
+            labels: vec![("a".to_string(), "b".to_string())],
+            value: 1.0,
+        }];
+        let mut expected = vec![0x0a, 0x16, 0x0a, 0x06, 0x0a, 0x01, b'a', 0x12, 0x01, b'b'];
+        expected.extend([0x12, 0x0c, 0x09]);
+        expected.extend(1.0f64.to_le_bytes());
+        expected.extend([0x10, 0xac, 0x02]);
+        assert_eq!(encode_write_request(&samples, 300), expected);
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    io::Write,
+};
+
+use anyhow::Result;
+
+use crate::{
+    exporter::Exporter,
+    meter::{BpfInfo, BpfStatsInfo},
+};
+
+/// Escape sequence clearing the terminal and moving the cursor to the top left corner
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Exports BpfInfo to a table in the terminal, redrawn on every tick like top
+#[derive(Default)]
+pub struct TableExporter {
+    /// Rows of ebpf programs
+    programs: Section,
+    /// Rows of ebpf maps
+    maps: Section,
+}
+
+/// Rows of ebpf programs/maps measured by the same meter
+#[derive(Default)]
+struct Section {
+    /// Tick of the rows being received
+    tick: u64,
+    /// Map of bpf program/map ids to their names and last stats
+    rows: HashMap<u32, (String, BpfStatsInfo)>,
+    /// Ids of bpf programs/maps received on the current tick
+    seen: HashSet<u32>,
+    /// Number of rows received on the previous tick
+    expected: usize,
+    /// The table was drawn with all rows of the current tick
+    drawn: bool,
+}
+
+impl Section {
+    /// Updates row of the program/map
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - BpfInfo to update row with
+    ///
+    /// Returns true if the table has to be redrawn
+    fn update(&mut self, data: &BpfInfo) -> bool {
+        let mut redraw = false;
+        if data.tick != self.tick {
+            // Rows of the previous tick are complete even if fewer of them were received
+            redraw = !self.drawn && !self.seen.is_empty();
+            // Programs/maps not seen on the previous tick are unloaded
+            self.rows.retain(|id, _| self.seen.contains(id));
+            self.expected = self.seen.len();
+            self.seen.clear();
+            self.tick = data.tick;
+            self.drawn = false;
+        }
+        self.rows
+            .insert(data.id, (data.name.to_string(), data.stats.clone()));
+        self.seen.insert(data.id);
+        // The same programs/maps are usually measured on consecutive ticks, so the tick
+        // is drawn as soon as the same number of rows is received
+        if !self.drawn && self.seen.len() >= self.expected {
+            self.drawn = true;
+            redraw = true;
+        }
+        redraw
+    }
+}
+
+impl TableExporter {
+    /// Creates a new TableExporter
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Renders tables of programs sorted by cpu usage and maps sorted by size
+    fn render(&self) -> String {
+        let mut out = String::new();
+        if !self.programs.rows.is_empty() {
+            let mut rows = self.programs.rows.iter().collect::<Vec<_>>();
+            rows.sort_by(|(a_id, (_, a)), (b_id, (_, b))| {
+                cpu_usage(b).total_cmp(&cpu_usage(a)).then(a_id.cmp(b_id))
+            });
+            let _ = writeln!(
+                out,
+                "{:>8}  {:<16}  {:>8}  {:>12}  {:>14}",
+                "ID", "NAME", "CPU %", "RUN TIME", "EVENTS"
+            );
+            for (id, (name, stats)) in rows {
+                let BpfStatsInfo::Cpu(stats) = stats else {
+                    continue;
+                };
+                let cpu = stats.exact_cpu_usage.map_or("-".to_string(), |cpu_usage| {
+                    format!("{:.2}", cpu_usage * 100.0)
+                });
+                let _ = writeln!(
+                    out,
+                    "{id:>8}  {name:<16}  {cpu:>8}  {:>11.3}s  {:>14}",
+                    stats.run_time.as_secs_f64(),
+                    stats.run_count
+                );
+            }
+        }
+        if !self.maps.rows.is_empty() {
+            if !out.is_empty() {
+                out.push('\n');
+            }
+            let mut rows = self.maps.rows.iter().collect::<Vec<_>>();
+            rows.sort_by_key(|(id, (_, stats))| (std::cmp::Reverse(map_size(stats)), **id));
+            let _ = writeln!(
+                out,
+                "{:>8}  {:<16}  {:>10}  {:>10}",
+                "ID", "NAME", "SIZE", "MAX SIZE"
+            );
+            for (id, (name, stats)) in rows {
+                let BpfStatsInfo::Map(stats) = stats else {
+                    continue;
+                };
+                let _ = writeln!(
+                    out,
+                    "{id:>8}  {name:<16}  {:>10}  {:>10}",
+                    stats.size, stats.max_size
+                );
+            }
+        }
+        out
+    }
+
+    /// Clears the terminal and draws the tables
+    fn draw(&self) -> Result<()> {
+        let mut stdout = std::io::stdout().lock();
+        write!(stdout, "{CLEAR_SCREEN}{}", self.render())?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Returns cpu usage of the program, programs without it are sorted last
+///
+/// # Arguments
+///
+/// * `stats` - Stats of the program
+fn cpu_usage(stats: &BpfStatsInfo) -> f32 {
+    match stats {
+        BpfStatsInfo::Cpu(stats) => stats.exact_cpu_usage.unwrap_or(-1.0),
+        BpfStatsInfo::Map(_) => -1.0,
+    }
+}
+
+/// Returns size of the map
+///
+/// # Arguments
+///
+/// * `stats` - Stats of the map
+fn map_size(stats: &BpfStatsInfo) -> u32 {
+    match stats {
+        BpfStatsInfo::Map(stats) => stats.size,
+        BpfStatsInfo::Cpu(_) => 0,
+    }
+}
+
+impl Exporter for TableExporter {
+    fn export_info(&mut self, data: &BpfInfo) -> Result<()> {
+        let redraw = match data.stats {
+            BpfStatsInfo::Cpu(_) => self.programs.update(data),
+            BpfStatsInfo::Map(_) => self.maps.update(data),
+        };
+        if redraw {
+            self.draw()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::meter::cpu_meter::BpfCPUStatsInfo;
+
+    fn prog_info(id: u32, tick: u64, exact_cpu_usage: f32) -> BpfInfo<'static> {
+        BpfInfo {
+            id,
+            name: "prog",
+            tick,
+            time_recieved: Duration::ZERO,
+            stats: BpfStatsInfo::Cpu(BpfCPUStatsInfo {
+                exact_cpu_usage: Some(exact_cpu_usage),
+                run_time: Duration::from_millis(1500),
+                run_count: 42,
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn test_rows_are_sorted_by_cpu_usage_and_unloaded_are_removed() {
+        let mut table = TableExporter::new();
+        table.programs.update(&prog_info(1, 1, 0.01));
+        table.programs.update(&prog_info(2, 1, 0.2));
+        table.programs.update(&prog_info(3, 1, 0.05));
+        assert_eq!(
+            table.render(),
+            "      ID  NAME                 CPU %      RUN TIME          EVENTS\n\
+             \x20      2  prog                 20.00        1.500s              42\n\
+             \x20      3  prog                  5.00        1.500s              42\n\
+             \x20      1  prog                  1.00        1.500s              42\n"
+        );
+
+        // Program 3 is unloaded, so fewer rows are received and the tick is drawn
+        // when the next one starts
+        assert!(!table.programs.update(&prog_info(1, 2, 0.01)));
+        assert!(!table.programs.update(&prog_info(2, 2, 0.2)));
+        assert!(table.programs.update(&prog_info(1, 3, 0.01)));
+        assert_eq!(
+            table.programs.rows.keys().copied().collect::<HashSet<_>>(),
+            HashSet::from([1, 2])
+        );
+    }
+}
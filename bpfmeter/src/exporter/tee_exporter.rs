@@ -0,0 +1,45 @@
+use std::{cell::RefCell, rc::Rc};
+
+use anyhow::Result;
+
+use crate::{exporter::Exporter, meter::BpfInfo};
+
+/// Exports BpfInfo to several exporters at once
+pub struct TeeExporter {
+    /// Exporters to forward BpfInfo to
+    exporters: Vec<Rc<RefCell<dyn Exporter>>>,
+}
+
+impl TeeExporter {
+    /// Creates a new TeeExporter
+    ///
+    /// # Arguments
+    ///
+    /// * `exporters` - Exporters to forward BpfInfo to
+    pub fn new(exporters: Vec<Rc<RefCell<dyn Exporter>>>) -> Self {
+        Self { exporters }
+    }
+}
+
+impl Exporter for TeeExporter {
+    fn export_info(&mut self, data: &BpfInfo) -> Result<()> {
+        for exporter in &self.exporters {
+            exporter.borrow_mut().export_info(data)?;
+        }
+        Ok(())
+    }
+
+    fn remove_info(&mut self, data: &BpfInfo) -> Result<()> {
+        for exporter in &self.exporters {
+            exporter.borrow_mut().remove_info(data)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for exporter in &self.exporters {
+            exporter.borrow_mut().flush()?;
+        }
+        Ok(())
+    }
+}
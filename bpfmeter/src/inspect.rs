@@ -0,0 +1,226 @@
+use std::{fs::File, io::BufReader, time::Duration};
+
+use anyhow::{Context, Result};
+use humantime::format_duration;
+
+use crate::{
+    capture::{
+        RowTime, Series, TimedRecord, csv_files, file_period, group_series, read_manifest,
+        read_timed_records_counting_errors, series_period,
+    },
+    config::InspectArgs,
+    meter::{cpu_meter::BpfCPUStatsInfo, map_meter::BpfMapStatsInfo},
+};
+
+/// Summary of a single series of the capture
+#[derive(Debug, serde::Serialize)]
+struct SeriesSummary {
+    /// Name of the series
+    name: String,
+    /// Csv files of the series
+    files: Vec<String>,
+    /// Measured value: exact_cpu_usage for programs or size for maps
+    value: &'static str,
+    /// Number of parsed rows
+    rows: usize,
+    /// Period of time between two rows
+    period: String,
+    /// Time covered by the rows, from the times the rows were received at
+    time_span: String,
+    /// Number of gaps: unparsable rows, resets of cumulative counters, ticks missing
+    /// between two rows and restarts of the measurements
+    gaps: usize,
+    /// Number of ticks missing between the rows
+    missed_ticks: u64,
+    /// Minimum of the measured value
+    min: Option<f64>,
+    /// Average of the measured value
+    avg: Option<f64>,
+    /// Maximum of the measured value
+    max: Option<f64>,
+}
+
+pub fn inspect(args: &InspectArgs) -> Result<()> {
+    let series = group_series(csv_files(&args.input)?, args.merge_daily);
+    let summaries = series.iter().map(summarize).collect::<Result<Vec<_>>>()?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&summaries)?);
+        return Ok(());
+    }
+
+    if args.input.is_dir()
+        && let Some(manifest) = read_manifest(&args.input)?
+    {
+        println!(
+            "Captured by bpfmeter {} on {} (kernel {}, {} CPUs) from {} to {}",
+            manifest.version,
+            manifest.host.hostname,
+            manifest.host.kernel,
+            manifest.host.cpus,
+            manifest.start,
+            manifest.end
+        );
+        if let Some(version) = manifest.workload_version {
+            println!("Workload version: {version}");
+        }
+        println!("Arguments: {}", manifest.args.join(" "));
+    }
+    println!("Series found: {}", summaries.len());
+    for summary in summaries {
+        println!();
+        println!("{}", summary.name);
+        println!("  Files: {}", summary.files.join(", "));
+        println!(
+            "  Rows: {}, period: {}, time span: {}",
+            summary.rows, summary.period, summary.time_span
+        );
+        println!(
+            "  Gaps: {}, missed ticks: {}",
+            summary.gaps, summary.missed_ticks
+        );
+        match (summary.min, summary.avg, summary.max) {
+            (Some(min), Some(avg), Some(max)) => println!(
+                "  {}: min {min:.6}, avg {avg:.6}, max {max:.6}",
+                summary.value
+            ),
+            _ => println!("  {}: no values", summary.value),
+        }
+    }
+    Ok(())
+}
+
+/// Summarizes measurements of the series
+///
+/// # Arguments
+///
+/// * `series` - Series to summarize
+fn summarize(series: &Series) -> Result<SeriesSummary> {
+    let first_file = series.files.first().context("Series has no files")?;
+    let period = file_period(first_file)?;
+    let period_duration = series_period(series)?;
+
+    // Map measurements have size column instead of cpu usage
+    let headers = csv::Reader::from_reader(BufReader::new(File::open(first_file)?))
+        .headers()?
+        .clone();
+    let (value, values, times, gaps) = if headers.iter().any(|h| h == "size") {
+        let (records, errors) = read_timed_records_counting_errors::<BpfMapStatsInfo>(series)?;
+        let values = records
+            .iter()
+            .map(|(_, r)| r.size as f64)
+            .collect::<Vec<_>>();
+        ("size", values, row_times(&records), errors)
+    } else {
+        let (records, errors) = read_timed_records_counting_errors::<BpfCPUStatsInfo>(series)?;
+        let values = records
+            .iter()
+            .filter_map(|(_, r)| r.exact_cpu_usage.map(f64::from))
+            .collect::<Vec<_>>();
+        // Cumulative counters go down only if the program was reloaded or measurements were lost
+        let resets = records
+            .windows(2)
+            .filter(|w| w[1].1.run_count < w[0].1.run_count || w[1].1.run_time < w[0].1.run_time)
+            .count();
+        (
+            "exact_cpu_usage",
+            values,
+            row_times(&records),
+            errors + resets,
+        )
+    };
+    let rows = times.len();
+    // Files written without the time columns are assumed to have a row on every tick
+    let (time_span, tick_gaps, missed_ticks) =
+        match times.iter().copied().collect::<Option<Vec<_>>>() {
+            Some(times) => tick_coverage(&times, period_duration),
+            None => (period_duration * rows as u32, 0, 0),
+        };
+
+    Ok(SeriesSummary {
+        name: series.name.clone(),
+        files: series
+            .files
+            .iter()
+            .map(|f| f.display().to_string())
+            .collect(),
+        value,
+        rows,
+        period,
+        time_span: format_duration(time_span).to_string(),
+        gaps: gaps + tick_gaps,
+        missed_ticks,
+        min: values.iter().copied().reduce(f64::min),
+        avg: (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64),
+        max: values.iter().copied().reduce(f64::max),
+    })
+}
+
+/// Returns times of the records
+///
+/// # Arguments
+///
+/// * `records` - Records with their times
+fn row_times<R>(records: &[TimedRecord<R>]) -> Vec<Option<RowTime>> {
+    records.iter().map(|(time, _)| *time).collect()
+}
+
+/// Calculates the time covered by the rows from the times they were received at
+/// and finds ticks missing between them. Tick numbers starting over mean the measurements
+/// were restarted, the time between the runs is not covered
+///
+/// # Arguments
+///
+/// * `times` - Times of the rows in chronological order
+///
+/// * `period` - Measurement period, the first row of a run covers one period before it
+///
+/// Returns the covered time, the number of gaps and the number of missed ticks
+fn tick_coverage(times: &[RowTime], period: Duration) -> (Duration, usize, u64) {
+    let (mut span, mut gaps, mut missed_ticks) = (Duration::ZERO, 0, 0);
+    let mut run_start = match times.first() {
+        Some(first) => first.time_recieved,
+        None => return (span, gaps, missed_ticks),
+    };
+    for pair in times.windows(2) {
+        let (prev, next) = (pair[0], pair[1]);
+        if next.tick <= prev.tick {
+            span += prev.time_recieved.saturating_sub(run_start) + period;
+            run_start = next.time_recieved;
+            gaps += 1;
+        } else if next.tick > prev.tick + 1 {
+            gaps += 1;
+            missed_ticks += next.tick - prev.tick - 1;
+        }
+    }
+    let last = times.last().map_or(run_start, |last| last.time_recieved);
+    span += last.saturating_sub(run_start) + period;
+    (span, gaps, missed_ticks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_coverage() {
+        let time = |tick, ms| RowTime {
+            tick,
+            time_recieved: Duration::from_millis(ms),
+        };
+        let period = Duration::from_secs(1);
+        // Ticks 3 and 4 are missed, then the measurements are restarted
+        let times = [
+            time(1, 1000),
+            time(2, 2000),
+            time(5, 5100),
+            time(1, 1000),
+            time(2, 2000),
+        ];
+        assert_eq!(
+            tick_coverage(&times, period),
+            (Duration::from_millis(5100 + 2000), 2, 2)
+        );
+        assert_eq!(tick_coverage(&[], period), (Duration::ZERO, 0, 0));
+    }
+}
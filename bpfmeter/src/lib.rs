@@ -0,0 +1,20 @@
+//! Performance measurement of eBPF programs and maps. The library collects the same stats
+//! as the `bpfmeter` binary, so they can be measured from inside another process:
+//! a [`Meter`] collects stats of loaded programs or maps on every tick and
+//! [`run::measure`] exports them with an [`Exporter`]
+
+pub mod capture;
+pub mod config;
+pub mod diff;
+#[cfg(feature = "draw")]
+pub mod draw;
+pub mod exporter;
+pub mod inspect;
+pub mod list;
+pub mod meter;
+pub mod probe;
+pub mod reprocess;
+pub mod run;
+
+pub use exporter::Exporter;
+pub use meter::{BpfRawStats, BpfStatsInfo, Meter, cpu_meter::CpuMeter, map_meter::MapMeter};
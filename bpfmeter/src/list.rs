@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use aya::{
+    maps::{self, MapType},
+    programs::{self, ProgramType},
+};
+
+use crate::{
+    config::ListArgs,
+    meter::{
+        CollectOptions, Meter,
+        bpf_types::{self, BpfMapType, BpfProgramType},
+        cpu_meter::CpuMeter,
+        map_meter::MapMeter,
+    },
+};
+
+/// Loaded ebpf program or map
+#[derive(Debug, serde::Serialize)]
+struct Entity {
+    /// Kind of the entity: program or map
+    kind: &'static str,
+    /// Ebpf program/map id
+    id: u32,
+    /// Ebpf program/map name
+    name: String,
+    /// Type of the program/map, names follow bpftool
+    #[serde(rename = "type")]
+    entity_type: String,
+    /// Maximum number of entries of the map
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_entries: Option<u32>,
+}
+
+pub fn list(args: &ListArgs) -> Result<()> {
+    let options = CollectOptions::default();
+    let mut entities = Vec::new();
+    if !args.maps_only {
+        let types: HashMap<u32, String> = programs::loaded_programs()
+            .filter_map(|p| p.ok())
+            .map(|p| (p.id(), program_type_name(p.program_type().ok())))
+            .collect();
+        entities.extend(with_types(
+            "program",
+            CpuMeter::get_id_name_entity_mapping(&options),
+            |id| Some((types.get(&id)?.clone(), None)),
+        ));
+    }
+    if !args.programs_only {
+        let types: HashMap<u32, (String, u32)> = maps::loaded_maps()
+            .filter_map(|m| m.ok())
+            .map(|m| (m.id(), (map_type_name(m.map_type().ok()), m.max_entries())))
+            .collect();
+        entities.extend(with_types(
+            "map",
+            MapMeter::get_id_name_entity_mapping(&options),
+            |id| {
+                let (map_type, max_entries) = types.get(&id)?;
+                Some((map_type.clone(), Some(*max_entries)))
+            },
+        ));
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&entities)?);
+        return Ok(());
+    }
+
+    println!(
+        "{:<8} {:<8} {:<20} {:<24} Max entries",
+        "Kind", "Id", "Name", "Type"
+    );
+    for entity in entities {
+        let max_entries = entity
+            .max_entries
+            .map_or_else(|| "-".to_string(), |max_entries| max_entries.to_string());
+        println!(
+            "{:<8} {:<8} {:<20} {:<24} {max_entries}",
+            entity.kind, entity.id, entity.name, entity.entity_type
+        );
+    }
+    Ok(())
+}
+
+/// Builds entities sorted by id from the mapping of a meter, entities unloaded since
+/// the mapping was read are skipped
+///
+/// # Arguments
+///
+/// * `kind` - Kind of the entities: program or map
+///
+/// * `mapping` - Mapping of ids to names returned by the meter
+///
+/// * `details` - Returns the type and max entries of the entity by its id
+fn with_types(
+    kind: &'static str,
+    mapping: HashMap<u32, String>,
+    details: impl Fn(u32) -> Option<(String, Option<u32>)>,
+) -> Vec<Entity> {
+    let mut entities = mapping
+        .into_iter()
+        .filter_map(|(id, name)| {
+            let (entity_type, max_entries) = details(id)?;
+            Some(Entity {
+                kind,
+                id,
+                name,
+                entity_type,
+                max_entries,
+            })
+        })
+        .collect::<Vec<_>>();
+    entities.sort_by_key(|entity| entity.id);
+    entities
+}
+
+/// Returns the bpftool name of the program type, unknown if aya does not recognize it
+fn program_type_name(program_type: Option<ProgramType>) -> String {
+    program_type
+        .and_then(bpf_types::type_name::<BpfProgramType, _>)
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Returns the bpftool name of the map type, unknown if aya does not recognize it
+fn map_type_name(map_type: Option<MapType>) -> String {
+    map_type
+        .and_then(bpf_types::type_name::<BpfMapType, _>)
+        .unwrap_or_else(|| "unknown".to_string())
+}
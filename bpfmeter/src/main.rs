@@ -1,15 +1,16 @@
-mod config;
-#[cfg(feature = "draw")]
-mod draw;
-mod exporter;
-mod meter;
-mod run;
-
 use anyhow::Result;
+#[cfg(feature = "draw")]
+use bpfmeter::draw;
+use bpfmeter::{config, diff, inspect, list, probe, reprocess, run};
 use log::LevelFilter;
 use std::time::SystemTime;
 
-fn setup_logger(level: LevelFilter) -> Result<(), fern::InitError> {
+fn setup_logger(level: LevelFilter, to_stderr: bool) -> Result<(), fern::InitError> {
+    let output: fern::Output = if to_stderr {
+        std::io::stderr().into()
+    } else {
+        std::io::stdout().into()
+    };
     fern::Dispatch::new()
         .format(|out, message, record| {
             out.finish(format_args!(
@@ -20,7 +21,7 @@ fn setup_logger(level: LevelFilter) -> Result<(), fern::InitError> {
             ))
         })
         .level(level)
-        .chain(std::io::stdout())
+        .chain(output)
         .apply()?;
     Ok(())
 }
@@ -28,11 +29,16 @@ fn setup_logger(level: LevelFilter) -> Result<(), fern::InitError> {
 fn main() -> Result<()> {
     // Init config
     let config = &*config::CONFIG;
-    setup_logger(config.log_level.parse()?)?;
+    setup_logger(config.log_level.parse()?, config.writes_to_stdout())?;
 
     match &config.command {
         config::SubCommands::Run(args) => run::run(args),
         #[cfg(feature = "draw")]
         config::SubCommands::Draw(args) => draw::draw(args),
+        config::SubCommands::Inspect(args) => inspect::inspect(args),
+        config::SubCommands::Diff(args) => diff::diff(args),
+        config::SubCommands::Probe(args) => probe::probe(args),
+        config::SubCommands::List(args) => list::list(args),
+        config::SubCommands::Reprocess(args) => reprocess::reprocess(args),
     }
 }
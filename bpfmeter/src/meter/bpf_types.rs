@@ -0,0 +1,164 @@
+use std::fmt::Debug;
+use std::sync::LazyLock;
+
+use aya::{maps::MapType, programs::ProgramType};
+use aya_obj::generated::{bpf_map_type, bpf_prog_type};
+use clap::{ValueEnum, builder::PossibleValue};
+
+use super::map_meter::TARGET_MAP_TYPES;
+
+/// Type of ebpf program accepted in command line arguments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BpfProgramType(pub ProgramType);
+
+/// Type of ebpf map accepted in command line arguments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BpfMapType(pub MapType);
+
+/// Program types known to aya and their names
+static PROGRAM_TYPES: LazyLock<Vec<(BpfProgramType, String)>> = LazyLock::new(|| {
+    aya_types::<bpf_prog_type, ProgramType>("BPF_PROG_TYPE_")
+        .into_iter()
+        .map(|(program_type, name)| (BpfProgramType(program_type), name))
+        .collect()
+});
+
+/// Map types known to aya and their names
+static MAP_TYPES: LazyLock<Vec<(BpfMapType, String)>> = LazyLock::new(|| {
+    aya_types::<bpf_map_type, MapType>("BPF_MAP_TYPE_")
+        .into_iter()
+        .map(|(map_type, name)| (BpfMapType(map_type), name))
+        .collect()
+});
+
+/// Returns all aya types converted from the kernel types with their names. Names follow
+/// bpftool: the kernel constants without the prefix in lower case, e.g. sched_cls
+///
+/// # Arguments
+///
+/// * `prefix` - Common prefix of the kernel constants
+fn aya_types<K, A>(prefix: &str) -> Vec<(A, String)>
+where
+    K: TryFrom<u32> + Debug + Copy,
+    A: TryFrom<K>,
+{
+    // Kernel types are numbered from 1 without gaps, 0 is unspecified type
+    (1..)
+        .map_while(|raw_type| K::try_from(raw_type).ok())
+        .filter_map(|kernel_type| {
+            let constant = format!("{kernel_type:?}");
+            let name = constant
+                .strip_prefix(prefix)?
+                .trim_end_matches("_DEPRECATED")
+                .to_lowercase();
+            Some((A::try_from(kernel_type).ok()?, name))
+        })
+        .collect()
+}
+
+/// Returns possible value of the type with the name taken from the table of types
+///
+/// # Arguments
+///
+/// * `types` - Types and their names
+///
+/// * `bpf_type` - Type to return possible value of
+fn possible_value<T: PartialEq>(
+    types: &'static [(T, String)],
+    bpf_type: &T,
+) -> Option<PossibleValue> {
+    types
+        .iter()
+        .find(|(t, _)| t == bpf_type)
+        .map(|(_, name)| PossibleValue::new(name.as_str()))
+}
+
+impl ValueEnum for BpfProgramType {
+    fn value_variants<'a>() -> &'a [Self] {
+        static VARIANTS: LazyLock<Vec<BpfProgramType>> =
+            LazyLock::new(|| PROGRAM_TYPES.iter().map(|(t, _)| *t).collect());
+        &VARIANTS
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        possible_value(&PROGRAM_TYPES, self)
+    }
+}
+
+impl ValueEnum for BpfMapType {
+    fn value_variants<'a>() -> &'a [Self] {
+        static VARIANTS: LazyLock<Vec<BpfMapType>> =
+            LazyLock::new(|| MAP_TYPES.iter().map(|(t, _)| *t).collect());
+        &VARIANTS
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        possible_value(&MAP_TYPES, self)
+    }
+}
+
+impl From<BpfProgramType> for ProgramType {
+    fn from(program_type: BpfProgramType) -> Self {
+        program_type.0
+    }
+}
+
+impl From<BpfMapType> for MapType {
+    fn from(map_type: BpfMapType) -> Self {
+        map_type.0
+    }
+}
+
+/// Returns the name of the aya program/map type as accepted in command line arguments,
+/// None if the type has no name there
+///
+/// # Arguments
+///
+/// * `aya_type` - Program or map type as reported by aya
+pub fn type_name<T, A>(aya_type: A) -> Option<String>
+where
+    T: ValueEnum + Copy + 'static,
+    A: From<T> + PartialEq,
+{
+    T::value_variants()
+        .iter()
+        .find(|v| A::from(**v) == aya_type)
+        .and_then(|v| v.to_possible_value())
+        .map(|v| v.get_name().to_string())
+}
+
+/// Returns map types accepted by --map-types, the ones which sizes can be measured
+pub fn measured_map_types() -> impl Iterator<Item = BpfMapType> {
+    BpfMapType::value_variants()
+        .iter()
+        .copied()
+        .filter(|map_type| TARGET_MAP_TYPES.contains(&MapType::from(*map_type)))
+}
+
+/// Returns names of all variants accepted in command line arguments
+pub fn type_names<T: ValueEnum + 'static>() -> impl Iterator<Item = String> {
+    T::value_variants()
+        .iter()
+        .filter_map(|v| v.to_possible_value())
+        .map(|v| v.get_name().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_type_names_follow_bpftool() {
+        let program_type = type_name::<BpfProgramType, _>(ProgramType::SchedClassifier);
+        assert_eq!(program_type.as_deref(), Some("sched_cls"));
+        assert_eq!(
+            type_name::<BpfMapType, _>(MapType::CgroupStorage).as_deref(),
+            Some("cgroup_storage")
+        );
+        assert_eq!(type_name::<BpfMapType, _>(MapType::Unspecified), None);
+        assert_eq!(
+            BpfProgramType::from_str("ext", false),
+            Ok(BpfProgramType(ProgramType::Extension))
+        );
+    }
+}
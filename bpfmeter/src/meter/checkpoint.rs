@@ -0,0 +1,54 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+
+/// Last seen cumulative counters of an ebpf program, persisted across bpfmeter restarts
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct CheckpointEntry {
+    /// Ebpf program tag, distinguishes a different program loaded with the same id
+    pub tag: u64,
+    /// Time spent in the ebpf program
+    pub run_time: Duration,
+    /// Number of times the ebpf program was run
+    pub run_count: u64,
+    /// Wall-clock time the counters were seen
+    pub timestamp: SystemTime,
+}
+
+/// Map of ebpf program ids to their last seen counters
+pub type Checkpoint = HashMap<u32, CheckpointEntry>;
+
+/// Loads checkpoint, an empty checkpoint is returned if the file doesn't exist yet
+///
+/// # Arguments
+///
+/// * `path` - Path to the checkpoint file
+pub fn load(path: &Path) -> Result<Checkpoint> {
+    if !path.exists() {
+        return Ok(Checkpoint::new());
+    }
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open checkpoint {}", path.display()))?;
+    serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Invalid checkpoint {}", path.display()))
+}
+
+/// Saves checkpoint, the previous checkpoint is replaced
+///
+/// # Arguments
+///
+/// * `path` - Path to the checkpoint file
+///
+/// * `checkpoint` - Checkpoint to save
+pub fn save(path: &Path, checkpoint: &Checkpoint) -> Result<()> {
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create checkpoint {}", path.display()))?;
+    serde_json::to_writer(BufWriter::new(file), checkpoint)?;
+    Ok(())
+}
@@ -1,102 +1,1055 @@
-use std::{collections::HashMap, ops::Sub, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
 
-use anyhow::{Result, bail};
-use aya::programs;
-use log::warn;
+use anyhow::{Context, Result, bail};
+use aya::programs::{self, ProgramType};
+use clap::ValueEnum;
+use log::{error, info, warn};
 use serde_with::DurationSecondsWithFrac;
 use serde_with::serde_as;
 use tokio::sync::mpsc::Sender;
 
 use crate::{
     meter::BpfStatsInfo,
-    meter::{BpfRawStats, Meter},
+    meter::{
+        BpfRawStats, CollectOptions, CollectStats, Meter, PhaseTimer, ProgramCounters,
+        checkpoint::{self, Checkpoint, CheckpointEntry},
+        entity_name, skip_unreadable, xdp_actions,
+    },
 };
 
 /// Measures CPU usage of the ebpf program
 pub struct CpuMeter {
     /// Map of bpf program ids to previous BpfRawStats to calculate cpu usage
     bpf_prog_info_map: HashMap<u32, BpfRawStats>,
+    /// Options of cpu usage calculation
+    options: CpuMeterOptions,
+    /// Map of bpf program tags to counters of their loaded and unloaded instances
+    tags: HashMap<u64, TagState>,
+    /// Tick tags that are gone were last pruned on
+    tags_pruned_tick: u64,
+    /// Map of bpf program ids to the number of consecutive ticks without changes of counters
+    unchanged_ticks: HashMap<u32, u32>,
+    /// Map of bpf program ids to their cpu usage on the last ticks of the z-score window
+    cpu_usage_history: HashMap<u32, VecDeque<f32>>,
+    /// Last seen counters of bpf programs, loaded on start and saved on drop
+    checkpoint: Checkpoint,
+    /// Map of bpf program ids to the time their cpu usage has been continuously above
+    /// the threshold
+    above_threshold: HashMap<u32, Duration>,
+    /// Map of bpf program ids to exponential moving average of their cpu usage
+    smoothed_cpu_usage: HashMap<u32, f32>,
+    /// Map of bpf program ids to the max cpu usage since their first measurement
+    peak_cpu_usage: HashMap<u32, f32>,
+}
+
+/// Time a tag is remembered after its last program is unloaded, so a program reloaded
+/// within it continues the counters of the tag. Tags seen on the previous tick are kept
+/// regardless of the time
+const TAG_RETENTION: Duration = Duration::from_secs(60);
+
+/// Counters of programs with the same tag
+#[derive(Debug, Default)]
+struct TagState {
+    /// Id of the first program seen with the tag, stats of the tag are reported under it
+    id: u32,
+    /// Map of ids of loaded programs with the tag to their counters on the last tick
+    instances: HashMap<u32, ProgramCounters>,
+    /// Counters accumulated by unloaded programs with the tag
+    offset: ProgramCounters,
+    /// Time the tag was last seen
+    last_seen: Duration,
+    /// Tick the tag was last seen on
+    last_seen_tick: u64,
+}
+
+/// Options of cpu usage calculation
+#[derive(Clone, Debug, Default)]
+pub struct CpuMeterOptions {
+    /// Export cumulative counters on the first observation of a program
+    pub raw_counters: bool,
+    /// Continue counters of programs with the same tag across reloads
+    pub dedupe_by_tag: bool,
+    /// Number of consecutive ticks without changes of run time and run count after which
+    /// the program is reported as stuck. Stuck programs are not detected if not specified
+    pub stuck_threshold: Option<u32>,
+    /// Number of previous ticks cpu usage z-score is calculated over.
+    /// Z-score is not calculated if not specified
+    pub zscore_window: Option<usize>,
+    /// Time the number of runs is divided by to calculate event rate
+    pub rate_denominator: RateDenominator,
+    /// File to save the last seen counters to and continue from after restart
+    pub checkpoint: Option<PathBuf>,
+    /// Cpu usage above which the time programs are continuously hot is tracked.
+    /// The time is not tracked if not specified
+    pub cpu_usage_threshold: Option<f32>,
+    /// Number of online CPUs cpu usage is divided by to get the share of all CPUs
+    /// of the host. The share is not calculated if not specified
+    pub online_cpus: Option<usize>,
+    /// Weight of the current cpu usage in its exponential moving average.
+    /// Cpu usage is not smoothed if not specified
+    pub cpu_smoothing_alpha: Option<f32>,
+}
+
+/// What cpu usage of ebpf programs is relative to
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum CpuUsageMode {
+    /// Share of a single CPU: run time over wall-clock time, exceeds 1 when the program
+    /// runs on several CPUs at once
+    #[default]
+    PerCore,
+    /// Share of all online CPUs of the host is added: per core usage divided by their
+    /// number, from 0 to 1
+    System,
+}
+
+/// Time the number of runs is divided by to calculate event rate
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum RateDenominator {
+    /// Wall-clock interval between two measurements: runs per second (throughput)
+    #[default]
+    Wall,
+    /// Time spent in the ebpf program in the interval: runs per second of execution
+    Runtime,
 }
 
 /// Serializable CPU usage information
 #[serde_as]
-#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct BpfCPUStatsInfo {
-    /// CPU usage in the interval between two measurements with time adjustments applied
-    pub exact_cpu_usage: f32,
+    /// CPU usage in the interval between two measurements with time adjustments applied.
+    /// Share of a single CPU, may exceed 1 if the program runs on several CPUs at once.
+    /// Empty for the first observation of a program exported in raw counters mode
+    pub exact_cpu_usage: Option<f32>,
     /// Time spent in the ebpf program starting from the first measurement
     #[serde_as(as = "DurationSecondsWithFrac<String>")]
     pub run_time: Duration,
     /// Number of times the ebpf program was run starting from the first measurement
     pub run_count: u64,
+    /// Max cpu usage since the first measurement or the last reset of counters.
+    /// Empty for the first observation of a program exported in raw counters mode
+    pub peak_cpu_usage: Option<f32>,
+    /// Number of packets processed by XDP program for each action code
+    #[serde(skip_serializing, skip_deserializing)]
+    pub xdp_actions: Vec<u64>,
+    /// Number of runs per second of the rate denominator in the interval between two
+    /// measurements. Empty if the program wasn't run in runtime denominator mode
+    #[serde(skip_serializing, skip_deserializing)]
+    pub event_rate: Option<f32>,
+    /// Counters of the ebpf program haven't changed for the stuck threshold number of ticks.
+    /// Empty if stuck programs are not detected
+    #[serde(skip_serializing, skip_deserializing)]
+    pub stuck: Option<bool>,
+    /// Z-score of cpu usage relative to the previous ticks of the window. Empty if z-score
+    /// is not calculated or the window doesn't have enough variation yet
+    #[serde(skip_serializing, skip_deserializing)]
+    pub cpu_usage_zscore: Option<f32>,
+    /// Time cpu usage has been continuously above the threshold, zero if it is below.
+    /// Empty if the time is not tracked
+    #[serde(skip_serializing, skip_deserializing)]
+    pub above_threshold: Option<Duration>,
+    /// Exponential moving average of cpu usage. Not set if cpu usage is not smoothed, so
+    /// files have no column for it, and empty for the first observation of a program
+    /// exported in raw counters mode
+    #[serde(skip_serializing_if = "Option::is_none", skip_deserializing)]
+    pub smoothed_cpu_usage: Option<Option<f32>>,
+    /// CPU usage as a share of all online CPUs of the host, from 0 to 1. Not set in
+    /// per-core mode, so files have no column for it, and empty for the first observation
+    /// of a program exported in raw counters mode
+    #[serde(skip_serializing_if = "Option::is_none", skip_deserializing)]
+    pub host_cpu_usage: Option<Option<f32>>,
 }
 
 impl CpuMeter {
-    pub fn new() -> Self {
+    /// Creates a new CpuMeter
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Options of cpu usage calculation
+    pub fn new(options: CpuMeterOptions) -> Self {
+        let checkpoint = match options.checkpoint.as_deref().map(checkpoint::load) {
+            Some(Ok(checkpoint)) => checkpoint,
+            Some(Err(e)) => {
+                warn!("Ignoring checkpoint: {e:#}");
+                Checkpoint::new()
+            }
+            None => Checkpoint::new(),
+        };
         Self {
+            checkpoint,
             bpf_prog_info_map: HashMap::new(),
+            options,
+            tags: HashMap::new(),
+            tags_pruned_tick: 0,
+            unchanged_ticks: HashMap::new(),
+            cpu_usage_history: HashMap::new(),
+            above_threshold: HashMap::new(),
+            smoothed_cpu_usage: HashMap::new(),
+            peak_cpu_usage: HashMap::new(),
+        }
+    }
+
+    /// Identifies program stats by the first program seen with the same tag and continues
+    /// its counters after the program is reloaded. Counters are tracked per program id,
+    /// so an unloaded program adds its last counters once, even while other programs
+    /// with the same tag stay loaded
+    ///
+    /// # Arguments
+    ///
+    /// * `raw_stats` - BpfRawStats summed over all loaded programs with the same tag
+    fn continue_by_tag(&mut self, raw_stats: &BpfRawStats) -> BpfRawStats {
+        self.prune_tags(raw_stats);
+
+        let instances = if raw_stats.tag_instances.is_empty() {
+            HashMap::from([(raw_stats.id, ProgramCounters::of(raw_stats))])
+        } else {
+            raw_stats.tag_instances.iter().cloned().collect()
+        };
+        let tag = self.tags.entry(raw_stats.tag).or_insert_with(|| TagState {
+            id: raw_stats.id,
+            ..Default::default()
+        });
+        for (id, prev) in &tag.instances {
+            // Program is unloaded, or reloaded with the same id and counts from zero again
+            if instances
+                .get(id)
+                .is_none_or(|counters| counters.went_down(prev))
+            {
+                tag.offset.add(prev);
+            }
+        }
+        tag.instances = instances;
+        tag.last_seen = raw_stats.time_recieved;
+        tag.last_seen_tick = raw_stats.tick;
+
+        let mut counters = ProgramCounters::of(raw_stats);
+        counters.add(&tag.offset);
+        BpfRawStats {
+            id: tag.id,
+            run_time: counters.run_time,
+            run_count: counters.run_count,
+            xdp_actions: counters.xdp_actions,
+            ..raw_stats.clone()
+        }
+    }
+
+    /// Forgets tags that haven't been seen since before the previous tick and for the
+    /// retention time once per tick, along with the state of programs their stats were
+    /// reported under
+    ///
+    /// # Arguments
+    ///
+    /// * `raw_stats` - BpfRawStats of the current tick
+    fn prune_tags(&mut self, raw_stats: &BpfRawStats) {
+        if raw_stats.tick == self.tags_pruned_tick {
+            return;
+        }
+        self.tags_pruned_tick = raw_stats.tick;
+        let now = raw_stats.time_recieved;
+        let gone = self
+            .tags
+            .iter()
+            .filter(|(_, tag)| {
+                tag.last_seen_tick + 1 < raw_stats.tick
+                    && now.saturating_sub(tag.last_seen) > TAG_RETENTION
+            })
+            .map(|(&tag, state)| (tag, state.id))
+            .collect::<Vec<_>>();
+        for (tag, id) in gone {
+            self.tags.remove(&tag);
+            self.bpf_prog_info_map.remove(&id);
+            self.reset_program_state(id);
+        }
+    }
+
+    /// Builds previous stats of the program from the checkpoint saved before restart
+    ///
+    /// # Arguments
+    ///
+    /// * `raw_stats` - BpfRawStats of the first observation of the program
+    ///
+    /// Returns previous stats and the interval since they were seen, None if the program
+    /// is not in the checkpoint or was reloaded since
+    fn restore_from_checkpoint(&self, raw_stats: &BpfRawStats) -> Option<(BpfRawStats, Duration)> {
+        let entry = self.checkpoint.get(&raw_stats.id)?;
+        // Counters go down if the program was reloaded and got the same id
+        if entry.tag != raw_stats.tag
+            || entry.run_time > raw_stats.run_time
+            || entry.run_count > raw_stats.run_count
+        {
+            return None;
+        }
+        let interval = SystemTime::now().duration_since(entry.timestamp).ok()?;
+        let prev_stats = BpfRawStats {
+            run_time: entry.run_time,
+            run_count: entry.run_count,
+            ..raw_stats.clone()
+        };
+        Some((prev_stats, interval))
+    }
+
+    /// Remembers counters of the program to be saved to the checkpoint
+    ///
+    /// # Arguments
+    ///
+    /// * `raw_stats` - Current BpfRawStats of the program
+    fn update_checkpoint(&mut self, raw_stats: &BpfRawStats) {
+        if self.options.checkpoint.is_none() {
+            return;
+        }
+        self.checkpoint.insert(
+            raw_stats.id,
+            CheckpointEntry {
+                tag: raw_stats.tag,
+                run_time: raw_stats.run_time,
+                run_count: raw_stats.run_count,
+                timestamp: SystemTime::now(),
+            },
+        );
+    }
+
+    /// Counts consecutive ticks without changes of program counters and reports
+    /// whether the program is stuck
+    ///
+    /// # Arguments
+    ///
+    /// * `raw_stats` - Current BpfRawStats of the program
+    ///
+    /// * `prev_stats` - BpfRawStats of the program on the previous tick
+    fn detect_stuck(&mut self, raw_stats: &BpfRawStats, prev_stats: &BpfRawStats) -> Option<bool> {
+        let threshold = self.options.stuck_threshold?;
+        let unchanged = self.unchanged_ticks.entry(raw_stats.id).or_default();
+        if raw_stats.run_time == prev_stats.run_time && raw_stats.run_count == prev_stats.run_count
+        {
+            *unchanged += 1;
+            if *unchanged == threshold {
+                warn!(
+                    "Counters of ebpf program {} ({}) haven't changed for {threshold} ticks",
+                    raw_stats.id, raw_stats.name
+                );
+            }
+        } else {
+            *unchanged = 0;
         }
+        Some(*unchanged >= threshold)
     }
+
+    /// Calculates z-score of cpu usage relative to the mean and standard deviation
+    /// of the program cpu usage on the previous ticks of the window
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Ebpf program id
+    ///
+    /// * `cpu_usage` - Current cpu usage of the program
+    fn cpu_usage_zscore(&mut self, id: u32, cpu_usage: f32) -> Option<f32> {
+        let window = self.options.zscore_window?;
+        let history = self.cpu_usage_history.entry(id).or_default();
+        let zscore = (history.len() >= 2)
+            .then(|| {
+                let n = history.len() as f32;
+                let mean = history.iter().sum::<f32>() / n;
+                let variance = history.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / n;
+                (cpu_usage - mean) / variance.sqrt()
+            })
+            // Constant cpu usage has zero deviation, so any change has no finite z-score
+            .filter(|zscore| zscore.is_finite());
+
+        history.push_back(cpu_usage);
+        if history.len() > window {
+            history.pop_front();
+        }
+        zscore
+    }
+
+    /// Accumulates the time cpu usage of the program has been continuously above the
+    /// threshold, the time is reset when the usage drops to the threshold or below
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Ebpf program id
+    ///
+    /// * `cpu_usage` - Cpu usage of the program in the interval
+    ///
+    /// * `interval` - Interval between two measurements
+    fn time_above_threshold(
+        &mut self,
+        id: u32,
+        cpu_usage: f32,
+        interval: Duration,
+    ) -> Option<Duration> {
+        let threshold = self.options.cpu_usage_threshold?;
+        let above = self.above_threshold.entry(id).or_default();
+        if cpu_usage > threshold {
+            *above += interval;
+        } else {
+            *above = Duration::ZERO;
+        }
+        Some(*above)
+    }
+
+    /// Updates exponential moving average of cpu usage of the program, the average of
+    /// a new program starts from its first cpu usage
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Ebpf program id
+    ///
+    /// * `cpu_usage` - Cpu usage of the program in the interval
+    fn smooth_cpu_usage(&mut self, id: u32, cpu_usage: f32) -> Option<f32> {
+        let alpha = self.options.cpu_smoothing_alpha?;
+        let smoothed = self
+            .smoothed_cpu_usage
+            .entry(id)
+            .and_modify(|smoothed| *smoothed += alpha * (cpu_usage - *smoothed))
+            .or_insert(cpu_usage);
+        Some(*smoothed)
+    }
+
+    /// Forgets everything accumulated from the previous cpu usage of the program, so
+    /// it is measured as a new program after its counters are reset
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Ebpf program id
+    fn reset_program_state(&mut self, id: u32) {
+        self.unchanged_ticks.remove(&id);
+        self.cpu_usage_history.remove(&id);
+        self.above_threshold.remove(&id);
+        self.smoothed_cpu_usage.remove(&id);
+        self.peak_cpu_usage.remove(&id);
+    }
+
+    /// Updates the max cpu usage of the program
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Ebpf program id
+    ///
+    /// * `cpu_usage` - Cpu usage of the program in the interval
+    fn peak_cpu_usage(&mut self, id: u32, cpu_usage: f32) -> f32 {
+        let peak = self.peak_cpu_usage.entry(id).or_insert(cpu_usage);
+        *peak = peak.max(cpu_usage);
+        *peak
+    }
+}
+
+/// Returns true if ebpf program is of a type collected with the options. Programs of
+/// unknown types are collected only if types are not restricted
+///
+/// # Arguments
+///
+/// * `options` - Options of collecting ebpf program stats
+///
+/// * `program` - Ebpf program
+fn is_collected_type(options: &CollectOptions, program: &programs::ProgramInfo) -> bool {
+    options.program_types.is_empty()
+        || program
+            .program_type()
+            .is_ok_and(|program_type| options.program_types.contains(&program_type))
 }
 
 impl Meter for CpuMeter {
-    fn get_id_name_entity_mapping() -> HashMap<u32, String> {
+    const NAME: &'static str = "cpu";
+
+    fn get_id_name_entity_mapping(options: &CollectOptions) -> HashMap<u32, String> {
         programs::loaded_programs()
             .filter_map(|p| p.ok())
-            .map(|p| (p.id(), p.name_as_str().map(|x| x.to_string()).unwrap()))
+            .filter(|p| is_collected_type(options, p))
+            .map(|p| (p.id(), entity_name(p.name_as_str())))
             .collect()
     }
 
     async fn collect_raw_stats(
-        prog_list_ids: &[u32],
+        options: &CollectOptions,
         base_stats: &BpfRawStats,
         tx: Sender<BpfRawStats>,
-    ) -> Result<()> {
-        let bpf_program_iter = programs::loaded_programs();
-        for program in bpf_program_iter
-            .filter_map(|p| p.ok())
-            .filter(|p| prog_list_ids.is_empty() || prog_list_ids.contains(&p.id()))
-        {
-            if tx.capacity() == 0 {
-                warn!("Channel is full, result may be inaccurate");
+    ) -> Result<CollectStats> {
+        let mut collect_stats = CollectStats::default();
+        collect_stats.filtered.insert("id", 0);
+        collect_stats.filtered.insert("baseline", 0);
+        collect_stats.filtered.insert("program_type", 0);
+
+        let mut timer = PhaseTimer::new(options.verbose_timing);
+        let mut programs = Vec::new();
+        for program in programs::loaded_programs() {
+            match skip_unreadable(options, program)? {
+                Some(program) => programs.push(program),
+                // Unreadable program can't be filtered, so it is counted as intended
+                None => collect_stats.intended += 1,
+            }
+        }
+        timer.finish_phase("enumeration");
+
+        let mut programs_stats = Vec::new();
+        for program in programs {
+            if !options.ids.is_empty() && !options.ids.contains(&program.id()) {
+                *collect_stats.filtered.entry("id").or_default() += 1;
+                continue;
             }
+            if options.baseline_ids.contains(&program.id()) {
+                *collect_stats.filtered.entry("baseline").or_default() += 1;
+                continue;
+            }
+            if !is_collected_type(options, &program) {
+                *collect_stats.filtered.entry("program_type").or_default() += 1;
+                continue;
+            }
+            collect_stats.intended += 1;
             let mut bpf_program_stats = base_stats.clone();
             bpf_program_stats.id = program.id();
-            bpf_program_stats.name = program.name_as_str().unwrap_or("unknown").to_string();
+            bpf_program_stats.name = entity_name(program.name_as_str());
+            bpf_program_stats.tag = program.tag();
             bpf_program_stats.run_count = program.run_count();
             bpf_program_stats.run_time = program.run_time();
+            if options.xdp_actions && matches!(program.program_type(), Ok(ProgramType::Xdp)) {
+                let actions = xdp_actions::read_xdp_actions(&program)
+                    .with_context(|| format!("Failed to read XDP actions of {}", program.id()));
+                if let Some(actions) = skip_unreadable(options, actions)? {
+                    bpf_program_stats.xdp_actions = actions.unwrap_or_default();
+                }
+            }
+            collect_stats.measured += 1;
+            programs_stats.push(bpf_program_stats);
+        }
 
+        if options.dedupe_by_tag {
+            programs_stats = sum_by_tag(programs_stats);
+        }
+        timer.finish_phase("reads");
+
+        for bpf_program_stats in programs_stats {
+            if tx.capacity() == 0 {
+                warn!("Channel is full, result may be inaccurate");
+            }
             if let Err(e) = tx.send(bpf_program_stats).await {
                 bail!("Failed to send program to channel: {e}");
             }
         }
-        Ok(())
+        timer.finish_phase("send");
+        timer.log(Self::NAME, base_stats.tick);
+        Ok(collect_stats)
     }
 
     fn generate_stats_info(&mut self, raw_stats: &BpfRawStats) -> Option<BpfStatsInfo> {
-        // Find previous info for the particular program id
-        let Some(prev_stats) = self.bpf_prog_info_map.get_mut(&raw_stats.id) else {
-            let id = raw_stats.id;
-            self.bpf_prog_info_map.insert(id, raw_stats.clone());
-            // Nothing to return, we should have at least two measurements to calculate cpu usage
-            return None;
+        let deduped_stats;
+        let raw_stats = if self.options.dedupe_by_tag {
+            deduped_stats = self.continue_by_tag(raw_stats);
+            &deduped_stats
+        } else {
+            raw_stats
         };
 
+        // Find previous info for the particular program id, the first observation after
+        // restart continues from the checkpoint
+        let prev = match self.bpf_prog_info_map.remove(&raw_stats.id) {
+            // Counters go down if the program was reloaded and got the same id, it is
+            // observed as a new program then
+            Some(prev_stats)
+                if raw_stats.run_time < prev_stats.run_time
+                    || raw_stats.run_count < prev_stats.run_count =>
+            {
+                info!(
+                    "Counters of ebpf program {} {} were reset, measuring it from the start",
+                    raw_stats.id, raw_stats.name
+                );
+                self.reset_program_state(raw_stats.id);
+                None
+            }
+            Some(prev_stats) => {
+                let interval = raw_stats
+                    .time_recieved
+                    .saturating_sub(prev_stats.time_recieved);
+                Some((prev_stats, interval))
+            }
+            None => self.restore_from_checkpoint(raw_stats),
+        };
+        self.update_checkpoint(raw_stats);
+        let (prev_stats, interval) = match prev {
+            Some(prev) => prev,
+            None => {
+                let id = raw_stats.id;
+                self.bpf_prog_info_map.insert(id, raw_stats.clone());
+                // We should have at least two measurements to calculate cpu usage,
+                // but cumulative counters are already known
+                return self
+                    .options
+                    .raw_counters
+                    .then_some(BpfStatsInfo::Cpu(BpfCPUStatsInfo {
+                        exact_cpu_usage: None,
+                        run_time: raw_stats.run_time,
+                        run_count: raw_stats.run_count,
+                        peak_cpu_usage: None,
+                        xdp_actions: raw_stats.xdp_actions.clone(),
+                        event_rate: None,
+                        stuck: self.options.stuck_threshold.map(|_| false),
+                        cpu_usage_zscore: None,
+                        above_threshold: self.options.cpu_usage_threshold.map(|_| Duration::ZERO),
+                        smoothed_cpu_usage: self.options.cpu_smoothing_alpha.map(|_| None),
+                        host_cpu_usage: self.options.online_cpus.map(|_| None),
+                    }));
+            }
+        };
+
+        // Stats received at the same time (coarse clock or a duplicated send) have no interval
+        // to divide by, the program is measured over the interval to the next tick instead
+        if interval.is_zero() {
+            self.bpf_prog_info_map.insert(raw_stats.id, prev_stats);
+            return None;
+        }
+
         // Calculate run time in the interval between two measurements
         let run_time_diff = raw_stats.run_time - prev_stats.run_time;
 
         // Calculate cpu usage
-        let interval = raw_stats.time_recieved.sub(prev_stats.time_recieved);
         let cpu_usage = run_time_diff.as_secs_f32() / interval.as_secs_f32();
+        let runs = raw_stats.run_count.saturating_sub(prev_stats.run_count) as f32;
+        let event_rate = match self.options.rate_denominator {
+            RateDenominator::Wall => Some(runs / interval.as_secs_f32()),
+            RateDenominator::Runtime => {
+                (!run_time_diff.is_zero()).then(|| runs / run_time_diff.as_secs_f32())
+            }
+        };
 
         let export_stats = BpfCPUStatsInfo {
-            exact_cpu_usage: cpu_usage,
+            exact_cpu_usage: Some(cpu_usage),
             run_time: raw_stats.run_time,
             run_count: raw_stats.run_count,
+            peak_cpu_usage: Some(self.peak_cpu_usage(raw_stats.id, cpu_usage)),
+            xdp_actions: raw_stats.xdp_actions.clone(),
+            event_rate,
+            stuck: self.detect_stuck(raw_stats, &prev_stats),
+            cpu_usage_zscore: self.cpu_usage_zscore(raw_stats.id, cpu_usage),
+            above_threshold: self.time_above_threshold(raw_stats.id, cpu_usage, interval),
+            smoothed_cpu_usage: self.smooth_cpu_usage(raw_stats.id, cpu_usage).map(Some),
+            host_cpu_usage: self
+                .options
+                .online_cpus
+                .map(|cpus| Some(cpu_usage / cpus as f32)),
         };
         // Set current info as previous info
-        *prev_stats = raw_stats.clone();
+        self.bpf_prog_info_map
+            .insert(raw_stats.id, raw_stats.clone());
 
         Some(BpfStatsInfo::Cpu(export_stats))
     }
 }
+
+impl Drop for CpuMeter {
+    fn drop(&mut self) {
+        let Some(path) = self.options.checkpoint.as_deref() else {
+            return;
+        };
+        // Programs that weren't seen by this run are unloaded, their counters are outdated
+        self.checkpoint
+            .retain(|id, _| self.bpf_prog_info_map.contains_key(id));
+        match checkpoint::save(path, &self.checkpoint) {
+            Ok(()) => info!("Checkpoint is saved to {}", path.display()),
+            Err(e) => error!("Failed to save checkpoint: {e:#}"),
+        }
+    }
+}
+
+/// Sums stats of programs with the same tag, the lowest id is used for the sum.
+/// Counters of each summed program are kept in the sum by its id
+///
+/// # Arguments
+///
+/// * `programs_stats` - Stats of loaded programs
+fn sum_by_tag(programs_stats: Vec<BpfRawStats>) -> Vec<BpfRawStats> {
+    let mut tag_stats: HashMap<u64, (BpfRawStats, ProgramCounters)> = HashMap::new();
+    for stats in programs_stats {
+        let counters = ProgramCounters::of(&stats);
+        let (sum, sum_counters) = tag_stats
+            .entry(stats.tag)
+            .or_insert_with(|| (stats.clone(), ProgramCounters::default()));
+        sum.id = sum.id.min(stats.id);
+        sum_counters.add(&counters);
+        sum.tag_instances.push((stats.id, counters));
+    }
+    tag_stats
+        .into_values()
+        .map(|(sum, counters)| BpfRawStats {
+            run_time: counters.run_time,
+            run_count: counters.run_count,
+            xdp_actions: counters.xdp_actions,
+            ..sum
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn raw_stats(time_recieved_ms: u64, run_time_ms: u64, run_count: u64) -> BpfRawStats {
+        BpfRawStats {
+            id: 1,
+            name: "test".to_string(),
+            time_recieved: Duration::from_millis(time_recieved_ms),
+            run_time: Duration::from_millis(run_time_ms),
+            run_count,
+            ..Default::default()
+        }
+    }
+
+    fn cpu_stats(info: Option<BpfStatsInfo>) -> BpfCPUStatsInfo {
+        match info {
+            Some(BpfStatsInfo::Cpu(stats)) => stats,
+            other => panic!("Expected cpu stats, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_counters_reset() {
+        let mut meter = CpuMeter::new(CpuMeterOptions {
+            stuck_threshold: Some(2),
+            zscore_window: Some(5),
+            cpu_usage_threshold: Some(0.05),
+            cpu_smoothing_alpha: Some(0.5),
+            ..Default::default()
+        });
+        assert!(meter.generate_stats_info(&raw_stats(0, 0, 0)).is_none());
+        meter.generate_stats_info(&raw_stats(1000, 500, 50));
+        meter.generate_stats_info(&raw_stats(2000, 800, 80));
+        assert!(
+            meter
+                .generate_stats_info(&raw_stats(3000, 100, 10))
+                .is_none()
+        );
+        assert!(!meter.unchanged_ticks.contains_key(&1));
+        assert!(!meter.cpu_usage_history.contains_key(&1));
+        assert!(!meter.above_threshold.contains_key(&1));
+        assert!(!meter.smoothed_cpu_usage.contains_key(&1));
+        assert!(!meter.peak_cpu_usage.contains_key(&1));
+
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(4000, 200, 20)));
+        assert!((stats.exact_cpu_usage.unwrap() - 0.1).abs() < 1e-6);
+        // Nothing measured before the reset is carried over
+        assert_eq!(stats.above_threshold, Some(Duration::from_secs(1)));
+        assert_eq!(stats.cpu_usage_zscore, None);
+        assert_eq!(stats.stuck, Some(false));
+        assert!((stats.smoothed_cpu_usage.flatten().unwrap() - 0.1).abs() < 1e-6);
+        assert!((stats.peak_cpu_usage.unwrap() - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_peak_cpu_usage_is_reset_with_counters() {
+        let mut meter = CpuMeter::new(CpuMeterOptions::default());
+        meter.generate_stats_info(&raw_stats(0, 0, 0));
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(1000, 500, 1)));
+        assert!((stats.peak_cpu_usage.unwrap() - 0.5).abs() < 1e-6);
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(2000, 600, 2)));
+        assert!((stats.peak_cpu_usage.unwrap() - 0.5).abs() < 1e-6);
+
+        assert!(
+            meter
+                .generate_stats_info(&raw_stats(3000, 100, 3))
+                .is_none()
+        );
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(4000, 300, 4)));
+        assert!((stats.peak_cpu_usage.unwrap() - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_zero_interval_is_skipped() {
+        let mut meter = CpuMeter::new(CpuMeterOptions::default());
+        assert!(meter.generate_stats_info(&raw_stats(1000, 0, 0)).is_none());
+        assert!(meter.generate_stats_info(&raw_stats(1000, 50, 5)).is_none());
+
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(2000, 100, 10)));
+        assert!((stats.exact_cpu_usage.unwrap() - 0.1).abs() < 1e-6);
+        assert!((stats.event_rate.unwrap() - 10.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_run_time_without_run_count() {
+        let mut meter = CpuMeter::new(CpuMeterOptions::default());
+        assert!(meter.generate_stats_info(&raw_stats(0, 0, 0)).is_none());
+
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(1000, 100, 0)));
+        let cpu_usage = stats.exact_cpu_usage.unwrap();
+        assert!(cpu_usage.is_finite());
+        assert!((cpu_usage - 0.1).abs() < 1e-6);
+        assert_eq!(stats.run_count, 0);
+    }
+
+    #[test]
+    fn test_run_count_without_run_time() {
+        let mut meter = CpuMeter::new(CpuMeterOptions::default());
+        assert!(meter.generate_stats_info(&raw_stats(0, 0, 0)).is_none());
+
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(1000, 0, 100)));
+        assert_eq!(stats.exact_cpu_usage, Some(0.0));
+        assert_eq!(stats.run_count, 100);
+    }
+
+    #[test]
+    fn test_raw_counters_first_observation() {
+        let mut meter = CpuMeter::new(CpuMeterOptions {
+            raw_counters: true,
+            ..Default::default()
+        });
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(0, 100, 10)));
+        assert_eq!(stats.exact_cpu_usage, None);
+        assert_eq!(stats.run_time, Duration::from_millis(100));
+        assert_eq!(stats.run_count, 10);
+    }
+
+    #[test]
+    fn test_smoothed_cpu_usage_keeps_csv_columns() {
+        let mut meter = CpuMeter::new(CpuMeterOptions {
+            raw_counters: true,
+            cpu_smoothing_alpha: Some(0.5),
+            ..Default::default()
+        });
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        for raw_stats in [raw_stats(0, 0, 0), raw_stats(1000, 500, 5)] {
+            let stats = cpu_stats(meter.generate_stats_info(&raw_stats));
+            writer.serialize(&stats).unwrap();
+        }
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "exact_cpu_usage,run_time,run_count,peak_cpu_usage,smoothed_cpu_usage\n\
+             ,0,0,,\n\
+             0.5,0.5,5,0.5,0.5\n"
+        );
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let read: BpfCPUStatsInfo = reader.deserialize().nth(1).unwrap().unwrap();
+        assert_eq!(read.exact_cpu_usage, Some(0.5));
+
+        // Files of runs without smoothing have no such column
+        let mut meter = CpuMeter::new(CpuMeterOptions::default());
+        meter.generate_stats_info(&raw_stats(0, 0, 0));
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(1000, 500, 5)));
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(&stats).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert!(csv.starts_with("exact_cpu_usage,run_time,run_count,peak_cpu_usage\n"));
+    }
+
+    #[test]
+    fn test_dedupe_by_tag_continues_after_reload() {
+        let mut meter = CpuMeter::new(CpuMeterOptions {
+            dedupe_by_tag: true,
+            ..Default::default()
+        });
+        let with_id = |id, stats: BpfRawStats| BpfRawStats {
+            id,
+            tag: 42,
+            ..stats
+        };
+        assert!(
+            meter
+                .generate_stats_info(&with_id(1, raw_stats(0, 100, 10)))
+                .is_none()
+        );
+        // Program is reloaded with a new id and counters starting from zero
+        let stats = cpu_stats(meter.generate_stats_info(&with_id(2, raw_stats(1000, 50, 5))));
+        assert!((stats.exact_cpu_usage.unwrap() - 0.05).abs() < 1e-6);
+        assert_eq!(stats.run_time, Duration::from_millis(150));
+        assert_eq!(stats.run_count, 15);
+    }
+
+    #[test]
+    fn test_dedupe_by_tag_with_overlapping_instances() {
+        let mut meter = CpuMeter::new(CpuMeterOptions {
+            dedupe_by_tag: true,
+            ..Default::default()
+        });
+        let instance = |id, tick, stats: BpfRawStats, xdp_actions| BpfRawStats {
+            id,
+            tick,
+            tag: 42,
+            xdp_actions,
+            ..stats
+        };
+        let tick = |meter: &mut CpuMeter, programs| {
+            let mut summed = sum_by_tag(programs);
+            assert_eq!(summed.len(), 1);
+            meter.generate_stats_info(&summed.remove(0))
+        };
+        assert!(
+            tick(
+                &mut meter,
+                vec![
+                    instance(1, 0, raw_stats(0, 100, 10), vec![1, 0]),
+                    instance(2, 0, raw_stats(0, 50, 5), vec![0, 1]),
+                ]
+            )
+            .is_none()
+        );
+        let stats = cpu_stats(tick(
+            &mut meter,
+            vec![
+                instance(1, 1, raw_stats(1000, 200, 20), vec![2, 0]),
+                instance(2, 1, raw_stats(1000, 100, 10), vec![0, 2]),
+            ],
+        ));
+        assert!((stats.exact_cpu_usage.unwrap() - 0.15).abs() < 1e-6);
+
+        // The first instance is unloaded while the second one keeps running, only
+        // the counters of the unloaded instance are carried over
+        let stats = cpu_stats(tick(
+            &mut meter,
+            vec![instance(2, 2, raw_stats(2000, 200, 20), vec![0, 3])],
+        ));
+        assert!((stats.exact_cpu_usage.unwrap() - 0.1).abs() < 1e-6);
+        assert_eq!(stats.run_time, Duration::from_millis(400));
+        assert_eq!(stats.run_count, 40);
+        assert_eq!(stats.xdp_actions, [2, 3]);
+
+        // The tag is forgotten once all of its programs are gone for the retention time
+        let other = BpfRawStats {
+            id: 3,
+            tick: 4,
+            tag: 7,
+            ..raw_stats(2000 + TAG_RETENTION.as_millis() as u64 + 1000, 0, 0)
+        };
+        meter.generate_stats_info(&other);
+        assert!(!meter.tags.contains_key(&42));
+        assert!(!meter.bpf_prog_info_map.contains_key(&1));
+    }
+
+    #[test]
+    fn test_system_cpu_usage_mode() {
+        let mut meter = CpuMeter::new(CpuMeterOptions {
+            online_cpus: Some(4),
+            ..Default::default()
+        });
+        assert!(meter.generate_stats_info(&raw_stats(0, 0, 0)).is_none());
+        // Program running on two CPUs at once for the whole second
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(1000, 2000, 10)));
+        // Per core usage is kept, the share of the host is added
+        assert!((stats.exact_cpu_usage.unwrap() - 2.0).abs() < 1e-6);
+        assert!((stats.host_cpu_usage.flatten().unwrap() - 0.5).abs() < 1e-6);
+        assert!(stats.peak_cpu_usage.unwrap() > 1.0);
+    }
+
+    #[test]
+    fn test_runtime_rate_denominator() {
+        let mut meter = CpuMeter::new(CpuMeterOptions {
+            rate_denominator: RateDenominator::Runtime,
+            ..Default::default()
+        });
+        assert!(meter.generate_stats_info(&raw_stats(0, 0, 0)).is_none());
+        // 100 runs in 10ms of execution during a second
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(1000, 10, 100)));
+        assert!((stats.event_rate.unwrap() - 10000.0).abs() < 1e-2);
+        // Idle program has no rate per second of execution
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(2000, 10, 100)));
+        assert_eq!(stats.event_rate, None);
+    }
+
+    #[test]
+    fn test_checkpoint_across_restart() {
+        let path = std::env::temp_dir().join(format!("bpfmeter_test_{}.json", std::process::id()));
+        let options = CpuMeterOptions {
+            checkpoint: Some(path.clone()),
+            ..Default::default()
+        };
+        let mut meter = CpuMeter::new(options.clone());
+        assert!(meter.generate_stats_info(&raw_stats(0, 100, 10)).is_none());
+        drop(meter);
+
+        // Time starts from zero again after restart
+        let mut meter = CpuMeter::new(options.clone());
+        let stats = cpu_stats(meter.generate_stats_info(&raw_stats(0, 150, 15)));
+        assert!(
+            stats
+                .exact_cpu_usage
+                .is_some_and(|cpu_usage| cpu_usage > 0.0)
+        );
+        assert!(stats.event_rate.is_some_and(|event_rate| event_rate > 0.0));
+        drop(meter);
+
+        // Counters went down, so the program was reloaded and has no previous stats
+        let mut meter = CpuMeter::new(options);
+        assert!(meter.generate_stats_info(&raw_stats(0, 50, 5)).is_none());
+        drop(meter);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_stuck_counters() {
+        let mut meter = CpuMeter::new(CpuMeterOptions {
+            stuck_threshold: Some(2),
+            ..Default::default()
+        });
+        assert!(meter.generate_stats_info(&raw_stats(0, 100, 10)).is_none());
+        let stuck = |meter: &mut CpuMeter, time_recieved_ms, run_time_ms, run_count| {
+            cpu_stats(meter.generate_stats_info(&raw_stats(
+                time_recieved_ms,
+                run_time_ms,
+                run_count,
+            )))
+            .stuck
+        };
+        assert_eq!(stuck(&mut meter, 1000, 100, 10), Some(false));
+        assert_eq!(stuck(&mut meter, 2000, 100, 10), Some(true));
+        assert_eq!(stuck(&mut meter, 3000, 100, 10), Some(true));
+        // Any change of counters resets detection
+        assert_eq!(stuck(&mut meter, 4000, 100, 11), Some(false));
+    }
+
+    #[test]
+    fn test_time_above_threshold() {
+        let mut meter = CpuMeter::new(CpuMeterOptions {
+            cpu_usage_threshold: Some(0.5),
+            ..Default::default()
+        });
+        let interval = Duration::from_secs(30);
+        assert_eq!(meter.time_above_threshold(1, 0.6, interval), Some(interval));
+        assert_eq!(
+            meter.time_above_threshold(1, 0.7, interval),
+            Some(interval * 2)
+        );
+        assert_eq!(
+            meter.time_above_threshold(2, 0.1, interval),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(
+            meter.time_above_threshold(1, 0.5, interval),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(meter.time_above_threshold(1, 0.9, interval), Some(interval));
+    }
+
+    #[test]
+    fn test_cpu_usage_zscore() {
+        let mut meter = CpuMeter::new(CpuMeterOptions {
+            zscore_window: Some(4),
+            ..Default::default()
+        });
+        assert_eq!(meter.cpu_usage_zscore(1, 0.1), None);
+        assert_eq!(meter.cpu_usage_zscore(1, 0.3), None);
+        // Mean of the window is 0.2 and standard deviation is 0.1
+        let zscore = meter.cpu_usage_zscore(1, 0.5).unwrap();
+        assert!((zscore - 3.0).abs() < 1e-4);
+        // Z-score doesn't depend on the magnitude of cpu usage
+        meter.cpu_usage_zscore(2, 0.01);
+        meter.cpu_usage_zscore(2, 0.03);
+        let zscore = meter.cpu_usage_zscore(2, 0.05).unwrap();
+        assert!((zscore - 3.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_smooth_cpu_usage() {
+        let mut meter = CpuMeter::new(CpuMeterOptions {
+            cpu_smoothing_alpha: Some(0.25),
+            ..Default::default()
+        });
+        // The average of a new program starts from its first cpu usage
+        assert_eq!(meter.smooth_cpu_usage(1, 0.8), Some(0.8));
+        assert_eq!(meter.smooth_cpu_usage(1, 0.4), Some(0.7));
+        assert_eq!(meter.smooth_cpu_usage(2, 0.2), Some(0.2));
+        assert_eq!(meter.smooth_cpu_usage(1, 0.7), Some(0.7));
+
+        let mut meter = CpuMeter::new(CpuMeterOptions::default());
+        assert_eq!(meter.smooth_cpu_usage(1, 0.8), None);
+    }
+}
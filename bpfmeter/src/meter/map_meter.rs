@@ -1,30 +1,63 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet, hash_map::DefaultHasher},
+    hash::{Hash, Hasher},
     os::fd::{AsFd, AsRawFd},
+    sync::atomic::Ordering,
+    time::Instant,
 };
 
 use anyhow::{Result, bail};
-use aya::maps::{self, MapType};
+use aya::maps::{self, MapError, MapType};
 use aya_obj::generated::{bpf_attr, bpf_cmd};
-use log::error;
-use serde_with::serde_as;
+use log::{debug, error};
+use serde_with::{StringWithSeparator, formats::SemicolonSeparator, serde_as};
 use tokio::sync::mpsc::Sender;
 
-use crate::meter::{BpfRawStats, BpfStatsInfo, Meter};
+use crate::meter::{
+    BpfRawStats, BpfStatsInfo, CollectOptions, CollectStats, Meter, PhaseTimer, entity_name,
+    skip_unreadable,
+};
 
-const TARGET_MAP_TYPES: [MapType; 4] = [
+/// Map types which sizes are measured, unless restricted by options
+pub const TARGET_MAP_TYPES: [MapType; 7] = [
     MapType::Hash,
     MapType::PerCpuHash,
     MapType::LruHash,
     MapType::LruPerCpuHash,
+    MapType::LpmTrie,
+    MapType::Array,
+    MapType::PerCpuArray,
 ];
 
+/// Map types with all entries preallocated, their size is always the max size
+const PREALLOCATED_MAP_TYPES: [MapType; 2] = [MapType::Array, MapType::PerCpuArray];
+
+/// Map types with a value per cpu, their entries are counted for each cpu
+const PER_CPU_MAP_TYPES: [MapType; 2] = [MapType::PerCpuHash, MapType::LruPerCpuHash];
+
+/// Number of keys walked between checks of the map walk budget
+const BUDGET_CHECK_KEYS: u32 = 1024;
+
+/// Error returned by the kernel for map types without batch operations, not exposed by libc
+const ENOTSUPP: i32 = 524;
+
 /// Measures Map usage of the ebpf program
-pub struct MapMeter;
+pub struct MapMeter {
+    /// Map of bpf map ids to their last measured sizes and per-cpu sizes, reported for
+    /// stale maps
+    sizes: HashMap<u32, (u32, Vec<u32>)>,
+    /// Count added and deleted entries of maps from differences of their keys between ticks
+    track_churn: bool,
+    /// Map of bpf map ids to hashes of their keys on the last measurement
+    keys: HashMap<u32, HashSet<u64>>,
+    /// Map of bpf map ids to the numbers of added and deleted entries since their first
+    /// measurement
+    churn: HashMap<u32, (u64, u64)>,
+}
 
 /// Serializable Map usage information
 #[serde_as]
-#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct BpfMapStatsInfo {
     /// Map max size
     #[serde(skip_serializing, skip_deserializing)]
@@ -32,83 +65,879 @@ pub struct BpfMapStatsInfo {
 
     /// Current number of elements in the map
     pub size: u32,
+
+    /// Fraction of the max size used by the map, from 0 to 1.
+    /// Empty if the max size is zero
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fill_ratio: Option<f32>,
+
+    /// Size is taken from the previous tick because the map walk budget was exhausted
+    #[serde(skip_serializing, skip_deserializing)]
+    pub stale: bool,
+
+    /// All entries of the map are preallocated (arrays), the size is the max size
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub preallocated: bool,
+
+    /// Number of entries added to the map since its first measurement.
+    /// Empty if churn is not tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adds: Option<u64>,
+
+    /// Number of entries deleted from the map since its first measurement.
+    /// Empty if churn is not tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deletes: Option<u64>,
+
+    /// Number of entries with a nonzero value on each cpu, separated by semicolons in csv.
+    /// Taken from the previous tick for stale sizes. Empty for maps without a value per cpu
+    #[serde_as(as = "Option<StringWithSeparator<SemicolonSeparator, u32>>")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub per_cpu_sizes: Option<Vec<u32>>,
 }
 
 impl MapMeter {
-    pub fn new() -> Self {
-        Self {}
+    /// Creates a new MapMeter
+    ///
+    /// # Arguments
+    ///
+    /// * `track_churn` - Count added and deleted entries of maps, raw stats must have
+    ///   hashes of the keys
+    pub fn new(track_churn: bool) -> Self {
+        Self {
+            sizes: HashMap::new(),
+            track_churn,
+            keys: HashMap::new(),
+            churn: HashMap::new(),
+        }
+    }
+
+    /// Updates the numbers of added and deleted entries of the map from its keys. An entry
+    /// deleted and added again with the same key between two ticks is not noticed
+    ///
+    /// # Arguments
+    ///
+    /// * `raw_stats` - Raw stats of the map
+    ///
+    /// Returns the numbers of added and deleted entries since the first measurement
+    fn update_churn(&mut self, raw_stats: &BpfRawStats) -> (u64, u64) {
+        let churn = self.churn.entry(raw_stats.id).or_default();
+        if !raw_stats.map_stale {
+            let keys = raw_stats
+                .map_key_hashes
+                .iter()
+                .copied()
+                .collect::<HashSet<_>>();
+            // Keys of the first measurement are the baseline, they are not counted as added
+            if let Some(prev_keys) = self.keys.get(&raw_stats.id) {
+                churn.0 += keys.difference(prev_keys).count() as u64;
+                churn.1 += prev_keys.difference(&keys).count() as u64;
+            }
+            self.keys.insert(raw_stats.id, keys);
+        }
+        *churn
     }
 }
 
 impl Meter for MapMeter {
-    fn get_id_name_entity_mapping() -> HashMap<u32, String> {
+    const NAME: &'static str = "map";
+
+    fn get_id_name_entity_mapping(options: &CollectOptions) -> HashMap<u32, String> {
         maps::loaded_maps()
             .filter_map(|p| p.ok())
-            .map(|p| (p.id(), p.name_as_str().map(|x| x.to_string()).unwrap()))
+            .filter(|p| {
+                p.map_type()
+                    .is_ok_and(|map_type| is_collected_type(options, map_type))
+            })
+            .map(|p| (p.id(), entity_name(p.name_as_str())))
             .collect()
     }
 
     async fn collect_raw_stats(
-        map_list_ids: &[u32],
+        options: &CollectOptions,
         base_stats: &BpfRawStats,
         tx: Sender<BpfRawStats>,
-    ) -> Result<()> {
-        let map_iter = maps::loaded_maps();
-        for map in map_iter
-            .filter_map(|p| p.ok())
-            .filter(|p| map_list_ids.is_empty() || map_list_ids.contains(&p.id()))
-            .filter(|p| TARGET_MAP_TYPES.contains(&p.map_type().unwrap()))
-        {
-            let mut attr = unsafe { std::mem::zeroed::<bpf_attr>() };
-            let mut next_key = vec![0u8; map.key_size() as usize];
-            let mut prev_key = vec![0u8; map.key_size() as usize];
-
-            let u = unsafe { &mut attr.__bindgen_anon_2 };
-            let map_fd = map.fd().unwrap();
-            let borrowed = map_fd.as_fd();
-            u.map_fd = borrowed.as_raw_fd() as u32;
-
-            u.key = 0;
-            u.__bindgen_anon_1.next_key = next_key.as_mut_ptr() as u64;
-
-            let mut map_entries = 0;
-            while unsafe {
-                libc::syscall(
-                    libc::SYS_bpf,
-                    bpf_cmd::BPF_MAP_GET_NEXT_KEY,
-                    &mut attr,
-                    std::mem::size_of::<bpf_attr>(),
-                ) == 0
-            } {
-                map_entries += 1;
-                prev_key.copy_from_slice(&next_key);
-                attr.__bindgen_anon_2.key = prev_key.as_mut_ptr() as u64;
-            }
-            // Check error
-            if let Some(error) = std::io::Error::last_os_error().raw_os_error()
-                && error != libc::ENOENT
-            {
-                error!("Failed to get next key: {error}")
+    ) -> Result<CollectStats> {
+        let mut collect_stats = CollectStats::default();
+        collect_stats.filtered.insert("id", 0);
+        collect_stats.filtered.insert("baseline", 0);
+        collect_stats.filtered.insert("map_type", 0);
+
+        let mut timer = PhaseTimer::new(options.verbose_timing);
+        let mut maps = Vec::new();
+        for map in maps::loaded_maps() {
+            match skip_unreadable(options, map)? {
+                Some(map) => maps.push(map),
+                // Unreadable map can't be filtered, so it is counted as intended
+                None => collect_stats.intended += 1,
             }
+        }
+        timer.finish_phase("enumeration");
+        // Maps left stale by the previous tick are walked first, so all of them are
+        // measured in turn when the budget is too small to walk them all on a tick
+        let resume_after = options.map_walk_resume.load(Ordering::Relaxed);
+        let start = walk_start_index(maps.iter().map(|map| map.id()), resume_after);
+        maps.rotate_left(start);
 
+        let walk_start = Instant::now();
+        let budget_exhausted = || {
+            options
+                .map_walk_budget
+                .is_some_and(|budget| walk_start.elapsed() >= budget)
+        };
+        let cpus = nr_cpus()?;
+        let mut stale_maps = 0;
+        let mut last_walked = 0;
+        let mut maps_stats = Vec::new();
+        for map in maps {
+            if !options.ids.is_empty() && !options.ids.contains(&map.id()) {
+                *collect_stats.filtered.entry("id").or_default() += 1;
+                continue;
+            }
+            if options.baseline_ids.contains(&map.id()) {
+                *collect_stats.filtered.entry("baseline").or_default() += 1;
+                continue;
+            }
+            let Some(map_type) = known_map_type(map.id(), map.map_type()) else {
+                *collect_stats.filtered.entry("map_type").or_default() += 1;
+                continue;
+            };
+            if !is_collected_type(options, map_type) {
+                *collect_stats.filtered.entry("map_type").or_default() += 1;
+                continue;
+            }
+            collect_stats.intended += 1;
             let mut bpf_map_stats = base_stats.clone();
-            bpf_map_stats.map_entries = map_entries;
             bpf_map_stats.id = map.id();
-            bpf_map_stats.name = map.name_as_str().unwrap_or("unknown").to_string();
+            bpf_map_stats.name = entity_name(map.name_as_str());
             bpf_map_stats.map_max_entries = map.max_entries();
+            // Arrays are not walked, all their entries exist from the creation
+            if PREALLOCATED_MAP_TYPES.contains(&map_type) {
+                bpf_map_stats.map_entries = map.max_entries();
+                bpf_map_stats.map_preallocated = true;
+                collect_stats.measured += 1;
+                maps_stats.push(bpf_map_stats);
+                continue;
+            }
+            // Remaining maps are not walked, so collection time is bounded by the budget
+            if budget_exhausted() {
+                bpf_map_stats.map_stale = true;
+                stale_maps += 1;
+                maps_stats.push(bpf_map_stats);
+                continue;
+            }
+
+            let Some(map_fd) = skip_unreadable(options, map.fd())? else {
+                continue;
+            };
+            let raw_fd = map_fd.as_fd().as_raw_fd() as u32;
+            let per_cpu = PER_CPU_MAP_TYPES.contains(&map_type);
+            let new_walk = || {
+                MapWalk::new(
+                    map.key_size(),
+                    map.value_size(),
+                    per_cpu.then_some(cpus),
+                    options.map_churn,
+                )
+            };
+            let (walk, result) =
+                walk_map(raw_fd, options.map_batch_size, new_walk, &budget_exhausted);
+            // Map abandoned in the middle of the walk is skipped next time too, so a map
+            // too large for the budget doesn't keep the others stale
+            last_walked = map.id();
+            if walk.stale {
+                bpf_map_stats.map_stale = true;
+                stale_maps += 1;
+                maps_stats.push(bpf_map_stats);
+                continue;
+            }
+            match result {
+                Err(error) if options.strict => {
+                    bail!("Failed to walk keys of map {}: {error}", map.id())
+                }
+                Err(error) => error!("Failed to walk keys of map {}: {error}", map.id()),
+                Ok(()) => collect_stats.measured += 1,
+            }
+
+            bpf_map_stats.map_entries = walk.entries;
+            bpf_map_stats.map_key_hashes = walk.key_hashes;
+            bpf_map_stats.map_per_cpu_entries = walk.per_cpu_entries;
+            maps_stats.push(bpf_map_stats);
+        }
+        timer.finish_phase("map walk");
+        let resume_after = if stale_maps > 0 { last_walked } else { 0 };
+        options
+            .map_walk_resume
+            .store(resume_after, Ordering::Relaxed);
+        if stale_maps > 0 {
+            debug!(
+                "Map walk budget is exhausted on tick {}, sizes of {stale_maps} maps are stale",
+                base_stats.tick
+            );
+        }
 
+        for bpf_map_stats in maps_stats {
             if let Err(e) = tx.send(bpf_map_stats).await {
                 bail!("Failed to send program to channel: {e}");
             }
         }
-        Ok(())
+        timer.finish_phase("send");
+        timer.log(Self::NAME, base_stats.tick);
+        Ok(collect_stats)
     }
 
     fn generate_stats_info(&mut self, raw_stats: &BpfRawStats) -> Option<BpfStatsInfo> {
+        // Stale map is reported with its last measured sizes, if it was ever measured, so
+        // its csv rows keep the same columns
+        let (size, per_cpu_sizes) = if raw_stats.map_stale {
+            self.sizes.get(&raw_stats.id)?.clone()
+        } else {
+            let sizes = (raw_stats.map_entries, raw_stats.map_per_cpu_entries.clone());
+            self.sizes.insert(raw_stats.id, sizes.clone());
+            sizes
+        };
+        let churn = self.track_churn.then(|| self.update_churn(raw_stats));
         let export_stats = BpfMapStatsInfo {
             max_size: raw_stats.map_max_entries,
-            size: raw_stats.map_entries,
+            size,
+            fill_ratio: fill_ratio(size, raw_stats.map_max_entries),
+            stale: raw_stats.map_stale,
+            preallocated: raw_stats.map_preallocated,
+            adds: churn.map(|(adds, _)| adds),
+            deletes: churn.map(|(_, deletes)| deletes),
+            per_cpu_sizes: (!per_cpu_sizes.is_empty()).then_some(per_cpu_sizes),
         };
         Some(BpfStatsInfo::Map(export_stats))
     }
 }
+
+/// Returns the index of the map the walk starts from, the first one after the map the
+/// previous walk stopped at, or the first map if there is none
+///
+/// # Arguments
+///
+/// * `ids` - Ids of loaded maps in ascending order
+///
+/// * `resume_after` - Id of the last map walked on the previous tick, zero to walk from
+///   the first map
+fn walk_start_index(ids: impl Iterator<Item = u32>, resume_after: u32) -> usize {
+    ids.enumerate()
+        .find(|(_, id)| *id > resume_after)
+        .map_or(0, |(index, _)| index)
+}
+
+/// Returns true if maps of the type are collected with the options
+///
+/// # Arguments
+///
+/// * `options` - Options of collecting ebpf map stats
+///
+/// * `map_type` - Type of the map
+fn is_collected_type(options: &CollectOptions, map_type: MapType) -> bool {
+    match options.map_types.is_empty() {
+        true => TARGET_MAP_TYPES.contains(&map_type),
+        false => options.map_types.contains(&map_type),
+    }
+}
+
+/// Returns type of the map, None if the type is unknown to aya and the map is skipped
+///
+/// # Arguments
+///
+/// * `id` - Ebpf map id
+///
+/// * `map_type` - Result of reading the map type
+fn known_map_type(id: u32, map_type: Result<MapType, MapError>) -> Option<MapType> {
+    map_type
+        .inspect_err(|e| debug!("Skipping ebpf map {id} of unknown type: {e}"))
+        .ok()
+}
+
+/// Returns the fraction of the max size used by the map, None if the max size is zero
+///
+/// # Arguments
+///
+/// * `size` - Current number of elements in the map
+///
+/// * `max_size` - Map max size
+pub fn fill_ratio(size: u32, max_size: u32) -> Option<f32> {
+    (max_size > 0).then(|| size as f32 / max_size as f32)
+}
+
+/// Returns the number of possible cpus, the number of values of per-cpu maps
+fn nr_cpus() -> Result<usize> {
+    aya::util::nr_cpus().map_err(|(_, e)| e.into())
+}
+
+/// Entries of a map counted while walking its keys
+struct MapWalk {
+    /// Size of the map key
+    key_size: usize,
+    /// Size of the values of all cpus of a single entry, values of per-cpu maps
+    /// are aligned to 8 bytes for each cpu
+    value_size: usize,
+    /// Collect hashes of the keys
+    hash_keys: bool,
+    /// Number of walked entries
+    entries: u32,
+    /// Hashes of the walked keys, empty if keys are not hashed
+    key_hashes: Vec<u64>,
+    /// Number of walked entries with a nonzero value on each cpu, empty for maps
+    /// without a value per cpu
+    per_cpu_entries: Vec<u32>,
+    /// Walk was stopped because the map walk budget was exhausted
+    stale: bool,
+}
+
+impl MapWalk {
+    /// Creates a new MapWalk
+    ///
+    /// # Arguments
+    ///
+    /// * `key_size` - Size of the map key
+    ///
+    /// * `value_size` - Size of the map value
+    ///
+    /// * `cpus` - Number of possible cpus if the map has a value per cpu
+    ///
+    /// * `hash_keys` - Collect hashes of the keys
+    fn new(key_size: u32, value_size: u32, cpus: Option<usize>, hash_keys: bool) -> Self {
+        let value_size = match cpus {
+            Some(cpus) => (value_size as usize).next_multiple_of(8) * cpus,
+            None => value_size as usize,
+        };
+        Self {
+            key_size: key_size as usize,
+            value_size,
+            hash_keys,
+            entries: 0,
+            key_hashes: Vec::new(),
+            per_cpu_entries: vec![0; cpus.unwrap_or_default()],
+            stale: false,
+        }
+    }
+
+    /// Counts the entry
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - Key of the entry
+    ///
+    /// * `value` - Values of all cpus of the entry, None if it wasn't looked up
+    fn add(&mut self, key: &[u8], value: Option<&[u8]>) {
+        self.entries += 1;
+        if self.hash_keys {
+            let mut hasher = DefaultHasher::new();
+            key.hash(&mut hasher);
+            self.key_hashes.push(hasher.finish());
+        }
+        if let Some(value) = value
+            && !self.per_cpu_entries.is_empty()
+        {
+            let value_stride = value.len() / self.per_cpu_entries.len();
+            for (entries, cpu_value) in self
+                .per_cpu_entries
+                .iter_mut()
+                .zip(value.chunks(value_stride))
+            {
+                if cpu_value.iter().any(|byte| *byte != 0) {
+                    *entries += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Walks the map in batches, or key by key if batch lookups are disabled or fail
+///
+/// # Arguments
+///
+/// * `map_fd` - Raw fd of the map
+///
+/// * `batch_size` - Maximum number of entries read with a single syscall, keys are walked
+///   one by one if zero
+///
+/// * `new_walk` - Creates an empty walk of the map
+///
+/// * `budget_exhausted` - Returns true once the map walk budget is exhausted
+///
+/// Returns entries counted during the walk and the error the walk failed with
+fn walk_map(
+    map_fd: u32,
+    batch_size: u32,
+    new_walk: impl Fn() -> MapWalk,
+    budget_exhausted: &impl Fn() -> bool,
+) -> (MapWalk, std::io::Result<()>) {
+    let mut walk = new_walk();
+    if batch_size > 0 {
+        match walk_batch(map_fd, batch_size, &mut walk, budget_exhausted) {
+            // Batch lookup is missing on kernels before 5.6 and a bucket of the hash map
+            // may not fit into the batch, such maps are walked key by key
+            Err(e)
+                if matches!(
+                    e.raw_os_error(),
+                    Some(libc::EINVAL | ENOTSUPP | libc::ENOSPC)
+                ) =>
+            {
+                debug!("Batch lookup of map failed, walking its keys: {e}");
+                walk = new_walk();
+            }
+            result => return (walk, result),
+        }
+    }
+    let result = walk_keys(map_fd, &mut walk, budget_exhausted);
+    (walk, result)
+}
+
+/// Walks keys of the map one by one with BPF_MAP_GET_NEXT_KEY, values of per-cpu maps
+/// are looked up for each key. The walk ends once there is no next key (ENOENT)
+///
+/// # Arguments
+///
+/// * `map_fd` - Raw fd of the map
+///
+/// * `walk` - Entries counted during the walk
+///
+/// * `budget_exhausted` - Returns true once the map walk budget is exhausted
+fn walk_keys(
+    map_fd: u32,
+    walk: &mut MapWalk,
+    budget_exhausted: &impl Fn() -> bool,
+) -> std::io::Result<()> {
+    let mut attr = unsafe { std::mem::zeroed::<bpf_attr>() };
+    let mut next_key = vec![0u8; walk.key_size];
+    let mut prev_key = vec![0u8; walk.key_size];
+    let mut value = vec![0u8; walk.value_size];
+
+    let u = unsafe { &mut attr.__bindgen_anon_2 };
+    u.map_fd = map_fd;
+    u.key = 0;
+    u.__bindgen_anon_1.next_key = next_key.as_mut_ptr() as u64;
+
+    loop {
+        if unsafe {
+            libc::syscall(
+                libc::SYS_bpf,
+                bpf_cmd::BPF_MAP_GET_NEXT_KEY,
+                &mut attr,
+                std::mem::size_of::<bpf_attr>(),
+            )
+        } != 0
+        {
+            // Errno is read right after the syscall. ENOENT means there is no next key,
+            // already for the first key of an empty map, any other error fails the walk
+            let error = std::io::Error::last_os_error();
+            return match error.raw_os_error() {
+                Some(libc::ENOENT) => Ok(()),
+                _ => Err(error),
+            };
+        }
+        let looked_up =
+            !walk.per_cpu_entries.is_empty() && lookup_value(map_fd, &mut next_key, &mut value);
+        walk.add(&next_key, looked_up.then_some(value.as_slice()));
+        if walk.entries.is_multiple_of(BUDGET_CHECK_KEYS) && budget_exhausted() {
+            walk.stale = true;
+            return Ok(());
+        }
+        prev_key.copy_from_slice(&next_key);
+        attr.__bindgen_anon_2.key = prev_key.as_mut_ptr() as u64;
+    }
+}
+
+/// Walks entries of the map in batches with BPF_MAP_LOOKUP_BATCH, which reads keys and
+/// values of many entries with a single syscall. The walk ends once there are no more
+/// entries (ENOENT), the last batch may still contain entries
+///
+/// # Arguments
+///
+/// * `map_fd` - Raw fd of the map
+///
+/// * `batch_size` - Maximum number of entries read with a single syscall
+///
+/// * `walk` - Entries counted during the walk
+///
+/// * `budget_exhausted` - Returns true once the map walk budget is exhausted
+fn walk_batch(
+    map_fd: u32,
+    batch_size: u32,
+    walk: &mut MapWalk,
+    budget_exhausted: &impl Fn() -> bool,
+) -> std::io::Result<()> {
+    let mut keys = vec![0u8; walk.key_size * batch_size as usize];
+    let mut values = vec![0u8; walk.value_size * batch_size as usize];
+    // Position of the walk in the map, a bucket index for hash maps
+    let batch_token_size = walk.key_size.max(std::mem::size_of::<u32>());
+    let mut in_batch = vec![0u8; batch_token_size];
+    let mut out_batch = vec![0u8; batch_token_size];
+    let mut first = true;
+    loop {
+        let mut attr = unsafe { std::mem::zeroed::<bpf_attr>() };
+        attr.batch.map_fd = map_fd;
+        // The first batch starts from the beginning of the map
+        attr.batch.in_batch = if first {
+            0
+        } else {
+            in_batch.as_mut_ptr() as u64
+        };
+        attr.batch.out_batch = out_batch.as_mut_ptr() as u64;
+        attr.batch.keys = keys.as_mut_ptr() as u64;
+        attr.batch.values = values.as_mut_ptr() as u64;
+        attr.batch.count = batch_size;
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_bpf,
+                bpf_cmd::BPF_MAP_LOOKUP_BATCH,
+                &mut attr,
+                std::mem::size_of::<bpf_attr>(),
+            )
+        };
+        let last = result != 0;
+        if last {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::ENOENT) {
+                return Err(error);
+            }
+        }
+
+        let count = unsafe { attr.batch.count } as usize;
+        for (key, value) in keys
+            .chunks(walk.key_size)
+            .zip(values.chunks(walk.value_size.max(1)))
+            .take(count)
+        {
+            walk.add(key, Some(value));
+        }
+        if last {
+            return Ok(());
+        }
+        if budget_exhausted() {
+            walk.stale = true;
+            return Ok(());
+        }
+        std::mem::swap(&mut in_batch, &mut out_batch);
+        first = false;
+    }
+}
+
+/// Looks up values of all cpus of the entry of per-cpu map
+///
+/// # Arguments
+///
+/// * `map_fd` - Raw fd of the map
+///
+/// * `key` - Key of the entry
+///
+/// * `value` - Buffer for values of all cpus
+///
+/// Returns false if the entry can't be looked up, e.g. it was deleted after its key was walked
+fn lookup_value(map_fd: u32, key: &mut [u8], value: &mut [u8]) -> bool {
+    let mut attr = unsafe { std::mem::zeroed::<bpf_attr>() };
+    let u = unsafe { &mut attr.__bindgen_anon_2 };
+    u.map_fd = map_fd;
+    u.key = key.as_mut_ptr() as u64;
+    u.__bindgen_anon_1.value = value.as_mut_ptr() as u64;
+    unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+            &mut attr,
+            std::mem::size_of::<bpf_attr>(),
+        ) == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        os::fd::{FromRawFd, OwnedFd},
+        time::Duration,
+    };
+
+    use aya_obj::generated::bpf_map_type;
+
+    use super::*;
+    use crate::{
+        exporter::{
+            Exporter,
+            file_exporter::{FileExporter, RotationLimits},
+            meter_metrics::MeterMetrics,
+        },
+        meter::Measurement,
+    };
+
+    fn map_stats(info: Option<BpfStatsInfo>) -> BpfMapStatsInfo {
+        match info {
+            Some(BpfStatsInfo::Map(stats)) => stats,
+            other => panic!("Expected map stats, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_stale_map_reports_previous_size() {
+        let mut meter = MapMeter::new(false);
+        let raw_stats = |map_entries, map_stale| BpfRawStats {
+            id: 1,
+            map_entries,
+            map_stale,
+            ..Default::default()
+        };
+        // Map that was never measured has no size to report
+        assert!(meter.generate_stats_info(&raw_stats(0, true)).is_none());
+
+        let stats = map_stats(meter.generate_stats_info(&raw_stats(10, false)));
+        assert_eq!((stats.size, stats.stale), (10, false));
+        // Max size is unknown
+        assert_eq!(stats.fill_ratio, None);
+        let stats = map_stats(meter.generate_stats_info(&raw_stats(0, true)));
+        assert_eq!((stats.size, stats.stale), (10, true));
+    }
+
+    #[test]
+    fn test_walk_resumes_after_last_walked_map() {
+        let ids = [3, 5, 8];
+        assert_eq!(walk_start_index(ids.into_iter(), 0), 0);
+        assert_eq!(walk_start_index(ids.into_iter(), 5), 2);
+        // Last walked map is unloaded, the walk starts from the next one
+        assert_eq!(walk_start_index(ids.into_iter(), 4), 1);
+        // All maps after the last walked one are walked, the walk wraps around
+        assert_eq!(walk_start_index(ids.into_iter(), 8), 0);
+    }
+
+    #[test]
+    fn test_maps_of_unknown_type_are_skipped() {
+        let maps = [
+            (1, Ok(MapType::Hash)),
+            (2, Err(MapError::InvalidMapType { map_type: 1000 })),
+            (3, Ok(MapType::Array)),
+        ];
+        let map_types = maps
+            .into_iter()
+            .filter_map(|(id, map_type)| known_map_type(id, map_type))
+            .collect::<Vec<_>>();
+        assert_eq!(map_types, [MapType::Hash, MapType::Array]);
+    }
+
+    #[test]
+    fn test_churn_of_steady_size_map() {
+        let mut meter = MapMeter::new(true);
+        let raw_stats = |map_key_hashes: Vec<u64>| BpfRawStats {
+            id: 1,
+            map_entries: map_key_hashes.len() as u32,
+            map_key_hashes,
+            ..Default::default()
+        };
+        let churn = |stats: BpfMapStatsInfo| (stats.size, stats.adds, stats.deletes);
+
+        let stats = map_stats(meter.generate_stats_info(&raw_stats(vec![1, 2, 3])));
+        assert_eq!(churn(stats), (3, Some(0), Some(0)));
+        // Two of three entries are replaced, the size stays the same
+        let stats = map_stats(meter.generate_stats_info(&raw_stats(vec![3, 4, 5])));
+        assert_eq!(churn(stats), (3, Some(2), Some(2)));
+        let stats = map_stats(meter.generate_stats_info(&raw_stats(vec![3, 4])));
+        assert_eq!(churn(stats), (2, Some(2), Some(3)));
+    }
+
+    /// Creates a map with u32 keys and u64 values, None without privileges to create maps.
+    /// Keys of LPM trie are u32 prefix length followed by u32 data
+    fn create_map(map_type: bpf_map_type, max_entries: u32) -> Option<OwnedFd> {
+        let mut attr = unsafe { std::mem::zeroed::<bpf_attr>() };
+        let u = unsafe { &mut attr.__bindgen_anon_1 };
+        u.map_type = map_type as u32;
+        u.key_size = 4;
+        u.value_size = 8;
+        u.max_entries = max_entries;
+        if map_type == bpf_map_type::BPF_MAP_TYPE_LPM_TRIE {
+            u.key_size = 8;
+            u.map_flags = aya_obj::generated::BPF_F_NO_PREALLOC;
+        }
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_bpf,
+                bpf_cmd::BPF_MAP_CREATE,
+                &mut attr,
+                std::mem::size_of::<bpf_attr>(),
+            )
+        };
+        (fd >= 0).then(|| unsafe { OwnedFd::from_raw_fd(fd as i32) })
+    }
+
+    /// Sets values of the entry of the map, a value for each cpu of per-cpu map
+    fn update_map(map_fd: &OwnedFd, key: &mut [u8], values: &mut [u64]) {
+        let mut attr = unsafe { std::mem::zeroed::<bpf_attr>() };
+        let u = unsafe { &mut attr.__bindgen_anon_2 };
+        u.map_fd = map_fd.as_raw_fd() as u32;
+        u.key = key.as_mut_ptr() as u64;
+        u.__bindgen_anon_1.value = values.as_mut_ptr() as u64;
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_bpf,
+                bpf_cmd::BPF_MAP_UPDATE_ELEM,
+                &mut attr,
+                std::mem::size_of::<bpf_attr>(),
+            )
+        };
+        assert_eq!(result, 0, "{}", std::io::Error::last_os_error());
+    }
+
+    #[test]
+    fn test_batch_walk_matches_key_walk() {
+        let cpus = nr_cpus().unwrap();
+        for (map_type, per_cpu) in [
+            (bpf_map_type::BPF_MAP_TYPE_HASH, None),
+            (bpf_map_type::BPF_MAP_TYPE_PERCPU_HASH, Some(cpus)),
+        ] {
+            let Some(map_fd) = create_map(map_type, 4096) else {
+                eprintln!("Skipped, ebpf maps can't be created");
+                return;
+            };
+            // Only the first cpu has nonzero values of odd keys
+            for key in 0..3000u32 {
+                let mut values = vec![0; per_cpu.unwrap_or(1)];
+                values[0] = (key % 2) as u64;
+                update_map(&map_fd, &mut key.to_ne_bytes(), &mut values);
+            }
+            let raw_fd = map_fd.as_raw_fd() as u32;
+            let mut key_walk = MapWalk::new(4, 8, per_cpu, true);
+            walk_keys(raw_fd, &mut key_walk, &|| false).unwrap();
+            let mut batch_walk = MapWalk::new(4, 8, per_cpu, true);
+            walk_batch(raw_fd, 64, &mut batch_walk, &|| false).unwrap();
+
+            assert_eq!(key_walk.entries, 3000);
+            assert_eq!(batch_walk.entries, 3000);
+            key_walk.key_hashes.sort();
+            batch_walk.key_hashes.sort();
+            assert_eq!(key_walk.key_hashes, batch_walk.key_hashes);
+            assert_eq!(key_walk.per_cpu_entries, batch_walk.per_cpu_entries);
+            if per_cpu.is_some() {
+                assert_eq!(batch_walk.per_cpu_entries[0], 1500);
+            }
+        }
+    }
+
+    #[test]
+    fn test_walk_boundaries() {
+        let Some(bad_fd) = create_map(bpf_map_type::BPF_MAP_TYPE_HASH, 1) else {
+            eprintln!("Skipped, ebpf maps can't be created");
+            return;
+        };
+        // Empty, single entry and full maps, every map is walked in batches smaller than,
+        // equal to and larger than the map
+        for (max_entries, len) in [(16, 0u32), (16, 1), (16, 16)] {
+            let map_fd = create_map(bpf_map_type::BPF_MAP_TYPE_HASH, max_entries).unwrap();
+            for key in 0..len {
+                update_map(&map_fd, &mut key.to_ne_bytes(), &mut [1]);
+            }
+            let raw_fd = map_fd.as_raw_fd() as u32;
+            // Zero batch size walks keys one by one, a single entry batch falls back to it
+            // once a bucket has more entries
+            for batch_size in [0, 1, 16, 64] {
+                let new_walk = || MapWalk::new(4, 8, None, false);
+                let (walk, result) = walk_map(raw_fd, batch_size, new_walk, &|| false);
+                result.unwrap();
+                assert_eq!(
+                    walk.entries, len,
+                    "batches of {batch_size} of {len} entries"
+                );
+            }
+        }
+
+        // Walk of a closed map is an error, not an empty map
+        let raw_fd = bad_fd.as_raw_fd() as u32;
+        drop(bad_fd);
+        let mut walk = MapWalk::new(4, 8, None, false);
+        let error = walk_keys(raw_fd, &mut walk, &|| false).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EBADF));
+        let error = walk_batch(raw_fd, 16, &mut walk, &|| false).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EBADF));
+    }
+
+    #[test]
+    fn test_lpm_trie_walk() {
+        let Some(map_fd) = create_map(bpf_map_type::BPF_MAP_TYPE_LPM_TRIE, 256) else {
+            eprintln!("Skipped, ebpf maps can't be created");
+            return;
+        };
+        for data in 0..100u32 {
+            let mut key = [32u32.to_ne_bytes(), data.to_be_bytes()].concat();
+            update_map(&map_fd, &mut key, &mut [1]);
+        }
+        // LPM trie has no batch operations, its keys are walked one by one
+        let new_walk = || MapWalk::new(8, 8, None, false);
+        let (walk, result) = walk_map(map_fd.as_raw_fd() as u32, 16, new_walk, &|| false);
+        result.unwrap();
+        assert_eq!(walk.entries, 100);
+    }
+
+    #[test]
+    fn test_per_cpu_sizes_csv_round_trip() {
+        let mut meter = MapMeter::new(false);
+        let stats = map_stats(meter.generate_stats_info(&BpfRawStats {
+            id: 1,
+            map_entries: 3,
+            map_per_cpu_entries: vec![3, 0, 1],
+            ..Default::default()
+        }));
+        assert_eq!(stats.per_cpu_sizes, Some(vec![3, 0, 1]));
+
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.serialize(&stats).unwrap();
+        let csv = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(csv, "size,per_cpu_sizes\n3,3;0;1\n");
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let read: BpfMapStatsInfo = reader.deserialize().next().unwrap().unwrap();
+        assert_eq!(read.per_cpu_sizes, stats.per_cpu_sizes);
+
+        // Csv files of maps without a value per cpu have no such column
+        let mut reader = csv::Reader::from_reader("size\n3\n".as_bytes());
+        let read: BpfMapStatsInfo = reader.deserialize().next().unwrap().unwrap();
+        assert_eq!(read.per_cpu_sizes, None);
+    }
+
+    #[test]
+    fn test_stale_per_cpu_map_keeps_csv_columns() {
+        let output_dir =
+            std::env::temp_dir().join(format!("bpfmeter_stale_per_cpu_{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+        let mut exporter = FileExporter::new(
+            Duration::from_secs(1),
+            "map",
+            &output_dir,
+            None,
+            &MeterMetrics::default(),
+            1,
+            None,
+            csv::WriterBuilder::new(),
+            RotationLimits::default(),
+        );
+        let mut meter = MapMeter::new(false);
+        let fresh = BpfRawStats {
+            id: 1,
+            name: "per_cpu".to_string(),
+            tick: 0,
+            map_entries: 3,
+            map_per_cpu_entries: vec![3, 0, 1],
+            ..Default::default()
+        };
+        let stale = BpfRawStats {
+            tick: 1,
+            map_entries: 0,
+            map_stale: true,
+            map_per_cpu_entries: Vec::new(),
+            ..fresh.clone()
+        };
+        for raw_stats in [fresh, stale] {
+            let stats = meter.generate_stats_info(&raw_stats).unwrap();
+            exporter
+                .export_info(&Measurement { raw_stats, stats }.info())
+                .unwrap();
+        }
+        drop(exporter);
+
+        let content = std::fs::read_to_string(output_dir.join("1_per_cpu_map_1s.csv")).unwrap();
+        std::fs::remove_dir_all(output_dir).unwrap();
+        assert_eq!(
+            content,
+            "tick,time_recieved,size,per_cpu_sizes\n0,0,3,3;0;1\n1,0,3,3;0;1\n"
+        );
+    }
+}
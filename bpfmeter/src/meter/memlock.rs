@@ -0,0 +1,138 @@
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+
+use aya::{
+    maps::{MapType, loaded_maps},
+    programs::loaded_programs,
+};
+
+/// Map types with a value per cpu, their memory grows with the number of cpus
+const PER_CPU_MAP_TYPES: [MapType; 4] = [
+    MapType::PerCpuHash,
+    MapType::PerCpuArray,
+    MapType::LruPerCpuHash,
+    MapType::PerCpuCgroupStorage,
+];
+
+/// Memory used by all loaded ebpf programs and maps in bytes, split into kinds
+/// the kernel accounts separately. Kinds don't overlap, each is None if it can't be read
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MemoryUsage {
+    /// Memory locked by programs, mostly their translated instructions
+    pub programs: Option<u64>,
+    /// Size of the JIT compiled images of programs, not included in their locked memory
+    pub programs_jited: Option<u64>,
+    /// Memory locked by maps with a single value per key
+    pub maps: Option<u64>,
+    /// Memory locked by maps with a value per cpu
+    pub per_cpu_maps: Option<u64>,
+}
+
+impl MemoryUsage {
+    /// Returns the total memory locked by programs and maps, the sum of memlock reported
+    /// in fdinfo of each of them, None if memlock of none of them can be read
+    pub fn total_locked(&self) -> Option<u64> {
+        let locked = [self.programs, self.maps, self.per_cpu_maps];
+        locked
+            .iter()
+            .any(Option::is_some)
+            .then(|| locked.iter().flatten().sum())
+    }
+
+    /// Returns kinds of memory with their sizes, kinds that can't be read are skipped
+    pub fn by_kind(&self) -> impl Iterator<Item = (&'static str, u64)> {
+        [
+            ("program", self.programs),
+            ("program_jited", self.programs_jited),
+            ("map", self.maps),
+            ("percpu_map", self.per_cpu_maps),
+        ]
+        .into_iter()
+        .filter_map(|(kind, bytes)| Some((kind, bytes?)))
+    }
+}
+
+/// Returns memory used by all loaded ebpf programs and maps, regardless of filters
+pub fn memory_usage() -> MemoryUsage {
+    let programs = loaded_programs().filter_map(|p| p.ok()).collect::<Vec<_>>();
+    let programs_memlock = programs
+        .iter()
+        .filter_map(|p| p.fd().ok())
+        .filter_map(|fd| fdinfo_memlock(fd.as_fd()))
+        .collect::<Vec<_>>();
+    let maps_memlock = loaded_maps()
+        .filter_map(|m| m.ok())
+        .filter_map(|m| {
+            let per_cpu = m
+                .map_type()
+                .is_ok_and(|map_type| PER_CPU_MAP_TYPES.contains(&map_type));
+            Some((per_cpu, fdinfo_memlock(m.fd().ok()?.as_fd())?))
+        })
+        .collect::<Vec<_>>();
+    let maps_sum = |per_cpu: bool| {
+        (!maps_memlock.is_empty()).then(|| {
+            maps_memlock
+                .iter()
+                .filter(|(map_per_cpu, _)| *map_per_cpu == per_cpu)
+                .map(|(_, memlock)| memlock)
+                .sum()
+        })
+    };
+    MemoryUsage {
+        programs: (!programs_memlock.is_empty()).then(|| programs_memlock.iter().sum()),
+        programs_jited: (!programs.is_empty())
+            .then(|| programs.iter().map(|p| p.size_jitted() as u64).sum()),
+        maps: maps_sum(false),
+        per_cpu_maps: maps_sum(true),
+    }
+}
+
+/// Reads memlock of ebpf program/map from fdinfo of its file descriptor
+///
+/// # Arguments
+///
+/// * `fd` - File descriptor of ebpf program/map
+fn fdinfo_memlock(fd: BorrowedFd<'_>) -> Option<u64> {
+    let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", fd.as_raw_fd())).ok()?;
+    parse_memlock(&fdinfo)
+}
+
+/// Parses memlock value from fdinfo
+///
+/// # Arguments
+///
+/// * `fdinfo` - Content of fdinfo file
+fn parse_memlock(fdinfo: &str) -> Option<u64> {
+    fdinfo
+        .lines()
+        .find_map(|line| line.strip_prefix("memlock:"))
+        .and_then(|value| value.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_memlock() {
+        let fdinfo = "pos:\t0\nflags:\t02000002\nmap_type:\t1\nmemlock:\t4096\nmap_id:\t7\n";
+        assert_eq!(parse_memlock(fdinfo), Some(4096));
+        assert_eq!(parse_memlock("pos:\t0\n"), None);
+    }
+
+    #[test]
+    fn test_memory_usage_kinds() {
+        let usage = MemoryUsage {
+            programs: Some(4096),
+            programs_jited: Some(512),
+            maps: Some(8192),
+            per_cpu_maps: None,
+        };
+        // Jited images are not locked memory
+        assert_eq!(usage.total_locked(), Some(12288));
+        assert_eq!(
+            usage.by_kind().collect::<Vec<_>>(),
+            [("program", 4096), ("program_jited", 512), ("map", 8192)]
+        );
+        assert_eq!(MemoryUsage::default().total_locked(), None);
+    }
+}
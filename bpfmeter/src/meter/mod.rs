@@ -1,15 +1,29 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, atomic::AtomicU32},
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
+use aya::{maps::MapType, programs::ProgramType};
+use clap::ValueEnum;
+use log::debug;
 use tokio::sync::mpsc::Sender;
 
-use crate::meter::{cpu_meter::BpfCPUStatsInfo, map_meter::BpfMapStatsInfo};
+use crate::meter::{
+    cpu_meter::BpfCPUStatsInfo, map_meter::BpfMapStatsInfo, sampling::AdaptiveSampling,
+};
 
+pub mod bpf_types;
+pub mod checkpoint;
 pub mod cpu_meter;
 pub mod map_meter;
+pub mod memlock;
+pub mod sampling;
+pub mod xdp_actions;
 
 /// Stores ebpf program/map stats
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct BpfRawStats {
     /// Ebpf program/map id
     pub id: u32,
@@ -20,18 +34,291 @@ pub struct BpfRawStats {
     /// Time the program/map stats were received
     pub time_recieved: Duration,
 
-    /// Number of times the program was run before the current tick
+    /// Ebpf program tag, hash of its instructions
+    pub tag: u64,
+
+    /// Number of times the program was run before the current tick.
+    ///
+    /// Some kernels report a nonzero run time together with zero run count (or vice versa),
+    /// so per-event values must not assume that one counter grows along with the other
     pub run_count: u64,
     /// Time the program was run before the current tick
     pub run_time: Duration,
+    /// Number of packets processed by XDP program for each action code, empty if not measured
+    pub xdp_actions: Vec<u64>,
 
     /// Map current size
     pub map_entries: u32,
     /// Map max size
     pub map_max_entries: u32,
+    /// Map wasn't walked on the current tick because the map walk budget was exhausted,
+    /// map size is unknown
+    pub map_stale: bool,
+    /// All entries of the map are preallocated (arrays), map size is the max size
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub map_preallocated: bool,
+    /// Hashes of keys of the map, empty if churn of maps is not tracked
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub map_key_hashes: Vec<u64>,
+    /// Number of entries of per-cpu map with a nonzero value on each cpu, empty for maps
+    /// of other types
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub map_per_cpu_entries: Vec<u32>,
+    /// Counters of each loaded program summed into the stats by program id, empty unless
+    /// programs with the same tag are deduplicated
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tag_instances: Vec<(u32, ProgramCounters)>,
 }
 
-#[derive(Clone, Debug)]
+/// Cumulative counters of an ebpf program
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ProgramCounters {
+    /// Number of times the program was run
+    pub run_count: u64,
+    /// Time the program was run
+    pub run_time: Duration,
+    /// Number of packets processed by XDP program for each action code
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub xdp_actions: Vec<u64>,
+}
+
+impl ProgramCounters {
+    /// Returns counters of the program stats
+    ///
+    /// # Arguments
+    ///
+    /// * `stats` - Stats of the program
+    pub fn of(stats: &BpfRawStats) -> Self {
+        Self {
+            run_count: stats.run_count,
+            run_time: stats.run_time,
+            xdp_actions: stats.xdp_actions.clone(),
+        }
+    }
+
+    /// Adds counters of another program
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Counters to add
+    pub fn add(&mut self, other: &ProgramCounters) {
+        self.run_count += other.run_count;
+        self.run_time += other.run_time;
+        if self.xdp_actions.len() < other.xdp_actions.len() {
+            self.xdp_actions.resize(other.xdp_actions.len(), 0);
+        }
+        self.xdp_actions
+            .iter_mut()
+            .zip(&other.xdp_actions)
+            .for_each(|(sum, count)| *sum += count);
+    }
+
+    /// Returns true if any counter is lower than in the previous counters, i.e. the
+    /// program was reloaded and counts from zero again
+    ///
+    /// # Arguments
+    ///
+    /// * `prev` - Previous counters of the program
+    pub fn went_down(&self, prev: &ProgramCounters) -> bool {
+        self.run_time < prev.run_time || self.run_count < prev.run_count
+    }
+}
+
+/// Options of collecting ebpf program/map stats
+#[derive(Debug, Clone, Default)]
+pub struct CollectOptions {
+    /// List of ebpf program/map ids to collect stats for. If empty, all programs/maps are collected
+    pub ids: Vec<u32>,
+    /// Ids of ebpf programs/maps loaded before the start, excluded from collection
+    pub baseline_ids: HashSet<u32>,
+    /// Sum stats of ebpf programs with the same tag into a single entry
+    pub dedupe_by_tag: bool,
+    /// Read per-action counters of XDP programs from their stats maps
+    pub xdp_actions: bool,
+    /// Log durations of collection phases on every tick
+    pub verbose_timing: bool,
+    /// Fail on the first ebpf program/map which stats can't be read instead of skipping it
+    pub strict: bool,
+    /// Maximum total time of walking all maps on a tick, maps are not limited if not specified
+    pub map_walk_budget: Option<Duration>,
+    /// Id of the last map walked on a tick which exhausted the walk budget, the next walk
+    /// starts after it. Zero if all maps were walked
+    pub map_walk_resume: Arc<AtomicU32>,
+    /// Types of maps to collect stats for. If empty, all supported map types are collected
+    pub map_types: Vec<MapType>,
+    /// Types of ebpf programs to collect stats for. If empty, programs of all types are collected
+    pub program_types: Vec<ProgramType>,
+    /// Collect hashes of keys of maps to count added and deleted entries
+    pub map_churn: bool,
+    /// Maximum number of map entries read with a single batch lookup, keys of maps
+    /// are walked one by one if zero
+    pub map_batch_size: u32,
+    /// Maximum random delay before the first collection, no delay if zero
+    pub start_jitter: Duration,
+    /// Read total memory locked by all ebpf programs and maps and its breakdown by kind
+    /// on every tick
+    pub total_locked_memory: bool,
+    /// Behavior when none of the requested ebpf programs/maps is loaded
+    pub on_missing: OnMissing,
+    /// Sample idle ebpf programs less often than hot ones, all of them are sampled
+    /// on every tick if not specified
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    /// Number of evenly spaced samples taken within a period and averaged into a single
+    /// measurement of the period, a single sample if not specified
+    pub sample_count: Option<u64>,
+}
+
+impl CollectOptions {
+    /// Replaces filters of ebpf programs/maps by the ones of other options, other options
+    /// of collecting keep their values
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - Options with the new filters
+    pub fn update_filters(&mut self, other: &CollectOptions) {
+        self.ids = other.ids.clone();
+        self.program_types = other.program_types.clone();
+        self.map_types = other.map_types.clone();
+    }
+
+    /// Returns true if the ebpf program/map passes the id and baseline filters
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Ebpf program/map id
+    pub fn is_requested(&self, id: u32) -> bool {
+        (self.ids.is_empty() || self.ids.contains(&id)) && !self.baseline_ids.contains(&id)
+    }
+}
+
+/// Behavior when none of the requested ebpf programs/maps is loaded at the start
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum)]
+pub enum OnMissing {
+    /// Wait until at least one of them is loaded
+    Wait,
+    /// Start measuring anyway, they are measured once loaded
+    Skip,
+    /// Exit with an error
+    #[default]
+    Fail,
+}
+
+/// Skips ebpf program/map which stats can't be read or fails in strict mode
+///
+/// # Arguments
+///
+/// * `options` - Options of collecting ebpf program/map stats
+///
+/// * `result` - Result of reading ebpf program/map stats
+///
+/// Returns None if the ebpf program/map is skipped
+pub fn skip_unreadable<T, E: Into<anyhow::Error>>(
+    options: &CollectOptions,
+    result: std::result::Result<T, E>,
+) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if options.strict => Err(e.into()),
+        Err(e) => {
+            debug!("Skipping unreadable ebpf program/map: {}", e.into());
+            Ok(None)
+        }
+    }
+}
+
+/// Returns name of ebpf program/map, "unknown" if the kernel doesn't report a readable name
+///
+/// # Arguments
+///
+/// * `name` - Name of ebpf program/map as reported by the kernel
+pub fn entity_name(name: Option<&str>) -> String {
+    name.unwrap_or("unknown").to_string()
+}
+
+/// Measures durations of collection phases, does nothing if verbose timing is disabled
+pub struct PhaseTimer {
+    /// Start of the current phase, None if verbose timing is disabled
+    phase_start: Option<Instant>,
+    /// Durations of finished phases, durations of phases with the same name are summed
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl PhaseTimer {
+    /// Creates a new PhaseTimer and starts the first phase
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Measure durations of phases
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            phase_start: enabled.then(Instant::now),
+            phases: Vec::new(),
+        }
+    }
+
+    /// Finishes the current phase and starts the next one
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the finished phase
+    pub fn finish_phase(&mut self, name: &'static str) {
+        let Some(phase_start) = self.phase_start.as_mut() else {
+            return;
+        };
+        let now = Instant::now();
+        let duration = now - *phase_start;
+        *phase_start = now;
+        match self.phases.iter_mut().find(|(phase, _)| *phase == name) {
+            Some((_, total)) => *total += duration,
+            None => self.phases.push((name, duration)),
+        }
+    }
+
+    /// Logs durations of finished phases at debug level
+    ///
+    /// # Arguments
+    ///
+    /// * `meter` - Name of the meter
+    ///
+    /// * `tick` - Tick number
+    pub fn log(&self, meter: &str, tick: u64) {
+        if self.phase_start.is_none() {
+            return;
+        }
+        let phases = self
+            .phases
+            .iter()
+            .map(|(name, duration)| format!("{name} {duration:?}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        debug!("{meter} meter tick {tick} timing: {phases}");
+    }
+}
+
+/// Summary of a single collection pass over ebpf programs/maps
+#[derive(Debug, Clone, Default)]
+pub struct CollectStats {
+    /// Number of ebpf programs/maps excluded by each filter, keyed by filter name
+    pub filtered: HashMap<&'static str, u64>,
+    /// Number of ebpf programs/maps that should have been measured: not excluded by filters
+    /// and supported by the meter. Unreadable ones are counted as well
+    pub intended: u64,
+    /// Number of intended ebpf programs/maps which stats were actually read
+    pub measured: u64,
+}
+
+impl CollectStats {
+    /// Returns the fraction of intended ebpf programs/maps that were measured,
+    /// 1 if nothing was intended
+    pub fn completeness(&self) -> f64 {
+        if self.intended == 0 {
+            return 1.0;
+        }
+        self.measured as f64 / self.intended as f64
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
 pub struct BpfInfo<'a> {
     /// Ebpf map id
     pub id: u32,
@@ -40,12 +327,38 @@ pub struct BpfInfo<'a> {
     /// Measurement number
     #[allow(dead_code)]
     pub tick: u64,
+    /// Time the stats were received since the start of the measurements
+    #[serde(skip)]
+    pub time_recieved: Duration,
 
+    #[serde(flatten)]
     pub stats: BpfStatsInfo,
 }
 
+/// Stats of ebpf program/map measured on a tick, owning the raw stats BpfInfo borrows from
+#[derive(Clone, Debug)]
+pub struct Measurement {
+    /// Stats as they were received from the kernel
+    pub raw_stats: BpfRawStats,
+    /// Stats calculated by the meter
+    pub stats: BpfStatsInfo,
+}
+
+impl Measurement {
+    /// Returns BpfInfo to export the measurement
+    pub fn info(&self) -> BpfInfo<'_> {
+        BpfInfo {
+            id: self.raw_stats.id,
+            name: &self.raw_stats.name,
+            tick: self.raw_stats.tick,
+            time_recieved: self.raw_stats.time_recieved,
+            stats: self.stats.clone(),
+        }
+    }
+}
+
 /// Contains information about ebpf program/map stats to be exported in table format
-#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
 #[serde(untagged)]
 pub enum BpfStatsInfo {
     Cpu(BpfCPUStatsInfo),
@@ -54,24 +367,33 @@ pub enum BpfStatsInfo {
 
 /// Trait for measuring ebpf program/map stats
 pub trait Meter {
-    /// Returns a mapping of ebpf program/map id to name
-    fn get_id_name_entity_mapping() -> HashMap<u32, String>;
+    /// Short name of the meter used in bpfmeter own metrics
+    const NAME: &'static str;
+
+    /// Returns a mapping of ebpf program/map id to name, of programs/maps of the types
+    /// collected with the options
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Options of collecting ebpf program/map stats
+    fn get_id_name_entity_mapping(options: &CollectOptions) -> HashMap<u32, String>;
 
     /// Asynchronously collects ebpf program/map stats and sends it to the channel
     ///
     /// # Arguments
     ///
-    /// * `prog_list_ids` - List of ebpf program/map ids to collect stats for.
-    ///   If empty, all programs/maps are collected.
+    /// * `options` - Options defining which ebpf programs/maps are collected and how
     ///
     /// * `base_stats` - Base stats with additional information
     ///
     /// * `tx` - Channel to send stats to
+    ///
+    /// Returns the summary of the collection pass
     fn collect_raw_stats(
-        prog_list_ids: &[u32],
+        options: &CollectOptions,
         base_raw_stats: &BpfRawStats,
         tx: Sender<BpfRawStats>,
-    ) -> impl std::future::Future<Output = Result<()>> + Send;
+    ) -> impl std::future::Future<Output = Result<CollectStats>> + Send;
 
     /// Generates BpfStatsInfo from BpfRawStats that can be serialized
     ///
@@ -80,3 +402,14 @@ pub trait Meter {
     /// * `raw_stats` - BpfRawStats to generate BpfStatsInfo from
     fn generate_stats_info(&mut self, raw_stats: &BpfRawStats) -> Option<BpfStatsInfo>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entity_name_without_name() {
+        assert_eq!(entity_name(Some("xdp_fw")), "xdp_fw");
+        assert_eq!(entity_name(None), "unknown");
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+
+use crate::meter::{
+    BpfStatsInfo,
+    cpu_meter::BpfCPUStatsInfo,
+    map_meter::{BpfMapStatsInfo, fill_ratio},
+};
+
+/// Options of adaptive sampling, where idle ebpf programs are sampled less often than hot ones
+#[derive(Debug, Clone, Copy)]
+pub struct AdaptiveSampling {
+    /// Maximum number of ticks between two samples of an idle program
+    pub max_interval: u64,
+    /// Cpu usage at or below which the program is considered idle
+    pub idle_threshold: f32,
+}
+
+/// Decides on which ticks ebpf programs are sampled. Programs are sampled on every tick
+/// while they are hot, the interval between samples of an idle program doubles on every
+/// idle sample up to the maximum and drops back to a single tick once it becomes hot
+#[derive(Debug)]
+pub struct SamplingSchedule {
+    /// Options of adaptive sampling
+    options: AdaptiveSampling,
+    /// Map of bpf program ids to the interval between their samples and the next tick
+    /// they are sampled on
+    programs: HashMap<u32, (u64, u64)>,
+}
+
+impl SamplingSchedule {
+    /// Creates a new SamplingSchedule, all programs are due until they are sampled
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Options of adaptive sampling
+    pub fn new(options: AdaptiveSampling) -> Self {
+        Self {
+            options,
+            programs: HashMap::new(),
+        }
+    }
+
+    /// Returns true if the program has to be sampled on the tick
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Ebpf program id
+    ///
+    /// * `tick` - Current tick
+    pub fn is_due(&self, id: u32, tick: u64) -> bool {
+        self.programs
+            .get(&id)
+            .is_none_or(|(_, next_tick)| tick >= *next_tick)
+    }
+
+    /// Schedules the next sample of the program according to its stats
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Ebpf program id
+    ///
+    /// * `tick` - Tick the program was sampled on
+    ///
+    /// * `stats` - Stats of the sample, maps and programs without cpu usage are sampled
+    ///   on every tick
+    pub fn update(&mut self, id: u32, tick: u64, stats: &BpfStatsInfo) {
+        let BpfStatsInfo::Cpu(stats) = stats else {
+            return;
+        };
+        let Some(cpu_usage) = stats.exact_cpu_usage else {
+            return;
+        };
+        let (interval, next_tick) = self.programs.entry(id).or_insert((1, tick));
+        *interval = if cpu_usage > self.options.idle_threshold {
+            1
+        } else {
+            (*interval * 2).min(self.options.max_interval)
+        };
+        *next_tick = tick + *interval;
+    }
+
+    /// Forgets programs not sampled for longer than the maximum interval, e.g. unloaded ones
+    ///
+    /// # Arguments
+    ///
+    /// * `tick` - Current tick
+    pub fn retain_recent(&mut self, tick: u64) {
+        self.programs
+            .retain(|_, (_, next_tick)| *next_tick + self.options.max_interval > tick);
+    }
+}
+
+/// Averages stats of several samples taken within a period into a single measurement
+/// of the period
+#[derive(Debug)]
+pub struct SampleAverager {
+    /// Number of samples taken within a period
+    sample_count: u64,
+    /// Map of bpf program/map ids to the period their pending samples were taken in
+    /// and the samples
+    pending: HashMap<u32, (u64, Vec<BpfStatsInfo>)>,
+}
+
+impl SampleAverager {
+    /// Creates a new SampleAverager
+    ///
+    /// # Arguments
+    ///
+    /// * `sample_count` - Number of samples taken within a period
+    pub fn new(sample_count: u64) -> Self {
+        Self {
+            sample_count,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Adds stats of a sample of the program/map
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Ebpf program/map id
+    ///
+    /// * `tick` - Tick of the sample, periods consist of sample count ticks
+    ///
+    /// * `stats` - Stats of the sample
+    ///
+    /// Returns the number of the period and the averaged stats once the last sample
+    /// of the period is added
+    pub fn add(&mut self, id: u32, tick: u64, stats: BpfStatsInfo) -> Option<(u64, BpfStatsInfo)> {
+        let period = tick / self.sample_count;
+        let (pending_period, samples) = self.pending.entry(id).or_insert((period, Vec::new()));
+        // Samples of a period the program/map missed the last sample of are dropped
+        if *pending_period != period {
+            *pending_period = period;
+            samples.clear();
+        }
+        samples.push(stats);
+        if tick % self.sample_count != self.sample_count - 1 {
+            return None;
+        }
+        let (_, samples) = self.pending.remove(&id)?;
+        Some((period, aggregate(&samples)?))
+    }
+}
+
+/// Aggregates stats of the window into a single row: gauges (cpu usage, event rate, map size)
+/// are averaged, cumulative counters (run time, run count) are taken from the last tick
+///
+/// # Arguments
+///
+/// * `window` - Stats of consecutive ticks of a single program/map
+pub fn aggregate(window: &[BpfStatsInfo]) -> Option<BpfStatsInfo> {
+    let last = window.last()?;
+    let stats = match last {
+        BpfStatsInfo::Cpu(last) => {
+            let cpu_usages = window
+                .iter()
+                .filter_map(|s| match s {
+                    BpfStatsInfo::Cpu(s) => s.exact_cpu_usage,
+                    BpfStatsInfo::Map(_) => None,
+                })
+                .collect::<Vec<_>>();
+            let event_rates = window
+                .iter()
+                .filter_map(|s| match s {
+                    BpfStatsInfo::Cpu(s) => s.event_rate,
+                    BpfStatsInfo::Map(_) => None,
+                })
+                .collect::<Vec<_>>();
+            BpfStatsInfo::Cpu(BpfCPUStatsInfo {
+                exact_cpu_usage: mean(&cpu_usages),
+                event_rate: mean(&event_rates),
+                ..last.clone()
+            })
+        }
+        BpfStatsInfo::Map(last) => {
+            let sizes = window
+                .iter()
+                .filter_map(|s| match s {
+                    BpfStatsInfo::Map(s) => Some(s.size as u64),
+                    BpfStatsInfo::Cpu(_) => None,
+                })
+                .collect::<Vec<_>>();
+            // Per-cpu sizes are averaged over the samples they were measured on
+            let per_cpu_sizes = window
+                .iter()
+                .filter_map(|s| match s {
+                    BpfStatsInfo::Map(s) => s.per_cpu_sizes.as_ref(),
+                    BpfStatsInfo::Cpu(_) => None,
+                })
+                .collect::<Vec<_>>();
+            let per_cpu_sizes = last.per_cpu_sizes.as_ref().map(|last_sizes| {
+                (0..last_sizes.len())
+                    .map(|cpu| {
+                        let sizes = per_cpu_sizes
+                            .iter()
+                            .filter_map(|sizes| sizes.get(cpu))
+                            .map(|size| *size as u64)
+                            .collect::<Vec<_>>();
+                        (sizes.iter().sum::<u64>() / sizes.len() as u64) as u32
+                    })
+                    .collect()
+            });
+            let size = (sizes.iter().sum::<u64>() / sizes.len() as u64) as u32;
+            BpfStatsInfo::Map(BpfMapStatsInfo {
+                size,
+                fill_ratio: fill_ratio(size, last.max_size),
+                per_cpu_sizes,
+                ..last.clone()
+            })
+        }
+    };
+    Some(stats)
+}
+
+/// Returns the mean of the values, None if there are no values
+///
+/// # Arguments
+///
+/// * `values` - Values to average
+fn mean(values: &[f32]) -> Option<f32> {
+    (!values.is_empty()).then(|| values.iter().sum::<f32>() / values.len() as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cpu_stats(cpu_usage: f32) -> BpfStatsInfo {
+        BpfStatsInfo::Cpu(BpfCPUStatsInfo {
+            exact_cpu_usage: Some(cpu_usage),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_idle_programs_are_sampled_less_often() {
+        let mut schedule = SamplingSchedule::new(AdaptiveSampling {
+            max_interval: 4,
+            idle_threshold: 0.01,
+        });
+        let mut sampled = Vec::new();
+        for tick in 0..16 {
+            if schedule.is_due(1, tick) {
+                sampled.push(tick);
+                // The program becomes hot on tick 11
+                let cpu_usage = if tick >= 11 { 0.5 } else { 0.0 };
+                schedule.update(1, tick, &cpu_stats(cpu_usage));
+            }
+        }
+        assert_eq!(sampled, [0, 2, 6, 10, 14, 15]);
+    }
+
+    #[test]
+    fn test_samples_are_averaged_per_period() {
+        let mut averager = SampleAverager::new(3);
+        let averaged = [0.1, 0.2, 0.6, 0.3, 0.3, 0.3]
+            .into_iter()
+            .enumerate()
+            .filter_map(|(tick, cpu_usage)| averager.add(1, tick as u64, cpu_stats(cpu_usage)))
+            .map(|(period, stats)| match stats {
+                BpfStatsInfo::Cpu(stats) => (period, stats.exact_cpu_usage.unwrap()),
+                BpfStatsInfo::Map(_) => panic!("Expected cpu stats"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(averaged.len(), 2);
+        assert_eq!(averaged[0].0, 0);
+        assert!((averaged[0].1 - 0.3).abs() < 1e-6);
+        assert_eq!(averaged[1].0, 1);
+        assert!((averaged[1].1 - 0.3).abs() < 1e-6);
+    }
+}
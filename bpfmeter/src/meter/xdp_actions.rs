@@ -0,0 +1,87 @@
+use std::os::fd::{AsFd, AsRawFd};
+
+use anyhow::{Context, Result, bail};
+use aya::{
+    maps::{MapInfo, MapType},
+    programs::ProgramInfo,
+};
+use aya_obj::generated::{bpf_attr, bpf_cmd};
+
+/// Prefix of the name of the map with per-action counters of XDP program
+pub const XDP_STATS_MAP_PREFIX: &str = "xdp_stats";
+
+/// Names of XDP actions, index is the action code
+pub const XDP_ACTIONS: [&str; 5] = ["aborted", "drop", "pass", "tx", "redirect"];
+
+/// Reads per-action packet counters of XDP program from its stats map.
+///
+/// The stats map must be used by the program, its name must start with `xdp_stats`
+/// and it must be an array or per-cpu array keyed by u32 action code. The first u64
+/// of the value is the number of packets, the rest of the value is ignored.
+///
+/// # Arguments
+///
+/// * `program` - XDP program to read counters of
+///
+/// Returns counters indexed by action code or None if the program has no stats map
+pub fn read_xdp_actions(program: &ProgramInfo) -> Result<Option<Vec<u64>>> {
+    let Some(map_ids) = program.map_ids()? else {
+        return Ok(None);
+    };
+    let Some(map) = map_ids
+        .into_iter()
+        .filter_map(|id| MapInfo::from_id(id).ok())
+        .find(|map| {
+            map.name_as_str()
+                .is_some_and(|name| name.starts_with(XDP_STATS_MAP_PREFIX))
+        })
+    else {
+        return Ok(None);
+    };
+
+    let value_size = map.value_size() as usize;
+    let cpus = match map.map_type()? {
+        MapType::Array => 1,
+        // Values of per-cpu maps are aligned to 8 bytes for each cpu
+        MapType::PerCpuArray => aya::util::nr_cpus().map_err(|(_, e)| e)?,
+        map_type => bail!("Unsupported type of XDP stats map: {map_type:?}"),
+    };
+    if map.key_size() != 4 || value_size < 8 {
+        bail!(
+            "XDP stats map must have u32 keys and at least u64 values, given key size {} and value size {value_size}",
+            map.key_size()
+        );
+    }
+    let value_stride = value_size.next_multiple_of(8);
+
+    let map_fd = map.fd()?;
+    let mut value = vec![0u8; value_stride * cpus];
+    let mut actions = Vec::with_capacity(XDP_ACTIONS.len());
+    for mut key in 0..(XDP_ACTIONS.len() as u32).min(map.max_entries()) {
+        let mut attr = unsafe { std::mem::zeroed::<bpf_attr>() };
+        let u = unsafe { &mut attr.__bindgen_anon_2 };
+        u.map_fd = map_fd.as_fd().as_raw_fd() as u32;
+        u.key = &mut key as *mut u32 as u64;
+        u.__bindgen_anon_1.value = value.as_mut_ptr() as u64;
+
+        if unsafe {
+            libc::syscall(
+                libc::SYS_bpf,
+                bpf_cmd::BPF_MAP_LOOKUP_ELEM,
+                &mut attr,
+                std::mem::size_of::<bpf_attr>(),
+            )
+        } != 0
+        {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to lookup XDP action {key}"));
+        }
+        actions.push(
+            value
+                .chunks(value_stride)
+                .map(|cpu_value| u64::from_ne_bytes(cpu_value[..8].try_into().unwrap()))
+                .sum(),
+        );
+    }
+    Ok(Some(actions))
+}
@@ -0,0 +1,270 @@
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::Path,
+};
+
+use anyhow::Result;
+use aya::{
+    maps::loaded_maps,
+    programs::loaded_programs,
+    sys::{self, Stats},
+};
+use aya_obj::generated::{BPF_F_NO_PREALLOC, bpf_attr, bpf_cmd, bpf_map_type};
+
+use crate::config::ProbeArgs;
+
+/// Availability of a kernel feature used by bpfmeter
+#[derive(Debug, serde::Serialize)]
+struct Feature {
+    /// Name of the feature
+    name: &'static str,
+    /// Options or metrics of bpfmeter depending on the feature
+    used_by: &'static str,
+    /// The feature is usable on the current kernel
+    available: bool,
+    /// Reason the feature is unavailable
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Feature {
+    /// Creates a new Feature from the result of its probe
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the feature
+    ///
+    /// * `used_by` - Options or metrics of bpfmeter depending on the feature
+    ///
+    /// * `probe` - Result of the probe, error describes why the feature is unavailable
+    fn new(name: &'static str, used_by: &'static str, probe: Result<(), String>) -> Self {
+        Self {
+            name,
+            used_by,
+            available: probe.is_ok(),
+            error: probe.err(),
+        }
+    }
+}
+
+/// Map types measured by bpfmeter or read for XDP actions, with features depending on them
+const PROBED_MAP_TYPES: [(&str, &str, bpf_map_type); 7] = [
+    ("hash map", "map size", bpf_map_type::BPF_MAP_TYPE_HASH),
+    (
+        "percpu_hash map",
+        "map size",
+        bpf_map_type::BPF_MAP_TYPE_PERCPU_HASH,
+    ),
+    (
+        "lru_hash map",
+        "map size",
+        bpf_map_type::BPF_MAP_TYPE_LRU_HASH,
+    ),
+    (
+        "lru_percpu_hash map",
+        "map size",
+        bpf_map_type::BPF_MAP_TYPE_LRU_PERCPU_HASH,
+    ),
+    (
+        "lpm_trie map",
+        "map size",
+        bpf_map_type::BPF_MAP_TYPE_LPM_TRIE,
+    ),
+    (
+        "array map",
+        "map size, --xdp-actions",
+        bpf_map_type::BPF_MAP_TYPE_ARRAY,
+    ),
+    (
+        "percpu_array map",
+        "map size, --xdp-actions",
+        bpf_map_type::BPF_MAP_TYPE_PERCPU_ARRAY,
+    ),
+];
+
+pub fn probe(args: &ProbeArgs) -> Result<()> {
+    let features = probe_features();
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&features)?);
+        return Ok(());
+    }
+
+    let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+    println!("Kernel release: {}", release.trim());
+    println!();
+    println!("{:<20} {:<10} Used by", "Feature", "Available");
+    for feature in features {
+        let status = if feature.available { "yes" } else { "no" };
+        println!("{:<20} {status:<10} {}", feature.name, feature.used_by);
+        if let Some(error) = feature.error {
+            println!("{:<20} {error}", "");
+        }
+    }
+    Ok(())
+}
+
+/// Probes all kernel features used by bpfmeter
+fn probe_features() -> Vec<Feature> {
+    let mut features = vec![
+        Feature::new(
+            "run time stats",
+            "cpu usage, run time, event count",
+            sys::enable_stats(Stats::RunTime)
+                .map(drop)
+                .map_err(|e| format!("{e}")),
+        ),
+        Feature::new(
+            "program listing",
+            "cpu meter",
+            loaded_programs()
+                .next()
+                .transpose()
+                .map(drop)
+                .map_err(|e| format!("{e}")),
+        ),
+        Feature::new(
+            "map listing",
+            "map meter, --xdp-actions",
+            loaded_maps()
+                .next()
+                .transpose()
+                .map(drop)
+                .map_err(|e| format!("{e}")),
+        ),
+        Feature::new(
+            "BTF",
+            "none, required by CO-RE programs",
+            if Path::new("/sys/kernel/btf/vmlinux").exists() {
+                Ok(())
+            } else {
+                Err("/sys/kernel/btf/vmlinux not found".to_string())
+            },
+        ),
+    ];
+
+    let hash_map = create_map(bpf_map_type::BPF_MAP_TYPE_HASH);
+    features.push(Feature::new(
+        "map key walk",
+        "map size",
+        probe_on_map(&hash_map, |fd| {
+            let mut next_key = 0u32;
+            let mut attr = unsafe { std::mem::zeroed::<bpf_attr>() };
+            let u = unsafe { &mut attr.__bindgen_anon_2 };
+            u.map_fd = fd.as_raw_fd() as u32;
+            u.__bindgen_anon_1.next_key = &mut next_key as *mut u32 as u64;
+            bpf_empty_map_cmd(bpf_cmd::BPF_MAP_GET_NEXT_KEY, &mut attr)
+        }),
+    ));
+    features.push(Feature::new(
+        "batch map lookup",
+        "map size, --map-batch-size",
+        probe_on_map(&hash_map, |fd| {
+            let mut out_batch = 0u32;
+            let mut keys = [0u32; 1];
+            let mut values = [0u64; 1];
+            let mut attr = unsafe { std::mem::zeroed::<bpf_attr>() };
+            attr.batch.map_fd = fd.as_raw_fd() as u32;
+            attr.batch.out_batch = &mut out_batch as *mut u32 as u64;
+            attr.batch.keys = keys.as_mut_ptr() as u64;
+            attr.batch.values = values.as_mut_ptr() as u64;
+            attr.batch.count = 1;
+            bpf_empty_map_cmd(bpf_cmd::BPF_MAP_LOOKUP_BATCH, &mut attr)
+        }),
+    ));
+    features.push(Feature::new(
+        "memlock in fdinfo",
+        "ebpf_total_locked_memory_bytes",
+        probe_on_map(&hash_map, |fd| {
+            let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", fd.as_raw_fd()))?;
+            if fdinfo.lines().any(|line| line.starts_with("memlock:")) {
+                Ok(())
+            } else {
+                Err(io::Error::other("memlock is not reported"))
+            }
+        }),
+    ));
+
+    for (name, used_by, map_type) in PROBED_MAP_TYPES {
+        features.push(Feature::new(
+            name,
+            used_by,
+            create_map(map_type).map(drop).map_err(|e| format!("{e}")),
+        ));
+    }
+    features
+}
+
+/// Runs the probe on the map created for probing
+///
+/// # Arguments
+///
+/// * `map` - Result of creating the map, the probe fails if the map wasn't created
+///
+/// * `probe` - Probe to run on the file descriptor of the map
+fn probe_on_map(
+    map: &io::Result<OwnedFd>,
+    probe: impl FnOnce(&OwnedFd) -> io::Result<()>,
+) -> Result<(), String> {
+    match map {
+        Ok(fd) => probe(fd).map_err(|e| format!("{e}")),
+        Err(e) => Err(format!("Failed to create map to probe: {e}")),
+    }
+}
+
+/// Runs the command on an empty map, the command is supported if it reports no entries
+///
+/// # Arguments
+///
+/// * `cmd` - Command of bpf syscall
+///
+/// * `attr` - Attributes of the command
+fn bpf_empty_map_cmd(cmd: bpf_cmd, attr: &mut bpf_attr) -> io::Result<()> {
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *mut bpf_attr,
+            std::mem::size_of::<bpf_attr>(),
+        )
+    };
+    let error = io::Error::last_os_error();
+    if ret == 0 || error.raw_os_error() == Some(libc::ENOENT) {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+/// Creates a map with a single entry, u32 keys and u64 values. Keys of LPM trie are
+/// u32 prefix length followed by u32 data
+///
+/// # Arguments
+///
+/// * `map_type` - Type of the map
+fn create_map(map_type: bpf_map_type) -> io::Result<OwnedFd> {
+    let mut attr = unsafe { std::mem::zeroed::<bpf_attr>() };
+    let u = unsafe { &mut attr.__bindgen_anon_1 };
+    u.map_type = map_type as u32;
+    u.key_size = size_of::<u32>() as u32;
+    u.value_size = size_of::<u64>() as u32;
+    u.max_entries = 1;
+    // LPM trie can't be preallocated and needs data after the prefix length in keys
+    if map_type == bpf_map_type::BPF_MAP_TYPE_LPM_TRIE {
+        u.key_size = 2 * size_of::<u32>() as u32;
+        u.map_flags = BPF_F_NO_PREALLOC;
+    }
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            bpf_cmd::BPF_MAP_CREATE,
+            &mut attr,
+            std::mem::size_of::<bpf_attr>(),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, bail};
+use log::info;
+
+use crate::{
+    config::ReprocessArgs,
+    exporter::{
+        Exporter,
+        file_exporter::{FileExporter, RotationLimits},
+        meter_metrics::MeterMetrics,
+        raw_exporter,
+    },
+    meter::{
+        BpfInfo, Meter,
+        cpu_meter::{CpuMeter, CpuMeterOptions},
+        map_meter::MapMeter,
+    },
+};
+
+/// Processes raw stats captured with --raw-output again and writes the results to csv files,
+/// as if they were measured by `run` with the given options
+pub fn reprocess(args: &ReprocessArgs) -> Result<()> {
+    let records = raw_exporter::read_raw(&args.input)?;
+    let mut cpu_meter = CpuMeter::new(CpuMeterOptions {
+        raw_counters: args.raw_counters,
+        dedupe_by_tag: args.dedupe_by_tag,
+        rate_denominator: args.rate_denominator,
+        ..Default::default()
+    });
+    // Churn of maps is counted if hashes of their keys were captured
+    let mut map_meter = MapMeter::new(
+        records
+            .iter()
+            .any(|record| !record.stats.map_key_hashes.is_empty()),
+    );
+    let meter_metrics = MeterMetrics::default();
+    // File exporters are created on the first record of the meter, the period is known there
+    let mut exporters: HashMap<String, FileExporter> = HashMap::new();
+
+    for record in &records {
+        let (stats_info, suffix) = match record.meter.as_str() {
+            CpuMeter::NAME => (cpu_meter.generate_stats_info(&record.stats), "prog"),
+            MapMeter::NAME => (map_meter.generate_stats_info(&record.stats), "map"),
+            meter => bail!("Unknown meter {meter} in raw stats"),
+        };
+        let Some(stats_info) = stats_info else {
+            continue;
+        };
+        let exporter = exporters.entry(record.meter.clone()).or_insert_with(|| {
+            FileExporter::new(
+                record.period,
+                suffix,
+                &args.output_dir,
+                None,
+                &meter_metrics,
+                1,
+                None,
+                csv::WriterBuilder::new(),
+                RotationLimits::default(),
+            )
+        });
+        exporter.export_info(&BpfInfo {
+            id: record.stats.id,
+            name: &record.stats.name,
+            tick: record.stats.tick,
+            time_recieved: record.stats.time_recieved,
+            stats: stats_info,
+        })?;
+    }
+    info!(
+        "Reprocessed {} raw stats into {}",
+        records.len(),
+        args.output_dir.display()
+    );
+    Ok(())
+}
@@ -1,183 +1,1830 @@
-use crate::config::RunArgs;
-use crate::exporter::prometheus_exporter::PromExportType;
-use crate::exporter::{Exporter, file_exporter, prometheus_exporter, prometheus_gc};
-use crate::meter::{self, BpfInfo, BpfRawStats, Meter};
+use crate::capture;
+use crate::config::{BindAddr, Config, OutputFormat, PrometheusArgs, RunArgs, SubCommands};
+#[cfg(feature = "cloudwatch")]
+use crate::exporter::cloudwatch_exporter;
+#[cfg(feature = "journald")]
+use crate::exporter::journald_exporter;
+use crate::exporter::meter_metrics::MeterMetrics;
+use crate::exporter::prometheus_exporter::{ProgramGroup, PromExportType};
+#[cfg(feature = "redis")]
+use crate::exporter::redis_exporter;
+use crate::exporter::{
+    Exporter, file_exporter, graphite_exporter, jsonl_exporter, on_change_exporter, otlp_exporter,
+    prometheus_exporter, prometheus_gc, pushgateway, rate_gate_exporter, raw_exporter,
+    table_exporter, tee_exporter,
+};
+use crate::meter::bpf_types::{self, BpfMapType, BpfProgramType};
+use crate::meter::cpu_meter::CpuUsageMode;
+use crate::meter::sampling::{AdaptiveSampling, SampleAverager, SamplingSchedule};
+use crate::meter::{self, BpfRawStats, CollectOptions, Measurement, Meter, OnMissing};
 
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::os::fd::OwnedFd;
+use std::path::Path;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll, ready};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{Context, Ok, Result, bail};
 use aya::sys;
-use log::{error, info, warn};
-use tokio::runtime::Builder;
+use aya::{maps::MapType, programs::ProgramType};
+use futures_core::Stream;
+use humantime::format_rfc3339_seconds;
+use log::{debug, error, info, warn};
+use tokio::runtime::{Builder, Runtime};
 use tokio::select;
-use tokio::sync::mpsc;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
 
+/// Minimal interval between warnings about collection taking longer than the period
+const OVERRUN_WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Rough time of reading stats of a single ebpf program
+const PROGRAM_READ_TIME: Duration = Duration::from_micros(20);
+
+/// Rough time of walking keys of a single ebpf map
+const MAP_WALK_TIME: Duration = Duration::from_millis(1);
+
+/// Ratio of cpu and map periods above which one of them is likely misconfigured
+const MAX_PERIODS_RATIO: u32 = 1000;
+
+/// Runs measurements with the exporters selected by the output mode. Ctrl+C and SIGTERM
+/// stop the measurements, SIGHUP reloads filters from the arguments and the config file
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
 pub fn run(args: &RunArgs) -> Result<()> {
-    let runtime = Builder::new_multi_thread()
+    let control = RunControl::new();
+    runtime()?.block_on(async {
+        tokio::spawn(forward_signals(control.clone())?);
+        run_measurements(args, None, &control).await
+    })
+}
+
+/// Handle to stop measurements started with [`run_with_exporters`] or
+/// [`run_with_exporters_async`] and to reload their filters from any task or thread.
+/// Clones of the handle control the same measurements
+#[derive(Clone)]
+pub struct RunControl {
+    /// Set to true when the measurements are requested to stop
+    stop: Arc<watch::Sender<bool>>,
+    /// Arguments with new filters of ebpf programs/maps
+    reload: Arc<watch::Sender<Option<RunArgs>>>,
+}
+
+impl Default for RunControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunControl {
+    /// Creates a new handle, measurements run until it is stopped or their ticks are over
+    pub fn new() -> Self {
+        Self {
+            stop: Arc::new(watch::Sender::new(false)),
+            reload: Arc::new(watch::Sender::new(None)),
+        }
+    }
+
+    /// Stops the measurements, values exported so far are flushed. Measurements started
+    /// after the call stop right away
+    pub fn stop(&self) {
+        self.stop.send_replace(true);
+    }
+
+    /// Replaces filters of ebpf programs/maps with the ones of the arguments,
+    /// the meters pick them up on their next tick. Other arguments are ignored
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - Run arguments with the new filters
+    pub fn reload(&self, args: RunArgs) {
+        self.reload.send_replace(Some(args));
+    }
+}
+
+/// Runs measurements like `run` with the given exporters instead of the ones selected by
+/// the output mode of the arguments. Exporters are wrapped by --on-change-only and
+/// --export-on-rate if they are set. No signal handlers are installed, measurements are
+/// stopped and reloaded through the control handle.
+///
+/// Exporters are shared by the meters through `Rc<RefCell>` and used only from the thread
+/// calling this function, so they don't have to be `Send` or `Sync`. Collection runs on
+/// a worker thread of the runtime, exporting blocks the calling thread, so a slow exporter
+/// delays the following measurements once the channel between them is full. The function
+/// starts its own runtime, await [`run_with_exporters_async`] from async code instead
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+///
+/// * `exporters` - Exporters of cpu and map meters, may be the same exporter
+///
+/// * `control` - Handle to stop the measurements or reload their filters
+pub fn run_with_exporters(
+    args: &RunArgs,
+    exporters: Exporters,
+    control: &RunControl,
+) -> Result<()> {
+    if tokio::runtime::Handle::try_current().is_ok() {
+        bail!(
+            "run_with_exporters can't start a runtime inside a runtime, await run_with_exporters_async instead"
+        );
+    }
+    runtime()?.block_on(run_measurements(args, Some(exporters), control))
+}
+
+/// Runs measurements like [`run_with_exporters`] on the runtime of the caller.
+///
+/// The future is not `Send`, as exporters are shared through `Rc<RefCell>`: await it
+/// with `block_on` or on a `LocalSet`. Collection is spawned on the runtime, so on
+/// a current thread runtime it shares the thread with exporting
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+///
+/// * `exporters` - Exporters of cpu and map meters, may be the same exporter
+///
+/// * `control` - Handle to stop the measurements or reload their filters
+pub async fn run_with_exporters_async(
+    args: &RunArgs,
+    exporters: Exporters,
+    control: &RunControl,
+) -> Result<()> {
+    run_measurements(args, Some(exporters), control).await
+}
+
+/// Builds the runtime measurements run on, collection runs on its single worker thread
+fn runtime() -> Result<Runtime> {
+    Builder::new_multi_thread()
         .worker_threads(1)
         .enable_all()
         .build()
-        .unwrap();
+        .with_context(|| "Failed to start tokio runtime")
+}
 
-    runtime.block_on(async {
-        // Create exporters for cpu and map meters
-        let cpu_exporter: &RefCell<dyn Exporter> = if let Some(ref output_dir) = args.output_mode.output_dir {
-            let file_exporter = file_exporter::FileExporter::new(args.cpu_period, "prog", output_dir);
-            &RefCell::new(file_exporter)
-        } else {
-            let gc = if args.output_mode.prometheus.gc_period != std::time::Duration::ZERO {
-                Some(prometheus_gc::PromGC::new(args.output_mode.prometheus.gc_period))
-            } else {
-                None
-            };
-            let mut prom_exporter = prometheus_exporter::PrometheusExporter::new(
-                args.output_mode.prometheus.labels.clone().unwrap_or_default(), gc,
-            );
-            prom_exporter
-                .start_local_server(args.output_mode.prometheus.port, &args.output_mode.prometheus.export_types)
-                .await?;
+/// Listens to signals of the process: Ctrl+C and SIGTERM stop the measurements, SIGHUP
+/// reloads filters from the arguments and the config file
+///
+/// # Arguments
+///
+/// * `control` - Handle of the measurements
+fn forward_signals(control: RunControl) -> Result<impl Future<Output = ()> + Send> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    // Service managers and container runtimes stop the process with SIGTERM, it is
+    // handled like Ctrl+C so files and the checkpoint are written out
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(async move {
+        loop {
+            select! {
+                _ = tokio::signal::ctrl_c() => {
+                    info!("Ctrl+C pressed, exiting");
+                    control.stop();
+                }
+                _ = terminate.recv() => {
+                    info!("SIGTERM received, exiting");
+                    control.stop();
+                }
+                _ = hangup.recv() => {
+                    info!("SIGHUP received, reloading filters of ebpf programs/maps");
+                    match reload_args() {
+                        Result::Ok(args) => control.reload(args),
+                        Err(e) => warn!("Failed to reload filters, keeping the current ones: {e:#}"),
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// Runs measurements with the exporters, creates them according to the output mode
+/// if they are not given
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+///
+/// * `exporters` - Exporters of cpu and map meters
+///
+/// * `control` - Handle to stop the measurements or reload their filters
+async fn run_measurements(
+    args: &RunArgs,
+    exporters: Option<Exporters>,
+    control: &RunControl,
+) -> Result<()> {
+    if args.list_program_types || args.list_map_types {
+        if args.list_program_types {
+            bpf_types::type_names::<BpfProgramType>().for_each(|name| println!("{name}"));
+        }
+        if args.list_map_types {
+            bpf_types::measured_map_types()
+                .filter_map(|map_type| {
+                    bpf_types::type_name::<BpfMapType, _>(MapType::from(map_type))
+                })
+                .for_each(|name| println!("{name}"));
+        }
+        return Ok(());
+    }
 
-            &RefCell::new(prom_exporter)
+    check_periods(args)?;
+    let online_cpus = host_cpus(args)?;
+
+    let start = SystemTime::now();
+
+    // Exporters are dropped with the future, so all files are flushed when it is finished
+    async {
+        // Metrics of bpfmeter itself are served only by prometheus exporter
+        let mut meter_metrics = MeterMetrics::default();
+        let (mut cpu_exporter, mut map_exporter) = match exporters {
+            Some(exporters) => exporters,
+            None => create_exporters(args, &mut meter_metrics).await?,
         };
-        let map_exporter: &RefCell<dyn Exporter> = if let Some(ref output_dir) = args.output_mode.output_dir {
-            // File exporter is different for cpu and map meters
-            let file_exporter = file_exporter::FileExporter::new(args.map_period, "map", output_dir);
-            &RefCell::new(file_exporter)
+        if args.on_change_only {
+            cpu_exporter = Rc::new(RefCell::new(on_change_exporter::OnChangeExporter::new(
+                cpu_exporter,
+                args.heartbeat_interval,
+            )));
+            map_exporter = Rc::new(RefCell::new(on_change_exporter::OnChangeExporter::new(
+                map_exporter,
+                args.heartbeat_interval,
+            )));
+        }
+        if let Some(gate) = rate_gate(args)? {
+            cpu_exporter = Rc::new(RefCell::new(rate_gate_exporter::RateGateExporter::new(
+                cpu_exporter,
+                gate,
+            )));
+        }
+
+        // Programs/maps loaded before the start are excluded, the ones loaded afterwards
+        // are discovered on every tick
+        let (prog_baseline, map_baseline) = if args.only_new {
+            let prog_baseline = meter::cpu_meter::CpuMeter::get_id_name_entity_mapping(&CollectOptions::default())
+                .into_keys()
+                .collect::<HashSet<_>>();
+            let map_baseline = meter::map_meter::MapMeter::get_id_name_entity_mapping(&CollectOptions::default())
+                .into_keys()
+                .collect::<HashSet<_>>();
+            info!(
+                "Excluding {} ebpf programs and {} maps loaded before the start",
+                prog_baseline.len(),
+                map_baseline.len()
+            );
+            (prog_baseline, map_baseline)
         } else {
-            if args.enable_maps && !args.output_mode.prometheus.export_types.contains(&PromExportType::MapSize) {
-                warn!("Map size is not exported to prometheus, but maps are enabled. Make sure you have enabled map size export type");
-            }
-            // Prometheus exporter is the same for both meters
-            cpu_exporter
+            Default::default()
         };
 
-        // Create meters for cpu and map meters
-        tokio::pin! {
-            let cpu_future = measure(args.cpu_period, args.channel_capacity, meter::cpu_meter::CpuMeter::new(), cpu_exporter,args.ticks, args.bpf_programs.as_ref());
-            let map_future = measure(args.map_period, args.channel_capacity, meter::map_meter::MapMeter::new(), map_exporter,args.ticks, args.bpf_maps.as_ref());
-        }
-        let mut status = Ok(());
-        let (mut cpu_ready, mut map_ready) = (args.disable_cpu, !args.enable_maps);
+        let (cpu_options, map_options) =
+            collect_options(args, prog_baseline.clone(), map_baseline.clone())?;
+        // Filters are reloaded through the control handle, monitoring tasks pick them up
+        // on their next tick
+        let (cpu_reload, cpu_reload_rx) = watch::channel(cpu_options.clone());
+        let (map_reload, map_reload_rx) = watch::channel(map_options.clone());
+        let mut stop = control.stop.subscribe();
+        let mut reload = control.reload.subscribe();
+
+        let cpu_meter_options = meter::cpu_meter::CpuMeterOptions {
+            raw_counters: args.raw_counters,
+            dedupe_by_tag: args.dedupe_by_tag,
+            stuck_threshold: args.stuck_threshold,
+            zscore_window: args.zscore_window.map(|window| window as usize),
+            rate_denominator: args.rate_denominator,
+            checkpoint: args.checkpoint.clone(),
+            cpu_usage_threshold: args.cpu_usage_threshold,
+            online_cpus,
+            cpu_smoothing_alpha: args.cpu_smoothing_alpha,
+        };
 
-        // If something is disabled then it is ready
-        if cpu_ready && map_ready {
+        // Raw stats of both meters are written to the same file
+        let raw_exporter = match args.raw_output {
+            Some(ref path) => Some(RefCell::new(raw_exporter::RawExporter::new(path)?)),
+            None => None,
+        };
+
+        if args.disable_cpu && !args.enable_maps {
             bail!("Nothing to measure, enable at least one of cpu or map meters");
         }
 
+        // Create meters for cpu and map meters, disabled ones are not run
+        let cpu_future = (!args.disable_cpu).then(|| measure(args.cpu_period, args.channel_capacity, meter::cpu_meter::CpuMeter::new(cpu_meter_options), &cpu_exporter, raw_exporter.as_ref(), meter_metrics.clone(), args.ticks, cpu_options, Some(cpu_reload_rx), args.output_mode.flush_ticks));
+        let map_future = args.enable_maps.then(|| measure(args.map_period, args.channel_capacity, meter::map_meter::MapMeter::new(args.map_churn), &map_exporter, raw_exporter.as_ref(), meter_metrics.clone(), args.ticks, map_options, Some(map_reload_rx), args.output_mode.flush_ticks));
+        let measurements = join_measurements(cpu_future, map_future);
+        tokio::pin!(measurements);
+
         info!("Starting measurements");
 
         loop {
             select! {
-                res = &mut cpu_future, if !cpu_ready  => {
-                    info!("CPU measurements finished");
-                    cpu_ready = true;
-                    status = res;
-                },
-                res = &mut map_future, if !map_ready => {
-                    info!("Map measurements finished");
-                    map_ready = true;
-                    status = res
+                res = &mut measurements => {
+                    res?;
+                    info!("All measurements finished");
+                    break;
                 },
-                _ = tokio::signal::ctrl_c() => {
-                    info!("Ctrl+C pressed, exiting");
+                _ = stop.wait_for(|stop| *stop) => {
+                    info!("Measurements are stopped");
                     break;
                 }
+                Result::Ok(()) = reload.changed() => {
+                    let Some(args) = reload.borrow_and_update().clone() else {
+                        continue;
+                    };
+                    match collect_options(&args, prog_baseline.clone(), map_baseline.clone()) {
+                        Result::Ok((cpu_options, map_options)) => {
+                            info!("Filters of ebpf programs/maps are reloaded");
+                            cpu_reload.send_replace(cpu_options);
+                            map_reload.send_replace(map_options);
+                        }
+                        Err(e) => warn!("Failed to reload filters, keeping the current ones: {e:#}"),
+                    }
+                }
             }
+        }
+        // Measurements exported so far are written out before the runtime unwinds,
+        // meters save their state when the measurements are dropped
+        cpu_exporter.borrow_mut().flush()?;
+        map_exporter.borrow_mut().flush()?;
+        Ok(())
+    }
+    .await?;
 
-            if cpu_ready && map_ready {
-                info!("All measurements finished");
-                break;
+    if args.output_mode.manifest
+        && let Some(ref output_dir) = args.output_mode.output_dir
+    {
+        write_manifest(output_dir, start, args.workload_version.clone())?;
+    }
+    Ok(())
+}
+
+/// Waits for measurements of the cpu and map meters. Failed measurements of one meter
+/// stop the other one, so the error isn't lost when the other one finishes
+///
+/// # Arguments
+///
+/// * `cpu` - Measurements of the cpu meter, None if the meter is disabled
+///
+/// * `map` - Measurements of the map meter, None if the meter is disabled
+///
+/// Returns the error of the first failed measurements
+async fn join_measurements(
+    cpu: Option<impl Future<Output = Result<()>>>,
+    map: Option<impl Future<Output = Result<()>>>,
+) -> Result<()> {
+    tokio::try_join!(
+        finish_measurements("CPU", cpu),
+        finish_measurements("Map", map)
+    )?;
+    Ok(())
+}
+
+/// Waits for measurements of the meter and logs how they finished
+///
+/// # Arguments
+///
+/// * `name` - Name of the meter in logs
+///
+/// * `measurements` - Measurements of the meter, None if the meter is disabled
+async fn finish_measurements(
+    name: &str,
+    measurements: Option<impl Future<Output = Result<()>>>,
+) -> Result<()> {
+    let Some(measurements) = measurements else {
+        return Ok(());
+    };
+    match measurements.await {
+        Result::Ok(()) => {
+            info!("{name} measurements finished");
+            Ok(())
+        }
+        Err(e) => {
+            error!("{name} measurements failed: {e:#}");
+            Err(e)
+        }
+    }
+}
+
+/// Writes manifest describing the capture session to the output directory
+///
+/// # Arguments
+///
+/// * `output_dir` - Directory with csv files of the run
+///
+/// * `start` - Start time of the run
+///
+/// * `workload_version` - Version of the monitored workload
+fn write_manifest(
+    output_dir: &Path,
+    start: SystemTime,
+    workload_version: Option<String>,
+) -> Result<()> {
+    // Files of previous runs in the same directory are not modified by this run
+    let mut files = capture::csv_files(output_dir)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|file| {
+            file.metadata()
+                .and_then(|m| m.modified())
+                .is_ok_and(|modified| modified >= start)
+        })
+        .filter_map(|file| Some(file.file_name()?.to_string_lossy().to_string()))
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let manifest = capture::Manifest {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        args: std::env::args().collect(),
+        start: format_rfc3339_seconds(start).to_string(),
+        end: format_rfc3339_seconds(SystemTime::now()).to_string(),
+        host: capture::HostInfo::current(),
+        workload_version,
+        files,
+    };
+    let path = output_dir.join(capture::MANIFEST_FILE);
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("Failed to create manifest {}", path.display()))?;
+    serde_json::to_writer_pretty(file, &manifest)?;
+    info!("Capture manifest is written to {}", path.display());
+    Ok(())
+}
+
+/// Exporters of cpu and map meters
+pub type Exporters = (Rc<RefCell<dyn Exporter>>, Rc<RefCell<dyn Exporter>>);
+
+/// Creates exporters for cpu and map meters according to the output mode
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+///
+/// * `meter_metrics` - Metrics of bpfmeter itself, replaced with the ones served by exporter
+async fn create_exporters(args: &RunArgs, meter_metrics: &mut MeterMetrics) -> Result<Exporters> {
+    if args.output_mode.output_format == OutputFormat::Table {
+        if args.output_mode.output_dir.is_some() {
+            bail!("Table is printed to the terminal, output directory can't be used with it");
+        }
+        // Table exporter is the same for both meters, programs and maps are drawn together
+        let exporter: Rc<RefCell<dyn Exporter>> =
+            Rc::new(RefCell::new(table_exporter::TableExporter::new()));
+        return Ok((exporter.clone(), exporter));
+    }
+
+    #[cfg(feature = "journald")]
+    if args.output_mode.output_format == OutputFormat::Journald {
+        if args.output_mode.output_dir.is_some() {
+            bail!(
+                "Measurements are written to systemd journal, output directory can't be used with it"
+            );
+        }
+        let exporter: Rc<RefCell<dyn Exporter>> =
+            Rc::new(RefCell::new(journald_exporter::JournaldExporter::new()?));
+        return Ok((exporter.clone(), exporter));
+    }
+
+    if let Some(ref output_dir) = args.output_mode.output_dir {
+        // Prometheus exporter serves the same metrics and bpfmeter own metrics in tee mode
+        let prom_exporter = match args.output_mode.tee_port {
+            Some(port) => {
+                let prom_exporter = create_prometheus_exporter(args, port).await?;
+                *meter_metrics = prom_exporter.meter_metrics();
+                Some(Rc::new(RefCell::new(prom_exporter)))
+            }
+            None => None,
+        };
+
+        if args.output_mode.output_format == OutputFormat::Jsonl {
+            if args.output_mode.csv_rotate_daily
+                || args.output_mode.csv_max_size.is_some()
+                || args.output_mode.csv_rotate_interval.is_some()
+                || args.output_mode.csv_aggregate.is_some()
+            {
+                bail!("Rotation and aggregation are only supported for csv files");
             }
+            let cpu_exporter: Rc<RefCell<dyn Exporter>> =
+                Rc::new(RefCell::new(jsonl_exporter::JsonLinesExporter::new(
+                    args.cpu_period,
+                    "prog",
+                    output_dir,
+                    meter_metrics,
+                )));
+            let map_exporter: Rc<RefCell<dyn Exporter>> =
+                Rc::new(RefCell::new(jsonl_exporter::JsonLinesExporter::new(
+                    args.map_period,
+                    "map",
+                    output_dir,
+                    meter_metrics,
+                )));
+            return Ok(tee_prometheus(cpu_exporter, map_exporter, prom_exporter));
         }
-        status
-    })
+
+        let daily_rotation = args
+            .output_mode
+            .csv_rotate_daily
+            .then_some(if args.output_mode.utc {
+                file_exporter::RotationTimezone::Utc
+            } else {
+                file_exporter::RotationTimezone::Local
+            });
+        // File exporter is different for cpu and map meters
+        let cpu_exporter: Rc<RefCell<dyn Exporter>> =
+            Rc::new(RefCell::new(file_exporter::FileExporter::new(
+                args.cpu_period,
+                "prog",
+                output_dir,
+                daily_rotation,
+                meter_metrics,
+                aggregate_ticks(
+                    args.output_mode.csv_aggregate.filter(|_| !args.disable_cpu),
+                    args.cpu_period,
+                )?,
+                args.output_mode
+                    .csv_max_files
+                    .map(|max_files| max_files as usize),
+                csv_writer_builder(args),
+                rotation_limits(args),
+            )));
+        let map_exporter: Rc<RefCell<dyn Exporter>> =
+            Rc::new(RefCell::new(file_exporter::FileExporter::new(
+                args.map_period,
+                "map",
+                output_dir,
+                daily_rotation,
+                meter_metrics,
+                aggregate_ticks(
+                    args.output_mode.csv_aggregate.filter(|_| args.enable_maps),
+                    args.map_period,
+                )?,
+                args.output_mode
+                    .csv_max_files
+                    .map(|max_files| max_files as usize),
+                csv_writer_builder(args),
+                rotation_limits(args),
+            )));
+
+        return Ok(tee_prometheus(cpu_exporter, map_exporter, prom_exporter));
+    }
+
+    #[cfg(feature = "cloudwatch")]
+    if let Some(ref namespace) = args.output_mode.cloudwatch.cloudwatch_namespace {
+        let exporter: Rc<RefCell<dyn Exporter>> = Rc::new(RefCell::new(
+            cloudwatch_exporter::CloudWatchExporter::new(
+                namespace,
+                args.output_mode.cloudwatch.cloudwatch_region.as_deref(),
+                static_labels(args),
+            )
+            .await?,
+        ));
+        // CloudWatch exporter is the same for both meters
+        return Ok((exporter.clone(), exporter));
+    }
+
+    #[cfg(feature = "redis")]
+    if let Some(ref url) = args.output_mode.redis_url {
+        let exporter: Rc<RefCell<dyn Exporter>> = Rc::new(RefCell::new(
+            redis_exporter::RedisExporter::new(url, static_labels(args)).await?,
+        ));
+        // Redis exporter is the same for both meters
+        return Ok((exporter.clone(), exporter));
+    }
+
+    if let Some(ref addr) = args.output_mode.graphite.graphite_addr {
+        let exporter: Rc<RefCell<dyn Exporter>> = Rc::new(RefCell::new(
+            graphite_exporter::GraphiteExporter::new(
+                addr,
+                &args.output_mode.graphite.graphite_prefix,
+                static_labels(args),
+            )
+            .await?,
+        ));
+        // Graphite exporter is the same for both meters
+        return Ok((exporter.clone(), exporter));
+    }
+
+    if let Some(ref endpoint) = args.output_mode.otlp.otlp_endpoint {
+        let exporter: Rc<RefCell<dyn Exporter>> =
+            Rc::new(RefCell::new(otlp_exporter::OtlpExporter::new(
+                endpoint,
+                args.output_mode.otlp.otlp_push_interval,
+                static_labels(args),
+            )?));
+        // OTLP exporter is the same for both meters
+        return Ok((exporter.clone(), exporter));
+    }
+
+    let prom_exporter = create_prometheus_exporter(args, args.output_mode.prometheus.port).await?;
+    *meter_metrics = prom_exporter.meter_metrics();
+    // Prometheus exporter is the same for both meters
+    let exporter: Rc<RefCell<dyn Exporter>> = Rc::new(RefCell::new(prom_exporter));
+    Ok((exporter.clone(), exporter))
+}
+
+/// Combines file exporters with prometheus exporter serving the same metrics in tee mode
+///
+/// # Arguments
+///
+/// * `cpu_exporter` - File exporter of cpu meter
+///
+/// * `map_exporter` - File exporter of map meter
+///
+/// * `prom_exporter` - Prometheus exporter, file exporters are returned as is if not set
+fn tee_prometheus(
+    cpu_exporter: Rc<RefCell<dyn Exporter>>,
+    map_exporter: Rc<RefCell<dyn Exporter>>,
+    prom_exporter: Option<Rc<RefCell<prometheus_exporter::PrometheusExporter>>>,
+) -> Exporters {
+    let Some(prom_exporter) = prom_exporter else {
+        return (cpu_exporter, map_exporter);
+    };
+    (
+        Rc::new(RefCell::new(tee_exporter::TeeExporter::new(vec![
+            cpu_exporter,
+            prom_exporter.clone(),
+        ]))),
+        Rc::new(RefCell::new(tee_exporter::TeeExporter::new(vec![
+            map_exporter,
+            prom_exporter,
+        ]))),
+    )
+}
+
+/// Returns the number of ticks aggregated into a single csv row
+///
+/// # Arguments
+///
+/// * `window` - Aggregation window, every tick is written if not specified
+///
+/// * `period` - Period of time between two measurements
+fn aggregate_ticks(window: Option<Duration>, period: Duration) -> Result<u32> {
+    let Some(window) = window else {
+        return Ok(1);
+    };
+    if window < period || window.as_nanos() % period.as_nanos() != 0 {
+        bail!("Aggregation window {window:?} must be a multiple of the period {period:?}");
+    }
+    Ok((window.as_nanos() / period.as_nanos()) as u32)
+}
+
+/// Creates prometheus exporter and starts its server
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+///
+/// * `port` - Port to start exporter on
+async fn create_prometheus_exporter(
+    args: &RunArgs,
+    port: u16,
+) -> Result<prometheus_exporter::PrometheusExporter> {
+    let gc = if args.output_mode.prometheus.gc_period != std::time::Duration::ZERO {
+        Some(prometheus_gc::PromGC::new(
+            args.output_mode.prometheus.gc_period,
+        ))
+    } else {
+        None
+    };
+    let mut prom_exporter = prometheus_exporter::PrometheusExporter::new(
+        static_labels(args),
+        gc,
+        prometheus_exporter::PromExporterOptions {
+            id_only_series: args.output_mode.prometheus.id_only_series,
+            sanitize_names: args.output_mode.prometheus.sanitize_names,
+            groups: program_groups(&args.output_mode.prometheus)?,
+            stuck_detection: args.stuck_threshold.is_some(),
+            zscore: args.zscore_window.is_some(),
+            above_threshold: args.cpu_usage_threshold.is_some(),
+            cpu_usage_smoothed: args.cpu_smoothing_alpha.is_some(),
+            stale_map_sizes: args.map_walk_budget.is_some(),
+            cpu_histogram_buckets: cpu_histogram_buckets(&args.output_mode.prometheus)?,
+            host_cpus: host_cpus(args)?,
+        },
+    );
+    let metadata = match args.output_mode.prometheus.metrics_metadata {
+        Some(ref path) => prometheus_exporter::load_metrics_metadata(path)?,
+        None => Default::default(),
+    };
+    match args.output_mode.prometheus.remote_write_url {
+        Some(ref url) if args.output_mode.prometheus.agent_mode => prom_exporter.start_agent(
+            url,
+            args.output_mode.prometheus.remote_write_interval,
+            &args.output_mode.prometheus.export_types,
+            &metadata,
+        ),
+        _ if let Some(ref url) = args.output_mode.prometheus.pushgateway_url => {
+            let hostname = capture::HostInfo::current().hostname;
+            prom_exporter.start_pushgateway(
+                &pushgateway::group_url(
+                    url,
+                    &args.output_mode.prometheus.pushgateway_job,
+                    &hostname,
+                ),
+                args.output_mode
+                    .prometheus
+                    .pushgateway_interval
+                    .unwrap_or(args.cpu_period),
+                &args.output_mode.prometheus.export_types,
+                &metadata,
+            )
+        }
+        _ => {
+            prom_exporter
+                .start_local_server(
+                    metrics_addr(&args.output_mode.prometheus.metrics_bind, port),
+                    &args.output_mode.prometheus.export_types,
+                    &metadata,
+                    &server_options(&args.output_mode.prometheus),
+                )
+                .await?
+        }
+    }
+
+    if args.enable_maps
+        && !args
+            .output_mode
+            .prometheus
+            .export_types
+            .contains(&PromExportType::MapSize)
+    {
+        warn!(
+            "Map size is not exported to prometheus, but maps are enabled. Make sure you have enabled map size export type"
+        );
+    }
+    if args.map_churn
+        && !args
+            .output_mode
+            .prometheus
+            .export_types
+            .contains(&PromExportType::MapChurn)
+    {
+        warn!(
+            "Map churn is not exported to prometheus, but its measurement is enabled. Make sure you have enabled map churn export type"
+        );
+    }
+    if args.xdp_actions
+        && !args
+            .output_mode
+            .prometheus
+            .export_types
+            .contains(&PromExportType::XdpActions)
+    {
+        warn!(
+            "XDP actions are not exported to prometheus, but their measurement is enabled. Make sure you have enabled xdp actions export type"
+        );
+    }
+    Ok(prom_exporter)
+}
+
+/// Returns static labels added to all metrics: labels given with --labels and the workload
+/// version if it is set
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+fn static_labels(args: &RunArgs) -> prometheus_exporter::Labels {
+    let mut labels = args
+        .output_mode
+        .prometheus
+        .labels
+        .clone()
+        .unwrap_or_default();
+    if let Some(ref version) = args.workload_version {
+        labels.push(("workload_version".to_string(), version.clone()));
+    }
+    labels
+}
+
+/// Returns the number of online CPUs of the host if cpu usage as a share of the host
+/// is calculated: in system cpu usage mode or if cpu usage host fraction is exported
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+fn host_cpus(args: &RunArgs) -> Result<Option<usize>> {
+    if args.cpu_usage_mode != CpuUsageMode::System
+        && !args
+            .output_mode
+            .prometheus
+            .export_types
+            .contains(&PromExportType::CPUUsageHostFraction)
+    {
+        return Ok(None);
+    }
+    let cpus = aya::util::online_cpus()
+        .map_err(|(path, e)| anyhow::anyhow!("Failed to read online CPUs from {path}: {e}"))?;
+    Ok(Some(cpus.len()))
+}
+
+/// Returns groups of ebpf programs, names of the groups must be unique
+///
+/// # Arguments
+///
+/// * `args` - Prometheus arguments
+fn program_groups(args: &PrometheusArgs) -> Result<Vec<ProgramGroup>> {
+    let mut names = HashSet::new();
+    for group in &args.groups {
+        if !names.insert(&group.name) {
+            bail!("Group {} is given more than once", group.name);
+        }
+    }
+    Ok(args.groups.clone())
+}
+
+/// Returns buckets of the cpu usage histogram if its export type is enabled
+///
+/// # Arguments
+///
+/// * `args` - Prometheus arguments
+fn cpu_histogram_buckets(args: &PrometheusArgs) -> Result<Option<Vec<f64>>> {
+    if !args
+        .export_types
+        .contains(&PromExportType::CPUUsageHistogram)
+    {
+        return Ok(None);
+    }
+    let buckets = &args.cpu_histogram_buckets;
+    if buckets.is_empty() || buckets.windows(2).any(|w| w[0] >= w[1]) {
+        bail!("Cpu histogram buckets must be non-empty and in ascending order");
+    }
+    Ok(Some(buckets.clone()))
+}
+
+/// Returns the address prometheus exporter listens on
+///
+/// # Arguments
+///
+/// * `bind` - Bind address, its port overrides the exporter port
+///
+/// * `port` - Exporter port
+fn metrics_addr(bind: &BindAddr, port: u16) -> SocketAddr {
+    match bind.port {
+        Some(bind_port) => {
+            if bind_port != port {
+                warn!("Port {bind_port} of the bind address overrides exporter port {port}");
+            }
+            SocketAddr::new(bind.ip, bind_port)
+        }
+        None => SocketAddr::new(bind.ip, port),
+    }
+}
+
+/// Builds options of collecting stats of ebpf programs and maps from arguments
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+///
+/// * `prog_baseline` - Ids of ebpf programs loaded before the start, excluded with --only-new
+///
+/// * `map_baseline` - Ids of ebpf maps loaded before the start, excluded with --only-new
+fn collect_options(
+    args: &RunArgs,
+    prog_baseline: HashSet<u32>,
+    map_baseline: HashSet<u32>,
+) -> Result<(CollectOptions, CollectOptions)> {
+    let cpu_options = CollectOptions {
+        ids: args.bpf_programs.clone().unwrap_or_default(),
+        baseline_ids: prog_baseline,
+        dedupe_by_tag: args.dedupe_by_tag,
+        xdp_actions: args.xdp_actions,
+        verbose_timing: args.verbose_timing,
+        strict: args.strict,
+        map_walk_budget: None,
+        map_walk_resume: Default::default(),
+        map_batch_size: 0,
+        map_types: Vec::new(),
+        program_types: program_types(args),
+        map_churn: false,
+        start_jitter: args.start_jitter,
+        // Host-level memory is read by one of the meters, the cpu one if it is enabled
+        total_locked_memory: !args.disable_cpu,
+        on_missing: args.on_missing,
+        adaptive_sampling: adaptive_sampling(args)?,
+        sample_count: args.sample_count,
+    };
+    let map_options = CollectOptions {
+        ids: args.bpf_maps.clone().unwrap_or_default(),
+        baseline_ids: map_baseline,
+        map_walk_budget: args.map_walk_budget,
+        map_batch_size: args.map_batch_size,
+        map_types: map_types(args),
+        map_churn: args.map_churn,
+        start_jitter: args.start_jitter,
+        verbose_timing: args.verbose_timing,
+        strict: args.strict,
+        total_locked_memory: args.disable_cpu,
+        on_missing: args.on_missing,
+        sample_count: args.sample_count,
+        ..Default::default()
+    };
+    Ok((cpu_options, map_options))
 }
 
-async fn measure<M: Meter>(
+/// Parses the arguments and the config file of the process again to get new filters
+/// of ebpf programs/maps
+fn reload_args() -> Result<RunArgs> {
+    // Only the first line of clap errors describes the problem, the rest is usage
+    let config = Config::try_new(std::env::args_os().collect()).map_err(|e| {
+        let message = e.to_string();
+        anyhow::anyhow!(
+            "{}",
+            message
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim_start_matches("error: ")
+        )
+    })?;
+    let SubCommands::Run(args) = config.command else {
+        bail!("Arguments of run are missing");
+    };
+    Ok(args)
+}
+
+/// Builds the builder of csv writers with the delimiter and header settings from arguments
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+fn csv_writer_builder(args: &RunArgs) -> csv::WriterBuilder {
+    let mut builder = csv::WriterBuilder::new();
+    builder
+        .delimiter(args.output_mode.csv_delimiter)
+        .has_headers(!args.output_mode.csv_no_header);
+    builder
+}
+
+/// Builds limits of the size and time of csv files from arguments
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+fn rotation_limits(args: &RunArgs) -> file_exporter::RotationLimits {
+    file_exporter::RotationLimits {
+        max_size: args.output_mode.csv_max_size,
+        interval: args.output_mode.csv_rotate_interval,
+    }
+}
+
+/// Builds options of adaptive sampling from arguments, None if it is disabled
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+fn adaptive_sampling(args: &RunArgs) -> Result<Option<AdaptiveSampling>> {
+    let Some(max_period) = args.adaptive_max_period else {
+        return Ok(None);
+    };
+    if max_period < args.cpu_period {
+        bail!(
+            "Adaptive max period {max_period:?} must not be shorter than cpu period {:?}",
+            args.cpu_period
+        );
+    }
+    Ok(Some(AdaptiveSampling {
+        max_interval: (max_period.as_nanos() / args.cpu_period.as_nanos()) as u64,
+        idle_threshold: args.adaptive_idle_threshold,
+    }))
+}
+
+/// Warns about measurement periods too short to collect stats of all ebpf programs/maps
+/// or extremely different from each other, errors instead in strict mode
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+fn check_periods(args: &RunArgs) -> Result<()> {
+    // Only programs/maps of the measured types are counted
+    let options = CollectOptions {
+        program_types: program_types(args),
+        map_types: map_types(args),
+        ..Default::default()
+    };
+    let cpu = (!args.disable_cpu).then(|| {
+        let programs = args.bpf_programs.as_ref().map_or_else(
+            || meter::cpu_meter::CpuMeter::get_id_name_entity_mapping(&options).len(),
+            Vec::len,
+        );
+        (
+            args.cpu_period / args.sample_count.unwrap_or(1) as u32,
+            programs,
+        )
+    });
+    // Walk of maps is bounded by the budget, so any period is enough for it
+    let map = (args.enable_maps && args.map_walk_budget.is_none()).then(|| {
+        let maps = args.bpf_maps.as_ref().map_or_else(
+            || meter::map_meter::MapMeter::get_id_name_entity_mapping(&options).len(),
+            Vec::len,
+        );
+        (
+            args.map_period / args.sample_count.unwrap_or(1) as u32,
+            maps,
+        )
+    });
+    let problems = period_problems(cpu, map);
+    if args.strict && !problems.is_empty() {
+        bail!("{}", problems.join("\n"));
+    }
+    problems.iter().for_each(|problem| warn!("{problem}"));
+    Ok(())
+}
+
+/// Describes problems of measurement periods with guidance on fixing them
+///
+/// # Arguments
+///
+/// * `cpu` - Period of the cpu meter and the number of measured programs, if it is enabled
+///
+/// * `map` - Period of the map meter and the number of measured maps, if it is enabled
+fn period_problems(cpu: Option<(Duration, usize)>, map: Option<(Duration, usize)>) -> Vec<String> {
+    let mut problems = Vec::new();
+    if let Some((period, programs)) = cpu
+        && period < PROGRAM_READ_TIME * programs as u32
+    {
+        problems.push(format!(
+            "Cpu period {period:?} is likely too short to read stats of {programs} ebpf programs, \
+             consider --cpu-period of at least {:?} or selecting programs with --bpf-programs",
+            PROGRAM_READ_TIME * programs as u32
+        ));
+    }
+    if let Some((period, maps)) = map
+        && period < MAP_WALK_TIME * maps as u32
+    {
+        problems.push(format!(
+            "Map period {period:?} is likely too short to walk {maps} ebpf maps, consider \
+             --map-period of at least {:?}, selecting maps with --bpf-maps or --map-walk-budget",
+            MAP_WALK_TIME * maps as u32
+        ));
+    }
+    if let (Some((cpu_period, _)), Some((map_period, _))) = (cpu, map) {
+        let (shorter, longer) = (cpu_period.min(map_period), cpu_period.max(map_period));
+        if longer > shorter * MAX_PERIODS_RATIO {
+            problems.push(format!(
+                "Cpu period {cpu_period:?} and map period {map_period:?} differ more than \
+                 {MAX_PERIODS_RATIO} times, one of them is likely misconfigured"
+            ));
+        }
+    }
+    problems
+}
+
+/// Builds types of ebpf programs to measure from arguments
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+fn program_types(args: &RunArgs) -> Vec<ProgramType> {
+    args.program_types
+        .iter()
+        .flatten()
+        .map(|program_type| ProgramType::from(*program_type))
+        .collect()
+}
+
+/// Builds types of maps to measure from arguments
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+fn map_types(args: &RunArgs) -> Vec<MapType> {
+    args.map_types
+        .iter()
+        .flatten()
+        .map(|map_type| MapType::from(*map_type))
+        .collect()
+}
+
+/// Builds thresholds of event rate gating export of ebpf programs from arguments
+///
+/// # Arguments
+///
+/// * `args` - Run arguments
+fn rate_gate(args: &RunArgs) -> Result<Option<rate_gate_exporter::RateGate>> {
+    let Some(on_rate) = args.export_on_rate else {
+        return Ok(None);
+    };
+    let off_rate = args.export_off_rate.unwrap_or(on_rate);
+    if off_rate > on_rate {
+        bail!("Export off rate {off_rate} must not be higher than export on rate {on_rate}");
+    }
+    Ok(Some(rate_gate_exporter::RateGate {
+        on_rate,
+        off_rate,
+        off_debounce: args.export_off_debounce,
+    }))
+}
+
+/// Builds options of the local prometheus server from arguments
+///
+/// # Arguments
+///
+/// * `args` - Prometheus exporter arguments
+fn server_options(args: &PrometheusArgs) -> prometheus_exporter::ServerOptions {
+    let auth = match (
+        &args.metrics_auth_token,
+        &args.metrics_basic_user,
+        &args.metrics_basic_pass,
+    ) {
+        (Some(token), _, _) => Some(prometheus_exporter::MetricsAuth::Bearer(token.clone())),
+        (None, Some(user), Some(pass)) => Some(prometheus_exporter::MetricsAuth::Basic(
+            user.clone(),
+            pass.clone(),
+        )),
+        _ => None,
+    };
+    prometheus_exporter::ServerOptions {
+        tls: args.tls_cert.clone().zip(args.tls_key.clone()),
+        auth,
+        metrics_path: args.metrics_path.clone(),
+        reuse_port: args.reuse_port,
+    }
+}
+
+/// Measures ebpf programs/maps with the meter every period and exports the stats until
+/// the number of ticks is measured or collection fails. Collection runs in a spawned task,
+/// so it must be awaited within a tokio runtime, while exporting happens in the awaiting task
+///
+/// # Arguments
+///
+/// * `period` - Period of time between two measurements (ticks)
+///
+/// * `channel_capacity` - Capacity of the channel between collection and export
+///
+/// * `meter` - Meter calculating stats from raw stats, e.g. CpuMeter or MapMeter
+///
+/// * `exporter` - Exporter of the stats
+///
+/// * `raw_exporter` - Exporter of raw stats as they were read from the kernel
+///
+/// * `meter_metrics` - Metrics of bpfmeter itself
+///
+/// * `ticks` - Number of measurements, unlimited if not set
+///
+/// * `options` - Options defining which ebpf programs/maps are collected and how
+///
+/// * `reload` - Receiver of options with reloaded filters of ebpf programs/maps
+///
+/// * `flush_ticks` - Number of ticks between two flushes of the exporter
+// Arguments are the independent parts of the run configuration used by a single meter
+#[allow(clippy::too_many_arguments)]
+pub async fn measure<M: Meter + Unpin + 'static>(
     period: Duration,
     channel_capacity: usize,
-    mut meter: M,
+    meter: M,
     exporter: &RefCell<dyn Exporter>,
+    raw_exporter: Option<&RefCell<raw_exporter::RawExporter>>,
+    meter_metrics: MeterMetrics,
     ticks: Option<u64>,
-    requested_ids: Option<&Vec<u32>>,
+    options: CollectOptions,
+    reload: Option<watch::Receiver<CollectOptions>>,
+    flush_ticks: u64,
 ) -> Result<()> {
-    let _fd = sys::enable_stats(sys::Stats::RunTime)
-        .with_context(|| "Failed to enable run time stats")?;
+    let mut measurements = MeterStream::start(
+        period,
+        channel_capacity,
+        meter,
+        raw_exporter,
+        meter_metrics,
+        ticks,
+        options,
+        reload,
+    )
+    .await?;
+    let mut last_flush_tick = 0;
+    while let Some(measurement) = measurements.next().await {
+        let measurement = measurement?;
+        exporter.borrow_mut().export_info(&measurement.info())?;
+        // Flushed on the first measurement of every flush_ticks-th tick, the rest is
+        // flushed when the exporter is dropped
+        if measurement.raw_stats.tick >= last_flush_tick + flush_ticks {
+            exporter.borrow_mut().flush()?;
+            last_flush_tick = measurement.raw_stats.tick;
+        }
+    }
+    Ok(())
+}
 
-    let requested_bpf_program_ids = if let Some(requested_ids) = requested_ids {
-        // Create mapping of ebpf program/map ids to their names
-        let mut bpf_id_name_map: HashMap<_, _> = M::get_id_name_entity_mapping();
-        bpf_id_name_map.retain(|&k, _| requested_ids.contains(&k));
+/// Stream of stats of ebpf programs/maps measured by the meter on every tick, so they can
+/// be consumed without exporters. Stats are collected by a background task, the stream
+/// ends when the task is finished and yields its error if it failed
+pub struct MeterStream<'a, M: Meter> {
+    /// Meter calculating stats from raw stats
+    meter: M,
+    /// Period of time between two samples
+    period: Duration,
+    /// Exporter of raw stats, all received raw stats are exported including skipped ones
+    raw_exporter: Option<&'a RefCell<raw_exporter::RawExporter>>,
+    /// Schedule of adaptive sampling, set if it is enabled
+    schedule: Option<SamplingSchedule>,
+    /// Averager of samples taken within a period, set if several samples are taken
+    averager: Option<SampleAverager>,
+    /// Tick of the last received raw stats
+    last_tick: u64,
+    /// Channel receiving raw stats from the monitoring task
+    rx: mpsc::Receiver<BpfRawStats>,
+    /// Handle of the monitoring task, taken once it is finished
+    monitor_handle: Option<JoinHandle<Result<()>>>,
+    /// Run time stats are enabled while the file descriptor is open
+    _stats_fd: OwnedFd,
+}
+
+impl<'a, M: Meter + Unpin + 'static> MeterStream<'a, M> {
+    /// Waits for the requested ebpf programs/maps and starts collecting their stats
+    ///
+    /// # Arguments
+    ///
+    /// * `period` - Period of time between two measurements (ticks)
+    ///
+    /// * `channel_capacity` - Capacity of the channel between collection and the stream
+    ///
+    /// * `meter` - Meter calculating stats from raw stats
+    ///
+    /// * `raw_exporter` - Exporter of raw stats
+    ///
+    /// * `meter_metrics` - Metrics of bpfmeter itself
+    ///
+    /// * `ticks` - Number of measurements, unlimited if not set
+    ///
+    /// * `options` - Options defining which ebpf programs/maps are collected and how
+    ///
+    /// * `reload` - Receiver of options with reloaded filters of ebpf programs/maps
+    // Arguments are the independent parts of the run configuration used by a single meter
+    #[allow(clippy::too_many_arguments)]
+    pub async fn start(
+        period: Duration,
+        channel_capacity: usize,
+        meter: M,
+        raw_exporter: Option<&'a RefCell<raw_exporter::RawExporter>>,
+        meter_metrics: MeterMetrics,
+        ticks: Option<u64>,
+        options: CollectOptions,
+        reload: Option<watch::Receiver<CollectOptions>>,
+    ) -> Result<Self> {
+        let stats_fd = sys::enable_stats(sys::Stats::RunTime)
+            .with_context(|| "Failed to enable run time stats")?;
+
+        if !options.ids.is_empty() {
+            wait_for_requested::<M>(&options, period).await?;
+        }
+
+        if !options.start_jitter.is_zero() {
+            let delay = random_delay(options.start_jitter);
+            info!("Delaying the first {} measurement by {delay:?}", M::NAME);
+            tokio::time::sleep(delay).await;
+        }
+
+        let schedule = options.adaptive_sampling.map(SamplingSchedule::new);
+        // Samples are collected as measurements of a shorter period, the last sample
+        // of the last period is collected on the tick bound
+        let sample_count = options.sample_count.unwrap_or(1);
+        let averager = (sample_count > 1).then(|| SampleAverager::new(sample_count));
+        let period = period / sample_count as u32;
+        let ticks = ticks.map(|ticks| (ticks + 1) * sample_count - 1);
+        let (tx, rx) = mpsc::channel(channel_capacity);
+        let monitor_handle = tokio::spawn(monitor::<M>(
+            period,
+            ticks,
+            options,
+            reload,
+            meter_metrics,
+            tx,
+        ));
+
+        Ok(Self {
+            meter,
+            period,
+            raw_exporter,
+            schedule,
+            averager,
+            last_tick: 0,
+            rx,
+            monitor_handle: Some(monitor_handle),
+            _stats_fd: stats_fd,
+        })
+    }
 
-        // Check that some of the ebpf programs/maps are now loaded
-        if !requested_ids
+    /// Returns the next measurement, None once collection is finished
+    pub async fn next(&mut self) -> Option<Result<Measurement>> {
+        std::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Calculates stats from raw stats received from the monitoring task
+    ///
+    /// # Arguments
+    ///
+    /// * `raw_stats` - Received raw stats
+    ///
+    /// Returns None if the stats are skipped by sampling or can't be calculated yet
+    fn process(&mut self, raw_stats: BpfRawStats) -> Result<Option<Measurement>> {
+        if let Some(raw_exporter) = self.raw_exporter {
+            raw_exporter
+                .borrow_mut()
+                .export_raw(M::NAME, self.period, &raw_stats)?;
+        }
+        if let Some(schedule) = self.schedule.as_mut() {
+            if raw_stats.tick != self.last_tick {
+                schedule.retain_recent(raw_stats.tick);
+                self.last_tick = raw_stats.tick;
+            }
+            // Skipped programs are measured over the whole interval on the next sample
+            if !schedule.is_due(raw_stats.id, raw_stats.tick) {
+                return Ok(None);
+            }
+        }
+        let Some(stats) = self.meter.generate_stats_info(&raw_stats) else {
+            return Ok(None);
+        };
+        if let Some(schedule) = self.schedule.as_mut() {
+            schedule.update(raw_stats.id, raw_stats.tick, &stats);
+        }
+        let Some(averager) = self.averager.as_mut() else {
+            return Ok(Some(Measurement { raw_stats, stats }));
+        };
+        // Measurement of the period is numbered by the period instead of the sample
+        Ok(averager
+            .add(raw_stats.id, raw_stats.tick, stats)
+            .map(|(tick, stats)| Measurement {
+                raw_stats: BpfRawStats { tick, ..raw_stats },
+                stats,
+            }))
+    }
+}
+
+impl<M: Meter + Unpin + 'static> Stream for MeterStream<'_, M> {
+    type Item = Result<Measurement>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(self.rx.poll_recv(cx)) {
+                Some(raw_stats) => {
+                    if let Some(measurement) = self.process(raw_stats).transpose() {
+                        return Poll::Ready(Some(measurement));
+                    }
+                }
+                // Channel is closed when monitoring is finished or failed
+                None => {
+                    let Some(monitor_handle) = self.monitor_handle.as_mut() else {
+                        return Poll::Ready(None);
+                    };
+                    let result = ready!(Pin::new(monitor_handle).poll(cx));
+                    self.monitor_handle = None;
+                    return Poll::Ready(
+                        result
+                            .with_context(|| "Monitoring task failed")
+                            .and_then(|result| result)
+                            .err()
+                            .map(Err),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Checks that some of the requested ebpf programs/maps are loaded, otherwise fails,
+/// proceeds or waits for them depending on the behavior
+///
+/// # Arguments
+///
+/// * `options` - Options with ids of the requested ebpf programs/maps and behavior when
+///   none of them is loaded
+///
+/// * `period` - Period of time between two checks while waiting
+async fn wait_for_requested<M: Meter>(options: &CollectOptions, period: Duration) -> Result<()> {
+    let requested_ids = &options.ids;
+    let mut first_check = true;
+    loop {
+        // Create mapping of ebpf program/map ids to their names
+        let bpf_id_name_map: HashMap<_, _> = M::get_id_name_entity_mapping(options);
+        let missing = requested_ids
             .iter()
-            .map(|id| (id, bpf_id_name_map.contains_key(id)))
-            .inspect(|(id, exists)| {
-                if !*exists {
-                    warn!("Ebpf program {id} not found")
+            .filter(|id| !bpf_id_name_map.contains_key(id))
+            .collect::<Vec<_>>();
+        if first_check {
+            for id in &missing {
+                warn!("Ebpf program {id} not found");
+            }
+        }
+        if missing.len() < requested_ids.len() {
+            if !first_check {
+                info!(
+                    "Requested ebpf {} is loaded, starting measurements",
+                    M::NAME
+                );
+            }
+            return Ok(());
+        }
+        match options.on_missing {
+            OnMissing::Fail => bail!("No ebpf programs with specified ids found"),
+            OnMissing::Skip => {
+                warn!("No ebpf programs with specified ids found, measuring them once loaded");
+                return Ok(());
+            }
+            OnMissing::Wait => {
+                if first_check {
+                    info!("Waiting for one of the requested ebpf programs to be loaded");
                 }
-            })
-            .any(|(_, exists)| exists)
-        {
-            bail!("No ebpf programs with specified ids found");
+            }
         }
+        first_check = false;
+        tokio::time::sleep(period).await;
+    }
+}
 
-        requested_ids
-    } else {
-        &Vec::new()
-    };
+/// Returns a random duration from zero to the maximum
+///
+/// # Arguments
+///
+/// * `max` - Maximum duration
+fn random_delay(max: Duration) -> Duration {
+    // Hasher keys are random for every RandomState, so the hash is a random number
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_nanos(random % (max.as_nanos() as u64 + 1))
+}
 
-    let (tx, mut rx) = mpsc::channel(channel_capacity);
+/// Collects ebpf program/map stats every period and sends them to the channel
+///
+/// # Arguments
+///
+/// * `period` - Period of time between two measurements
+///
+/// * `ticks` - Number of measurements to take, unlimited if not specified
+///
+/// * `options` - Options of collecting ebpf program/map stats
+///
+/// * `reload` - Receiver of options with reloaded filters, checked on every tick
+///
+/// * `meter_metrics` - Metrics of bpfmeter itself
+///
+/// * `tx` - Channel to send stats to
+async fn monitor<M: Meter>(
+    period: Duration,
+    ticks: Option<u64>,
+    mut options: CollectOptions,
+    mut reload: Option<watch::Receiver<CollectOptions>>,
+    meter_metrics: MeterMetrics,
+    tx: mpsc::Sender<BpfRawStats>,
+) -> Result<()> {
+    let timer = Instant::now();
+    let mut last_overrun_warning: Option<Instant> = None;
 
-    let prog_list_ids = requested_bpf_program_ids.clone();
-    tokio::spawn(async move {
-        let timer = Instant::now();
+    for tick in 0.. {
+        let cur_time = timer.elapsed();
 
-        'monitor: for tick in 0.. {
-            let cur_time = timer.elapsed();
+        if let Some(reload) = reload.as_mut()
+            && reload.has_changed().unwrap_or_default()
+        {
+            options.update_filters(&reload.borrow_and_update());
+            // Gone programs/maps are retired by exporters, new ones are collected from now on
+            let matching = M::get_id_name_entity_mapping(&options)
+                .into_keys()
+                .filter(|id| options.is_requested(*id))
+                .count();
+            info!(
+                "Reloaded filters of the {} meter, {matching} ebpf programs/maps match",
+                M::NAME
+            );
+        }
 
-            let bpf_program_stats = BpfRawStats {
-                tick,
-                time_recieved: cur_time,
-                ..Default::default()
-            };
+        let bpf_program_stats = BpfRawStats {
+            tick,
+            time_recieved: cur_time,
+            ..Default::default()
+        };
 
-            if let Err(err) =
-                M::collect_raw_stats(&prog_list_ids, &bpf_program_stats, tx.clone()).await
-            {
-                error!("Stopping monitoring: {err}");
-                break 'monitor;
+        match M::collect_raw_stats(&options, &bpf_program_stats, tx.clone()).await {
+            Result::Ok(collect_stats) => {
+                meter_metrics.set_completeness(M::NAME, collect_stats.completeness());
+                for (reason, count) in collect_stats.filtered {
+                    meter_metrics.set_filtered(M::NAME, reason, count);
+                }
+            }
+            Err(err) => return Err(err.context("Stopping monitoring")),
+        }
+        if options.total_locked_memory {
+            let memory_usage = meter::memlock::memory_usage();
+            match memory_usage.total_locked() {
+                Some(bytes) => meter_metrics.set_total_locked_memory(bytes),
+                None => debug!("Total locked memory of ebpf programs and maps is unavailable"),
+            }
+            for (kind, bytes) in memory_usage.by_kind() {
+                meter_metrics.set_memory(kind, bytes);
             }
+        }
 
-            if let Some(tick_bound) = ticks
-                && tick >= tick_bound
-            {
-                break;
+        if let Some(tick_bound) = ticks
+            && tick >= tick_bound
+        {
+            break;
+        }
+
+        // Adjust period to the actual time spent in the loop
+        let elapsed = timer.elapsed() - cur_time;
+        if elapsed >= period {
+            // Measurements are taken back to back, so the rates are calculated over
+            // a longer period than configured
+            meter_metrics.inc_period_overrun(M::NAME);
+            if last_overrun_warning.is_none_or(|t| t.elapsed() >= OVERRUN_WARNING_INTERVAL) {
+                warn!(
+                    "Collecting {} stats took {elapsed:?}, which is longer than the period {period:?}",
+                    M::NAME
+                );
+                last_overrun_warning = Some(Instant::now());
             }
+        }
+        // Elapsed time may be greater than period, so we must use checked_sub and set wait_time to zero
+        let wait_time = period.checked_sub(elapsed).unwrap_or_default();
+        tokio::time::sleep(wait_time).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meter::{BpfStatsInfo, CollectStats};
 
-            // Adjust period to the actual time spent in the loop
-            let elapsed = timer.elapsed() - cur_time;
-            // Elapsed time may be greater than period, so we must use checked_sub and set wait_time to zero
-            let wait_time = period.checked_sub(elapsed).unwrap_or_default();
-            tokio::time::sleep(wait_time).await;
+    const SLOW_COLLECTION_TIME: Duration = Duration::from_millis(20);
+
+    /// Meter that takes longer to collect stats than the measurement period
+    struct SlowMeter;
+
+    impl Meter for SlowMeter {
+        const NAME: &'static str = "slow";
+
+        fn get_id_name_entity_mapping(_options: &CollectOptions) -> HashMap<u32, String> {
+            HashMap::new()
+        }
+
+        async fn collect_raw_stats(
+            _options: &CollectOptions,
+            _base_raw_stats: &BpfRawStats,
+            _tx: mpsc::Sender<BpfRawStats>,
+        ) -> Result<CollectStats> {
+            tokio::time::sleep(SLOW_COLLECTION_TIME).await;
+            Ok(CollectStats::default())
         }
-    });
 
-    // Receive results from channel
-    while let Some(cur_stats) = rx.recv().await {
-        if let Some(stats_info) = meter.generate_stats_info(&cur_stats) {
-            let export_info = BpfInfo {
-                id: cur_stats.id,
-                name: &cur_stats.name,
-                tick: cur_stats.tick,
-                stats: stats_info,
-            };
-            exporter.borrow_mut().export_info(&export_info)?;
+        fn generate_stats_info(&mut self, _raw_stats: &BpfRawStats) -> Option<BpfStatsInfo> {
+            None
         }
     }
 
-    Ok(())
+    /// Exporter counting flushes
+    #[derive(Default)]
+    struct FlushCounter {
+        flushes: usize,
+    }
+
+    impl Exporter for FlushCounter {
+        fn export_info(&mut self, _: &meter::BpfInfo) -> Result<()> {
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_with_exporters_from_async_caller() {
+        let config = Config::try_new(["bpfmeter", "run"].map(Into::into).to_vec()).unwrap();
+        let SubCommands::Run(args) = config.command else {
+            panic!("Expected run arguments");
+        };
+        let exporter = Rc::new(RefCell::new(FlushCounter::default()));
+        let control = RunControl::new();
+
+        // Starting another runtime inside the runtime of the caller is refused
+        let err =
+            run_with_exporters(&args, (exporter.clone(), exporter.clone()), &control).unwrap_err();
+        assert!(err.to_string().contains("run_with_exporters_async"));
+
+        control.stop();
+        run_with_exporters_async(&args, (exporter.clone(), exporter.clone()), &control)
+            .await
+            .unwrap();
+        // Both meters flush the shared exporter when stopped
+        assert_eq!(exporter.borrow().flushes, 2);
+    }
+
+    #[tokio::test]
+    async fn test_failed_measurements_are_not_overwritten() {
+        let failed = async { Err(anyhow::anyhow!("Stats of ebpf program 1 can't be read")) };
+        let finished_later = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Ok(())
+        };
+        let err = join_measurements(Some(failed), Some(finished_later))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("can't be read"));
+
+        // The other meter doesn't have to finish for the error to be returned
+        let failed = async { Err(anyhow::anyhow!("Stats of ebpf map 1 can't be read")) };
+        let join = join_measurements(Some(std::future::pending()), Some(failed));
+        let res = tokio::time::timeout(Duration::from_secs(1), join).await;
+        assert!(res.unwrap().is_err());
+
+        let disabled: Option<std::future::Ready<Result<()>>> = None;
+        assert!(
+            join_measurements(disabled, Some(async { Ok(()) }))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_metrics_addr() {
+        let ip = "127.0.0.1".parse().unwrap();
+        assert_eq!(
+            metrics_addr(&BindAddr { ip, port: None }, 9100),
+            SocketAddr::new(ip, 9100)
+        );
+        assert_eq!(
+            metrics_addr(
+                &BindAddr {
+                    ip,
+                    port: Some(9200)
+                },
+                9100
+            ),
+            SocketAddr::new(ip, 9200)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_period_overrun() {
+        let meter_metrics = MeterMetrics::default();
+        let (tx, _rx) = mpsc::channel(1);
+        monitor::<SlowMeter>(
+            SLOW_COLLECTION_TIME / 4,
+            Some(3),
+            CollectOptions::default(),
+            None,
+            meter_metrics.clone(),
+            tx,
+        )
+        .await
+        .unwrap();
+        // The last tick stops monitoring before the period is checked
+        assert_eq!(meter_metrics.period_overruns(SlowMeter::NAME), 3);
+    }
+
+    #[tokio::test]
+    async fn test_no_period_overrun() {
+        let meter_metrics = MeterMetrics::default();
+        let (tx, _rx) = mpsc::channel(1);
+        monitor::<SlowMeter>(
+            SLOW_COLLECTION_TIME * 4,
+            Some(1),
+            CollectOptions::default(),
+            None,
+            meter_metrics.clone(),
+            tx,
+        )
+        .await
+        .unwrap();
+        assert_eq!(meter_metrics.period_overruns(SlowMeter::NAME), 0);
+    }
+
+    #[test]
+    fn test_random_delay_is_bounded() {
+        let max = Duration::from_millis(10);
+        assert!((0..100).all(|_| random_delay(max) <= max));
+        assert_eq!(random_delay(Duration::ZERO), Duration::ZERO);
+    }
+
+    /// Meter that fails to read stats of one of four intended programs
+    struct PartialMeter;
+
+    impl Meter for PartialMeter {
+        const NAME: &'static str = "partial";
+
+        fn get_id_name_entity_mapping(_options: &CollectOptions) -> HashMap<u32, String> {
+            HashMap::new()
+        }
+
+        async fn collect_raw_stats(
+            _options: &CollectOptions,
+            _base_raw_stats: &BpfRawStats,
+            _tx: mpsc::Sender<BpfRawStats>,
+        ) -> Result<CollectStats> {
+            Ok(CollectStats {
+                intended: 4,
+                measured: 3,
+                ..Default::default()
+            })
+        }
+
+        fn generate_stats_info(&mut self, _raw_stats: &BpfRawStats) -> Option<BpfStatsInfo> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn test_collection_completeness() {
+        let meter_metrics = MeterMetrics::default();
+        let (tx, _rx) = mpsc::channel(1);
+        monitor::<PartialMeter>(
+            Duration::from_millis(1),
+            Some(0),
+            CollectOptions::default(),
+            None,
+            meter_metrics.clone(),
+            tx,
+        )
+        .await
+        .unwrap();
+        assert_eq!(meter_metrics.completeness(PartialMeter::NAME), 0.75);
+    }
+
+    /// Meter measuring the requested programs out of four intended ones
+    struct FilteredMeter;
+
+    impl Meter for FilteredMeter {
+        const NAME: &'static str = "filtered";
+
+        fn get_id_name_entity_mapping(_options: &CollectOptions) -> HashMap<u32, String> {
+            HashMap::new()
+        }
+
+        async fn collect_raw_stats(
+            options: &CollectOptions,
+            _base_raw_stats: &BpfRawStats,
+            _tx: mpsc::Sender<BpfRawStats>,
+        ) -> Result<CollectStats> {
+            Ok(CollectStats {
+                intended: 4,
+                measured: options.ids.len() as u64,
+                ..Default::default()
+            })
+        }
+
+        fn generate_stats_info(&mut self, _raw_stats: &BpfRawStats) -> Option<BpfStatsInfo> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn test_filters_are_reloaded() {
+        let meter_metrics = MeterMetrics::default();
+        let (tx, _rx) = mpsc::channel(1);
+        let (reload, reload_rx) = watch::channel(CollectOptions::default());
+        reload.send_replace(CollectOptions {
+            ids: vec![1, 2],
+            ..Default::default()
+        });
+        monitor::<FilteredMeter>(
+            Duration::from_millis(1),
+            Some(0),
+            CollectOptions::default(),
+            Some(reload_rx),
+            meter_metrics.clone(),
+            tx,
+        )
+        .await
+        .unwrap();
+        assert_eq!(meter_metrics.completeness(FilteredMeter::NAME), 0.5);
+    }
+
+    #[test]
+    fn test_period_problems() {
+        let cpu = Some((Duration::from_secs(1), 10));
+        assert!(period_problems(cpu, Some((Duration::from_secs(30), 10))).is_empty());
+        // Walking 100 maps every 10ms can't keep up
+        assert_eq!(
+            period_problems(cpu, Some((Duration::from_millis(10), 100))).len(),
+            1
+        );
+        // Periods differing 3600 times
+        assert_eq!(
+            period_problems(cpu, Some((Duration::from_secs(3600), 10))).len(),
+            1
+        );
+        assert!(period_problems(None, Some((Duration::from_secs(3600), 10))).is_empty());
+    }
+
+    #[test]
+    fn test_group_names_are_unique() {
+        let config = Config::try_new(
+            ["bpfmeter", "run", "--group", "lb=1", "--group", "lb=2"]
+                .map(Into::into)
+                .to_vec(),
+        )
+        .unwrap();
+        let SubCommands::Run(args) = config.command else {
+            panic!("Expected run command");
+        };
+        assert!(program_groups(&args.output_mode.prometheus).is_err());
+    }
 }
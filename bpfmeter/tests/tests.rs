@@ -33,9 +33,8 @@ fn test_cpu_measurement() {
     reader.read_line(&mut stdout).expect("Cannot read stdout");
     reader.read_line(&mut stdout).expect("Cannot read stdout");
     assert!(
-        stdout.contains(&format!(
-            "Prometheus node exporter is running at port: {port}"
-        )),
+        stdout.contains("Prometheus node exporter is running at: ")
+            && stdout.contains(&format!(":{port}/metrics")),
         "Prometheus node exporter is not started"
     );
 
@@ -92,9 +91,8 @@ fn test_map_measurement() {
     reader.read_line(&mut stdout).expect("Cannot read stdout");
     reader.read_line(&mut stdout).expect("Cannot read stdout");
     assert!(
-        stdout.contains(&format!(
-            "Prometheus node exporter is running at port: {port}"
-        )),
+        stdout.contains("Prometheus node exporter is running at: ")
+            && stdout.contains(&format!(":{port}/metrics")),
         "Prometheus node exporter is not started {}",
         stdout
     );
@@ -118,3 +116,107 @@ fn test_map_measurement() {
     }
     assert!(passed, "Map size is not found for bpftrace map");
 }
+
+#[test]
+fn test_csv_is_complete_after_ctrl_c() {
+    let output_dir = std::env::temp_dir().join(format!("bpfmeter_ctrl_c_{}", std::process::id()));
+    std::fs::create_dir_all(&output_dir).expect("Cannot create output directory");
+    let child = Command::new(EXE_BPFMETER)
+        .stdout(Stdio::null())
+        .args([
+            "run",
+            "--cpu-period",
+            "500ms",
+            "-o",
+            output_dir.to_str().unwrap(),
+        ])
+        .spawn()
+        .expect("failed to start bpfmeter");
+
+    // Let it measure a couple of ticks
+    std::thread::sleep(std::time::Duration::from_millis(2200));
+    let mut guard = ChildGuard(child);
+    unsafe { libc::kill(guard.0.id() as i32, libc::SIGINT) };
+    let status = guard.0.wait().expect("Cannot wait for bpfmeter");
+    assert!(status.success(), "bpfmeter exited with {status}");
+
+    let file = std::fs::read_dir(&output_dir)
+        .expect("Cannot read output directory")
+        .map_while(Result::ok)
+        .map(|entry| entry.path())
+        .find(|path| path.to_string_lossy().contains("sys_enter_opena"))
+        .expect("Csv file is not found for bpftrace program");
+    let content = std::fs::read_to_string(&file).expect("Cannot read csv file");
+    std::fs::remove_dir_all(&output_dir).expect("Cannot remove output directory");
+
+    // Every row including the last one is complete
+    assert!(content.ends_with('\n'), "Last row is truncated");
+    let rows = content.lines().collect::<Vec<_>>();
+    assert!(rows.len() > 2, "Rows of the ticks are missing");
+    let columns = rows[0].split(',').count();
+    assert!(
+        rows.iter().all(|row| row.split(',').count() == columns),
+        "Some rows are incomplete"
+    );
+}
+
+#[test]
+fn test_failed_meter_exits_with_error() {
+    let output_dir = std::env::temp_dir().join(format!("bpfmeter_failed_{}", std::process::id()));
+    std::fs::create_dir_all(&output_dir).expect("Cannot create output directory");
+    // The cpu meter fails on the missing program while the map meter finishes its ticks
+    let output = Command::new(EXE_BPFMETER)
+        .args([
+            "run",
+            "--strict",
+            "-p",
+            "4000000000",
+            "--on-missing",
+            "fail",
+            "--enable-maps",
+            "--map-period",
+            "500ms",
+            "--ticks",
+            "2",
+            "-o",
+            output_dir.to_str().unwrap(),
+        ])
+        .output()
+        .expect("failed to start bpfmeter");
+    std::fs::remove_dir_all(&output_dir).expect("Cannot remove output directory");
+
+    assert!(!output.status.success(), "bpfmeter exited successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("CPU measurements failed"),
+        "Error of the cpu meter is not logged"
+    );
+}
+
+#[test]
+fn test_checkpoint_is_saved_on_sigterm() {
+    let checkpoint =
+        std::env::temp_dir().join(format!("bpfmeter_checkpoint_{}.json", std::process::id()));
+    let child = Command::new(EXE_BPFMETER)
+        .stdout(Stdio::null())
+        .args([
+            "run",
+            "--cpu-period",
+            "500ms",
+            "--checkpoint",
+            checkpoint.to_str().unwrap(),
+        ])
+        .spawn()
+        .expect("failed to start bpfmeter");
+
+    // Let it measure a couple of ticks
+    std::thread::sleep(std::time::Duration::from_millis(1200));
+    let mut guard = ChildGuard(child);
+    unsafe { libc::kill(guard.0.id() as i32, libc::SIGTERM) };
+    let status = guard.0.wait().expect("Cannot wait for bpfmeter");
+    assert!(status.success(), "bpfmeter exited with {status}");
+
+    let content = std::fs::read_to_string(&checkpoint).expect("Checkpoint is not saved");
+    std::fs::remove_file(&checkpoint).expect("Cannot remove checkpoint");
+    assert!(!content.is_empty(), "Checkpoint is empty");
+}